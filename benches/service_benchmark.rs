@@ -4,7 +4,7 @@ pub mod screen_service {
 
 use prost::Message;
 use prost_types::Timestamp;
-use screen_service::{departure::{self}, CalendarEvent, Departure, KittyDebt, ScreenContentReply, Time};
+use screen_service::{CalendarEvent, Departure, KittyDebt, ScreenContentReply, Time};
 use std::{
     hash::{Hash, Hasher},
     sync::{Arc, Mutex}, time::SystemTime,
@@ -51,8 +51,8 @@ fn get_dummy_proto() -> ScreenContentReply {
         },
     ];
     let bus_departures = vec![
-        Departure {destination_enum: departure::DestinationEnum::Flon.into(), departure_time: Some(Timestamp::from(SystemTime::now()))},
-        Departure {destination_enum: departure::DestinationEnum::Renens.into(), departure_time: Some(Timestamp::from(SystemTime::now()))},
+        Departure {destination: "FLON".into(), departure_time: Some(Timestamp::from(SystemTime::now())), ..Default::default()},
+        Departure {destination: "RENENS".into(), departure_time: Some(Timestamp::from(SystemTime::now())), ..Default::default()},
     ];
     let next_upcoming_event = Some(CalendarEvent {
         event_title: "This is a rather long event title".into(),
@@ -2,7 +2,7 @@ use std::{env, path::PathBuf};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("proto");
-    let proto_files = vec![root.join("config.proto")];
+    let proto_files = vec![root.join("config.proto"), root.join("gtfs_realtime.proto")];
 
     // Tell cargo to recompile if any of these proto files are changed
     for proto_file in &proto_files {
@@ -0,0 +1,100 @@
+//! Guards the admin RPCs (refreshes, modes, brightness overrides, pushed messages, the log
+//! tail...), which the screen clients never need and which change the server's state or show its
+//! logs, URLs and API-keyed requests included.
+//!
+//! With an `admin_token` in the server config, callers must send it as an "authorization: Bearer
+//! <token>" metadata entry, as the admin console does. Without one, only callers on this host get
+//! through.
+
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+// Put in the requests' extensions by the interceptor, for the admin RPCs to check
+#[derive(Debug, Clone, Copy)]
+struct Admin;
+
+/// What the admin RPCs fail with when the interceptor didn't let their request through
+#[derive(Debug)]
+pub struct NotAdmin;
+
+impl From<NotAdmin> for Status {
+    fn from(_: NotAdmin) -> Self {
+        Status::permission_denied(
+            "Admin RPCs need the admin token (or to come from the server's host without one)",
+        )
+    }
+}
+
+/// Marks the requests allowed to use the admin RPCs, and turns away the ones with a wrong token
+#[derive(Debug, Clone)]
+pub struct AdminInterceptor {
+    token: String,
+}
+
+impl AdminInterceptor {
+    pub fn new(token: String) -> Self {
+        AdminInterceptor { token }
+    }
+}
+
+impl Interceptor for AdminInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let bearer = request
+            .metadata()
+            .get("authorization")
+            .and_then(|authorization| authorization.to_str().ok())
+            .and_then(|authorization| authorization.strip_prefix("Bearer "));
+        let is_admin = match (self.token.is_empty(), bearer) {
+            (true, _) => request
+                .remote_addr()
+                .is_some_and(|address| address.ip().is_loopback()),
+            (false, Some(bearer)) if bearer == self.token => true,
+            (false, Some(_)) => return Err(Status::unauthenticated("Wrong admin token")),
+            (false, None) => false,
+        };
+        if is_admin {
+            request.extensions_mut().insert(Admin);
+        }
+        Ok(request)
+    }
+}
+
+/// Fails the admin RPCs whose request the interceptor didn't let through
+pub fn require_admin<T>(request: &Request<T>) -> Result<(), NotAdmin> {
+    request
+        .extensions()
+        .get::<Admin>()
+        .map(|_| ())
+        .ok_or(NotAdmin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(authorization) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn lets_admins_through() {
+        let mut interceptor = AdminInterceptor::new("s3cret".into());
+        let admin = interceptor.call(request(Some("Bearer s3cret"))).unwrap();
+        assert!(require_admin(&admin).is_ok());
+        // The screen clients still get the content, just not the admin RPCs
+        let client = interceptor.call(request(None)).unwrap();
+        assert!(require_admin(&client).is_err());
+        assert!(interceptor.call(request(Some("Bearer guess"))).is_err());
+
+        // Without a token, only from this host (and tests have no address at all)
+        let mut interceptor = AdminInterceptor::new(String::new());
+        let remote = interceptor.call(request(Some("Bearer s3cret"))).unwrap();
+        assert!(require_admin(&remote).is_err());
+    }
+}
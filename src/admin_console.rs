@@ -0,0 +1,297 @@
+use crate::config_extractor::api_config::ApiConfig;
+use crate::dummy_client::screen_service::{
+    screen_service_client::ScreenServiceClient, AcknowledgeRequest, ApproveSourceRequest,
+    CompleteChoreRequest, LogTailRequest, PushMessageRequest, RefreshRequest, SetBrightnessRequest,
+    SetUpdaterModeRequest, StatusRequest,
+};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+use tracing::info;
+
+const HELP: &str = "Commands:
+  status                          show the updaters' state, brightness and hash
+  refresh <source>                update the given source right away
+  mode <source> <dummy|real>      switch the given source to fabricated or real data
+  approve <source>                let the given source's content onto the screen
+  ack <source>                    dismiss the given source's alerts (e.g. finished appliances)
+  done <chore>                    mark the given chore done (e.g. Trash)
+  set-brightness <0.0-1.0|auto>   override the brightness, or go back to the config map
+  push-message <seconds> <text>   show a notice on the screen for some time
+  tail-logs [lines]               print the last lines of the server logs (default 20)
+  help                            show this
+  quit                            exit the console";
+
+type AdminClient = ScreenServiceClient<InterceptedService<Channel, AdminToken>>;
+
+// Sends the server config's admin token along with every request, if there's one
+#[derive(Clone)]
+struct AdminToken(Option<MetadataValue<Ascii>>);
+
+impl Interceptor for AdminToken {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.0 {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        Ok(request)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum AdminCommand {
+    Status,
+    Refresh(String),
+    SetMode(String, bool),
+    Approve(String),
+    Acknowledge(String),
+    CompleteChore(String),
+    SetBrightness(Option<f32>),
+    PushMessage(u32, String),
+    TailLogs(u32),
+    Help,
+    Quit,
+}
+
+fn parse_command(line: &str) -> Result<AdminCommand, String> {
+    let line = line.trim();
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    let args = args.trim();
+    match command {
+        "status" => Ok(AdminCommand::Status),
+        "refresh" if !args.is_empty() => Ok(AdminCommand::Refresh(args.to_string())),
+        "refresh" => Err("usage: refresh <source>".into()),
+        "mode" => match args
+            .split_once(' ')
+            .map(|(source, mode)| (source, mode.trim()))
+        {
+            Some((source, "dummy")) => Ok(AdminCommand::SetMode(source.to_string(), true)),
+            Some((source, "real")) => Ok(AdminCommand::SetMode(source.to_string(), false)),
+            _ => Err("usage: mode <source> <dummy|real>".into()),
+        },
+        "approve" if !args.is_empty() => Ok(AdminCommand::Approve(args.to_string())),
+        "approve" => Err("usage: approve <source>".into()),
+        "ack" if !args.is_empty() => Ok(AdminCommand::Acknowledge(args.to_string())),
+        "ack" => Err("usage: ack <source>".into()),
+        "done" if !args.is_empty() => Ok(AdminCommand::CompleteChore(args.to_string())),
+        "done" => Err("usage: done <chore>".into()),
+        "set-brightness" if args == "auto" => Ok(AdminCommand::SetBrightness(None)),
+        "set-brightness" => args
+            .parse::<f32>()
+            .ok()
+            .filter(|b| (0.0..=1.0).contains(b))
+            .map(|b| AdminCommand::SetBrightness(Some(b)))
+            .ok_or_else(|| "usage: set-brightness <0.0-1.0|auto>".into()),
+        "push-message" => {
+            let (seconds, text) = args.split_once(' ').unwrap_or((args, ""));
+            match (seconds.parse::<u32>(), text.trim()) {
+                (Ok(seconds), text) if !text.is_empty() => {
+                    Ok(AdminCommand::PushMessage(seconds, text.to_string()))
+                }
+                _ => Err("usage: push-message <seconds> <text>".into()),
+            }
+        }
+        "tail-logs" if args.is_empty() => Ok(AdminCommand::TailLogs(20)),
+        "tail-logs" => args
+            .parse::<u32>()
+            .map(AdminCommand::TailLogs)
+            .map_err(|_| "usage: tail-logs [lines]".into()),
+        "help" | "?" => Ok(AdminCommand::Help),
+        "quit" | "exit" => Ok(AdminCommand::Quit),
+        other => Err(format!("unknown command '{}', try 'help'", other)),
+    }
+}
+
+async fn execute(client: &mut AdminClient, command: AdminCommand) -> Result<String, tonic::Status> {
+    let output = match command {
+        AdminCommand::Status => {
+            let status = client.get_status(StatusRequest {}).await?.into_inner();
+            let mut output = format!(
+                "brightness: {}{}\nhash: {}",
+                status.brightness,
+                if status.brightness_overridden {
+                    " (overridden)"
+                } else {
+                    ""
+                },
+                status.hash
+            );
+            for updater in status.updaters {
+                output += &format!(
+                    "\n  {}: {} ({}, {} updates, {} failed, last one took {}ms)",
+                    updater.name,
+                    if updater.error { "ERROR" } else { "ok" },
+                    if updater.dummy_mode { "dummy" } else { "real" },
+                    updater.updates,
+                    updater.failures,
+                    updater.last_duration_ms
+                );
+                if let Some(staged) = &updater.staged_content {
+                    output += &format!("\n    under review, would show: {:?}", staged);
+                }
+            }
+            output
+        }
+        AdminCommand::Refresh(source) => {
+            client.refresh_now(RefreshRequest { source }).await?;
+            "refresh requested".into()
+        }
+        AdminCommand::SetMode(source, dummy_mode) => {
+            client
+                .set_updater_mode(SetUpdaterModeRequest { source, dummy_mode })
+                .await?;
+            "mode change requested".into()
+        }
+        AdminCommand::Approve(source) => {
+            client
+                .approve_source(ApproveSourceRequest { source })
+                .await?;
+            "source approved".into()
+        }
+        AdminCommand::Acknowledge(source) => {
+            client.acknowledge(AcknowledgeRequest { source }).await?;
+            "alerts dismissed".into()
+        }
+        AdminCommand::CompleteChore(chore) => {
+            client
+                .complete_chore(CompleteChoreRequest { chore })
+                .await?;
+            "chore done".into()
+        }
+        AdminCommand::SetBrightness(brightness) => {
+            client
+                .set_brightness(SetBrightnessRequest {
+                    brightness: brightness.unwrap_or_default(),
+                    clear_override: brightness.is_none(),
+                })
+                .await?;
+            "brightness set".into()
+        }
+        AdminCommand::PushMessage(duration_seconds, text) => {
+            client
+                .push_message(PushMessageRequest {
+                    text,
+                    duration_seconds,
+                })
+                .await?;
+            "message pushed".into()
+        }
+        AdminCommand::TailLogs(lines) => client
+            .get_log_tail(LogTailRequest { lines })
+            .await?
+            .into_inner()
+            .lines
+            .join("\n"),
+        AdminCommand::Help => HELP.into(),
+        // Handled by the caller
+        AdminCommand::Quit => String::new(),
+    };
+    Ok(output)
+}
+
+/// Runs an interactive console on stdin, sending admin commands to the configured server
+pub async fn run(api_config: &ApiConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let address = crate::config_extractor::get_server_address(api_config);
+    info!("Admin console connecting to {:?}", address.uri());
+    let token = api_config
+        .server
+        .as_ref()
+        .map(|server| server.admin_token.as_str())
+        .filter(|token| !token.is_empty())
+        .map(|token| format!("Bearer {}", token).parse())
+        .transpose()?;
+    let mut client =
+        ScreenServiceClient::with_interceptor(address.connect().await?, AdminToken(token));
+
+    let mut stdout = tokio::io::stdout();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        stdout.write_all(b"admin> ").await?;
+        stdout.flush().await?;
+        let Some(line) = lines.next_line().await? else {
+            // EOF, e.g. Ctrl-D
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let output = match parse_command(&line) {
+            Ok(AdminCommand::Quit) => break,
+            Ok(command) => execute(&mut client, command)
+                .await
+                .unwrap_or_else(|status| format!("error: {}", status.message())),
+            Err(usage) => usage,
+        };
+        stdout.write_all(format!("{}\n", output).as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse_command("status"), Ok(AdminCommand::Status));
+        assert_eq!(
+            parse_command("  refresh transport "),
+            Ok(AdminCommand::Refresh("transport".into()))
+        );
+        assert_eq!(
+            parse_command("mode gcal dummy"),
+            Ok(AdminCommand::SetMode("gcal".into(), true))
+        );
+        assert_eq!(
+            parse_command("mode transport  real"),
+            Ok(AdminCommand::SetMode("transport".into(), false))
+        );
+        assert_eq!(
+            parse_command("approve gcal"),
+            Ok(AdminCommand::Approve("gcal".into()))
+        );
+        assert_eq!(
+            parse_command("ack smart_plugs"),
+            Ok(AdminCommand::Acknowledge("smart_plugs".into()))
+        );
+        assert_eq!(
+            parse_command("done Trash"),
+            Ok(AdminCommand::CompleteChore("Trash".into()))
+        );
+        assert_eq!(
+            parse_command("set-brightness 0.5"),
+            Ok(AdminCommand::SetBrightness(Some(0.5)))
+        );
+        assert_eq!(
+            parse_command("set-brightness auto"),
+            Ok(AdminCommand::SetBrightness(None))
+        );
+        assert_eq!(
+            parse_command("push-message 60 Dinner is ready"),
+            Ok(AdminCommand::PushMessage(60, "Dinner is ready".into()))
+        );
+        assert_eq!(parse_command("tail-logs"), Ok(AdminCommand::TailLogs(20)));
+        assert_eq!(parse_command("tail-logs 5"), Ok(AdminCommand::TailLogs(5)));
+        assert_eq!(parse_command("exit"), Ok(AdminCommand::Quit));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse_command("refresh").is_err());
+        assert!(parse_command("mode gcal").is_err());
+        assert!(parse_command("mode gcal fake").is_err());
+        assert!(parse_command("approve").is_err());
+        assert!(parse_command("ack").is_err());
+        assert!(parse_command("set-brightness 2").is_err());
+        assert!(parse_command("set-brightness bright").is_err());
+        assert!(parse_command("push-message soon hello").is_err());
+        assert!(parse_command("push-message 60").is_err());
+        assert!(parse_command("tail-logs many").is_err());
+        assert!(parse_command("reboot").is_err());
+    }
+}
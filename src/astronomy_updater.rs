@@ -0,0 +1,171 @@
+//! Sunrise, sunset and moon phase where the screen is, computed rather than fetched: the usual
+//! approximations (see https://en.wikipedia.org/wiki/Sunrise_equation) are within a couple of
+//! minutes, plenty for a screen showing minutes.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::screen_service::Astronomy;
+use chrono::{DateTime, Local, NaiveDate, Timelike};
+use prost_types::Timestamp;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::info;
+
+// Nothing to fetch, and the times only change once a day
+const UPDATE_PERIOD: Duration = Duration::from_secs(3600);
+// Julian days of the POSIX epoch, and of the J2000 epoch the sun formulas count from
+const UNIX_EPOCH_JD: f64 = 2440587.5;
+const J2000_JD: f64 = 2451545.0;
+// A new moon (2000-01-06 18:14 UTC), and how long the moon takes to get back to new on average
+const NEW_MOON_JD: f64 = 2451550.26;
+const SYNODIC_MONTH_DAYS: f64 = 29.530588853;
+
+#[derive(Debug)]
+pub struct AstronomyUpdater {
+    update_mode: UpdateMode,
+    latitude: f64,
+    longitude: f64,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for AstronomyUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(37),
+            UpdateMode::Real => Instant::now() + UPDATE_PERIOD,
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} astronomy", self.update_mode);
+        let now = chrono::offset::Local::now();
+        let astronomy = match self.update_mode {
+            UpdateMode::Dummy => {
+                // Night for the first half of every hour, and a moon going round once an hour
+                let hour_start = now.timestamp() - i64::from(now.minute() * 60 + now.second());
+                Astronomy {
+                    sunrise: Some(to_timestamp(hour_start + 30 * 60)),
+                    sunset: Some(to_timestamp(hour_start + 3600)),
+                    moon_phase: now.minute() as f32 / 60.0,
+                }
+            }
+            UpdateMode::Real => get_astronomy(now, self.latitude, self.longitude),
+        };
+        // Computing can't fail
+        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+        vec![ContentUpdate::Astronomy(astronomy)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl AstronomyUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let astronomy_config = config.astronomy.as_ref().ok_or("No astronomy config")?;
+        if !(-90.0..=90.0).contains(&astronomy_config.latitude)
+            || !(-180.0..=180.0).contains(&astronomy_config.longitude)
+        {
+            return Err(format!(
+                "Invalid astronomy coordinates {}, {}",
+                astronomy_config.latitude, astronomy_config.longitude
+            )
+            .into());
+        }
+        Ok(AstronomyUpdater {
+            update_mode,
+            latitude: astronomy_config.latitude,
+            longitude: astronomy_config.longitude,
+        })
+    }
+}
+
+// The sun times of the local day of `now`, and the moon as of `now`
+fn get_astronomy(now: DateTime<Local>, latitude: f64, longitude: f64) -> Astronomy {
+    let sun_times = get_sun_times(now.date_naive(), latitude, longitude);
+    Astronomy {
+        sunrise: sun_times.map(|(sunrise, _)| to_timestamp(sunrise)),
+        sunset: sun_times.map(|(_, sunset)| to_timestamp(sunset)),
+        moon_phase: get_moon_phase(now.timestamp()),
+    }
+}
+
+/// When the sun rises and sets on `date` at the given place (degrees, north and east positive),
+/// in POSIX time. None on the days it doesn't, far enough north or south.
+pub fn get_sun_times(date: NaiveDate, latitude: f64, longitude: f64) -> Option<(i64, i64)> {
+    let midnight_jd = date.and_time(chrono::NaiveTime::MIN).and_utc().timestamp() as f64 / 86400.0
+        + UNIX_EPOCH_JD;
+    // Days since J2000, then the mean solar noon at our longitude
+    let day = (midnight_jd - J2000_JD + 0.0008).ceil();
+    let mean_noon = day - longitude / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_noon)
+        .rem_euclid(360.0)
+        .to_radians();
+    let center =
+        1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic_longitude = (anomaly.to_degrees() + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit =
+        J2000_JD + mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+    let declination = (ecliptic_longitude.sin() * 23.4397_f64.to_radians().sin()).asin();
+    // The sun's center 0.833° below the horizon, for the refraction and its radius
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = ((-0.833_f64).to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let half_day = cos_hour_angle.acos().to_degrees() / 360.0;
+    let to_posix = |jd: f64| ((jd - UNIX_EPOCH_JD) * 86400.0).round() as i64;
+    Some((to_posix(transit - half_day), to_posix(transit + half_day)))
+}
+
+/// Where the moon is in its cycle at `time` (POSIX): 0 at new moon, 0.5 at full moon
+pub fn get_moon_phase(time: i64) -> f32 {
+    let jd = time as f64 / 86400.0 + UNIX_EPOCH_JD;
+    ((jd - NEW_MOON_JD) / SYNODIC_MONTH_DAYS).rem_euclid(1.0) as f32
+}
+
+fn to_timestamp(seconds: i64) -> Timestamp {
+    Timestamp { seconds, nanos: 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn computes_sun_times() {
+        // Lausanne, on 2024-07-20: sunrise at 05:59 and sunset at 21:17 (CEST)
+        let date = NaiveDate::from_ymd_opt(2024, 7, 20).unwrap();
+        let (sunrise, sunset) = get_sun_times(date, 46.52, 6.63).unwrap();
+        let at = |hour, minute| chrono::Utc.with_ymd_and_hms(2024, 7, 20, hour, minute, 0);
+        assert!((sunrise - at(3, 59).unwrap().timestamp()).abs() < 180);
+        assert!((sunset - at(19, 17).unwrap().timestamp()).abs() < 180);
+        // No sunset up north in the summer, nor sunrise in the winter
+        assert_eq!(get_sun_times(date, 78.22, 15.65), None);
+        let winter = NaiveDate::from_ymd_opt(2024, 12, 20).unwrap();
+        assert_eq!(get_sun_times(winter, 78.22, 15.65), None);
+    }
+
+    #[test]
+    fn computes_moon_phases() {
+        let phase = |day, hour| {
+            let time = chrono::Utc
+                .with_ymd_and_hms(2024, 7, day, hour, 0, 0)
+                .unwrap();
+            get_moon_phase(time.timestamp())
+        };
+        // New moon on 2024-07-05 at 22:57 UTC, full moon on the 21st at 10:17 UTC
+        let new_moon = phase(5, 23);
+        assert!(!(0.02..=0.98).contains(&new_moon), "{}", new_moon);
+        assert!((phase(21, 10) - 0.5).abs() < 0.02);
+    }
+}
@@ -0,0 +1,219 @@
+//! Loads BDF fonts (the X11 bitmap font format, see e.g. the fonts of the rpi-rgb-led-matrix
+//! library) for the client to draw some texts with, say a tall clock, instead of the compiled-in
+//! fonts.
+//!
+//! embedded-graphics only draws monospaced fonts from a single image, so each glyph gets a cell as
+//! big as the font's bounding box, and the cells are laid side by side in one image.
+
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct BdfFont {
+    pub cell_width: u32,
+    pub cell_height: u32,
+    // The row of the cells the glyphs stand on
+    pub baseline: u32,
+    // In the order of their cells
+    pub chars: Vec<char>,
+    // 1 bit per pixel, most significant first, rows padded to whole bytes
+    pub image: Vec<u8>,
+    pub image_width: u32,
+}
+
+// A glyph as the file has it: its bitmap rows and where its box sits relative to the origin
+struct Glyph {
+    c: char,
+    width: u32,
+    height: u32,
+    x_offset: i32,
+    y_offset: i32,
+    rows: Vec<Vec<u8>>,
+}
+
+impl BdfFont {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(bdf: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut bounding_box = None;
+        let mut glyphs = vec![];
+        let mut lines = bdf.lines().map(str::trim);
+        while let Some(line) = lines.next() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("FONTBOUNDINGBOX") => bounding_box = Some(parse_box(words)?),
+                Some("STARTCHAR") => {
+                    if let Some(glyph) = parse_glyph(&mut lines)? {
+                        glyphs.push(glyph);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let (cell_width, cell_height, x_offset, y_offset) =
+            bounding_box.ok_or("No FONTBOUNDINGBOX in the font")?;
+        if glyphs.is_empty() || cell_width == 0 || cell_height == 0 {
+            return Err("No glyphs in the font".into());
+        }
+        // The bounding box reaches -y_offset rows under the baseline
+        let baseline = (cell_height as i32 + y_offset - 1).max(0) as u32;
+        let image_width = cell_width * glyphs.len() as u32;
+        let stride = image_width.div_ceil(8) as usize;
+        let mut image = vec![0u8; stride * cell_height as usize];
+        for (i, glyph) in glyphs.iter().enumerate() {
+            // The glyph's bottom row is y_offset above the baseline
+            let top = baseline as i32 - glyph.y_offset - (glyph.height as i32 - 1);
+            let left = glyph.x_offset - x_offset;
+            for (r, row) in glyph.rows.iter().enumerate() {
+                for c in 0..glyph.width as usize {
+                    if row
+                        .get(c / 8)
+                        .is_none_or(|byte| byte & (0x80 >> (c % 8)) == 0)
+                    {
+                        continue;
+                    }
+                    let (x, y) = (left + c as i32, top + r as i32);
+                    // Glyphs sticking out of the bounding box get clipped
+                    if x < 0 || y < 0 || x >= cell_width as i32 || y >= cell_height as i32 {
+                        continue;
+                    }
+                    let x = i * cell_width as usize + x as usize;
+                    image[y as usize * stride + x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        Ok(BdfFont {
+            cell_width,
+            cell_height,
+            baseline,
+            chars: glyphs.iter().map(|glyph| glyph.c).collect(),
+            image,
+            image_width,
+        })
+    }
+}
+
+// e.g. "8 16 0 -4" for 8x16 boxes going 4 rows under the baseline
+fn parse_box<'a>(
+    words: impl Iterator<Item = &'a str>,
+) -> Result<(u32, u32, i32, i32), Box<dyn std::error::Error>> {
+    let numbers = words
+        .map(str::parse::<i32>)
+        .collect::<Result<Vec<i32>, _>>()?;
+    match numbers[..] {
+        [width, height, x_offset, y_offset] if width >= 0 && height >= 0 => {
+            Ok((width as u32, height as u32, x_offset, y_offset))
+        }
+        _ => Err(format!("Bad bounding box {:?}", numbers).into()),
+    }
+}
+
+// Reads up to the glyph's ENDCHAR, None for the glyphs without a (Unicode) encoding
+fn parse_glyph<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+) -> Result<Option<Glyph>, Box<dyn std::error::Error>> {
+    let mut encoding = None;
+    let mut bounding_box = None;
+    let mut rows = vec![];
+    let mut in_bitmap = false;
+    for line in lines.by_ref() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("ENDCHAR") => {
+                let (Some(c), Some((width, height, x_offset, y_offset))) =
+                    (encoding.and_then(char::from_u32), bounding_box)
+                else {
+                    return Ok(None);
+                };
+                return Ok(Some(Glyph {
+                    c,
+                    width,
+                    height,
+                    x_offset,
+                    y_offset,
+                    rows,
+                }));
+            }
+            Some(hex) if in_bitmap => rows.push(parse_hex(hex)?),
+            Some("ENCODING") => encoding = words.next().and_then(|code| code.parse().ok()),
+            Some("BBX") => bounding_box = Some(parse_box(words)?),
+            Some("BITMAP") => in_bitmap = true,
+            _ => {}
+        }
+    }
+    Err("Glyph without ENDCHAR".into())
+}
+
+// e.g. "3C80" for two bytes of pixels
+fn parse_hex(hex: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err(format!("Bad bitmap row {:?}", hex).into());
+    }
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_glyphs_out_in_cells() {
+        let bdf = "STARTFONT 2.1
+FONT -misc-tiny
+SIZE 4 75 75
+FONTBOUNDINGBOX 4 5 0 -1
+STARTPROPERTIES 2
+FONT_ASCENT 4
+FONT_DESCENT 1
+ENDPROPERTIES
+CHARS 3
+STARTCHAR one
+ENCODING 49
+SWIDTH 500 0
+DWIDTH 4 0
+BBX 2 4 1 0
+BITMAP
+40
+C0
+40
+40
+ENDCHAR
+STARTCHAR unencoded
+ENCODING -1
+BBX 1 1 0 0
+BITMAP
+80
+ENDCHAR
+STARTCHAR underscore
+ENCODING 95
+BBX 4 1 0 -1
+BITMAP
+F0
+ENDCHAR
+ENDFONT
+";
+        let font = BdfFont::parse(bdf).unwrap();
+        assert_eq!((font.cell_width, font.cell_height), (4, 5));
+        assert_eq!(font.baseline, 3);
+        assert_eq!(font.chars, ['1', '_']);
+        // Two cells in a row, the '1' shifted right by one, the '_' under the baseline
+        assert_eq!(font.image_width, 8);
+        assert_eq!(
+            font.image,
+            [
+                0b0010_0000,
+                0b0110_0000,
+                0b0010_0000,
+                0b0010_0000,
+                0b0000_1111
+            ]
+        );
+
+        assert!(BdfFont::parse("STARTFONT 2.1\nENDFONT\n").is_err());
+        assert!(BdfFont::parse(&bdf.replace("C0", "C")).is_err());
+    }
+}
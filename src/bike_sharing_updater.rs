@@ -0,0 +1,273 @@
+//! How many bikes and e-bikes are left at a few bike sharing stations, e.g. PubliBike's, which
+//! the screen shows next to the departures since they're another way to make the same trip.
+//!
+//! Any GBFS feed works (https://gbfs.org), PubliBike publishes one through sharedmobility.ch. The
+//! station status tells how many vehicles of each type are docked, and the vehicle types which of
+//! those types are electric.
+
+use crate::config_extractor::api_config;
+use crate::config_extractor::api_config::bike_sharing_config::Station;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::BikeStation;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::Client;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+#[derive(Debug)]
+pub struct BikeSharingUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    station_status_url: String,
+    vehicle_types_url: String,
+    stations: Vec<Station>,
+    bike_sharing_period: ExponentialBackoff,
+    // The electric vehicle types, they hardly ever change so they're only fetched once
+    electric_types: Option<HashSet<String>>,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for BikeSharingUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(31),
+            UpdateMode::Real => Instant::now() + self.bike_sharing_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} bike sharing", self.update_mode);
+        let stations;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // Emptying over the hour
+                stations = vec![BikeStation {
+                    label: "PB".into(),
+                    bikes: 5 - now.minute() / 12,
+                    ebikes: 3 - now.minute() / 20,
+                }];
+                error_bit.store(
+                    now.second().is_multiple_of(31),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                stations = match self.get_stations().await {
+                    Ok(fetched) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.bike_sharing_period.set_success();
+                        fetched
+                    }
+                    Err(e) => {
+                        error!("Error getting the bike stations: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.bike_sharing_period.set_error();
+                        vec![]
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::BikeStations(stations)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl BikeSharingUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let bike_sharing_config = config
+            .bike_sharing
+            .as_ref()
+            .ok_or("No bike sharing config")?;
+        if bike_sharing_config.station_status_url.is_empty() {
+            return Err("No bike sharing station status URL".into());
+        }
+        if bike_sharing_config.stations.is_empty() {
+            return Err("No bike sharing stations configured".into());
+        }
+        let bike_sharing_period_config = Duration::from_secs(
+            bike_sharing_config
+                .update_period
+                .as_ref()
+                .ok_or("no bike sharing update period")?
+                .seconds
+                .try_into()?,
+        );
+        let bike_sharing_period = ExponentialBackoff::new(
+            bike_sharing_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(BikeSharingUpdater {
+            update_mode,
+            client,
+            station_status_url: bike_sharing_config.station_status_url.clone(),
+            vehicle_types_url: bike_sharing_config.vehicle_types_url.clone(),
+            stations: bike_sharing_config.stations.clone(),
+            bike_sharing_period,
+            electric_types: None,
+        })
+    }
+
+    async fn fetch(&self, url: &str) -> Result<String, reqwest::Error> {
+        traced(
+            "fetch",
+            retry_http("Bike sharing fetch", || async {
+                self.client
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await
+    }
+
+    // In the config's order
+    async fn get_stations(&mut self) -> Result<Vec<BikeStation>, Box<dyn std::error::Error>> {
+        // Without the vehicle types, every vehicle counts as a bike
+        if self.electric_types.is_none() && !self.vehicle_types_url.is_empty() {
+            let body = self.fetch(&self.vehicle_types_url).await?;
+            self.electric_types = Some(traced_sync("parse", || parse_electric_types(&body))?);
+        }
+        let body = self.fetch(&self.station_status_url).await?;
+        let electric_types = self.electric_types.clone().unwrap_or_default();
+        traced_sync("parse", || {
+            parse_station_status(&body, &self.stations, &electric_types)
+        })
+    }
+}
+
+fn parse_electric_types(body: &str) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+    let vehicle_types: Value = serde_json::from_str(body)?;
+    let vehicle_types = vehicle_types
+        .pointer("/data/vehicle_types")
+        .and_then(Value::as_array)
+        .ok_or("No vehicle types in the feed")?;
+    Ok(vehicle_types
+        .iter()
+        .filter(|vehicle_type| {
+            let propulsion = vehicle_type.get("propulsion_type").and_then(Value::as_str);
+            propulsion.is_some_and(|propulsion| propulsion != "human")
+        })
+        .filter_map(|vehicle_type| vehicle_type.get("vehicle_type_id")?.as_str())
+        .map(str::to_string)
+        .collect())
+}
+
+fn parse_station_status(
+    body: &str,
+    stations: &[Station],
+    electric_types: &HashSet<String>,
+) -> Result<Vec<BikeStation>, Box<dyn std::error::Error>> {
+    let status: Value = serde_json::from_str(body)?;
+    let statuses = status
+        .pointer("/data/stations")
+        .and_then(Value::as_array)
+        .ok_or("No stations in the status")?;
+    let mut bike_stations = vec![];
+    for station in stations {
+        let status = statuses
+            .iter()
+            .find(|status| {
+                // Some feeds have numeric IDs, even though the spec says they're strings
+                match status.get("station_id") {
+                    Some(Value::String(id)) => *id == station.station_id,
+                    Some(id) => id.as_u64() == station.station_id.parse().ok(),
+                    None => false,
+                }
+            })
+            .ok_or_else(|| format!("No station {} in the status", station.station_id))?;
+        let count = |value: &Value| value.as_u64().unwrap_or(0) as u32;
+        let available = status.get("num_bikes_available").map_or(0, count);
+        let ebikes = match status
+            .get("vehicle_types_available")
+            .and_then(Value::as_array)
+        {
+            Some(types) => types
+                .iter()
+                .filter(|available| {
+                    available
+                        .get("vehicle_type_id")
+                        .and_then(Value::as_str)
+                        .is_some_and(|id| electric_types.contains(id))
+                })
+                .map(|available| available.get("count").map_or(0, count))
+                .sum(),
+            None => 0,
+        };
+        bike_stations.push(BikeStation {
+            label: match station.label.is_empty() {
+                true => station.station_id.clone(),
+                false => station.label.clone(),
+            },
+            bikes: available.saturating_sub(ebikes),
+            ebikes,
+        });
+    }
+    Ok(bike_stations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_bikes_and_ebikes() {
+        let vehicle_types = r#"{"last_updated": 1729600000, "ttl": 60, "version": "2.3",
+            "data": {"vehicle_types": [
+                {"vehicle_type_id": "1", "form_factor": "bicycle", "propulsion_type": "human"},
+                {"vehicle_type_id": "2", "form_factor": "bicycle",
+                 "propulsion_type": "electric_assist"}
+            ]}}"#;
+        let electric_types = parse_electric_types(vehicle_types).unwrap();
+        assert_eq!(electric_types, HashSet::from(["2".to_string()]));
+
+        let status = r#"{"last_updated": 1729600000, "ttl": 60, "version": "2.3",
+            "data": {"stations": [
+                {"station_id": "230", "num_bikes_available": 5, "num_docks_available": 7,
+                 "vehicle_types_available": [
+                    {"vehicle_type_id": "1", "count": 3}, {"vehicle_type_id": "2", "count": 2}
+                 ]},
+                {"station_id": 231, "num_bikes_available": 1, "num_docks_available": 11}
+            ]}}"#;
+        let station = |station_id: &str, label: &str| Station {
+            station_id: station_id.into(),
+            label: label.into(),
+        };
+        let stations = [station("231", ""), station("230", "Gare")];
+        let bike_stations = parse_station_status(status, &stations, &electric_types).unwrap();
+        assert_eq!(
+            bike_stations,
+            [
+                BikeStation {
+                    label: "231".into(),
+                    bikes: 1,
+                    ebikes: 0
+                },
+                BikeStation {
+                    label: "Gare".into(),
+                    bikes: 3,
+                    ebikes: 2
+                }
+            ]
+        );
+        assert!(parse_station_status(status, &[station("999", "")], &electric_types).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+//! Hours during which the client keeps the panel blank whatever the server's brightness, say the
+//! small hours, when even the dimmest text glows in a dark hallway.
+
+use crate::config_extractor::api_config::Client;
+use log::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Blanking {
+    // Local hours, from start to end (excluded), over midnight when end comes first
+    start: u32,
+    end: u32,
+}
+
+impl Blanking {
+    /// None when the config has no (valid) blanking hours
+    pub fn new(config: Option<&Client>) -> Option<Self> {
+        let hours = config.and_then(|client| client.blanking_hours.as_ref())?;
+        let (start, end) = (hours.start_hour, hours.end_hour);
+        if start > 23 || end > 23 || start == end {
+            warn!("Invalid blanking hours {}-{}, never blanking", start, end);
+            return None;
+        }
+        info!("Blanking the panel from {}h to {}h", start, end);
+        Some(Blanking { start, end })
+    }
+
+    pub fn is_blank(&self, hour: u32) -> bool {
+        match self.start < self.end {
+            true => self.start <= hour && hour < self.end,
+            false => hour >= self.start || hour < self.end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_extractor::api_config::BlankingHours;
+
+    #[test]
+    fn blanks_over_midnight() {
+        assert_eq!(Blanking::new(None), None);
+        let mut config = Client {
+            blanking_hours: Some(BlankingHours {
+                start_hour: 23,
+                end_hour: 6,
+            }),
+            ..Default::default()
+        };
+        let night = Blanking::new(Some(&config)).unwrap();
+        assert!(!night.is_blank(22));
+        assert!(night.is_blank(23));
+        assert!(night.is_blank(0));
+        assert!(night.is_blank(5));
+        assert!(!night.is_blank(6));
+
+        config.blanking_hours = Some(BlankingHours {
+            start_hour: 1,
+            end_hour: 5,
+        });
+        let small_hours = Blanking::new(Some(&config)).unwrap();
+        assert!(!small_hours.is_blank(0));
+        assert!(small_hours.is_blank(1));
+        assert!(!small_hours.is_blank(5));
+
+        config.blanking_hours = Some(BlankingHours {
+            start_hour: 24,
+            end_hour: 6,
+        });
+        assert_eq!(Blanking::new(Some(&config)), None);
+    }
+}
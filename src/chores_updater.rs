@@ -0,0 +1,274 @@
+//! Whose turn it is for the flat's chores, as text widgets such as "Trash: Sid".
+//!
+//! A chore with a period passes from one person to the next every period since its start date,
+//! and its widget hides once someone marked it done for the current period. A chore without one
+//! passes to the next person each time it's marked done. Either way, completions come through the
+//! CompleteChore RPC, and are saved to the config's completions file (if any) to survive restarts.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::screen_service::TextWidget;
+use crate::updater_registry::UpdaterCommand;
+use chrono::{Local, NaiveDate};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub const SOURCE: &str = "chores";
+// Turns only change with the periods (days, usually) or completions, which trigger an update
+const CHECK_PERIOD: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Chore {
+    label: String,
+    people: Vec<String>,
+    // In seconds, with the timestamp of the local midnight it starts from
+    period: Option<(i64, i64)>,
+}
+
+// What was done of a chore so far
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Completions {
+    count: u64,
+    last: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct ChoresUpdater {
+    update_mode: UpdateMode,
+    chores: Vec<Chore>,
+    // By chore label
+    completions: HashMap<String, Completions>,
+    completions_file: Option<PathBuf>,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for ChoresUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        Instant::now() + CHECK_PERIOD
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} chores", self.update_mode);
+        let now = Local::now().timestamp();
+        let widgets = match self.update_mode {
+            UpdateMode::Dummy => {
+                let chore = Chore {
+                    label: "Trash".into(),
+                    people: vec!["Sid".into(), "Bob".into()],
+                    period: Some((60, 0)),
+                };
+                get_widgets(&[chore], &self.completions, now)
+            }
+            UpdateMode::Real => get_widgets(&self.chores, &self.completions, now),
+        };
+        // Saving the completions is the only thing that can fail, and it's done on completion
+        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+        vec![ContentUpdate::TextWidgets {
+            source: SOURCE,
+            widgets,
+        }]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+
+    fn command(&mut self, command: UpdaterCommand) -> Result<(), String> {
+        let UpdaterCommand::Complete(item) = command else {
+            return Err(format!("{:?} isn't supported", command));
+        };
+        let chore = self
+            .chores
+            .iter()
+            .find(|chore| chore.label.eq_ignore_ascii_case(&item))
+            .ok_or_else(|| format!("No chore named '{}' to complete", item))?;
+        let completions = self.completions.entry(chore.label.clone()).or_default();
+        completions.count += 1;
+        completions.last = Some(Local::now().timestamp());
+        info!("{} done, {} times so far", chore.label, completions.count);
+        // Dummy completions are only for trying things out
+        if let (UpdateMode::Real, Some(path)) = (&self.update_mode, &self.completions_file) {
+            if let Err(e) = save_completions(path, &self.completions) {
+                warn!("Couldn't save the chore completions to {:?}: {}", path, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ChoresUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let chores_config = config.chores.as_ref().ok_or("No chores config")?;
+        let mut chores = vec![];
+        for chore in &chores_config.chores {
+            if chore.people.is_empty() {
+                return Err(format!("Nobody to do {}", chore.label).into());
+            }
+            let period = match &chore.period {
+                Some(period) if period.seconds > 0 => {
+                    let start = NaiveDate::parse_from_str(&chore.start, "%Y-%m-%d")
+                        .ok()
+                        .and_then(|start| start.and_hms_opt(0, 0, 0))
+                        .and_then(|start| start.and_local_timezone(Local).earliest())
+                        .ok_or_else(|| {
+                            format!("Bad start {:?} for {}", chore.start, chore.label)
+                        })?;
+                    Some((period.seconds, start.timestamp()))
+                }
+                _ => None,
+            };
+            chores.push(Chore {
+                label: chore.label.clone(),
+                people: chore.people.clone(),
+                period,
+            });
+        }
+        let completions_file = Some(PathBuf::from(&chores_config.completions_file))
+            .filter(|path| !path.as_os_str().is_empty());
+        // A missing file only means nothing was done yet
+        let completions = match &completions_file {
+            Some(path) if path.exists() => load_completions(path)
+                .map_err(|e| format!("Bad chore completions in {:?}: {}", path, e))?,
+            _ => HashMap::new(),
+        };
+        Ok(ChoresUpdater {
+            update_mode,
+            chores,
+            completions,
+            completions_file,
+        })
+    }
+}
+
+// e.g. {"Trash": {"count": 12, "last": 1729600000}}
+fn load_completions(
+    path: &PathBuf,
+) -> Result<HashMap<String, Completions>, Box<dyn std::error::Error>> {
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let saved = saved.as_object().ok_or("not a JSON object")?;
+    Ok(saved
+        .iter()
+        .map(|(label, completions)| {
+            let completions = Completions {
+                count: completions["count"].as_u64().unwrap_or_default(),
+                last: completions["last"].as_i64(),
+            };
+            (label.clone(), completions)
+        })
+        .collect())
+}
+
+// Through a temporary file, like the content store, so a crash can't lose them all
+fn save_completions(
+    path: &PathBuf,
+    completions: &HashMap<String, Completions>,
+) -> std::io::Result<()> {
+    let saved: Map<String, Value> = completions
+        .iter()
+        .map(|(label, completions)| {
+            let completions = json!({"count": completions.count, "last": completions.last});
+            (label.clone(), completions)
+        })
+        .collect();
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, Value::Object(saved).to_string())?;
+    std::fs::rename(temp_path, path)
+}
+
+// Whose turn it is for each chore, in the config's order, leaving out those done for the period
+fn get_widgets(
+    chores: &[Chore],
+    completions: &HashMap<String, Completions>,
+    now: i64,
+) -> Vec<TextWidget> {
+    chores
+        .iter()
+        .filter_map(|chore| {
+            let completions = completions.get(&chore.label).copied().unwrap_or_default();
+            let turn = match chore.period {
+                Some((period, start)) => {
+                    let turn = (now - start).div_euclid(period);
+                    let done = completions
+                        .last
+                        .is_some_and(|last| (last - start).div_euclid(period) == turn);
+                    (!done).then_some(turn)?
+                }
+                None => completions.count as i64,
+            };
+            let person = &chore.people[turn.rem_euclid(chore.people.len() as i64) as usize];
+            Some(TextWidget {
+                label: chore.label.clone(),
+                text: person.clone(),
+                source: SOURCE.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_whose_turn_it_is() {
+        const WEEK: i64 = 7 * 24 * 3600;
+        let chores = [
+            Chore {
+                label: "Trash".into(),
+                people: vec!["Sid".into(), "Bob".into(), "Ana".into()],
+                period: Some((WEEK, 0)),
+            },
+            Chore {
+                label: "Descaling".into(),
+                people: vec!["Sid".into(), "Bob".into()],
+                period: None,
+            },
+        ];
+        let mut completions = HashMap::new();
+        let turns = |completions: &HashMap<String, Completions>, now: i64| {
+            get_widgets(&chores, completions, now)
+                .into_iter()
+                .map(|widget| format!("{}: {}", widget.label, widget.text))
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(turns(&completions, 0), ["Trash: Sid", "Descaling: Sid"]);
+        assert_eq!(
+            turns(&completions, 4 * WEEK + 1),
+            ["Trash: Bob", "Descaling: Sid"]
+        );
+
+        completions.insert(
+            "Trash".to_string(),
+            Completions {
+                count: 1,
+                last: Some(4 * WEEK + 2),
+            },
+        );
+        completions.insert(
+            "Descaling".to_string(),
+            Completions {
+                count: 3,
+                last: Some(0),
+            },
+        );
+        // Done for this week, Ana's turn the next
+        assert_eq!(turns(&completions, 4 * WEEK + 3), ["Descaling: Bob"]);
+        assert_eq!(
+            turns(&completions, 5 * WEEK),
+            ["Trash: Ana", "Descaling: Bob"]
+        );
+
+        let path = std::env::temp_dir().join("chore_completions_test.json");
+        save_completions(&path, &completions).unwrap();
+        assert_eq!(load_completions(&path).unwrap(), completions);
+        std::fs::remove_file(path).unwrap();
+    }
+}
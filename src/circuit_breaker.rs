@@ -0,0 +1,204 @@
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::screen_service::ScreenContentReply;
+use crate::updater_registry::UpdaterCommand;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// What the server wraps its updaters with: give up for half an hour after 5 failures in a row
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+pub const DEFAULT_COOL_DOWN: Duration = Duration::from_secs(30 * 60);
+
+/// Wraps an updater to stop calling its upstream for a cool-down window once it failed too many
+/// times in a row (e.g. an expired API key), marking its section as degraded in the meantime.
+/// After the cool-down, a single update is let through: success closes the breaker again, failure
+/// re-opens it for another cool-down.
+pub struct CircuitBreaker {
+    name: &'static str,
+    inner: Box<dyn DataUpdater>,
+    failure_threshold: u32,
+    cool_down: Duration,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(
+        name: &'static str,
+        inner: Box<dyn DataUpdater>,
+        failure_threshold: u32,
+        cool_down: Duration,
+    ) -> Self {
+        CircuitBreaker {
+            name,
+            inner,
+            failure_threshold,
+            cool_down,
+            consecutive_failures: 0,
+            open_until: None,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.open_until
+            .is_some_and(|open_until| Instant::now() < open_until)
+    }
+
+    fn degraded(&self, degraded: bool) -> ContentUpdate {
+        ContentUpdate::Degraded {
+            source: self.name,
+            degraded,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl DataUpdater for CircuitBreaker {
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        if self.is_open() {
+            // e.g. a manual refresh, the upstream gets left alone all the same
+            info!("Circuit breaker of {} is open, skipping update", self.name);
+            return vec![];
+        }
+
+        let mut updates = self.inner.update(error_bit).await;
+
+        if !error_bit.load(Ordering::Relaxed) {
+            if self.open_until.take().is_some() {
+                info!("{} recovered, closing its circuit breaker", self.name);
+            }
+            self.consecutive_failures = 0;
+            updates.push(self.degraded(false));
+            return updates;
+        }
+        self.consecutive_failures += 1;
+        if self.consecutive_failures >= self.failure_threshold {
+            warn!(
+                "{} failed {} times in a row, not calling it for {:?}",
+                self.name, self.consecutive_failures, self.cool_down
+            );
+            self.open_until = Some(Instant::now() + self.cool_down);
+            updates.push(self.degraded(true));
+        }
+        updates
+    }
+
+    fn get_next_update_time(&self) -> Instant {
+        match self.open_until {
+            Some(open_until) if self.is_open() => open_until,
+            _ => self.inner.get_next_update_time(),
+        }
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        self.inner.update_mode()
+    }
+
+    fn staged_content(&self) -> Option<ScreenContentReply> {
+        self.inner.staged_content()
+    }
+
+    fn command(&mut self, command: UpdaterCommand) -> Result<(), String> {
+        self.inner.command(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen_service::ScreenContentReply;
+    use std::sync::atomic::AtomicUsize;
+
+    struct FlakyUpdater {
+        calls: Arc<AtomicUsize>,
+        fails: Arc<AtomicBool>,
+    }
+
+    #[tonic::async_trait]
+    impl DataUpdater for FlakyUpdater {
+        async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            error_bit.store(self.fails.load(Ordering::Relaxed), Ordering::Relaxed);
+            vec![]
+        }
+
+        fn get_next_update_time(&self) -> Instant {
+            Instant::now()
+        }
+    }
+
+    fn breaker(cool_down: Duration) -> (CircuitBreaker, Arc<AtomicUsize>, Arc<AtomicBool>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fails = Arc::new(AtomicBool::new(true));
+        let updater = FlakyUpdater {
+            calls: Arc::clone(&calls),
+            fails: Arc::clone(&fails),
+        };
+        (
+            CircuitBreaker::new("flaky", Box::new(updater), 2, cool_down),
+            calls,
+            fails,
+        )
+    }
+
+    // Runs an update and merges its result like the aggregator would
+    async fn update(
+        breaker: &mut CircuitBreaker,
+        content: &mut ScreenContentReply,
+        error_bit: &Arc<AtomicBool>,
+    ) {
+        for update in breaker.update(error_bit).await {
+            update.apply(content);
+        }
+    }
+
+    #[tokio::test]
+    async fn opens_after_repeated_failures() {
+        let (mut breaker, calls, _) = breaker(Duration::from_secs(60));
+        let mut content = ScreenContentReply::default();
+        let error_bit = Arc::new(AtomicBool::new(false));
+
+        update(&mut breaker, &mut content, &error_bit).await;
+        assert!(content.degraded_sources.is_empty());
+        update(&mut breaker, &mut content, &error_bit).await;
+        assert_eq!(content.degraded_sources, vec!["flaky"]);
+        assert!(breaker.get_next_update_time() > Instant::now() + Duration::from_secs(59));
+
+        // Open: the upstream isn't called anymore
+        update(&mut breaker, &mut content, &error_bit).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        assert!(error_bit.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn closes_after_successful_trial() {
+        let (mut breaker, calls, fails) = breaker(Duration::from_millis(10));
+        let mut content = ScreenContentReply::default();
+        let error_bit = Arc::new(AtomicBool::new(false));
+        update(&mut breaker, &mut content, &error_bit).await;
+        update(&mut breaker, &mut content, &error_bit).await;
+        assert_eq!(content.degraded_sources, vec!["flaky"]);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        fails.store(false, Ordering::Relaxed);
+        update(&mut breaker, &mut content, &error_bit).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert!(content.degraded_sources.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reopens_after_failed_trial() {
+        let (mut breaker, calls, _) = breaker(Duration::from_millis(10));
+        let mut content = ScreenContentReply::default();
+        let error_bit = Arc::new(AtomicBool::new(false));
+        update(&mut breaker, &mut content, &error_bit).await;
+        update(&mut breaker, &mut content, &error_bit).await;
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        update(&mut breaker, &mut content, &error_bit).await;
+        update(&mut breaker, &mut content, &error_bit).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert_eq!(content.degraded_sources, vec!["flaky"]);
+    }
+}
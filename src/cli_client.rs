@@ -1,12 +1,19 @@
+mod admin_console;
 mod config_extractor;
+mod config_migration;
 mod dummy_client;
+mod event_format;
+mod hash_beacon;
+mod time_util;
 
 use crate::config_extractor::{cli, extract_config};
 use crate::dummy_client::{start, ClientMode};
+use clap::Command;
 use log::info;
 use log::LevelFilter;
 use log4rs::append::console::ConsoleAppender;
 use log4rs::config::{Appender, Config, Root};
+use std::path::PathBuf;
 
 fn logging_setup() -> () {
     let stdout = ConsoleAppender::builder().build();
@@ -22,9 +29,34 @@ fn logging_setup() -> () {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     logging_setup();
 
-    let matches = cli().get_matches();
+    let matches =
+        cli()
+            .subcommand(
+                Command::new("admin").about("Interactive console to operate the server remotely"),
+            )
+            .subcommand(Command::new("migrate-config").about(
+                "Upgrade the config file to the current schema, keeping the original as a .bak",
+            ))
+            .get_matches();
+
+    // Before reading the config, which may not read anymore
+    if matches.subcommand_matches("migrate-config").is_some() {
+        let path: &PathBuf = matches.get_one("path").ok_or("Missing config path")?;
+        let changes = config_migration::migrate_file(path)?;
+        if changes.is_empty() {
+            info!("{:?} is already up to date", path);
+        }
+        for change in changes {
+            info!("{}", change);
+        }
+        return Ok(());
+    }
     let api_config = extract_config(&matches).expect("Error reading config");
 
+    if matches.subcommand_matches("admin").is_some() {
+        return crate::admin_console::run(&api_config).await;
+    }
+
     let mode: ClientMode;
     if matches.get_flag("dummy_client") {
         mode = ClientMode::OneShot;
@@ -9,9 +9,12 @@ pub mod api_config {
 use crate::config_extractor::api_config::ApiConfig;
 use clap::ArgMatches;
 use clap::{Arg, Command};
-use log::info;
-use tonic::transport::Endpoint;
 use std::{fs::File, io::BufReader, path::PathBuf};
+use tonic::transport::Endpoint;
+use tracing::info;
+
+/// The current config schema, bump it along with a new migration in config_migration.rs
+pub const CONFIG_VERSION: u32 = 1;
 
 pub fn cli() -> Command {
     Command::new("API tester")
@@ -49,7 +52,13 @@ pub fn extract_config(matches: &ArgMatches) -> Result<ApiConfig, Box<dyn std::er
     // Open the file in a buffered reader
     let reader = BufReader::new(file);
     // This needs to known to use the ApiConfig deserializer, hence the explicit type
-    let api_config: ApiConfig = serde_json::from_reader(reader)?;
+    let api_config: ApiConfig = serde_json::from_reader(reader).map_err(|e| {
+        // e.g. the fields of older schemas, which the current one doesn't know anymore
+        format!(
+            "Couldn't read config {:?}: {} (configs older than schema v{} need migrate-config)",
+            path, e, CONFIG_VERSION
+        )
+    })?;
 
     Ok(api_config)
 }
@@ -0,0 +1,196 @@
+//! Upgrades JSON configs written for older schemas to the current one, for the cli client's
+//! `migrate-config` subcommand.
+//!
+//! Configs carry their schema in `configVersion` (missing for the ones older than the field, i.e.
+//! v0) and go through the migrations below one version at a time. Migrations work on the raw JSON,
+//! since old configs may not read as an `ApiConfig` anymore, and accept both the camelCase and
+//! snake_case field names pbjson reads.
+
+use crate::config_extractor::api_config::ApiConfig;
+use crate::config_extractor::CONFIG_VERSION;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+// Migrates a config from `version` to `version + 1`, noting down what it changed
+type Migration = fn(&mut Map<String, Value>, &mut Vec<String>);
+
+// MIGRATIONS[i] migrates from version i
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [migrate_v0_to_v1];
+
+// v1 lists the transport stops, v0 had a single one (whose fields v1 configs don't read anymore)
+fn migrate_v0_to_v1(config: &mut Map<String, Value>, changes: &mut Vec<String>) {
+    let Some(transport) = config.get_mut("transport").and_then(Value::as_object_mut) else {
+        return;
+    };
+    let stop_id = take_field(transport, "stopId", "stop_id");
+    let destination_points = take_field(transport, "destinationPoints", "destination_points");
+    if stop_id.is_none() && destination_points.is_none() {
+        return;
+    }
+    let has_stops = transport
+        .get("stops")
+        .and_then(Value::as_array)
+        .is_some_and(|stops| !stops.is_empty());
+    if has_stops {
+        changes.push(
+            "Dropped transport.stopId and destinationPoints, stops already lists the stops to use"
+                .into(),
+        );
+        return;
+    }
+    let mut stop = Map::new();
+    if let Some(stop_id) = stop_id {
+        stop.insert("stopId".into(), stop_id);
+    }
+    if let Some(destination_points) = destination_points {
+        stop.insert("destinationPoints".into(), destination_points);
+    }
+    transport.insert("stops".into(), Value::Array(vec![Value::Object(stop)]));
+    changes.push("Moved transport.stopId and destinationPoints into transport.stops".into());
+}
+
+// Removes a field under either of its names
+fn take_field(
+    object: &mut Map<String, Value>,
+    camel_case: &str,
+    snake_case: &str,
+) -> Option<Value> {
+    let camel = object.remove(camel_case);
+    let snake = object.remove(snake_case);
+    camel.or(snake)
+}
+
+/// Migrates the config to the current schema, returning what changed (nothing if it was current)
+pub fn migrate(config: &mut Value) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let object = config
+        .as_object_mut()
+        .ok_or("The config isn't a JSON object")?;
+    let version = take_field(object, "configVersion", "config_version")
+        .map(|v| v.as_u64().ok_or("configVersion isn't a number"))
+        .transpose()?
+        .unwrap_or(0);
+    if version > u64::from(CONFIG_VERSION) {
+        return Err(format!(
+            "Config is at schema v{}, newer than ours (v{})",
+            version, CONFIG_VERSION
+        )
+        .into());
+    }
+    let mut changes = vec![];
+    for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!("Migrating the config from v{}", from_version);
+        migration(object, &mut changes);
+    }
+    object.insert("configVersion".into(), CONFIG_VERSION.into());
+    if version < u64::from(CONFIG_VERSION) {
+        changes.push(format!(
+            "Set configVersion from {} to {}",
+            version, CONFIG_VERSION
+        ));
+    }
+    // Don't hand back something the server would refuse
+    serde_json::from_value::<ApiConfig>(config.clone())
+        .map_err(|e| format!("The migrated config doesn't read as a config: {}", e))?;
+    Ok(changes)
+}
+
+/// Migrates the config file at `path` in place, keeping the original next to it as a backup.
+/// Returns what changed, in which case the backup was written.
+pub fn migrate_file(path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let original = std::fs::read_to_string(path)?;
+    let mut config: Value = serde_json::from_str(&original)?;
+    let changes = migrate(&mut config)?;
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+    let backup_path = PathBuf::from(format!("{}.bak", path.display()));
+    std::fs::write(&backup_path, original)?;
+    info!("Backed the original config up to {:?}", backup_path);
+    std::fs::write(path, serde_json::to_string_pretty(&config)? + "\n")?;
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn moves_the_single_stop_into_stops() {
+        let mut config = json!({
+            "server": {"address": "127.0.0.1", "port": 50051},
+            "transport": {
+                "url": "https://example.com/ojp",
+                "stop_id": 8588845,
+                "destinationPoints": [{"stops": [456], "destinationName": "FLON"}]
+            }
+        });
+        // The server doesn't read it as is anymore
+        assert!(serde_json::from_value::<ApiConfig>(config.clone()).is_err());
+        let changes = migrate(&mut config).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            config,
+            json!({
+                "server": {"address": "127.0.0.1", "port": 50051},
+                "transport": {
+                    "url": "https://example.com/ojp",
+                    "stops": [{
+                        "stopId": 8588845,
+                        "destinationPoints": [{"stops": [456], "destinationName": "FLON"}]
+                    }]
+                },
+                "configVersion": CONFIG_VERSION
+            })
+        );
+
+        // Migrating again changes nothing
+        let migrated = config.clone();
+        assert_eq!(migrate(&mut config).unwrap(), Vec::<String>::new());
+        assert_eq!(config, migrated);
+    }
+
+    #[test]
+    fn keeps_existing_stops() {
+        let stops = json!([{"stopId": 1}, {"stopId": 2}]);
+        let mut config = json!({"transport": {"stopId": 8588845, "stops": stops}});
+        migrate(&mut config).unwrap();
+        assert_eq!(config["transport"], json!({"stops": stops}));
+    }
+
+    #[test]
+    fn refuses_newer_or_broken_configs() {
+        let mut config = json!({"configVersion": CONFIG_VERSION + 1});
+        assert!(migrate(&mut config).is_err());
+        assert!(migrate(&mut json!([])).is_err());
+        // Not a number where the config expects one
+        assert!(migrate(&mut json!({"server": {"port": "fifty"}})).is_err());
+    }
+
+    #[test]
+    fn backs_up_migrated_files() {
+        let dir = std::env::temp_dir().join(format!("config_migration_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        let original = r#"{"transport": {"stopId": 8588845}}"#;
+        std::fs::write(&path, original).unwrap();
+
+        assert_eq!(migrate_file(&path).unwrap().len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("config.json.bak")).unwrap(),
+            original
+        );
+        let migrated: Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(migrated["transport"]["stops"], json!([{"stopId": 8588845}]));
+
+        // Nothing to do the second time, so the backup stays the original
+        assert!(migrate_file(&path).unwrap().is_empty());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("config.json.bak")).unwrap(),
+            original
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
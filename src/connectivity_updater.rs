@@ -0,0 +1,256 @@
+//! Whether the server reaches the internet, by pinging a few hosts outside the LAN (and optionally
+//! the gateway, to tell a dead router from a dead internet connection).
+//!
+//! An outage makes every other updater fail, so the screen would only show the error bit. With
+//! this, it can say what's actually wrong instead.
+//!
+//! We leave the ICMP to the system's `ping`, which has the privileges for it.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::screen_service::{Connectivity, PingResult};
+use chrono::Timelike;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+const DEFAULT_HOSTS: [&str; 1] = ["1.1.1.1"];
+const DEFAULT_PING_COUNT: u32 = 3;
+// How long each ping waits for its reply
+const PING_TIMEOUT_SECONDS: u32 = 2;
+
+#[derive(Debug)]
+pub struct ConnectivityUpdater {
+    update_mode: UpdateMode,
+    gateway: Option<String>,
+    hosts: Vec<String>,
+    ping_count: u32,
+    ping_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for ConnectivityUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(29),
+            UpdateMode::Real => Instant::now() + self.ping_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} connectivity", self.update_mode);
+        let connectivity;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // The internet goes down for the last ten minutes of every hour, and the LAN
+                // too for the last five
+                let ping = |host: &str, gateway: bool, down: bool| PingResult {
+                    host: host.into(),
+                    latency_ms: (!down).then_some(10.0 + now.second() as f32 / 2.0),
+                    packet_loss: if down { 100.0 } else { 0.0 },
+                    gateway,
+                };
+                connectivity = Some(get_connectivity(vec![
+                    ping("192.168.1.1", true, now.minute() >= 55),
+                    ping("1.1.1.1", false, now.minute() >= 50),
+                ]));
+                error_bit.store(
+                    now.second().is_multiple_of(31),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                connectivity = match self.ping_all().await {
+                    Ok(pings) => {
+                        // Unreachable hosts are what we report, not an error of ours
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.ping_period.set_success();
+                        Some(get_connectivity(pings))
+                    }
+                    Err(e) => {
+                        error!("Error pinging: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.ping_period.set_error();
+                        None
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Connectivity(connectivity)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl ConnectivityUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let connectivity_config = config
+            .connectivity
+            .as_ref()
+            .ok_or("No connectivity config")?;
+        let ping_period_config = Duration::from_secs(
+            connectivity_config
+                .update_period
+                .as_ref()
+                .ok_or("no connectivity update period")?
+                .seconds
+                .try_into()?,
+        );
+        let ping_period = ExponentialBackoff::new(
+            ping_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(ConnectivityUpdater {
+            update_mode,
+            gateway: Some(connectivity_config.gateway.clone()).filter(|g| !g.is_empty()),
+            hosts: match connectivity_config.hosts.is_empty() {
+                true => DEFAULT_HOSTS.iter().map(|h| h.to_string()).collect(),
+                false => connectivity_config.hosts.clone(),
+            },
+            ping_count: connectivity_config
+                .ping_count
+                .unwrap_or(DEFAULT_PING_COUNT)
+                .max(1),
+            ping_period,
+        })
+    }
+
+    // Pings the gateway and every host at once, so an update takes as long as the slowest one
+    async fn ping_all(&self) -> Result<Vec<PingResult>, Box<dyn std::error::Error>> {
+        let hosts = self
+            .gateway
+            .iter()
+            .map(|gateway| (gateway, true))
+            .chain(self.hosts.iter().map(|host| (host, false)));
+        let mut pings = vec![];
+        for (host, gateway) in hosts {
+            let child = tokio::process::Command::new("ping")
+                .args(["-q", "-n"])
+                .args(["-c", &self.ping_count.to_string()])
+                .args(["-W", &PING_TIMEOUT_SECONDS.to_string()])
+                .arg(host)
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| format!("Can't run ping: {}", e))?;
+            pings.push((host, gateway, child));
+        }
+        let mut results = vec![];
+        for (host, gateway, child) in pings {
+            let output = child.wait_with_output().await?;
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            // Unknown hosts and the like, which are as unreachable as it gets
+            let (packet_loss, latency_ms) = parse_ping(&stdout).unwrap_or_else(|| {
+                warn!(
+                    "Unexpected ping output for {}: {:?} {:?}",
+                    host,
+                    stdout,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                (100.0, None)
+            });
+            results.push(PingResult {
+                host: host.clone(),
+                latency_ms,
+                packet_loss,
+                gateway,
+            });
+        }
+        Ok(results)
+    }
+}
+
+// The packet loss (in percent) and average round trip (in ms, if any ping came back) from the
+// summary of `ping -q`, from iputils (e.g. "rtt min/avg/max/mdev = ...") or busybox
+// ("round-trip min/avg/max = ...")
+fn parse_ping(output: &str) -> Option<(f32, Option<f32>)> {
+    let packet_loss = output
+        .lines()
+        .find(|line| line.contains("packet loss"))?
+        .split(',')
+        .find_map(|part| part.trim().strip_suffix("% packet loss"))?
+        .parse()
+        .ok()?;
+    let latency_ms = output
+        .lines()
+        .find(|line| line.contains("min/avg/max"))
+        .and_then(|line| {
+            line.split('=')
+                .nth(1)?
+                .split('/')
+                .nth(1)?
+                .trim()
+                .parse()
+                .ok()
+        });
+    Some((packet_loss, latency_ms))
+}
+
+// The internet is down when none of its hosts answers, and so is the LAN if the gateway doesn't
+// either (some gateways just ignore pings)
+fn get_connectivity(pings: Vec<PingResult>) -> Connectivity {
+    let unreachable = |ping: &&PingResult| ping.latency_ms.is_none();
+    let (gateways, hosts): (Vec<&PingResult>, Vec<&PingResult>) =
+        pings.iter().partition(|ping| ping.gateway);
+    let internet_down = !hosts.is_empty() && hosts.iter().all(unreachable);
+    Connectivity {
+        internet_down,
+        lan_down: internet_down && !gateways.is_empty() && gateways.iter().all(unreachable),
+        pings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ping_summaries() {
+        let iputils = "PING 1.1.1.1 (1.1.1.1) 56(84) bytes of data.
+
+--- 1.1.1.1 ping statistics ---
+3 packets transmitted, 2 received, 33.3333% packet loss, time 2003ms
+rtt min/avg/max/mdev = 10.123/11.456/12.789/1.333 ms
+";
+        assert_eq!(parse_ping(iputils), Some((33.3333, Some(11.456))));
+        let busybox = "PING 192.168.1.1 (192.168.1.1): 56 data bytes
+
+--- 192.168.1.1 ping statistics ---
+3 packets transmitted, 3 packets received, 0% packet loss
+round-trip min/avg/max = 0.512/0.634/0.801 ms
+";
+        assert_eq!(parse_ping(busybox), Some((0.0, Some(0.634))));
+        let lost = "--- 1.1.1.1 ping statistics ---
+3 packets transmitted, 0 received, 100% packet loss, time 2040ms
+";
+        assert_eq!(parse_ping(lost), Some((100.0, None)));
+        assert_eq!(parse_ping(""), None);
+
+        let ping = |gateway: bool, latency_ms: Option<f32>| PingResult {
+            latency_ms,
+            gateway,
+            ..Default::default()
+        };
+        let connectivity = get_connectivity(vec![ping(true, Some(1.0)), ping(false, None)]);
+        assert!(connectivity.internet_down);
+        assert!(!connectivity.lan_down);
+        // One host answering is enough
+        let connectivity = get_connectivity(vec![ping(false, None), ping(false, Some(20.0))]);
+        assert!(!connectivity.internet_down);
+        // Nor is the LAN down when the internet is up
+        let connectivity = get_connectivity(vec![ping(true, None), ping(false, Some(20.0))]);
+        assert!(!connectivity.lan_down);
+        let connectivity = get_connectivity(vec![ping(true, None), ping(false, None)]);
+        assert!(connectivity.internet_down && connectivity.lan_down);
+    }
+}
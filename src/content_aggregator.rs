@@ -0,0 +1,111 @@
+//! The single owner of the screen content: updaters (through the scheduler) and the service send
+//! `ContentUpdate`s over a channel, the aggregator merges them and publishes every new version of
+//! the content on a watch channel, which readers clone their snapshots from.
+
+use crate::data_updater::ContentUpdate;
+use crate::screen_service::ScreenContentReply;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+use tracing::{debug, info};
+
+/// Creates the channels of the pipeline, starting from `initial_content`, and the aggregator that
+/// connects them (to be run, see `ContentAggregator::run`)
+pub fn pipeline(
+    initial_content: ScreenContentReply,
+) -> (
+    UnboundedSender<ContentUpdate>,
+    ContentAggregator,
+    watch::Receiver<ScreenContentReply>,
+) {
+    let (update_sender, updates) = mpsc::unbounded_channel();
+    let (content, content_receiver) = watch::channel(initial_content);
+    (
+        update_sender,
+        ContentAggregator { updates, content },
+        content_receiver,
+    )
+}
+
+pub struct ContentAggregator {
+    updates: UnboundedReceiver<ContentUpdate>,
+    content: watch::Sender<ScreenContentReply>,
+}
+
+impl ContentAggregator {
+    /// Merges updates until all their senders are gone
+    pub async fn run(mut self) {
+        while let Some(update) = self.updates.recv().await {
+            debug!("Applying content update {:?}", update);
+            self.content.send_modify(|content| update.apply(content));
+        }
+        info!("No more content updates can come, stopping the aggregator");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen_service::{KittyDebt, Notice};
+    use prost_types::Timestamp;
+    use std::time::{Duration, SystemTime};
+
+    #[tokio::test]
+    async fn publishes_merged_updates() {
+        let (update_sender, aggregator, content) = pipeline(ScreenContentReply::default());
+        let run = tokio::spawn(aggregator.run());
+
+        let debts = vec![KittyDebt {
+            who: "Sid".into(),
+            how_much: 12.0,
+            whom: "Moses".into(),
+            ..Default::default()
+        }];
+        update_sender
+            .send(ContentUpdate::KittyDebts {
+                debts: debts.clone(),
+                balances: vec![],
+                alert: true,
+            })
+            .unwrap();
+        update_sender
+            .send(ContentUpdate::Degraded {
+                source: "gcal",
+                degraded: true,
+            })
+            .unwrap();
+        drop(update_sender);
+        run.await.unwrap();
+
+        let content = content.borrow();
+        assert_eq!(content.kitty_debts, debts);
+        assert!(content.kitty_alert);
+        assert_eq!(content.degraded_sources, vec!["gcal"]);
+    }
+
+    #[test]
+    fn lists_degraded_sources_once() {
+        let mut content = ScreenContentReply::default();
+        let degraded = |degraded| ContentUpdate::Degraded {
+            source: "kitty",
+            degraded,
+        };
+        degraded(true).apply(&mut content);
+        degraded(true).apply(&mut content);
+        assert_eq!(content.degraded_sources, vec!["kitty"]);
+        degraded(false).apply(&mut content);
+        assert!(content.degraded_sources.is_empty());
+
+        // Expired notices make way for new ones
+        let in_a_minute = Timestamp::from(SystemTime::now() + Duration::from_secs(60));
+        let notice = |text: &str| Notice {
+            text: text.into(),
+            expires_at: Some(in_a_minute),
+        };
+        content.notices.push(Notice {
+            text: "Gone".into(),
+            expires_at: Some(Timestamp::from(SystemTime::now() - Duration::from_secs(60))),
+        });
+        ContentUpdate::Notice(notice("Dinner's ready")).apply(&mut content);
+        assert_eq!(content.notices, vec![notice("Dinner's ready")]);
+    }
+}
@@ -0,0 +1,261 @@
+//! Alternate encodings of the screen content for constrained clients (e.g. an ESP32 driving a
+//! smaller panel) that can't afford a full gRPC stack.
+//!
+//! These clients talk plain TCP on the configured `compact_port`: they connect and send a single
+//! byte of capability flags, then the server answers with one byte for the `ContentEncoding` it
+//! picked, the payload length as a big-endian u32, and the payload itself, then closes.
+
+use crate::my_screen_service::MyScreenService;
+use crate::screen_service::{
+    CompactContent, CompactDeparture, ContentEncoding, ScreenContentReply,
+};
+use prost::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Duration;
+use tracing::{debug, info, warn};
+
+// For clients that connect and never send their capabilities not to keep a socket forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// Before accepting again after a failure (e.g. out of file descriptors), not to spin on it
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The client can decode a `CompactContent`
+pub const CAPABILITY_COMPACT_PROTO: u8 = 0b0000_0001;
+
+pub trait ContentEncoder: Sync {
+    /// The capability flags a client must have set to get this encoding
+    fn required_capabilities(&self) -> u8;
+    fn encoding(&self) -> ContentEncoding;
+    fn encode(&self, content: &ScreenContentReply) -> Vec<u8>;
+}
+
+/// The plain `ScreenContentReply`, which every client understands
+struct FullProtoEncoder;
+
+impl ContentEncoder for FullProtoEncoder {
+    fn required_capabilities(&self) -> u8 {
+        0
+    }
+
+    fn encoding(&self) -> ContentEncoding {
+        ContentEncoding::FullProto
+    }
+
+    fn encode(&self, content: &ScreenContentReply) -> Vec<u8> {
+        content.encode_to_vec()
+    }
+}
+
+struct CompactProtoEncoder;
+
+impl ContentEncoder for CompactProtoEncoder {
+    fn required_capabilities(&self) -> u8 {
+        CAPABILITY_COMPACT_PROTO
+    }
+
+    fn encoding(&self) -> ContentEncoding {
+        ContentEncoding::CompactProto
+    }
+
+    fn encode(&self, content: &ScreenContentReply) -> Vec<u8> {
+        to_compact_content(content).encode_to_vec()
+    }
+}
+
+// The encoders we know of, most preferred first
+const ENCODERS: &[&dyn ContentEncoder] = &[&CompactProtoEncoder, &FullProtoEncoder];
+
+/// Picks the preferred encoder among those the client's capabilities allow
+pub fn pick_encoder(capabilities: u8) -> &'static dyn ContentEncoder {
+    ENCODERS
+        .iter()
+        .find(|encoder| {
+            capabilities & encoder.required_capabilities() == encoder.required_capabilities()
+        })
+        .copied()
+        .unwrap_or(&FullProtoEncoder)
+}
+
+// The initial the kitty updater picked, or the first letter of the name
+fn initial_or_first_char(initial: &str, name: &str) -> String {
+    match initial.is_empty() {
+        true => name.chars().next().unwrap_or('?').to_string(),
+        false => initial.to_string(),
+    }
+}
+
+// Francs go without saying, other currencies follow the amount
+fn currency_suffix(currency: &str) -> &str {
+    match currency {
+        "CHF" => "",
+        other => other,
+    }
+}
+
+fn to_compact_content(content: &ScreenContentReply) -> CompactContent {
+    let debts = content
+        .kitty_debts
+        .iter()
+        .map(|debt| {
+            format!(
+                "{}>{}:{}{}",
+                initial_or_first_char(&debt.who_initial, &debt.who),
+                initial_or_first_char(&debt.whom_initial, &debt.whom),
+                debt.how_much as i32,
+                currency_suffix(&debt.currency)
+            )
+        })
+        .collect();
+    let departures = content
+        .bus_departures
+        .iter()
+        .map(|departure| CompactDeparture {
+            destination: departure.destination.clone(),
+            departure_time: departure.departure_time.map_or(0, |t| t.seconds),
+            line: departure.line.clone(),
+            platform: departure.platform.clone(),
+            arrival: departure.arrival,
+            deviation: departure.deviation,
+            scheduled_only: departure.scheduled_only,
+            delay_minutes: departure.delay_minutes,
+        })
+        .collect();
+    let (event_title, event_start) = match &content.next_upcoming_event {
+        Some(event) => (
+            event.event_title.clone(),
+            event.event_start.map_or(0, |t| t.seconds),
+        ),
+        None => (String::new(), 0),
+    };
+    CompactContent {
+        brightness_percent: (content.brightness.clamp(0.0, 1.0) * 100.0).round() as u32,
+        error: content.error,
+        debts,
+        departures,
+        event_title,
+        event_start,
+        notices: content.notices.iter().map(|n| n.text.clone()).collect(),
+        disruptions: content.disruptions.clone(),
+    }
+}
+
+/// Serves the composed content to constrained clients, forever (or until we can't listen)
+pub async fn serve_compact(service: MyScreenService, port: u32) -> std::io::Result<()> {
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    info!("Serving constrained clients on port {}", port);
+    loop {
+        let (mut socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Error accepting a constrained client: {}", e);
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let service = service.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_one(&mut socket, &service).await {
+                warn!("Error serving constrained client {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn serve_one(socket: &mut TcpStream, service: &MyScreenService) -> Result<(), String> {
+    let capabilities = tokio::time::timeout(REQUEST_TIMEOUT, socket.read_u8())
+        .await
+        .map_err(|_| "Timed out reading the capabilities".to_string())?
+        .map_err(|e| e.to_string())?;
+    let encoder = pick_encoder(capabilities);
+    debug!(
+        "Constrained client has capabilities {:#010b}, serving {:?}",
+        capabilities,
+        encoder.encoding()
+    );
+    let content = service.get_composed_content();
+    let payload = encoder.encode(&content);
+
+    let mut message = Vec::with_capacity(payload.len() + 5);
+    message.push(encoder.encoding() as u8);
+    message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    message.extend_from_slice(&payload);
+    socket
+        .write_all(&message)
+        .await
+        .map_err(|e| e.to_string())?;
+    socket.shutdown().await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen_service::{Departure, KittyDebt};
+    use prost_types::Timestamp;
+
+    #[test]
+    fn picks_best_supported_encoder() {
+        assert_eq!(pick_encoder(0).encoding(), ContentEncoding::FullProto);
+        assert_eq!(
+            pick_encoder(CAPABILITY_COMPACT_PROTO).encoding(),
+            ContentEncoding::CompactProto
+        );
+        // Unknown flags don't matter
+        assert_eq!(
+            pick_encoder(0b1000_0000).encoding(),
+            ContentEncoding::FullProto
+        );
+    }
+
+    #[test]
+    fn encodes_compact_content() {
+        let content = ScreenContentReply {
+            brightness: 0.5,
+            kitty_debts: vec![
+                KittyDebt {
+                    who: "Sid".into(),
+                    how_much: 72.5,
+                    whom: "Moses".into(),
+                    currency: "CHF".into(),
+                    ..Default::default()
+                },
+                KittyDebt {
+                    who: "Bini".into(),
+                    how_much: 20.0,
+                    whom: "Moses".into(),
+                    currency: "EUR".into(),
+                    who_initial: "Bi".into(),
+                    ..Default::default()
+                },
+            ],
+            bus_departures: vec![Departure {
+                destination: "FLON".into(),
+                departure_time: Some(Timestamp {
+                    seconds: 1721732550,
+                    nanos: 0,
+                }),
+                line: "7".into(),
+                platform: "B".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let encoded = pick_encoder(CAPABILITY_COMPACT_PROTO).encode(&content);
+        let decoded = CompactContent::decode(encoded.as_slice()).unwrap();
+        assert_eq!(
+            decoded,
+            CompactContent {
+                brightness_percent: 50,
+                debts: vec!["S>M:72".into(), "Bi>M:20EUR".into()],
+                departures: vec![CompactDeparture {
+                    destination: "FLON".into(),
+                    departure_time: 1721732550,
+                    line: "7".into(),
+                    platform: "B".into(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }
+        );
+    }
+}
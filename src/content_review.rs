@@ -0,0 +1,105 @@
+//! Sources listed for review in the server config don't go straight to the screen: their updates
+//! are applied to a staging copy of the content instead, which the admin console shows in its
+//! status, until someone approves the source from there.
+
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::screen_service::ScreenContentReply;
+use crate::updater_registry::UpdaterCommand;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+/// Wraps an updater to hold its updates back until it gets approved
+pub struct UnderReview {
+    name: &'static str,
+    inner: Box<dyn DataUpdater>,
+    // What the updates would have shown, gone once approved
+    staging: Option<ScreenContentReply>,
+}
+
+impl UnderReview {
+    pub fn new(name: &'static str, inner: Box<dyn DataUpdater>) -> Self {
+        UnderReview {
+            name,
+            inner,
+            staging: Some(ScreenContentReply::default()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl DataUpdater for UnderReview {
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        let updates = self.inner.update(error_bit).await;
+        let Some(staging) = self.staging.as_mut() else {
+            return updates;
+        };
+        debug!("Staging {} updates until approved", self.name);
+        for update in updates {
+            update.apply(staging);
+        }
+        vec![]
+    }
+
+    fn get_next_update_time(&self) -> Instant {
+        self.inner.get_next_update_time()
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        self.inner.update_mode()
+    }
+
+    fn staged_content(&self) -> Option<ScreenContentReply> {
+        self.staging.clone()
+    }
+
+    fn command(&mut self, command: UpdaterCommand) -> Result<(), String> {
+        if command != UpdaterCommand::Approve {
+            return self.inner.command(command);
+        }
+        if self.staging.take().is_some() {
+            info!("{} approved, its updates now go to the screen", self.name);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen_service::CalendarEvent;
+
+    struct EventUpdater;
+
+    #[tonic::async_trait]
+    impl DataUpdater for EventUpdater {
+        async fn update(&mut self, _error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+            vec![ContentUpdate::UpcomingEvents(vec![CalendarEvent {
+                event_title: "<div>garbage</div>".into(),
+                event_start: None,
+                ..Default::default()
+            }])]
+        }
+
+        fn get_next_update_time(&self) -> Instant {
+            Instant::now()
+        }
+    }
+
+    #[tokio::test]
+    async fn stages_updates_until_approved() {
+        let error_bit = Arc::new(AtomicBool::new(false));
+        let mut updater = UnderReview::new("gcal", Box::new(EventUpdater));
+        assert_eq!(updater.update(&error_bit).await, vec![]);
+        let staged = updater.staged_content().expect("should be under review");
+        assert_eq!(
+            staged.next_upcoming_event.unwrap().event_title,
+            "<div>garbage</div>"
+        );
+
+        updater.command(UpdaterCommand::Approve).unwrap();
+        assert_eq!(updater.staged_content(), None);
+        assert_eq!(updater.update(&error_bit).await.len(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+//! Persists the screen content to disk, so a restarted server has something to show before its
+//! updaters come back.
+//!
+//! Blobs start with the `MAGIC` bytes and the schema version as a big-endian u32, followed by the
+//! serialized `ScreenContentReply`. Blobs from older schema versions go through the migrations
+//! below on load, blobs from newer ones are refused.
+
+use crate::screen_service::ScreenContentReply;
+use prost::Message;
+use prost_types::Timestamp;
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use tracing::{debug, info, warn};
+
+const MAGIC: &[u8; 4] = b"RPSC";
+const HEADER_SIZE: usize = MAGIC.len() + 4;
+/// Bump this and add a migration whenever what we store changes meaning
+const SCHEMA_VERSION: u32 = 2;
+const SAVE_PERIOD: Duration = Duration::from_secs(60);
+
+// Migrates a blob's content from `version` to `version + 1`
+type Migration = fn(ScreenContentReply) -> ScreenContentReply;
+
+// MIGRATIONS[i] migrates from version i + 1
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize - 1] = [migrate_v1_to_v2];
+
+// v1 departures had their destination as an enum, which v2 reads as no destination at all
+fn migrate_v1_to_v2(content: ScreenContentReply) -> ScreenContentReply {
+    ScreenContentReply {
+        bus_departures: vec![],
+        ..content
+    }
+}
+
+// Brightness and error get recomputed on every request, and degraded sources come from circuit
+// breakers that don't survive a restart, so restoring them would only show stale values. Pushed
+// notices only come once, so the ones still showing are kept.
+fn without_composed_fields(content: ScreenContentReply) -> ScreenContentReply {
+    let now = Timestamp::from(SystemTime::now());
+    let notices = content
+        .notices
+        .into_iter()
+        .filter(|notice| {
+            notice
+                .expires_at
+                .is_some_and(|expiry| (expiry.seconds, expiry.nanos) > (now.seconds, now.nanos))
+        })
+        .collect();
+    ScreenContentReply {
+        brightness: 0.0,
+        error: false,
+        notices,
+        degraded_sources: vec![],
+        ..content
+    }
+}
+
+fn encode(content: &ScreenContentReply) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + content.encoded_len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&SCHEMA_VERSION.to_be_bytes());
+    content.encode(&mut bytes).expect("Vec grows as needed");
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Result<ScreenContentReply, Box<dyn std::error::Error>> {
+    if bytes.len() < HEADER_SIZE || &bytes[..MAGIC.len()] != MAGIC {
+        return Err("Not a content store blob".into());
+    }
+    let version = u32::from_be_bytes(bytes[MAGIC.len()..HEADER_SIZE].try_into()?);
+    if version == 0 || version > SCHEMA_VERSION {
+        return Err(format!(
+            "Unsupported schema version {} (we're at {})",
+            version, SCHEMA_VERSION
+        )
+        .into());
+    }
+    let mut content = ScreenContentReply::decode(&bytes[HEADER_SIZE..])?;
+    for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        debug!("Migrating stored content from v{}", from_version + 1);
+        content = migration(content);
+    }
+    Ok(content)
+}
+
+/// Loads the content saved at `path`, migrating it to the current schema if needed
+pub fn load(path: &Path) -> Result<ScreenContentReply, Box<dyn std::error::Error>> {
+    decode(&std::fs::read(path)?)
+}
+
+/// Saves the content at `path`, through a temporary file so a crash can't leave half a blob
+pub fn save(path: &Path, content: &ScreenContentReply) -> std::io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, encode(content))?;
+    std::fs::rename(temp_path, path)
+}
+
+/// Saves the content every `SAVE_PERIOD`, forever
+pub async fn save_periodically(path: String, screen_content: watch::Receiver<ScreenContentReply>) {
+    info!("Saving the content to {} every {:?}", path, SAVE_PERIOD);
+    let mut interval = tokio::time::interval(SAVE_PERIOD);
+    loop {
+        interval.tick().await;
+        let content = without_composed_fields(screen_content.borrow().clone());
+        if let Err(e) = save(Path::new(&path), &content) {
+            warn!("Couldn't save the content to {}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen_service::{Departure, KittyDebt, Notice};
+
+    fn content() -> ScreenContentReply {
+        ScreenContentReply {
+            kitty_debts: vec![KittyDebt {
+                who: "Sid".into(),
+                how_much: 72.5,
+                whom: "Moses".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn blob(version: u32, content: &ScreenContentReply) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&version.to_be_bytes());
+        bytes.extend_from_slice(&content.encode_to_vec());
+        bytes
+    }
+
+    #[test]
+    fn roundtrips_current_version() {
+        assert_eq!(decode(&encode(&content())).unwrap(), content());
+    }
+
+    #[test]
+    fn keeps_the_notices_still_showing() {
+        let notice = |text: &str, expires_at: SystemTime| Notice {
+            text: text.into(),
+            expires_at: Some(Timestamp::from(expires_at)),
+        };
+        let in_an_hour = SystemTime::now() + Duration::from_secs(3600);
+        let shown = ScreenContentReply {
+            brightness: 0.7,
+            notices: vec![
+                notice("Washing done", SystemTime::UNIX_EPOCH),
+                notice("Door open", in_an_hour),
+            ],
+            ..content()
+        };
+        let saved = decode(&encode(&without_composed_fields(shown))).unwrap();
+        assert_eq!(
+            saved,
+            ScreenContentReply {
+                notices: vec![notice("Door open", in_an_hour)],
+                ..content()
+            }
+        );
+    }
+
+    #[test]
+    fn drops_v1_departures() {
+        let v1 = ScreenContentReply {
+            bus_departures: vec![Departure {
+                line: "7".into(),
+                ..Default::default()
+            }],
+            ..content()
+        };
+        assert_eq!(decode(&blob(1, &v1)).unwrap(), content());
+    }
+
+    #[test]
+    fn refuses_unknown_versions() {
+        assert!(decode(&blob(0, &content())).is_err());
+        assert!(decode(&blob(SCHEMA_VERSION + 1, &content())).is_err());
+    }
+
+    #[test]
+    fn refuses_foreign_blobs() {
+        assert!(decode(b"").is_err());
+        assert!(decode(&content().encode_to_vec()).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! Counts the days down to a few dates from the config (e.g. "Holidays: in 12 days"), as text
+//! widgets that only show once the date is close enough. Nothing to fetch, the countdowns only
+//! change at midnight.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::event_format;
+use crate::screen_service::TextWidget;
+use chrono::{Datelike, Local, NaiveDate, Timelike};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::info;
+
+pub const SOURCE: &str = "countdowns";
+const DEFAULT_HORIZON_DAYS: u32 = 30;
+// In case the next midnight can't be told, e.g. skipped by a clock change
+const FALLBACK_PERIOD: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Date {
+    Once(NaiveDate),
+    // Month and day, e.g. for birthdays
+    Yearly(u32, u32),
+}
+
+#[derive(Debug)]
+struct Countdown {
+    label: String,
+    date: Date,
+    horizon_days: u32,
+}
+
+#[derive(Debug)]
+pub struct CountdownUpdater {
+    update_mode: UpdateMode,
+    countdowns: Vec<Countdown>,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for CountdownUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(47),
+            UpdateMode::Real => {
+                let now = Local::now();
+                let until_midnight = now
+                    .date_naive()
+                    .succ_opt()
+                    .and_then(|tomorrow| tomorrow.and_hms_opt(0, 0, 0))
+                    .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+                    .and_then(|midnight| (midnight - now).to_std().ok());
+                Instant::now() + until_midnight.unwrap_or(FALLBACK_PERIOD)
+            }
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} countdowns", self.update_mode);
+        let now = chrono::offset::Local::now();
+        let widgets = match self.update_mode {
+            UpdateMode::Dummy => {
+                // Down from 4 days to today, over and over
+                let days = 4 - now.minute() as i64 % 5;
+                let countdown = Countdown {
+                    label: "Dummy".into(),
+                    date: Date::Once(now.date_naive() + chrono::Days::new(days as u64)),
+                    horizon_days: DEFAULT_HORIZON_DAYS,
+                };
+                get_widgets(&[countdown], now.date_naive())
+            }
+            UpdateMode::Real => get_widgets(&self.countdowns, now.date_naive()),
+        };
+        // Counting can't fail
+        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+        vec![ContentUpdate::TextWidgets {
+            source: SOURCE,
+            widgets,
+        }]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl CountdownUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let countdowns_config = config.countdowns.as_ref().ok_or("No countdowns config")?;
+        let horizon_days = countdowns_config
+            .horizon_days
+            .unwrap_or(DEFAULT_HORIZON_DAYS);
+        let mut countdowns = vec![];
+        for countdown in &countdowns_config.countdowns {
+            countdowns.push(Countdown {
+                label: countdown.label.clone(),
+                date: parse_date(&countdown.date).ok_or_else(|| {
+                    format!("Bad date {:?} for {}", countdown.date, countdown.label)
+                })?,
+                horizon_days: countdown.horizon_days.unwrap_or(horizon_days),
+            });
+        }
+        Ok(CountdownUpdater {
+            update_mode,
+            countdowns,
+        })
+    }
+}
+
+// "2025-07-12" once, or "07-12" every year
+fn parse_date(date: &str) -> Option<Date> {
+    if let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return Some(Date::Once(date));
+    }
+    let (month, day) = date.split_once('-')?;
+    let (month, day) = (month.parse().ok()?, day.parse().ok()?);
+    // Any leap year, for February 29th to be valid
+    NaiveDate::from_ymd_opt(2000, month, day)?;
+    Some(Date::Yearly(month, day))
+}
+
+// The next time the date comes, today included
+fn next_occurrence(date: Date, today: NaiveDate) -> Option<NaiveDate> {
+    match date {
+        Date::Once(date) => Some(date).filter(|date| *date >= today),
+        // Up to the next leap year for February 29th
+        Date::Yearly(month, day) => (today.year()..today.year() + 5)
+            .filter_map(|year| NaiveDate::from_ymd_opt(year, month, day))
+            .find(|date| *date >= today),
+    }
+}
+
+// The countdowns within their horizon, soonest first
+fn get_widgets(countdowns: &[Countdown], today: NaiveDate) -> Vec<TextWidget> {
+    let mut upcoming: Vec<(i64, &Countdown)> = countdowns
+        .iter()
+        .filter_map(|countdown| {
+            let days = (next_occurrence(countdown.date, today)? - today).num_days();
+            Some((days, countdown)).filter(|_| days <= countdown.horizon_days as i64)
+        })
+        .collect();
+    upcoming.sort_by_key(|(days, _)| *days);
+    upcoming
+        .into_iter()
+        .map(|(days, countdown)| TextWidget {
+            label: countdown.label.clone(),
+            text: event_format::countdown(days as i32),
+            source: SOURCE.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_down_close_dates() {
+        let countdown = |label: &str, date: &str, horizon_days: u32| Countdown {
+            label: label.into(),
+            date: parse_date(date).unwrap(),
+            horizon_days,
+        };
+        let countdowns = [
+            countdown("Holidays", "2025-07-12", 30),
+            countdown("Birthday", "07-01", 30),
+            countdown("Leap", "02-29", 1000),
+            countdown("Past", "2025-06-01", 30),
+            countdown("Far", "2025-12-24", 30),
+        ];
+        let today = NaiveDate::from_ymd_opt(2025, 6, 30).unwrap();
+        let widgets: Vec<(String, String)> = get_widgets(&countdowns, today)
+            .into_iter()
+            .map(|widget| (widget.label, widget.text))
+            .collect();
+        assert_eq!(
+            widgets,
+            [
+                ("Birthday".to_string(), "tomorrow".to_string()),
+                ("Holidays".to_string(), "in 12 days".to_string()),
+                ("Leap".to_string(), "in 974 days".to_string()),
+            ]
+        );
+        assert!(parse_date("13-01").is_none());
+        assert!(parse_date("next week").is_none());
+    }
+}
@@ -1,9 +1,153 @@
-use crate::screen_service::ScreenContentReply;
-use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use crate::screen_service::{
+    Appliance, Astronomy, BikeStation, CalendarEvent, Connectivity, Departure, Diagnostics,
+    EvCharger, Headline, Indoor, KittyBalance, KittyDebt, Match, NasStatus, Notice, Openings,
+    Printer, Quote, RaceSession, ScreenContentReply, Solar, Tasks, TextWidget, Weather,
+};
+use crate::updater_registry::UpdaterCommand;
+use prost_types::Timestamp;
+use std::sync::{atomic::AtomicBool, Arc};
+use std::time::SystemTime;
 use tokio::time::Instant;
 
+/// Selected from each updater's config, Dummy is for manual testing with fabricated data instead
+/// of hitting the actual sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateMode {
+    Dummy,
+    Real,
+}
+
+impl UpdateMode {
+    pub fn from_dummy(dummy_mode: bool) -> Self {
+        match dummy_mode {
+            true => UpdateMode::Dummy,
+            false => UpdateMode::Real,
+        }
+    }
+}
+
+/// A piece of the screen content, as produced by an update. Updaters never touch the content
+/// themselves: the content aggregator merges these into it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContentUpdate {
+    KittyDebts {
+        debts: Vec<KittyDebt>,
+        balances: Vec<KittyBalance>,
+        alert: bool,
+    },
+    Departures(Vec<Departure>),
+    Disruptions(Vec<String>),
+    UpcomingEvents(Vec<CalendarEvent>),
+    Weather(Option<Weather>),
+    Astronomy(Astronomy),
+    Indoor(Option<Indoor>),
+    Diagnostics(Option<Diagnostics>),
+    Nas(Option<NasStatus>),
+    Connectivity(Option<Connectivity>),
+    Solar(Option<Solar>),
+    Quotes(Vec<Quote>),
+    Headlines(Vec<Headline>),
+    Tasks(Option<Tasks>),
+    BikeStations(Vec<BikeStation>),
+    EvCharger(Option<EvCharger>),
+    Matches(Vec<Match>),
+    RaceSession(Option<RaceSession>),
+    Appliances(Vec<Appliance>),
+    Openings(Option<Openings>),
+    Printer(Option<Printer>),
+    // Replaces the widgets from `source` only
+    TextWidgets {
+        source: &'static str,
+        widgets: Vec<TextWidget>,
+    },
+    Notice(Notice),
+    Degraded {
+        source: &'static str,
+        degraded: bool,
+    },
+}
+
+impl ContentUpdate {
+    pub fn apply(self, content: &mut ScreenContentReply) {
+        match self {
+            ContentUpdate::KittyDebts {
+                debts,
+                balances,
+                alert,
+            } => {
+                content.kitty_debts = debts;
+                content.kitty_balances = balances;
+                content.kitty_alert = alert;
+            }
+            ContentUpdate::Departures(departures) => content.bus_departures = departures,
+            ContentUpdate::Disruptions(disruptions) => content.disruptions = disruptions,
+            ContentUpdate::UpcomingEvents(events) => {
+                content.next_upcoming_event = events.first().cloned();
+                content.upcoming_events = events;
+            }
+            ContentUpdate::Weather(weather) => content.weather = weather,
+            ContentUpdate::Astronomy(astronomy) => content.astronomy = Some(astronomy),
+            ContentUpdate::Indoor(indoor) => content.indoor = indoor,
+            ContentUpdate::Diagnostics(diagnostics) => content.diagnostics = diagnostics,
+            ContentUpdate::Nas(nas) => content.nas = nas,
+            ContentUpdate::Connectivity(connectivity) => content.connectivity = connectivity,
+            ContentUpdate::Solar(solar) => content.solar = solar,
+            ContentUpdate::Quotes(quotes) => content.quotes = quotes,
+            ContentUpdate::Headlines(headlines) => content.headlines = headlines,
+            ContentUpdate::Tasks(tasks) => content.tasks = tasks,
+            ContentUpdate::BikeStations(stations) => content.bike_stations = stations,
+            ContentUpdate::EvCharger(charger) => content.ev_charger = charger,
+            ContentUpdate::Matches(matches) => content.matches = matches,
+            ContentUpdate::RaceSession(session) => content.race_session = session,
+            ContentUpdate::Appliances(appliances) => content.appliances = appliances,
+            ContentUpdate::Openings(openings) => content.openings = openings,
+            ContentUpdate::Printer(printer) => content.printer = printer,
+            ContentUpdate::TextWidgets { source, widgets } => {
+                content
+                    .text_widgets
+                    .retain(|widget| widget.source != source);
+                content.text_widgets.extend(widgets);
+            }
+            ContentUpdate::Notice(notice) => {
+                // Composing the content only hides expired notices, drop them for good here
+                let now = Timestamp::from(SystemTime::now());
+                content.notices.retain(|shown| {
+                    shown.expires_at.is_some_and(|expiry| {
+                        (expiry.seconds, expiry.nanos) > (now.seconds, now.nanos)
+                    })
+                });
+                content.notices.push(notice);
+            }
+            ContentUpdate::Degraded { source, degraded } => {
+                let sources = &mut content.degraded_sources;
+                let is_listed = sources.iter().any(|listed| listed == source);
+                if degraded && !is_listed {
+                    sources.push(source.to_string());
+                } else if !degraded && is_listed {
+                    sources.retain(|listed| listed != source);
+                }
+            }
+        }
+    }
+}
+
 #[tonic::async_trait]
-pub trait DataUpdater {
-    async fn update(&mut self, screen_content: &Arc<Mutex<ScreenContentReply>>, error_bit: &Arc<AtomicBool>);
+pub trait DataUpdater: Send {
+    /// Fetches fresh data, returning what should change in the screen content
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate>;
     fn get_next_update_time(&self) -> Instant;
+    /// Whether the updater fabricates its data or fetches it, to read or switch, None for the
+    /// updaters without a Dummy mode
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        None
+    }
+    /// What the updater would have shown, while its updates are held back for review
+    fn staged_content(&self) -> Option<ScreenContentReply> {
+        None
+    }
+    /// Applies the commands the scheduler leaves to the updater (approving, acknowledging,
+    /// completing an item...), failing for the ones it doesn't support
+    fn command(&mut self, command: UpdaterCommand) -> Result<(), String> {
+        Err(format!("{:?} isn't supported", command))
+    }
 }
@@ -0,0 +1,78 @@
+//! Tells which of a stop's configured destinations a departure goes to, whatever backend it comes
+//! from: by the stops it calls at after ours, or failing that by its destination text.
+
+use crate::config_extractor::api_config::transport_config::Stop;
+use regex::Regex;
+
+/// The destination a call at the given station leads to, if any
+pub fn get_destination(station: u32, stop: &Stop) -> Option<String> {
+    let dest = stop
+        .destination_points
+        .iter()
+        .find(|dest| dest.stops.contains(&station))?;
+    Some(dest.destination_name.clone())
+}
+
+/// The destination whose regex matches a departure's destination text (e.g. "Lausanne, Flon"), if
+/// any. Only for the departures none of the stops told apart, new route variants keep ending at
+/// stops missing from the lists.
+pub fn get_destination_by_text(text: &str, stop: &Stop) -> Option<String> {
+    let dest = stop.destination_points.iter().find(|dest| {
+        !dest.destination_text_regex.is_empty()
+            // Checked when the updater starts, a few small regexes per response are cheap to build
+            && Regex::new(&dest.destination_text_regex).is_ok_and(|regex| regex.is_match(text))
+    })?;
+    Some(dest.destination_name.clone())
+}
+
+/// Makes sure the destination regexes of all the stops are valid
+pub fn check_regexes(stops: &[Stop]) -> Result<(), Box<dyn std::error::Error>> {
+    for dest in stops.iter().flat_map(|stop| &stop.destination_points) {
+        if !dest.destination_text_regex.is_empty() {
+            Regex::new(&dest.destination_text_regex).map_err(|e| {
+                format!(
+                    "Invalid destination text regex for {}: {}",
+                    dest.destination_name, e
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_extractor::api_config::transport_config::DestinationPoints;
+
+    #[test]
+    fn matches_stops_then_text() {
+        let stop = Stop {
+            destination_points: vec![
+                DestinationPoints {
+                    stops: vec![8592050],
+                    destination_name: "FLON".into(),
+                    destination_text_regex: "(?i)flon|ouchy".into(),
+                },
+                DestinationPoints {
+                    stops: vec![8501118],
+                    destination_name: "RENENS".into(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(get_destination(8501118, &stop), Some("RENENS".into()));
+        assert_eq!(get_destination(1, &stop), None);
+        assert_eq!(
+            get_destination_by_text("Lausanne, Ouchy-Olympique", &stop),
+            Some("FLON".into())
+        );
+        assert_eq!(get_destination_by_text("Renens VD, gare", &stop), None);
+        assert!(check_regexes(std::slice::from_ref(&stop)).is_ok());
+
+        let mut broken = stop;
+        broken.destination_points[1].destination_text_regex = "(renens".into();
+        assert!(check_regexes(&[broken]).is_err());
+    }
+}
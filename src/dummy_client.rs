@@ -1,13 +1,19 @@
 use crate::config_extractor::api_config::ApiConfig;
+use crate::event_format;
+use crate::hash_beacon;
+use crate::time_util;
 use chrono::{DateTime, Datelike, Local, Timelike};
-use log::{debug, error, info};
 use screen_service::{
-    screen_service_client::ScreenServiceClient, ScreenContentReply, ScreenContentRequest,
-    ScreenHashRequest,
+    appliance::State as ApplianceState, calendar_event::DateHint, ev_charger::State,
+    kitty_debt::Trend, screen_service_client::ScreenServiceClient, ScreenContentReply,
+    ScreenContentRequest, ScreenHashRequest,
 };
 use tokio::task::JoinHandle;
 use tonic::transport::Channel;
+use tracing::{debug, error, info};
 
+// Clients don't use every message (e.g. the ones for constrained clients)
+#[allow(dead_code)]
 pub mod screen_service {
     tonic::include_proto!("screen_service"); // The string specified here must match the proto package name
 }
@@ -62,18 +68,31 @@ fn start_hash_queries(api_config: &ApiConfig) -> JoinHandle<()> {
     debug!("update interval: {:?}", update_interval);
     let address = crate::config_extractor::get_server_address(api_config);
     debug!("address: {:?}", address);
+    let beacon_config = api_config.beacon.clone();
     tokio::spawn(async move {
         // Let the server start up
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
         let mut client = ScreenServiceClient::connect(address)
             .await
             .expect("Couldn't start dummy client");
+        // Without a beacon we poll the hash from the server at every tick
+        let beacon = match beacon_config {
+            Some(beacon_config) => Some(
+                hash_beacon::listen(&beacon_config)
+                    .await
+                    .expect("Couldn't listen for hash beacons"),
+            ),
+            None => None,
+        };
         let mut interval = tokio::time::interval(update_interval);
         let mut hash: u64 = 0;
         let mut minutes: u32 = Local::now().minute();
         loop {
-            interval.tick().await;
-            let new_hash = make_hash_request(&mut client).await;
+            let new_hash =
+                match hash_beacon::wait_for_hash(beacon.as_ref(), &mut interval, hash).await {
+                    Some(hash) => hash,
+                    None => make_hash_request(&mut client).await,
+                };
             if hash != new_hash || minutes != Local::now().minute() {
                 hash = new_hash;
                 minutes = Local::now().minute();
@@ -84,12 +103,30 @@ fn start_hash_queries(api_config: &ApiConfig) -> JoinHandle<()> {
     })
 }
 
+// The initial the server picked for a name, or its first letter for servers that don't
+fn debt_initial(initial: &str, name: &str) -> String {
+    if !initial.is_empty() {
+        return initial.to_string();
+    }
+    name.chars()
+        .next()
+        .unwrap_or_else(|| {
+            error!("No first char in debt's name");
+            '?'
+        })
+        .to_string()
+}
+
 fn content_pretty_print(content: ScreenContentReply) -> Result<(), Box<dyn std::error::Error>> {
     // TODO: handle all the unwraps
     let now = Local::now();
     info!("------------------");
     info!("[b:{}]", content.brightness);
     info!("[e:{}]", content.error);
+    info!("[k:{}]", content.kitty_alert);
+    if !content.degraded_sources.is_empty() {
+        info!("[d:{}]", content.degraded_sources.join(","));
+    }
     // On the real client this will be updated every minute, not with incoming messages
     // (otherwise we'd need to wait for e.g. a bus departure to have the minutes change)
     info!("{}", now.format("%H:%M"));
@@ -98,66 +135,284 @@ fn content_pretty_print(content: ScreenContentReply) -> Result<(), Box<dyn std::
             .kitty_debts
             .iter()
             .map(|debt| {
+                // Debts of a labelled kitty group say which one
+                let group = match debt.group.is_empty() {
+                    true => String::new(),
+                    false => format!("{}: ", debt.group),
+                };
+                // Amounts that changed since the last fetch say by how much
+                let delta = match debt.delta != 0.0 {
+                    true => format!(" ({:+})", debt.delta as i32),
+                    false => String::new(),
+                };
+                // How it went over the last week
+                let trend = match debt.trend() {
+                    Trend::Steady => "",
+                    Trend::Rising => " ↑",
+                    Trend::Falling => " ↓",
+                };
                 format!(
-                    "{}>{}:{}",
-                    debt.who
-                        .chars()
-                        .next()
-                        .or_else(|| {
-                            error!("No first char in debt's who");
-                            Some('?')
-                        })
-                        .unwrap(),
-                    debt.whom
-                        .chars()
-                        .next()
-                        .or_else(|| {
-                            error!("No first char in debt's who");
-                            Some('?')
-                        })
-                        .unwrap(),
-                    debt.how_much as i32
+                    "{}{}>{}:{} {}{}{}",
+                    group,
+                    debt_initial(&debt.who_initial, &debt.who),
+                    debt_initial(&debt.whom_initial, &debt.whom),
+                    debt.how_much as i32,
+                    debt.currency,
+                    trend,
+                    delta
                 )
             })
             .collect::<Vec<String>>()
             .join(" - ");
         info!("{}", debts);
     }
-    if !content.bus_departures.is_empty() {
-        let departures = content
-            .bus_departures
+    if !content.kitty_balances.is_empty() {
+        // e.g. "S:-108 M:+150"
+        let balances = content
+            .kitty_balances
             .iter()
-            .map(|dep| {
-                let proto_ts = dep
-                    .departure_time
-                    .or_else(|| {
-                        error!("Departure without a time");
-                        Some(
-                            prost_types::Timestamp::date(2000, 01, 01)
-                                .expect("Can't even make a hardcoded proto"),
-                        )
-                    })
-                    .unwrap();
-                let departure_time: DateTime<Local> = DateTime::from_timestamp(
-                    proto_ts.seconds,
-                    proto_ts.nanos.try_into().expect("Invalid TS nanos"),
-                )
-                // We can't use `?` here because the function (we're in the lambda) doesn't return a Result
-                .expect("Unable to convert departure proto TS into DateTime")
-                .into();
-                let departure_minutes_from_now =
-                    departure_time.signed_duration_since(now).num_minutes();
+            .map(|balance| {
                 format!(
-                    "{}:{}'",
-                    dep.destination_enum().as_str_name().chars().next().unwrap(),
-                    departure_minutes_from_now
+                    "{}:{:+}",
+                    debt_initial(&balance.initial, &balance.who),
+                    balance.balance as i32
                 )
             })
             .collect::<Vec<String>>()
+            .join(" ");
+        info!("{}", balances);
+    }
+    if !content.bus_departures.is_empty() {
+        // Departures come earliest first, those after the next join the line of their destination
+        let mut lines: Vec<((String, bool), String)> = vec![];
+        for dep in &content.bus_departures {
+            let proto_ts = dep
+                .departure_time
+                .or_else(|| {
+                    error!("Departure without a time");
+                    Some(
+                        prost_types::Timestamp::date(2000, 01, 01)
+                            .expect("Can't even make a hardcoded proto"),
+                    )
+                })
+                .unwrap();
+            let departure_minutes_from_now = time_util::minutes_until(&proto_ts, &now)?;
+            let mark = match (dep.deviation, dep.scheduled_only) {
+                (true, _) => '!',
+                (false, true) => '~',
+                (false, false) => '\'',
+            };
+            let destination = (dep.destination.clone(), dep.arrival);
+            if let Some((_, line)) = lines.iter_mut().find(|(d, _)| *d == destination) {
+                line.push_str(&format!(",{}{}", departure_minutes_from_now, mark));
+                continue;
+            }
+            lines.push((
+                destination,
+                format!(
+                    "{}{}{}{}{}{}{}",
+                    dep.line,
+                    dep.destination.chars().next().unwrap_or('?'),
+                    if dep.arrival { '>' } else { ':' },
+                    departure_minutes_from_now,
+                    mark,
+                    dep.platform,
+                    match dep.delay_minutes {
+                        0 => String::new(),
+                        delay => format!(" {:+}'", delay),
+                    }
+                ),
+            ));
+        }
+        let departures = lines
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect::<Vec<String>>()
             .join(" - ");
         info!("{}", departures);
     }
-    if let Some(event) = content.next_upcoming_event {
+    if let Some(astronomy) = &content.astronomy {
+        let time = |time: Option<prost_types::Timestamp>| {
+            time.and_then(|time| DateTime::from_timestamp(time.seconds, 0))
+                .map(|time| time.with_timezone(&Local).format("%H:%M").to_string())
+                .unwrap_or("--:--".into())
+        };
+        info!(
+            "Sun {}-{}, moon {:.0}% through its cycle",
+            time(astronomy.sunrise),
+            time(astronomy.sunset),
+            astronomy.moon_phase * 100.0
+        );
+    }
+    if let Some(indoor) = &content.indoor {
+        // e.g. "Indoor 21.5°C 45% 812ppm", without what no sensor measures
+        let measurements = [
+            indoor.temperature.map(|t| format!("{:.1}°C", t)),
+            indoor.humidity.map(|h| format!("{:.0}%", h)),
+            indoor.co2.map(|c| format!("{:.0}ppm", c)),
+        ];
+        let measurements: Vec<String> = measurements.into_iter().flatten().collect();
+        info!("Indoor {}", measurements.join(" "));
+    }
+    if let Some(diagnostics) = &content.diagnostics {
+        let temperature = diagnostics
+            .cpu_temperature
+            .map(|t| format!("{:.0}°C ", t))
+            .unwrap_or_default();
+        info!(
+            "Server {}load:{:.2} mem:{:.0}% disk:{:.0}%{}",
+            temperature,
+            diagnostics.load,
+            diagnostics.memory_used,
+            diagnostics.disk_used,
+            if diagnostics.throttled {
+                " THROTTLED"
+            } else {
+                ""
+            }
+        );
+    }
+    if let Some(nas) = &content.nas {
+        let parity_check = nas
+            .parity_check_progress
+            .map(|p| format!(", parity check at {:.0}%", p))
+            .unwrap_or_default();
+        match nas.warning.is_empty() {
+            true => info!("NAS {}{}", nas.array_state.to_lowercase(), parity_check),
+            false => info!("{}{}", nas.warning, parity_check),
+        }
+    }
+    if let Some(connectivity) = &content.connectivity {
+        // e.g. "Pings 192.168.1.1:1ms 1.1.1.1:12ms(33% lost)"
+        let pings: Vec<String> = connectivity
+            .pings
+            .iter()
+            .map(|ping| match (ping.latency_ms, ping.packet_loss > 0.0) {
+                (None, _) => format!("{}:down", ping.host),
+                (Some(ms), false) => format!("{}:{:.0}ms", ping.host, ms),
+                (Some(ms), true) => {
+                    format!("{}:{:.0}ms({:.0}% lost)", ping.host, ms, ping.packet_loss)
+                }
+            })
+            .collect();
+        info!("Pings {}", pings.join(" "));
+        if connectivity.lan_down {
+            info!("NETWORK DOWN");
+        } else if connectivity.internet_down {
+            info!("INTERNET DOWN");
+        }
+    }
+    for widget in &content.text_widgets {
+        info!("{}: {}", widget.label, widget.text);
+    }
+    if let Some(solar) = &content.solar {
+        // e.g. "Solar 3200W, using 1100W, battery 80%"
+        let mut solar_text = format!("Solar {:.0}W", solar.production);
+        if let Some(consumption) = solar.consumption {
+            solar_text.push_str(&format!(", using {:.0}W", consumption));
+        }
+        if let Some(battery) = solar.battery {
+            solar_text.push_str(&format!(", battery {:.0}%", battery));
+        }
+        info!("{}", solar_text);
+    }
+    for quote in &content.quotes {
+        info!(
+            "{} {} ({:+.1}%)",
+            quote.label, quote.price_text, quote.change
+        );
+    }
+    for m in content.matches.iter().filter(|m| m.live) {
+        info!("Live {}", m.text);
+    }
+    if let Some(charger) = &content.ev_charger {
+        // e.g. "EV Charging 11040W, 4.0kWh so far, done at 18:30"
+        let mut charger_text = format!(
+            "EV {:?} {:.0}W, {:.1}kWh so far",
+            charger.state(),
+            charger.power,
+            charger.session_energy
+        );
+        let finish = charger
+            .estimated_finish
+            .filter(|_| charger.state() == State::Charging)
+            .and_then(|finish| DateTime::from_timestamp(finish.seconds, 0));
+        if let Some(finish) = finish {
+            let finish = finish.with_timezone(&Local).format("%H:%M");
+            charger_text.push_str(&format!(", done at {}", finish));
+        }
+        info!("{}", charger_text);
+    }
+    if let Some(session) = &content.race_session {
+        // e.g. "F1 Quali in 2d3h"
+        let start = session.start.map_or(0, |start| start.seconds);
+        info!(
+            "{} {} in {}",
+            session.series,
+            session.session,
+            event_format::until(start - now.timestamp())
+        );
+    }
+    for appliance in &content.appliances {
+        // e.g. "Washer running (450W)", or "Washer FINISHED" until acknowledged
+        match appliance.state() {
+            ApplianceState::Idle => info!("{} idle", appliance.label),
+            ApplianceState::Running => {
+                info!("{} running ({:.0}W)", appliance.label, appliance.power)
+            }
+            ApplianceState::Finished => info!("{} FINISHED", appliance.label),
+        }
+    }
+    if let Some(printer) = &content.printer {
+        // e.g. "Printing benchy.gcode: 42%, done at 18:30"
+        let mut printer_text = format!(
+            "{} {}: {:.0}%",
+            if printer.paused { "Paused" } else { "Printing" },
+            printer.file,
+            printer.progress
+        );
+        let eta = printer
+            .eta
+            .and_then(|eta| DateTime::from_timestamp(eta.seconds, 0));
+        if let Some(eta) = eta {
+            let eta = eta.with_timezone(&Local).format("%H:%M");
+            printer_text.push_str(&format!(", done at {}", eta));
+        }
+        info!("{}", printer_text);
+    }
+    if let Some(openings) = content.openings.as_ref().filter(|o| !o.open.is_empty()) {
+        // e.g. "1 door, 2 windows open: Front door, Kitchen, Bathroom"
+        info!(
+            "{} door(s), {} window(s) open: {}",
+            openings.doors,
+            openings.windows,
+            openings.open.join(", ")
+        );
+    }
+    for station in &content.bike_stations {
+        info!(
+            "Bikes at {}: {} bikes, {} e-bikes",
+            station.label, station.bikes, station.ebikes
+        );
+    }
+    if let Some(tasks) = &content.tasks {
+        info!("{} {}: {}", tasks.label, tasks.due_today, tasks.top_task);
+    }
+    for headline in &content.headlines {
+        info!("News {}: {}", headline.source, headline.title);
+    }
+    for notice in &content.notices {
+        info!("! {}", notice.text);
+    }
+    for disruption in &content.disruptions {
+        info!("Disrupted: {}", disruption);
+    }
+    // Older servers only send the next one
+    let events = match content.upcoming_events.is_empty() {
+        true => content.next_upcoming_event.into_iter().collect(),
+        false => content.upcoming_events,
+    };
+    for event in events {
         let proto_ts = event
             .event_start
             .or_else(|| {
@@ -174,12 +429,29 @@ fn content_pretty_print(content: ScreenContentReply) -> Result<(), Box<dyn std::
         )
         .ok_or("Unable to convert event proto TS into DateTime")?
         .into();
-        info!(
-            "{}.{}-{}",
-            event_time.day(),
-            event_time.month(),
-            event.event_title
-        );
+        // All-day events start at a midnight that means nothing
+        let time = match event.all_day {
+            true => String::new(),
+            false => event_time.format(" %H:%M").to_string(),
+        };
+        let location = match event.location.is_empty() {
+            true => String::new(),
+            false => format!(" - {}", event.location),
+        };
+        if event.in_progress {
+            let end = event
+                .event_end
+                .and_then(|end| DateTime::from_timestamp(end.seconds, 0))
+                .map(|end| end.with_timezone(&Local).format(" until %H:%M").to_string())
+                .unwrap_or_default();
+            info!("now{}-{}{}", end, event.event_title, location);
+            continue;
+        }
+        let date = match event.date_hint() {
+            DateHint::Countdown => event_format::countdown(event.days_until),
+            DateHint::Date => format!("{}.{}", event_time.day(), event_time.month()),
+        };
+        info!("{}{}-{}{}", date, time, event.event_title, location);
     }
 
     Ok(())
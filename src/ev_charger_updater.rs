@@ -0,0 +1,272 @@
+//! What the home EV charger is up to: whether the car charges, at what power, and when it should
+//! be done.
+//!
+//! This reads the chargers' local HTTP APIs: go-e's /api/status (API v2, to be enabled in its app)
+//! and OpenEVSE's /status. The finish time is an estimate from the energy left to charge at the
+//! current power, which needs an energy limit set on the go-e or a target in the config.
+
+use crate::config_extractor::api_config;
+use crate::config_extractor::api_config::ev_charger_config::Vendor;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::ev_charger::State;
+use crate::screen_service::EvCharger;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use prost_types::Timestamp;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+// What the chargers tell, in their own units already converted
+#[derive(Debug, PartialEq)]
+struct Reading {
+    state: State,
+    // In W
+    power: f32,
+    // In kWh, since the car was plugged in
+    session_energy: f32,
+    // In kWh, the energy limit set on the charger
+    limit: Option<f32>,
+}
+
+#[derive(Debug)]
+pub struct EvChargerUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    vendor: Vendor,
+    url: String,
+    target_kwh: Option<f32>,
+    ev_charger_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for EvChargerUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(57),
+            UpdateMode::Real => Instant::now() + self.ev_charger_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} EV charger", self.update_mode);
+        let charger;
+        let now = chrono::offset::Local::now();
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                // 8.25 kWh at 11 kW over the first 45 minutes of every hour, done after that
+                let session_energy = (now.minute() as f32 / 60.0 * 11.0).min(8.25);
+                let reading = Reading {
+                    state: match now.minute() < 45 {
+                        true => State::Charging,
+                        false => State::Complete,
+                    },
+                    power: match now.minute() < 45 {
+                        true => 11000.0,
+                        false => 0.0,
+                    },
+                    session_energy,
+                    limit: Some(8.25),
+                };
+                charger = Some(get_charger(&reading, None, now.timestamp()));
+                error_bit.store(
+                    now.second().is_multiple_of(57),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                charger = match self.get_reading().await {
+                    Ok(reading) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.ev_charger_period.set_success();
+                        Some(get_charger(&reading, self.target_kwh, now.timestamp()))
+                    }
+                    Err(e) => {
+                        error!("Error getting the EV charger status: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.ev_charger_period.set_error();
+                        None
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::EvCharger(charger)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl EvChargerUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let ev_charger_config = config.ev_charger.as_ref().ok_or("No EV charger config")?;
+        if ev_charger_config.url.is_empty() {
+            return Err("No EV charger URL".into());
+        }
+        let ev_charger_period_config = Duration::from_secs(
+            ev_charger_config
+                .update_period
+                .as_ref()
+                .ok_or("no EV charger update period")?
+                .seconds
+                .try_into()?,
+        );
+        let ev_charger_period = ExponentialBackoff::new(
+            ev_charger_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(EvChargerUpdater {
+            update_mode,
+            client,
+            vendor: ev_charger_config.vendor(),
+            url: ev_charger_config.url.trim_end_matches('/').to_string(),
+            target_kwh: ev_charger_config.target_kwh,
+            ev_charger_period,
+        })
+    }
+
+    async fn get_reading(&self) -> Result<Reading, Box<dyn std::error::Error>> {
+        let url = match self.vendor {
+            // Only the keys we read, the whole status is a few kB
+            Vendor::GoE => format!("{}/api/status?filter=car,nrg,wh,dwo", self.url),
+            Vendor::OpenEvse => format!("{}/status", self.url),
+        };
+        let body = traced(
+            "fetch",
+            retry_http("EV charger fetch", || async {
+                self.client
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        traced_sync("parse", || match self.vendor {
+            Vendor::GoE => parse_go_e_status(&body),
+            Vendor::OpenEvse => parse_openevse_status(&body),
+        })
+    }
+}
+
+fn parse_go_e_status(body: &str) -> Result<Reading, Box<dyn std::error::Error>> {
+    let status: Value = serde_json::from_str(body)?;
+    let state = match status.get("car").and_then(Value::as_u64) {
+        Some(1) => State::Idle,
+        Some(2) => State::Charging,
+        Some(3) => State::Connected,
+        Some(4) => State::Complete,
+        Some(5) => State::Error,
+        _ => return Err(format!("No car state in the go-e status: {}", body).into()),
+    };
+    let number = |pointer: &str| status.pointer(pointer).and_then(Value::as_f64);
+    Ok(Reading {
+        state,
+        // The 12th energy value is the total power
+        power: number("/nrg/11").unwrap_or(0.0) as f32,
+        session_energy: (number("/wh").unwrap_or(0.0) / 1000.0) as f32,
+        // Null without a limit
+        limit: number("/dwo").map(|wh| (wh / 1000.0) as f32),
+    })
+}
+
+fn parse_openevse_status(body: &str) -> Result<Reading, Box<dyn std::error::Error>> {
+    let status: Value = serde_json::from_str(body)?;
+    let number = |name: &str| status.get(name).and_then(Value::as_f64);
+    let vehicle_connected = number("vehicle") == Some(1.0);
+    let state = match number("state").map(|state| state as u64) {
+        Some(1) => State::Idle,
+        Some(2) => State::Connected,
+        Some(3) => State::Charging,
+        Some(4..=10) => State::Error,
+        // Sleeping or disabled, e.g. once the limit is reached
+        Some(254 | 255) if vehicle_connected => State::Connected,
+        Some(254 | 255) => State::Idle,
+        _ => return Err(format!("No state in the OpenEVSE status: {}", body).into()),
+    };
+    // Older firmwares only have the current (in mA) and the voltage
+    let power = number("power").or_else(|| Some(number("amp")? / 1000.0 * number("voltage")?));
+    Ok(Reading {
+        state,
+        power: power.unwrap_or(0.0) as f32,
+        session_energy: (number("session_energy").unwrap_or(0.0) / 1000.0) as f32,
+        limit: None,
+    })
+}
+
+// The finish time is only estimated while charging, towards the charger's limit or else the
+// config's target
+fn get_charger(reading: &Reading, target_kwh: Option<f32>, now: i64) -> EvCharger {
+    let target = reading.limit.or(target_kwh);
+    let estimated_finish = match (reading.state, target) {
+        (State::Charging, Some(target)) if reading.power > 0.0 => {
+            let left_kwh = (target - reading.session_energy).max(0.0);
+            let left_seconds = left_kwh * 1000.0 / reading.power * 3600.0;
+            Some(Timestamp {
+                seconds: now + left_seconds.round() as i64,
+                nanos: 0,
+            })
+        }
+        _ => None,
+    };
+    let mut charger = EvCharger {
+        power: reading.power,
+        session_energy: reading.session_energy,
+        estimated_finish,
+        ..Default::default()
+    };
+    charger.set_state(reading.state);
+    charger
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_the_finish_time() {
+        let go_e = r#"{"car": 2, "wh": 4000.0, "dwo": 10000, "nrg": [230, 231, 229, 0, 16, 16, 16,
+            3680, 3700, 3660, 0, 11040, 100, 100, 100, 100]}"#;
+        let reading = parse_go_e_status(go_e).unwrap();
+        assert_eq!(
+            reading,
+            Reading {
+                state: State::Charging,
+                power: 11040.0,
+                session_energy: 4.0,
+                limit: Some(10.0),
+            }
+        );
+        let charger = get_charger(&reading, Some(20.0), 0);
+        assert_eq!(charger.state(), State::Charging);
+        // 6 kWh left at 11.04 kW
+        assert_eq!(charger.estimated_finish.unwrap().seconds, 1957);
+
+        let openevse = r#"{"mode": "STA", "state": 254, "vehicle": 1, "amp": 0,
+            "voltage": 240, "session_energy": 12500.5}"#;
+        let reading = parse_openevse_status(openevse).unwrap();
+        assert_eq!(reading.state, State::Connected);
+        assert_eq!(reading.power, 0.0);
+        let charger = get_charger(&reading, Some(20.0), 0);
+        assert!(charger.estimated_finish.is_none());
+
+        let openevse = r#"{"state": 3, "vehicle": 1, "amp": 16000, "voltage": 230,
+            "session_energy": 2000}"#;
+        let charger = get_charger(&parse_openevse_status(openevse).unwrap(), Some(5.68), 0);
+        // 3.68 kWh left at 3.68 kW
+        assert_eq!(charger.estimated_finish.unwrap().seconds, 3600);
+    }
+}
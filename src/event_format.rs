@@ -0,0 +1,44 @@
+//! How the clients write when an event is, so they all say it the same way.
+
+/// "today", "tomorrow", "in 3 days", or "yesterday" for the events still shown after midnight
+pub fn countdown(days_until: i32) -> String {
+    match days_until {
+        ..=-2 => format!("{} days ago", -days_until),
+        -1 => "yesterday".into(),
+        0 => "today".into(),
+        1 => "tomorrow".into(),
+        _ => format!("in {} days", days_until),
+    }
+}
+
+/// "2d3h", "3h12m" or "12m" until something starts, "now" once it has
+pub fn until(seconds: i64) -> String {
+    let minutes = (seconds + 59) / 60;
+    match minutes {
+        ..=0 => "now".into(),
+        1..=59 => format!("{}m", minutes),
+        60..=1439 => format!("{}h{}m", minutes / 60, minutes % 60),
+        _ => format!("{}d{}h", minutes / 1440, minutes % 1440 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_down_days() {
+        assert_eq!(countdown(-1), "yesterday");
+        assert_eq!(countdown(0), "today");
+        assert_eq!(countdown(1), "tomorrow");
+        assert_eq!(countdown(3), "in 3 days");
+    }
+
+    #[test]
+    fn counts_down_to_the_minute() {
+        assert_eq!(until(-30), "now");
+        assert_eq!(until(30), "1m");
+        assert_eq!(until(3 * 3600 + 12 * 60), "3h12m");
+        assert_eq!(until(2 * 86400 + 3 * 3600 + 60), "2d3h");
+    }
+}
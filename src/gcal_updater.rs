@@ -1,85 +1,112 @@
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
 use crate::exponential_backoff::ExponentialBackoff;
-use crate::screen_service::{CalendarEvent, ScreenContentReply};
-use crate::{config_extractor::api_config, data_updater::DataUpdater};
-use chrono::{Datelike, NaiveDateTime, Timelike};
-use log::{debug, error, info};
+use crate::retry::retry_http;
+use crate::screen_service::CalendarEvent;
+use crate::time_util;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::{Local, Timelike};
 use prost_types::Timestamp;
-use reqwest::Client;
+use reqwest::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, StatusCode};
 use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::SystemTime;
 use tokio::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
 
-#[derive(Debug)]
-// We switch from one to the other for manual testing, but it's actually fine to keep both.
-#[allow(dead_code)]
-pub enum GcalUpdateMode {
-    Dummy,
-    Real,
-}
+const DEFAULT_UPCOMING_EVENTS: u32 = 3;
 
 #[derive(Debug)]
 pub struct GcalUpdater {
-    update_mode: GcalUpdateMode,
+    update_mode: UpdateMode,
     client: Client,
     ics_url: String,
     gcal_period: ExponentialBackoff,
+    upcoming_events: usize,
+    // The events of the last ICS we got, kept through fetch failures
+    cache: Option<IcsCache>,
+}
+
+// Along with the validators to only download the ICS again when it changed
+#[derive(Debug)]
+struct IcsCache {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    events: Vec<CalendarEvent>,
 }
 
 #[tonic::async_trait]
 impl DataUpdater for GcalUpdater {
     fn get_next_update_time(&self) -> Instant {
         match self.update_mode {
-            GcalUpdateMode::Dummy => Instant::now() + Duration::from_secs(29),
-            GcalUpdateMode::Real => Instant::now() + self.gcal_period.get_current_duration(),
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(29),
+            UpdateMode::Real => Instant::now() + self.gcal_period.get_current_duration(),
         }
     }
 
-    async fn update(
-        &mut self,
-        screen_content: &Arc<Mutex<ScreenContentReply>>,
-        error_bit: &Arc<AtomicBool>,
-    ) {
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
         info!("Updating {:?} gCal", self.update_mode);
-        let event;
+        let events;
         match self.update_mode {
-            GcalUpdateMode::Dummy => {
+            UpdateMode::Dummy => {
                 let now = chrono::offset::Local::now();
-                event = Some(CalendarEvent {
-                    event_start: Some(Timestamp::from(SystemTime::from(now))),
-                    event_title: "dummy event".into(),
-                });
+                events = vec![
+                    CalendarEvent {
+                        event_start: Some(Timestamp::from(SystemTime::from(now))),
+                        event_title: "dummy event".into(),
+                        location: "dummy place".into(),
+                        event_end: Some(Timestamp::from(SystemTime::from(
+                            now + chrono::Duration::hours(1),
+                        ))),
+                        ..Default::default()
+                    },
+                    CalendarEvent {
+                        event_start: Some(Timestamp::from(SystemTime::from(
+                            now + chrono::Duration::days(1),
+                        ))),
+                        event_title: "dummy event tomorrow".into(),
+                        all_day: true,
+                        ..Default::default()
+                    },
+                ];
                 error_bit.store(now.second() % 10 == 0, std::sync::atomic::Ordering::Relaxed);
             }
-            GcalUpdateMode::Real => {
-                event = match self.get_next_event().await {
-                    Ok(returned_event) => {
+            UpdateMode::Real => {
+                match self.fetch_events().await {
+                    Ok(()) => {
                         // Make sure the server knows there are no errors
                         error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
                         // And potentially resume normal update cadence
                         self.gcal_period.set_success();
-                        returned_event
                     }
                     Err(e) => {
-                        error!("Error getting gCal event: {}", e);
+                        error!("Error getting gCal events: {}", e);
                         error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
                         self.gcal_period.set_error();
-                        None
                     }
                 }
+                // Rather than blanking the calendar when the fetch failed
+                let now = chrono::Utc::now().timestamp();
+                events = match &self.cache {
+                    Some(cache) => upcoming_events(&cache.events, self.upcoming_events, now),
+                    None => vec![],
+                };
             }
         }
-        match screen_content.lock() {
-            Ok(mut content) => content.next_upcoming_event = event,
-            Err(e) => error!("Poisoned lock when writing debts: {}", e),
-        };
+        vec![ContentUpdate::UpcomingEvents(events)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
     }
 }
 
 impl GcalUpdater {
     pub fn new(
-        update_mode: GcalUpdateMode,
+        update_mode: UpdateMode,
         config: &api_config::ApiConfig,
+        client: Client,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let gcal_config = config.gcal.as_ref().ok_or("No gCal config")?;
         let ics_url = gcal_config.ics_url.clone();
@@ -98,66 +125,188 @@ impl GcalUpdater {
         );
         Ok(GcalUpdater {
             update_mode,
-            client: Client::new(),
+            client,
             ics_url,
             gcal_period,
+            upcoming_events: gcal_config
+                .upcoming_events
+                .unwrap_or(DEFAULT_UPCOMING_EVENTS)
+                .try_into()?,
+            cache: None,
         })
     }
 
-    async fn get_next_event(&self) -> Result<Option<CalendarEvent>, Box<dyn std::error::Error>> {
-        let ics: String = self.client.get(&self.ics_url).send().await?.text().await?;
-        parse_next_event(ics).map_err(|err| format!("Error parsing ics content: {:?}", err).into())
+    // Downloads and parses the ICS into the cache, unless the server says it didn't change
+    async fn fetch_events(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let (status, etag, last_modified, ics) = traced(
+            "fetch",
+            retry_http("ICS fetch", || async {
+                let mut request = self.client.get(&self.ics_url);
+                if let Some(cache) = &self.cache {
+                    if let Some(etag) = &cache.etag {
+                        request = request.header(IF_NONE_MATCH, etag);
+                    }
+                    if let Some(last_modified) = &cache.last_modified {
+                        request = request.header(IF_MODIFIED_SINCE, last_modified);
+                    }
+                }
+                let response = request.send().await?.error_for_status()?;
+                let headers = response.headers();
+                let etag = headers.get(ETAG).cloned();
+                let last_modified = headers.get(LAST_MODIFIED).cloned();
+                Ok((
+                    response.status(),
+                    etag,
+                    last_modified,
+                    response.text().await?,
+                ))
+            }),
+        )
+        .await?;
+        if status == StatusCode::NOT_MODIFIED && self.cache.is_some() {
+            debug!("The ICS didn't change, keeping the cached events");
+            return Ok(());
+        }
+        let events = traced_sync("parse", || parse_events(ics))
+            .map_err(|err| format!("Error parsing ics content: {:?}", err))?;
+        self.cache = Some(IcsCache {
+            etag,
+            last_modified,
+            events,
+        });
+        Ok(())
     }
 }
 
-// Note: this function assumes that the ics passed used the following gCal options:
-// ?futureevents=true&orderby=starttime&sortorder=ascending
-// This means that the first event in the body is the next upcoming event, so we can just look that up.
-fn parse_next_event(ics: String) -> Result<Option<CalendarEvent>, Box<dyn std::error::Error>> {
-    let mut lines = ics.lines();
-
+// All the events of the ICS, in its order. gCal can be asked for only future events, sorted
+// (?futureevents=true&orderby=starttime&sortorder=ascending), but other ICS providers don't honor
+// those, see `upcoming_events`.
+pub fn parse_events(ics: String) -> Result<Vec<CalendarEvent>, Box<dyn std::error::Error>> {
     // None of the ical parsing crates out there do a good job, so let's just do it manually.
-    let mut first_upcoming_event: Option<CalendarEvent> = None;
-    while let Some(line) = lines.next() {
-        if line == "END:VEVENT" {
-            debug!("Fund end of first event");
-            break;
+    let mut events: Vec<CalendarEvent> = vec![];
+    let mut event: Option<CalendarEvent> = None;
+    // Why the current event's time can't be read, to skip it rather than the whole calendar
+    let mut bad_time: Option<String> = None;
+    for line in unfold_lines(&ics) {
+        let line = line.as_str();
+        if line == "BEGIN:VEVENT" {
+            // Forget whatever came before, e.g. the time zone definitions' DTSTARTs
+            event = None;
+            bad_time = None;
+        } else if line == "END:VEVENT" {
+            debug!("Found end of event {}", events.len());
+            keep_readable(&mut events, event.take(), bad_time.take());
         } else if let Some(title) = line.strip_prefix("SUMMARY:") {
             debug!("Found event title: {}", title);
-            first_upcoming_event
-                .get_or_insert(CalendarEvent::default())
-                .event_title = title.to_string();
-        } else if let Some(ts) = line.strip_prefix("DTSTART:") {
-            debug!("Parsing ICS timestamp: {:#?}", ts);
-            // Consider exporting this in a helper module (see also transport updater)
-            let rust_ts = NaiveDateTime::parse_from_str(ts, "%Y%m%dT%H%M%SZ")
-                .map_err(|e| format!("ICS timestamp parsing error {:?} parsing '{}'", e, ts))?;
-            debug!("Parsed timestamp: {:#?}", rust_ts);
-            let time = Timestamp::date_time(
-                rust_ts.year().try_into().unwrap(),
-                rust_ts.month().try_into().unwrap(),
-                rust_ts.day().try_into().unwrap(),
-                rust_ts.hour().try_into().unwrap(),
-                rust_ts.minute().try_into().unwrap(),
-                rust_ts.second().try_into().unwrap(),
-            )
-            .map_err(|e| format!("Proto timestamp creation error: {:?}", e))?;
-            first_upcoming_event
-                .get_or_insert(CalendarEvent::default())
-                .event_start = Some(time);
+            event.get_or_insert(CalendarEvent::default()).event_title = unescape_text(title);
+        } else if let Some(location) = line.strip_prefix("LOCATION:") {
+            event.get_or_insert(CalendarEvent::default()).location = unescape_text(location);
+        } else if let Some(property) = line.strip_prefix("DTSTART") {
+            debug!("Parsing ICS timestamp: {:#?}", property);
+            let event = event.get_or_insert(CalendarEvent::default());
+            match time_util::parse_ics_datetime(property, &Local) {
+                Ok(time) => {
+                    debug!("Parsed timestamp: {:#?}", time);
+                    event.event_start = Some(time);
+                    event.all_day = time_util::is_ics_date(property);
+                }
+                Err(e) => bad_time = Some(e.to_string()),
+            }
+        } else if let Some(property) = line.strip_prefix("DTEND") {
+            let event = event.get_or_insert(CalendarEvent::default());
+            match time_util::parse_ics_datetime(property, &Local) {
+                Ok(time) => event.event_end = Some(time),
+                Err(e) => bad_time = Some(e.to_string()),
+            }
+        }
+    }
+    // An event cut short still tells what comes next
+    keep_readable(&mut events, event, bad_time);
+    Ok(events)
+}
+
+// Adds the event unless its time couldn't be read
+fn keep_readable(
+    events: &mut Vec<CalendarEvent>,
+    event: Option<CalendarEvent>,
+    bad_time: Option<String>,
+) {
+    match (event, bad_time) {
+        (Some(event), Some(e)) => warn!("Skipping event {:?}: {}", event.event_title, e),
+        (event, _) => events.extend(event),
+    }
+}
+
+// The first `n` events not over by `now` (POSIX time), soonest first
+fn upcoming_events(events: &[CalendarEvent], n: usize, now: i64) -> Vec<CalendarEvent> {
+    let mut upcoming_events: Vec<CalendarEvent> = events
+        .iter()
+        .filter(|event| !is_over(event, now))
+        .cloned()
+        .collect();
+    // Those without a start last, there's no telling when they are
+    upcoming_events.sort_by_key(|event| event.event_start.map_or(i64::MAX, |start| start.seconds));
+    upcoming_events.truncate(n);
+    upcoming_events
+}
+
+// Once it ended, or a day after it started when the calendar doesn't say how long it lasts (how
+// long started events are shown is up to the server)
+fn is_over(event: &CalendarEvent, now: i64) -> bool {
+    match (event.event_end, event.event_start) {
+        (Some(end), _) => end.seconds <= now,
+        (None, Some(start)) => start.seconds + 24 * 3600 <= now,
+        (None, None) => false,
+    }
+}
+
+// Joins the lines folded at 75 octets back together (RFC 5545, section 3.1): a line starting with
+// a space or a tab continues the previous one, without that first character
+fn unfold_lines(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in ics.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
         }
     }
+    lines
+}
 
-    Ok(first_upcoming_event)
+// Undoes the ICS text escaping, titles and addresses are full of escaped commas
+fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // The screen only has the one line
+            Some('n') | Some('N') => unescaped.push(' '),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push(c),
+        }
+    }
+    unescaped
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn parse_upcoming_events(
+        ics: String,
+        n: usize,
+        now: i64,
+    ) -> Result<Vec<CalendarEvent>, Box<dyn std::error::Error>> {
+        Ok(upcoming_events(&parse_events(ics)?, n, now))
+    }
+
     #[test]
     fn parses_event() {
-        let ics = "BEGIN:VCALENDAR
+        let ics: String = "BEGIN:VCALENDAR
 PRODID:-//Google Inc//Google Calendar 70.9054//EN
 VERSION:2.0
 CALSCALE:GREGORIAN
@@ -185,22 +334,71 @@ CREATED:20240714T132024Z
 LAST-MODIFIED:20240714T132024Z
 SEQUENCE:0
 STATUS:CONFIRMED
-SUMMARY:Test next event
+SUMMARY:Apéro\\, drinks
+LOCATION:Rue de Lausanne 1\\, Renens
 TRANSP:OPAQUE
 END:VEVENT
 END:VCALENDAR
 "
         .into();
-        let parsed = parse_next_event(ics).unwrap();
-        let expected = Some(CalendarEvent {
+        let parsed = parse_upcoming_events(ics.clone(), 1, 0).unwrap();
+        let expected = CalendarEvent {
             event_start: Some(Timestamp {
                 // 2024-07-20 11:00 UTC
                 seconds: 1721473200,
                 nanos: 0,
             }),
             event_title: "Test event".into(),
-        });
-        assert_eq!(parsed, expected);
+            event_end: Some(Timestamp {
+                // 2024-07-20 12:00 UTC
+                seconds: 1721476800,
+                nanos: 0,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(parsed, vec![expected.clone()]);
+
+        let next_expected = CalendarEvent {
+            event_start: Some(Timestamp {
+                // 2024-07-20 13:00 UTC
+                seconds: 1721480400,
+                nanos: 0,
+            }),
+            event_title: "Apéro, drinks".into(),
+            location: "Rue de Lausanne 1, Renens".into(),
+            event_end: Some(Timestamp {
+                // 2024-07-20 14:00 UTC
+                seconds: 1721484000,
+                nanos: 0,
+            }),
+            ..Default::default()
+        };
+        // There are only two
+        let parsed = parse_upcoming_events(ics, 3, 0).unwrap();
+        assert_eq!(parsed, vec![expected, next_expected]);
+    }
+
+    #[test]
+    fn parses_all_day_event() {
+        let ics = "BEGIN:VCALENDAR
+X-WR-TIMEZONE:Europe/Zurich
+BEGIN:VEVENT
+DTSTART;VALUE=DATE:20241023
+DTEND;VALUE=DATE:20241024
+SUMMARY:Birthday
+END:VEVENT
+END:VCALENDAR
+"
+        .into();
+        let parsed = parse_upcoming_events(ics, 3, 0).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert!(parsed[0].all_day);
+        assert_eq!(parsed[0].event_title, "Birthday");
+        // The next midnight
+        assert_eq!(
+            parsed[0].event_end.unwrap().seconds - parsed[0].event_start.unwrap().seconds,
+            24 * 3600
+        );
     }
 
     #[test]
@@ -215,15 +413,126 @@ X-WR-TIMEZONE:Europe/Zurich
 END:VCALENDAR
 "
         .into();
-        let parsed = parse_next_event(ics).unwrap();
-        assert_eq!(parsed, None);
+        let parsed = parse_upcoming_events(ics, 3, 0).unwrap();
+        assert!(parsed.is_empty());
+    }
+
+    #[test]
+    fn picks_the_next_events_of_unsorted_ics() {
+        let ics: String = "BEGIN:VCALENDAR
+BEGIN:VTIMEZONE
+TZID:Europe/Zurich
+BEGIN:STANDARD
+DTSTART:19701025T030000
+END:STANDARD
+END:VTIMEZONE
+BEGIN:VEVENT
+DTSTART:20240722T180000Z
+DTEND:20240722T200000Z
+SUMMARY:Later
+END:VEVENT
+BEGIN:VEVENT
+DTSTART:20240719T180000Z
+DTEND:20240719T200000Z
+SUMMARY:Past
+END:VEVENT
+BEGIN:VEVENT
+DTSTART:20240720T120000Z
+DTEND:20240720T130000Z
+SUMMARY:Ongoing
+END:VEVENT
+BEGIN:VEVENT
+DTSTART:20240721T180000Z
+SUMMARY:Next
+END:VEVENT
+END:VCALENDAR
+"
+        .into();
+        // 2024-07-20 12:30 UTC
+        let parsed = parse_upcoming_events(ics, 2, 1721478600).unwrap();
+        let titles: Vec<&str> = parsed.iter().map(|e| e.event_title.as_str()).collect();
+        assert_eq!(titles, ["Ongoing", "Next"]);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_cached_events() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/basic.ics", listener.local_addr().unwrap());
+        // Sends the ICS, then says it didn't change when asked with its ETag, then fails
+        let server = tokio::spawn(async move {
+            let ics = "BEGIN:VEVENT\r\nDTSTART:20990720T110000Z\r\nSUMMARY:Far\r\nEND:VEVENT\r\n";
+            let mut requests = vec![];
+            for reply in [
+                format!(
+                    "200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n{}",
+                    ics.len(),
+                    ics
+                ),
+                "304 Not Modified\r\n\r\n".to_string(),
+                "404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+            ] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let length = stream.read(&mut request).await.unwrap();
+                requests.push(String::from_utf8_lossy(&request[..length]).to_lowercase());
+                let reply = format!("HTTP/1.1 {}", reply);
+                stream.write_all(reply.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        let mut updater = GcalUpdater {
+            update_mode: UpdateMode::Real,
+            client: Client::new(),
+            ics_url: url,
+            gcal_period: ExponentialBackoff::new(
+                Duration::from_secs(60),
+                Duration::from_secs(60),
+                Duration::from_secs(60),
+            ),
+            upcoming_events: 3,
+            cache: None,
+        };
+        let error_bit = Arc::new(AtomicBool::new(false));
+        for _ in 0..3 {
+            let updates = updater.update(&error_bit).await;
+            let [ContentUpdate::UpcomingEvents(events)] = updates.as_slice() else {
+                panic!("Unexpected updates {:?}", updates);
+            };
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].event_title, "Far");
+        }
+        // The last one failed, but still sent what we had
+        assert!(error_bit.load(std::sync::atomic::Ordering::Relaxed));
+        let requests = server.await.unwrap();
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+    }
+
+    #[test]
+    fn unfolds_long_lines() {
+        let ics: String = "BEGIN:VEVENT\r
+DTSTART:20240720T110000Z\r
+SUMMARY:Apéro for Nico's birthday at the usual place, don't forget the prese\r
+ nt\r
+LOCATION:Rue de\r
+\t Lausanne 1\r
+END:VEVENT\r
+"
+        .into();
+        let parsed = parse_upcoming_events(ics, 1, 0).unwrap();
+        assert_eq!(
+            parsed[0].event_title,
+            "Apéro for Nico's birthday at the usual place, don't forget the present"
+        );
+        assert_eq!(parsed[0].location, "Rue de Lausanne 1");
     }
 
     #[test]
     fn returns_default_on_empty_ics() {
         let ics = "".into();
-        let parsed = parse_next_event(ics).unwrap();
-        assert_eq!(parsed, None);
+        let parsed = parse_upcoming_events(ics, 3, 0).unwrap();
+        assert!(parsed.is_empty());
     }
 
     #[test]
@@ -233,27 +542,30 @@ not?an
 <h1>ICS</h1>
 ]});"
             .into();
-        let parsed = parse_next_event(ics).unwrap();
-        assert_eq!(parsed, None);
+        let parsed = parse_upcoming_events(ics, 3, 0).unwrap();
+        assert!(parsed.is_empty());
     }
 
     #[test]
-    fn doesnt_panic_on_malformed_time() {
-        let ics = "
+    fn skips_events_with_malformed_times() {
+        let ics = "BEGIN:VEVENT
 DTSTART:20240732T110000Z
 DTEND:20240720T120000Z
-DTSTAMP:20240714T150338Z
-UID:3cil81id3a6r6l6ccm9tbdcvj9@google.com
-CREATED:20240714T130605Z
-LAST-MODIFIED:20240714T130605Z
-SEQUENCE:0
-STATUS:CONFIRMED
 SUMMARY:Test event
-TRANSP:OPAQUE
+END:VEVENT
+BEGIN:VEVENT
+DTSTART:20240720T130000Z
+SUMMARY:Test next event
+DTEND:2024-07-20
+END:VEVENT
+BEGIN:VEVENT
+DTSTART:20240721T130000Z
+SUMMARY:Test last event
+END:VEVENT
 "
         .into();
-        let result = parse_next_event(ics);
-        let err = result.err().unwrap();
-        assert!(err.to_string().contains("timestamp parsing error"));
+        let parsed = parse_upcoming_events(ics, 3, 0).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].event_title, "Test last event");
     }
 }
@@ -0,0 +1,141 @@
+//! The compiled-in fonts only cover printable ASCII, loaded ones whatever glyphs their file has,
+//! and embedded-graphics draws anything else as a blank (think accented event titles). This swaps
+//! such characters for a visible replacement glyph instead, and logs each offending character once
+//! (with the text it first showed up in) so we know which sources need attention.
+
+use log::warn;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+pub const DEFAULT_REPLACEMENT: char = '?';
+
+// What the ascii fonts of embedded-graphics (FONT_4X6, FONT_5X7, FONT_9X15_BOLD...) can draw
+fn is_covered(c: char) -> bool {
+    c == ' ' || c == '\n' || c.is_ascii_graphic()
+}
+
+/// Which characters the font a text gets drawn with can draw
+#[derive(Debug, Clone, Copy)]
+pub enum Coverage<'a> {
+    BuiltIn,
+    // A loaded font's cell of each character it has
+    Loaded(&'a HashMap<char, usize>),
+}
+
+impl Coverage<'_> {
+    fn covers(&self, c: char) -> bool {
+        match self {
+            Coverage::BuiltIn => is_covered(c),
+            Coverage::Loaded(cells) => c == '\n' || cells.contains_key(&c),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GlyphFallback {
+    replacement: char,
+    // Characters we already warned about, we redraw the same titles every minute. Keyed on the
+    // characters rather than the texts so it doesn't grow with every new title over weeks.
+    reported: HashSet<char>,
+}
+
+impl GlyphFallback {
+    /// Falls back to `DEFAULT_REPLACEMENT` if the configured replacement isn't covered either
+    pub fn new(replacement: Option<&str>) -> Self {
+        let replacement = match replacement.map(|r| r.chars().collect::<Vec<char>>()) {
+            None => DEFAULT_REPLACEMENT,
+            Some(chars) if chars.len() == 1 && is_covered(chars[0]) && chars[0] != '\n' => chars[0],
+            Some(_) => {
+                warn!(
+                    "Replacement glyph {:?} isn't a single drawable character, using '{}'",
+                    replacement, DEFAULT_REPLACEMENT
+                );
+                DEFAULT_REPLACEMENT
+            }
+        };
+        GlyphFallback {
+            replacement,
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Returns `text` with the characters the font can't draw replaced. Loaded fonts without the
+    /// replacement glyph draw their own fallback cell for it.
+    pub fn cover<'a>(&mut self, text: &'a str, coverage: Coverage) -> Cow<'a, str> {
+        if text.chars().all(|c| coverage.covers(c)) {
+            return Cow::Borrowed(text);
+        }
+        let new: String = text
+            .chars()
+            .filter(|c| !coverage.covers(*c) && self.reported.insert(*c))
+            .collect();
+        if !new.is_empty() {
+            warn!(
+                "Replacing glyphs our fonts can't draw ({:?}) with '{}' in {:?}",
+                new, self.replacement, text
+            );
+        }
+        Cow::Owned(
+            text.chars()
+                .map(|c| match coverage.covers(c) {
+                    true => c,
+                    false => self.replacement,
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_drawable_text_as_is() {
+        let mut fallback = GlyphFallback::new(None);
+        let text = "23.10: Escape game\nS>M:42";
+        assert!(matches!(fallback.cover(text, Coverage::BuiltIn), Cow::Borrowed(t) if t == text));
+        assert!(fallback.reported.is_empty());
+    }
+
+    #[test]
+    fn replaces_uncovered_glyphs() {
+        let mut fallback = GlyphFallback::new(Some("*"));
+        assert_eq!(
+            fallback.cover("Fête à Zürich", Coverage::BuiltIn),
+            "F*te * Z*rich"
+        );
+        assert_eq!(fallback.cover("Apéro 🍻", Coverage::BuiltIn), "Ap*ro *");
+        assert_eq!(fallback.reported, HashSet::from(['ê', 'à', 'ü', 'é', '🍻']));
+        // Only reported the first time, however many texts they're in
+        fallback.cover("Apéro à 🍻", Coverage::BuiltIn);
+        assert_eq!(fallback.reported.len(), 5);
+    }
+
+    #[test]
+    fn covers_what_loaded_fonts_have() {
+        let mut fallback = GlyphFallback::new(None);
+        let cells: HashMap<char, usize> = "Apéro ".chars().zip(0..).collect();
+        let loaded = Coverage::Loaded(&cells);
+        assert!(matches!(fallback.cover("Apéro", loaded), Cow::Borrowed(_)));
+        assert_eq!(fallback.cover("Apéro 🍻!", loaded), "Apéro ??");
+        assert_eq!(fallback.reported, HashSet::from(['🍻', '!']));
+    }
+
+    #[test]
+    fn refuses_undrawable_replacements() {
+        assert_eq!(
+            GlyphFallback::new(Some("é")).replacement,
+            DEFAULT_REPLACEMENT
+        );
+        assert_eq!(
+            GlyphFallback::new(Some("ab")).replacement,
+            DEFAULT_REPLACEMENT
+        );
+        assert_eq!(
+            GlyphFallback::new(Some("")).replacement,
+            DEFAULT_REPLACEMENT
+        );
+        assert_eq!(GlyphFallback::new(Some("#")).replacement, '#');
+    }
+}
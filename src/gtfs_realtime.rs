@@ -0,0 +1,268 @@
+//! Reads departures out of a GTFS Realtime feed, for the transport updater's GTFS_REALTIME backend.
+//!
+//! Feeds are protobuf and cover a whole network: we look for the trip updates calling at our stop,
+//! and tell where they go from the stops they call at afterwards. Only the times published in the
+//! feed are used, updates giving just a delay need the static timetable we don't have.
+
+use crate::config_extractor::api_config::transport_config::{stop::EventType, Stop};
+use crate::destinations::get_destination;
+use crate::screen_service::Departure;
+use crate::time_util;
+use prost::Message;
+use prost_types::Timestamp;
+use tracing::{debug, info};
+use transit_realtime::trip_descriptor::ScheduleRelationship as TripRelationship;
+use transit_realtime::trip_update::stop_time_update::ScheduleRelationship as StopRelationship;
+use transit_realtime::trip_update::{StopTimeEvent, StopTimeUpdate};
+use transit_realtime::FeedMessage;
+
+pub mod transit_realtime {
+    // Generated by [`prost-build`]
+    include!(concat!(env!("OUT_DIR"), "/transit_realtime.rs"));
+}
+
+pub fn decode_feed(body: &[u8]) -> Result<FeedMessage, Box<dyn std::error::Error>> {
+    Ok(FeedMessage::decode(body)?)
+}
+
+/// Reads the departures (or arrivals, depending on the stop's event type) at the stop out of the
+/// feed, towards the stop's destinations from `departures_from` (POSIX time) on
+pub fn extract_departures(feed: &FeedMessage, stop: &Stop, departures_from: i64) -> Vec<Departure> {
+    let arrival = stop.event_type() == EventType::Arrival;
+    let mut departures = vec![];
+    let trip_updates = feed
+        .entity
+        .iter()
+        .filter(|entity| !entity.is_deleted())
+        .filter_map(|entity| entity.trip_update.as_ref());
+    for trip_update in trip_updates {
+        let trip = &trip_update.trip;
+        // Stop time updates come in the order the trip calls at the stops
+        let mut stop_time_updates = trip_update.stop_time_update.iter();
+        let Some(ours) = stop_time_updates
+            .by_ref()
+            .find(|update| station_of(update.stop_id()) == Some(stop.stop_id))
+        else {
+            continue;
+        };
+        // Better show the next bus that does come
+        if matches!(
+            trip.schedule_relationship(),
+            TripRelationship::Canceled | TripRelationship::Deleted
+        ) || ours.schedule_relationship() == StopRelationship::Skipped
+        {
+            info!("Skipping cancelled trip {:?}", trip.trip_id());
+            continue;
+        }
+        let Some((time, event)) = get_event(ours, arrival).and_then(|e| Some((e.time?, e))) else {
+            debug!("No time for trip {:?} at our stop", trip.trip_id());
+            continue;
+        };
+        if time < departures_from {
+            continue;
+        }
+        let Some(destination) = stop_time_updates
+            .find_map(|update| get_destination(station_of(update.stop_id())?, stop))
+        else {
+            debug!("Trip {:?} goes to none of our destinations", trip.trip_id());
+            continue;
+        };
+        let departure = Departure {
+            destination,
+            departure_time: Some(Timestamp {
+                seconds: time,
+                nanos: 0,
+            }),
+            // Feeds publish the delay along with the time, if they know it
+            timetabled_time: Some(Timestamp {
+                seconds: time - i64::from(event.delay()),
+                nanos: 0,
+            }),
+            delay_minutes: time_util::delay_minutes(time - i64::from(event.delay()), time),
+            // Feeds only have the route ID, which is often the published line name
+            line: trip.route_id().into(),
+            platform: String::new(),
+            arrival,
+            deviation: matches!(
+                trip.schedule_relationship(),
+                TripRelationship::Added
+                    | TripRelationship::Unscheduled
+                    | TripRelationship::Replacement
+                    | TripRelationship::Duplicated
+            ),
+            scheduled_only: false,
+        };
+        debug!("Found {:?}", departure);
+        departures.push(departure);
+    }
+    departures
+}
+
+/// The stop ID of the config a GTFS stop ID is about, which may be one of its platforms (e.g.
+/// "8588845:0:A" for 8588845)
+pub fn station_of(gtfs_stop_id: &str) -> Option<u32> {
+    gtfs_stop_id.split(':').next()?.parse().ok()
+}
+
+// When the trip gets to (or leaves) the stop. Feeds often only publish one of both for a stop, in
+// which case the other one is the closest we have.
+fn get_event(update: &StopTimeUpdate, arrival: bool) -> Option<&StopTimeEvent> {
+    let (wanted, other) = match arrival {
+        true => (&update.arrival, &update.departure),
+        false => (&update.departure, &update.arrival),
+    };
+    wanted.as_ref().or(other.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_extractor::api_config::transport_config::DestinationPoints;
+    use transit_realtime::{FeedEntity, FeedHeader, TripDescriptor, TripUpdate};
+
+    fn stop_time(stop_id: &str, time: i64) -> StopTimeUpdate {
+        StopTimeUpdate {
+            stop_id: Some(stop_id.into()),
+            departure: Some(StopTimeEvent {
+                time: Some(time),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn trip(route_id: &str, stop_time_update: Vec<StopTimeUpdate>) -> FeedEntity {
+        FeedEntity {
+            id: route_id.into(),
+            trip_update: Some(TripUpdate {
+                trip: TripDescriptor {
+                    route_id: Some(route_id.into()),
+                    ..Default::default()
+                },
+                stop_time_update,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn extracts_departures() {
+        let stop = Stop {
+            stop_id: 8588845,
+            destination_points: vec![
+                DestinationPoints {
+                    stops: vec![8592050],
+                    destination_name: "FLON".into(),
+                    ..Default::default()
+                },
+                DestinationPoints {
+                    stops: vec![8501118],
+                    destination_name: "RENENS".into(),
+                    ..Default::default()
+                },
+            ],
+            event_type: EventType::Departure.into(),
+            ..Default::default()
+        };
+        let mut cancelled = trip(
+            "7",
+            vec![stop_time("8588845", 1000), stop_time("8592050", 1100)],
+        );
+        cancelled
+            .trip_update
+            .as_mut()
+            .unwrap()
+            .trip
+            .set_schedule_relationship(TripRelationship::Canceled);
+        let feed = FeedMessage {
+            header: FeedHeader::default(),
+            entity: vec![
+                // Already gone
+                trip(
+                    "7",
+                    vec![stop_time("8588845", 500), stop_time("8592050", 600)],
+                ),
+                cancelled,
+                trip(
+                    "7",
+                    vec![stop_time("8588845:0:A", 1200), stop_time("8592050", 1300)],
+                ),
+                trip(
+                    "7",
+                    vec![stop_time("8588845:0:A", 1500), stop_time("8592050", 1600)],
+                ),
+                // Calls at Renens before our stop, then goes elsewhere
+                trip(
+                    "33",
+                    vec![
+                        stop_time("8501118", 1000),
+                        stop_time("8588845", 1100),
+                        stop_time("1", 1200),
+                    ],
+                ),
+                trip(
+                    "M1",
+                    vec![
+                        StopTimeUpdate {
+                            departure: Some(StopTimeEvent {
+                                time: Some(1400),
+                                delay: Some(180),
+                                ..Default::default()
+                            }),
+                            ..stop_time("8588845:0:B", 1400)
+                        },
+                        stop_time("8501118:0:1", 1500),
+                    ],
+                ),
+            ],
+        };
+        // Round trip through the wire format, like the feed we'd download
+        let feed = decode_feed(&feed.encode_to_vec()).unwrap();
+
+        let mut departures = extract_departures(&feed, &stop, 1000);
+        departures.sort_by_key(|d| d.departure_time.unwrap().seconds);
+        assert_eq!(departures.len(), 3);
+        assert_eq!(departures[2].departure_time.unwrap().seconds, 1500);
+        assert_eq!(
+            departures[..2],
+            [
+                Departure {
+                    destination: "FLON".into(),
+                    departure_time: Some(Timestamp {
+                        seconds: 1200,
+                        nanos: 0
+                    }),
+                    timetabled_time: Some(Timestamp {
+                        seconds: 1200,
+                        nanos: 0
+                    }),
+                    line: "7".into(),
+                    ..Default::default()
+                },
+                Departure {
+                    destination: "RENENS".into(),
+                    departure_time: Some(Timestamp {
+                        seconds: 1400,
+                        nanos: 0
+                    }),
+                    timetabled_time: Some(Timestamp {
+                        seconds: 1220,
+                        nanos: 0
+                    }),
+                    delay_minutes: 3,
+                    line: "M1".into(),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_platforms_of_the_stop() {
+        assert_eq!(station_of("8588845"), Some(8588845));
+        assert_eq!(station_of("8588845:0:A"), Some(8588845));
+        assert_eq!(station_of("Parent8588845"), None);
+        assert_eq!(station_of(""), None);
+    }
+}
@@ -0,0 +1,445 @@
+//! The timetable of a GTFS static extract, filling in the departures while the real-time API is
+//! down. Those come marked as scheduled only, since no delay or cancellation makes it in.
+//!
+//! Extracts are directories of unzipped GTFS files. Only the calls at the configured stops and
+//! destinations are kept when loading, so the timetable stays small whatever the extract covers.
+
+use crate::config_extractor::api_config::transport_config::{stop::EventType, Stop};
+use crate::destinations::get_destination;
+use crate::gtfs_realtime::station_of;
+use crate::screen_service::Departure;
+use chrono::{DateTime, Datelike, Days, NaiveDate, TimeZone};
+use prost_types::Timestamp;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use tracing::{info, warn};
+
+#[derive(Debug)]
+pub struct Timetable {
+    trips: Vec<ScheduledTrip>,
+    services: HashMap<String, Service>,
+}
+
+#[derive(Debug)]
+struct ScheduledTrip {
+    service_id: String,
+    line: String,
+    // In the order the trip calls at them
+    calls: Vec<Call>,
+}
+
+#[derive(Debug)]
+struct Call {
+    sequence: u32,
+    station: u32,
+    // Seconds from the start of the service day, which may go past 24h
+    arrival: Option<u32>,
+    departure: Option<u32>,
+}
+
+// Which days trips run on, from calendar.txt and its exceptions in calendar_dates.txt
+#[derive(Debug, Default)]
+struct Service {
+    // Monday first
+    weekdays: [bool; 7],
+    first_day: Option<NaiveDate>,
+    last_day: Option<NaiveDate>,
+    added: HashSet<NaiveDate>,
+    removed: HashSet<NaiveDate>,
+}
+
+impl Service {
+    fn runs_on(&self, date: NaiveDate) -> bool {
+        if self.removed.contains(&date) {
+            return false;
+        }
+        if self.added.contains(&date) {
+            return true;
+        }
+        let in_range = self
+            .first_day
+            .zip(self.last_day)
+            .is_some_and(|(first, last)| first <= date && date <= last);
+        in_range && self.weekdays[date.weekday().num_days_from_monday() as usize]
+    }
+}
+
+impl Timetable {
+    /// Loads the trips calling at the stops from the extract in `dir`
+    pub fn load(dir: &Path, stops: &[Stop]) -> Result<Self, Box<dyn std::error::Error>> {
+        let stations: HashSet<u32> = stops
+            .iter()
+            .flat_map(|stop| {
+                let destinations = stop.destination_points.iter();
+                std::iter::once(stop.stop_id).chain(destinations.flat_map(|d| d.stops.clone()))
+            })
+            .collect();
+        let mut calls = HashMap::<String, Vec<Call>>::default();
+        read_table(
+            &dir.join("stop_times.txt"),
+            &[
+                "trip_id",
+                "stop_id",
+                "stop_sequence",
+                "arrival_time",
+                "departure_time",
+            ],
+            |row| {
+                let Some(station) = station_of(row[1]).filter(|s| stations.contains(s)) else {
+                    return;
+                };
+                calls.entry(row[0].to_owned()).or_default().push(Call {
+                    sequence: row[2].parse().unwrap_or_default(),
+                    station,
+                    arrival: parse_time(row[3]),
+                    departure: parse_time(row[4]),
+                });
+            },
+        )?;
+        // Trips going by our destinations without stopping at our stops don't matter
+        calls.retain(|_, calls| {
+            calls
+                .iter()
+                .any(|call| stops.iter().any(|stop| stop.stop_id == call.station))
+        });
+
+        let mut trip_services = HashMap::<String, (String, String)>::default();
+        read_table(
+            &dir.join("trips.txt"),
+            &["trip_id", "service_id", "route_id"],
+            |row| {
+                if calls.contains_key(row[0]) {
+                    trip_services.insert(row[0].to_owned(), (row[1].to_owned(), row[2].to_owned()));
+                }
+            },
+        )?;
+        let mut lines = HashMap::<String, String>::default();
+        read_table(
+            &dir.join("routes.txt"),
+            &["route_id", "route_short_name"],
+            |row| {
+                if !row[1].is_empty() {
+                    lines.insert(row[0].to_owned(), row[1].to_owned());
+                }
+            },
+        )?;
+        let services = read_services(dir)?;
+
+        let mut trips = vec![];
+        for (trip_id, mut calls) in calls {
+            let Some((service_id, route_id)) = trip_services.remove(&trip_id) else {
+                warn!("Trip {} has stop times but isn't in trips.txt", trip_id);
+                continue;
+            };
+            calls.sort_by_key(|call| call.sequence);
+            trips.push(ScheduledTrip {
+                service_id,
+                // Better the route ID than nothing
+                line: lines.get(&route_id).cloned().unwrap_or(route_id),
+                calls,
+            });
+        }
+        info!(
+            "Loaded {} scheduled trips at our stops from {:?}",
+            trips.len(),
+            dir
+        );
+        Ok(Timetable { trips, services })
+    }
+
+    /// The scheduled departures (or arrivals, depending on the stop's event type) at the stop
+    /// towards its destinations, from `from` on up to tomorrow's
+    pub fn departures<Tz: TimeZone>(&self, stop: &Stop, from: &DateTime<Tz>) -> Vec<Departure> {
+        let arrival = stop.event_type() == EventType::Arrival;
+        let mut departures = vec![];
+        // Yesterday's service day runs on past midnight (e.g. at 25:10:00)
+        let today = from.date_naive();
+        let days = [
+            today.checked_sub_days(Days::new(1)),
+            Some(today),
+            today.checked_add_days(Days::new(1)),
+        ];
+        for date in days.into_iter().flatten() {
+            let Some(day_start) = service_day_start(&from.timezone(), date) else {
+                continue;
+            };
+            let running = self.trips.iter().filter(|trip| {
+                self.services
+                    .get(&trip.service_id)
+                    .is_some_and(|service| service.runs_on(date))
+            });
+            for trip in running {
+                let Some(position) = trip.calls.iter().position(|c| c.station == stop.stop_id)
+                else {
+                    continue;
+                };
+                let call = &trip.calls[position];
+                let seconds = match arrival {
+                    true => call.arrival.or(call.departure),
+                    false => call.departure.or(call.arrival),
+                };
+                let Some(time) = seconds.map(|s| day_start + i64::from(s)) else {
+                    continue;
+                };
+                if time < from.timestamp() {
+                    continue;
+                }
+                let Some(destination) = trip.calls[position + 1..]
+                    .iter()
+                    .find_map(|call| get_destination(call.station, stop))
+                else {
+                    continue;
+                };
+                departures.push(Departure {
+                    destination,
+                    departure_time: Some(Timestamp {
+                        seconds: time,
+                        nanos: 0,
+                    }),
+                    timetabled_time: Some(Timestamp {
+                        seconds: time,
+                        nanos: 0,
+                    }),
+                    line: trip.line.clone(),
+                    arrival,
+                    scheduled_only: true,
+                    ..Default::default()
+                });
+            }
+        }
+        departures
+    }
+}
+
+// Either calendar.txt or calendar_dates.txt may be left out, but not both
+fn read_services(dir: &Path) -> Result<HashMap<String, Service>, Box<dyn std::error::Error>> {
+    let calendar = dir.join("calendar.txt");
+    let calendar_dates = dir.join("calendar_dates.txt");
+    if !calendar.exists() && !calendar_dates.exists() {
+        return Err("Neither calendar.txt nor calendar_dates.txt in the GTFS extract".into());
+    }
+    let mut services = HashMap::<String, Service>::default();
+    let weekdays = [
+        "monday",
+        "tuesday",
+        "wednesday",
+        "thursday",
+        "friday",
+        "saturday",
+        "sunday",
+    ];
+    if calendar.exists() {
+        let columns = [&["service_id", "start_date", "end_date"], &weekdays[..]].concat();
+        read_table(&calendar, &columns, |row| {
+            let service = services.entry(row[0].to_owned()).or_default();
+            service.first_day = parse_date(row[1]);
+            service.last_day = parse_date(row[2]);
+            for (day, runs) in row[3..].iter().enumerate() {
+                service.weekdays[day] = *runs == "1";
+            }
+        })?;
+    }
+    if calendar_dates.exists() {
+        read_table(
+            &calendar_dates,
+            &["service_id", "date", "exception_type"],
+            |row| {
+                let Some(date) = parse_date(row[1]) else {
+                    return;
+                };
+                let service = services.entry(row[0].to_owned()).or_default();
+                match row[2] {
+                    "1" => service.added.insert(date),
+                    "2" => service.removed.insert(date),
+                    _ => false,
+                };
+            },
+        )?;
+    }
+    Ok(services)
+}
+
+// Calls `on_row` with the given columns of each record of a GTFS file (empty for those it lacks)
+fn read_table(
+    path: &Path,
+    columns: &[&str],
+    mut on_row: impl FnMut(&[&str]),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::open(path).map_err(|e| format!("Couldn't open {:?}: {}", path, e))?;
+    let mut lines = BufReader::new(file).lines();
+    let header = lines.next().ok_or(format!("{:?} is empty", path))??;
+    let header = split_record(header.trim_start_matches('\u{feff}'));
+    let indices: Vec<Option<usize>> = columns
+        .iter()
+        .map(|column| header.iter().position(|name| name.trim() == *column))
+        .collect();
+    for line in lines {
+        let record = split_record(&line?);
+        let row: Vec<&str> = indices
+            .iter()
+            .map(|index| index.and_then(|i| record.get(i)).map_or("", |f| f.trim()))
+            .collect();
+        on_row(&row);
+    }
+    Ok(())
+}
+
+// Splits a CSV record into its fields, unquoting them ("" being a quote inside quotes)
+fn split_record(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+// Parses a GTFS time ("H:MM:SS" or "HH:MM:SS", past 24h for trips running on after midnight)
+fn parse_time(time: &str) -> Option<u32> {
+    let mut parts = time.split(':').map(|part| part.parse::<u32>().ok());
+    match (parts.next()?, parts.next()?, parts.next()?, parts.next()) {
+        (Some(h), Some(m), Some(s), None) => Some(h * 3600 + m * 60 + s),
+        _ => None,
+    }
+}
+
+// Parses a GTFS date, e.g. "20240720"
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y%m%d").ok()
+}
+
+// GTFS times count from noon minus 12h, which is midnight but on the days the clocks change
+fn service_day_start<Tz: TimeZone>(tz: &Tz, date: NaiveDate) -> Option<i64> {
+    let noon = tz
+        .from_local_datetime(&date.and_hms_opt(12, 0, 0)?)
+        .earliest()?;
+    Some(noon.timestamp() - 12 * 3600)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_extractor::api_config::transport_config::DestinationPoints;
+    use chrono::Utc;
+
+    #[test]
+    fn splits_records() {
+        assert_eq!(split_record("a,b,,c\r"), vec!["a", "b", "", "c"]);
+        assert_eq!(
+            split_record(r#"1,"Lausanne, Flon","say ""hi""""#),
+            vec!["1", "Lausanne, Flon", r#"say "hi""#]
+        );
+        assert_eq!(parse_time("7:05:00"), Some(7 * 3600 + 5 * 60));
+        assert_eq!(parse_time("25:10:30"), Some(25 * 3600 + 10 * 60 + 30));
+        assert_eq!(parse_time(""), None);
+        assert_eq!(parse_time("12:00"), None);
+    }
+
+    #[test]
+    fn reads_departures_from_the_timetable() {
+        let dir = std::env::temp_dir().join(format!("gtfs_static_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = [
+            (
+                "stop_times.txt",
+                "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                 weekday,08:00:00,08:00:00,8588845:0:A,1\n\
+                 weekday,08:10:00,08:10:00,8592050,2\n\
+                 late,24:30:00,24:30:00,8588845,1\n\
+                 late,24:40:00,24:40:00,8592050,2\n\
+                 elsewhere,08:00:00,08:00:00,1,1\n",
+            ),
+            (
+                "trips.txt",
+                "route_id,service_id,trip_id\nr7,weekdays,weekday\nr7,daily,late\n",
+            ),
+            ("routes.txt", "\u{feff}route_id,route_short_name\nr7,7\n"),
+            (
+                "calendar.txt",
+                "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+                 weekdays,1,1,1,1,1,0,0,20240101,20241231\n\
+                 daily,1,1,1,1,1,1,1,20240101,20241231\n",
+            ),
+            (
+                "calendar_dates.txt",
+                "service_id,date,exception_type\nweekdays,20240722,2\n",
+            ),
+        ];
+        for (name, content) in files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        let stop = Stop {
+            stop_id: 8588845,
+            destination_points: vec![DestinationPoints {
+                stops: vec![8592050],
+                destination_name: "FLON".into(),
+                ..Default::default()
+            }],
+            event_type: EventType::Departure.into(),
+            ..Default::default()
+        };
+        let timetable = Timetable::load(&dir, std::slice::from_ref(&stop)).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(timetable.trips.len(), 2);
+
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let seconds = |departures: Vec<Departure>| {
+            let mut seconds: Vec<i64> = departures
+                .iter()
+                .map(|d| d.departure_time.unwrap().seconds)
+                .collect();
+            seconds.sort();
+            seconds
+        };
+        // Friday morning: the weekday trip, then the late one tonight and tomorrow night
+        let mut departures = timetable.departures(&stop, &at("2024-07-19T07:00:00Z"));
+        departures.sort_by_key(|d| d.departure_time.unwrap().seconds);
+        assert_eq!(
+            departures[0],
+            Departure {
+                destination: "FLON".into(),
+                departure_time: Some(Timestamp {
+                    seconds: at("2024-07-19T08:00:00Z").timestamp(),
+                    nanos: 0
+                }),
+                timetabled_time: Some(Timestamp {
+                    seconds: at("2024-07-19T08:00:00Z").timestamp(),
+                    nanos: 0
+                }),
+                line: "7".into(),
+                scheduled_only: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            seconds(departures),
+            vec![
+                at("2024-07-19T08:00:00Z").timestamp(),
+                at("2024-07-20T00:30:00Z").timestamp(),
+                at("2024-07-21T00:30:00Z").timestamp(),
+            ]
+        );
+        // The weekday trip doesn't run on the Monday it's removed from
+        assert_eq!(
+            seconds(timetable.departures(&stop, &at("2024-07-22T07:00:00Z"))),
+            vec![
+                at("2024-07-23T00:30:00Z").timestamp(),
+                at("2024-07-23T08:00:00Z").timestamp(),
+                at("2024-07-24T00:30:00Z").timestamp(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Broadcasts the content hash on the LAN over UDP multicast, so clients only need to make a
+//! gRPC request when the content actually changed.
+//!
+//! A beacon datagram is the `MAGIC` bytes, one byte of schema version, then the hash as a
+//! big-endian u64.
+
+use crate::config_extractor::api_config::Beacon;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use tokio::net::UdpSocket;
+use tokio::time::{Duration, Interval};
+use tracing::{debug, info, warn};
+
+const MAGIC: &[u8; 4] = b"RPSS";
+const BEACON_SIZE: usize = MAGIC.len() + 1 + 8;
+/// Bumped whenever the screen content proto changes in a way clients must know about
+pub const SCHEMA_VERSION: u8 = 2;
+
+#[derive(Debug, PartialEq)]
+pub struct HashBeacon {
+    pub schema_version: u8,
+    pub hash: u64,
+}
+
+impl HashBeacon {
+    fn encode(&self) -> [u8; BEACON_SIZE] {
+        let mut bytes = [0; BEACON_SIZE];
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[4] = self.schema_version;
+        bytes[5..].copy_from_slice(&self.hash.to_be_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if bytes.len() != BEACON_SIZE || &bytes[..4] != MAGIC {
+            return Err(format!("Not a beacon datagram ({} bytes)", bytes.len()).into());
+        }
+        Ok(HashBeacon {
+            schema_version: bytes[4],
+            hash: u64::from_be_bytes(bytes[5..].try_into()?),
+        })
+    }
+}
+
+fn get_multicast_address(config: &Beacon) -> Result<SocketAddrV4, Box<dyn std::error::Error>> {
+    let address: SocketAddrV4 = config.multicast_address.parse()?;
+    if !address.ip().is_multicast() {
+        return Err(format!("{} is not a multicast address", address).into());
+    }
+    Ok(address)
+}
+
+// This is used only in the server binary, so will issue an analyzer warning
+#[allow(dead_code)]
+/// Sends the hash returned by `get_hash` every period, forever (hashes that couldn't be
+/// computed are just skipped)
+pub async fn broadcast(
+    config: &Beacon,
+    get_hash: impl Fn() -> Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let address = get_multicast_address(config)?;
+    let period = config.period.map_or(Ok(Duration::from_secs(5)), |p| {
+        p.seconds.try_into().map(Duration::from_secs)
+    })?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    // Don't leave the LAN
+    socket.set_multicast_ttl_v4(1)?;
+    info!(
+        "Broadcasting the content hash to {} every {:?}",
+        address, period
+    );
+
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let Some(hash) = get_hash() else {
+            continue;
+        };
+        let beacon = HashBeacon {
+            schema_version: SCHEMA_VERSION,
+            hash,
+        };
+        if let Err(e) = socket.send_to(&beacon.encode(), address).await {
+            warn!("Error sending hash beacon: {}", e);
+        }
+    }
+}
+
+// This is only used by the clients, so the server compilation complains that we never use it.
+#[allow(dead_code)]
+/// Joins the beacon's multicast group, to then `receive` beacons on the returned socket
+pub async fn listen(config: &Beacon) -> Result<UdpSocket, Box<dyn std::error::Error>> {
+    let address = get_multicast_address(config)?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, address.port())).await?;
+    socket.join_multicast_v4(*address.ip(), Ipv4Addr::UNSPECIFIED)?;
+    info!("Listening for hash beacons on {}", address);
+    Ok(socket)
+}
+
+// This is only used by the clients, so the server compilation complains that we never use it.
+#[allow(dead_code)]
+/// Waits for the next valid beacon, ignoring unrelated datagrams
+pub async fn receive(socket: &UdpSocket) -> std::io::Result<HashBeacon> {
+    let mut buf = [0; 64];
+    loop {
+        let (size, sender) = socket.recv_from(&mut buf).await?;
+        match HashBeacon::decode(&buf[..size]) {
+            Ok(beacon) => return Ok(beacon),
+            Err(e) => debug!("Ignoring datagram from {}: {}", sender, e),
+        }
+    }
+}
+
+// This is only used by the clients, so the server compilation complains that we never use it.
+#[allow(dead_code)]
+/// Waits for either the next client tick or an incoming beacon, and returns the latest hash we
+/// know of, or `None` if the client should ask the server for it (no beacon socket, or a beacon
+/// with a schema version we don't understand)
+pub async fn wait_for_hash(
+    socket: Option<&UdpSocket>,
+    interval: &mut Interval,
+    current_hash: u64,
+) -> Option<u64> {
+    let Some(socket) = socket else {
+        interval.tick().await;
+        return None;
+    };
+    tokio::select! {
+        // Nothing new, but the client still needs to redraw the time every minute
+        _ = interval.tick() => Some(current_hash),
+        beacon = receive(socket) => match beacon {
+            Ok(beacon) if beacon.schema_version == SCHEMA_VERSION => Some(beacon.hash),
+            Ok(beacon) => {
+                warn!(
+                    "Got a beacon with schema version {} (we know {}), asking the server",
+                    beacon.schema_version, SCHEMA_VERSION
+                );
+                None
+            }
+            Err(e) => {
+                warn!("Error receiving hash beacon: {}", e);
+                Some(current_hash)
+            }
+        },
+    }
+}
+
+// This is only used by the rpi client, so the other compilations complain that we never use it.
+#[allow(dead_code)]
+/// Waits, without any polling, for a beacon announcing a hash other than `current_hash`, and
+/// returns it, or `None` if the client should ask the server for it (unknown schema version)
+pub async fn wait_for_change(socket: &UdpSocket, current_hash: u64) -> Option<u64> {
+    loop {
+        match receive(socket).await {
+            Ok(beacon) if beacon.schema_version != SCHEMA_VERSION => return None,
+            Ok(beacon) if beacon.hash != current_hash => return Some(beacon.hash),
+            Ok(_) => (),
+            Err(e) => {
+                warn!("Error receiving hash beacon: {}", e);
+                // Don't spin on a broken socket
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrips_beacon() {
+        let beacon = HashBeacon {
+            schema_version: 3,
+            hash: 0x0123_4567_89ab_cdef,
+        };
+        assert_eq!(HashBeacon::decode(&beacon.encode()).unwrap(), beacon);
+    }
+
+    #[test]
+    fn rejects_foreign_datagrams() {
+        assert!(HashBeacon::decode(b"").is_err());
+        assert!(HashBeacon::decode(b"XXXX\x01\x00\x00\x00\x00\x00\x00\x00\x00").is_err());
+        assert!(HashBeacon::decode(b"RPSS\x01\x00").is_err());
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_different_hash() {
+        let receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        sender
+            .connect(receiver.local_addr().unwrap())
+            .await
+            .unwrap();
+        for (schema_version, hash) in [(SCHEMA_VERSION, 42), (SCHEMA_VERSION, 43)] {
+            let beacon = HashBeacon {
+                schema_version,
+                hash,
+            };
+            sender.send(&beacon.encode()).await.unwrap();
+        }
+        sender.send(b"noise").await.unwrap();
+        sender
+            .send(
+                &HashBeacon {
+                    schema_version: SCHEMA_VERSION + 1,
+                    hash: 44,
+                }
+                .encode(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(wait_for_change(&receiver, 42).await, Some(43));
+        assert_eq!(wait_for_change(&receiver, 43).await, None);
+    }
+
+    #[test]
+    fn requires_multicast_address() {
+        let config = |address: &str| Beacon {
+            multicast_address: address.into(),
+            period: None,
+        };
+        assert!(get_multicast_address(&config("239.255.42.42:4242")).is_ok());
+        assert!(get_multicast_address(&config("192.168.1.2:4242")).is_err());
+        assert!(get_multicast_address(&config("not an address")).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! The states of a few Home Assistant entities (e.g. a room's temperature, whether the washing
+//! machine runs), shown as text widgets.
+//!
+//! See https://developers.home-assistant.io/docs/api/rest/ for its REST API, which takes a
+//! long-lived access token made in the user's profile.
+
+use crate::config_extractor::api_config::{self, HomeAssistantEntity};
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::TextWidget;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::header::AUTHORIZATION;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub const SOURCE: &str = "home_assistant";
+
+#[derive(Debug)]
+pub struct HomeAssistantUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    url: String,
+    token: String,
+    entities: Vec<HomeAssistantEntity>,
+    home_assistant_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for HomeAssistantUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(43),
+            UpdateMode::Real => Instant::now() + self.home_assistant_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} Home Assistant", self.update_mode);
+        let widgets;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                let washer = match now.minute() < 40 {
+                    true => "on",
+                    false => "off",
+                };
+                widgets = vec![
+                    widget(
+                        "Living",
+                        &format!("{:.1}°C", 20.0 + now.minute() as f32 / 20.0),
+                    ),
+                    widget("Washer", washer),
+                ];
+                error_bit.store(
+                    now.second().is_multiple_of(29),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                widgets = match self.get_widgets().await {
+                    Ok(fetched) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.home_assistant_period.set_success();
+                        fetched
+                    }
+                    Err(e) => {
+                        error!("Error getting the Home Assistant states: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.home_assistant_period.set_error();
+                        vec![]
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::TextWidgets {
+            source: SOURCE,
+            widgets,
+        }]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl HomeAssistantUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let home_assistant_config = config
+            .home_assistant
+            .as_ref()
+            .ok_or("No Home Assistant config")?;
+        if home_assistant_config.url.is_empty() {
+            return Err("No Home Assistant URL".into());
+        }
+        let home_assistant_period_config = Duration::from_secs(
+            home_assistant_config
+                .update_period
+                .as_ref()
+                .ok_or("no Home Assistant update period")?
+                .seconds
+                .try_into()?,
+        );
+        let home_assistant_period = ExponentialBackoff::new(
+            home_assistant_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(HomeAssistantUpdater {
+            update_mode,
+            client,
+            url: home_assistant_config.url.trim_end_matches('/').to_string(),
+            token: home_assistant_config.token.clone(),
+            entities: home_assistant_config.entities.clone(),
+            home_assistant_period,
+        })
+    }
+
+    // One widget per entity, in the config's order
+    async fn get_widgets(&self) -> Result<Vec<TextWidget>, Box<dyn std::error::Error>> {
+        let mut widgets = vec![];
+        for entity in &self.entities {
+            let url = format!("{}/api/states/{}", self.url, entity.entity_id);
+            let body = traced(
+                "fetch",
+                retry_http("Home Assistant fetch", || async {
+                    self.client
+                        .get(&url)
+                        .header(AUTHORIZATION, format!("Bearer {}", self.token))
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .text()
+                        .await
+                }),
+            )
+            .await
+            .map_err(|e| format!("{}: {}", entity.entity_id, e))?;
+            widgets.push(traced_sync("parse", || parse_state(&body, entity))?);
+        }
+        Ok(widgets)
+    }
+}
+
+fn widget(label: &str, text: &str) -> TextWidget {
+    TextWidget {
+        label: label.to_string(),
+        text: text.to_string(),
+        source: SOURCE.to_string(),
+    }
+}
+
+// The entity's state along with its unit (e.g. "21.5°C"), labeled as configured or after the
+// entity's own name
+fn parse_state(
+    body: &str,
+    entity: &HomeAssistantEntity,
+) -> Result<TextWidget, Box<dyn std::error::Error>> {
+    let state: Value = serde_json::from_str(body)?;
+    let text = |pointer: &str| state.pointer(pointer).and_then(Value::as_str);
+    let value = text("/state").ok_or_else(|| format!("No state for {}", entity.entity_id))?;
+    let unit = text("/attributes/unit_of_measurement").unwrap_or_default();
+    let label = match entity.label.is_empty() {
+        false => entity.label.as_str(),
+        true => text("/attributes/friendly_name").unwrap_or(&entity.entity_id),
+    };
+    Ok(widget(label, &format!("{}{}", value, unit)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entity_states() {
+        let entity = |entity_id: &str, label: &str| HomeAssistantEntity {
+            entity_id: entity_id.into(),
+            label: label.into(),
+        };
+        let body = r#"{"entity_id": "sensor.living_room_temperature", "state": "21.5",
+            "attributes": {"unit_of_measurement": "°C", "friendly_name": "Living room"}}"#;
+        let widget = parse_state(body, &entity("sensor.living_room_temperature", "")).unwrap();
+        assert_eq!(
+            (widget.label.as_str(), widget.text.as_str()),
+            ("Living room", "21.5°C")
+        );
+
+        let body = r#"{"entity_id": "binary_sensor.washer", "state": "on", "attributes": {}}"#;
+        let widget = parse_state(body, &entity("binary_sensor.washer", "Washer")).unwrap();
+        assert_eq!(
+            (widget.label.as_str(), widget.text.as_str()),
+            ("Washer", "on")
+        );
+        assert_eq!(widget.source, SOURCE);
+
+        assert!(parse_state(r#"{"message": "Entity not found."}"#, &entity("a.b", "")).is_err());
+    }
+}
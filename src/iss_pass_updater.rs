@@ -0,0 +1,306 @@
+//! Tells when the ISS flies over visibly, as a notice showing from a few minutes before the pass
+//! until it's over, e.g. "ISS 20:41 NW>SE". Notices expire on their own, like the pushed ones.
+//!
+//! The passes come from N2YO's visual passes API (a free key is enough), a couple of days ahead,
+//! so the updater only fetches every update period and otherwise wakes up for the notices.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::Notice;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::{DateTime, Local, Timelike};
+use prost_types::Timestamp;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+const ISS_NORAD_ID: u32 = 25544;
+// How far ahead to ask for passes (N2YO allows up to 10), and how long they must be visible for
+const DAYS_AHEAD: u32 = 2;
+const MIN_VISIBILITY_SECONDS: u32 = 60;
+const DEFAULT_LEAD_MINUTES: u32 = 15;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Pass {
+    // Timestamps
+    start: i64,
+    end: i64,
+    // Where it appears and disappears, e.g. "NW"
+    from: String,
+    to: String,
+}
+
+#[derive(Debug)]
+pub struct IssPassUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    api_key: String,
+    latitude: f64,
+    longitude: f64,
+    altitude: f64,
+    // In seconds, how early before the passes their notice shows
+    lead: i64,
+    passes: Vec<Pass>,
+    // The mode the passes come from
+    passes_mode: UpdateMode,
+    // The start of the last pass announced, not to announce it again
+    announced: Option<i64>,
+    next_fetch: Instant,
+    iss_passes_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for IssPassUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        let now = Local::now().timestamp();
+        let next_notice = self
+            .passes
+            .iter()
+            .filter(|pass| pass.end > now)
+            .find(|pass| {
+                self.announced
+                    .is_none_or(|announced| pass.start > announced)
+            })
+            .map(|pass| {
+                let until = (pass.start - self.lead - now).max(0) as u64;
+                Instant::now() + Duration::from_secs(until)
+            });
+        next_notice.map_or(self.next_fetch, |next_notice| {
+            next_notice.min(self.next_fetch)
+        })
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} ISS passes", self.update_mode);
+        // The other mode's passes don't count
+        if self.passes_mode != self.update_mode {
+            self.passes.clear();
+            self.announced = None;
+            self.next_fetch = Instant::now();
+            self.passes_mode = self.update_mode;
+        }
+        let now = Local::now();
+        if Instant::now() >= self.next_fetch {
+            match self.update_mode {
+                UpdateMode::Dummy => {
+                    // 6 minutes long, 20 minutes into this hour and the next
+                    let hour_start = now.timestamp() - i64::from(now.minute() * 60 + now.second());
+                    self.passes = [hour_start, hour_start + 3600]
+                        .map(|hour| Pass {
+                            start: hour + 20 * 60,
+                            end: hour + 26 * 60,
+                            from: "NW".into(),
+                            to: "SE".into(),
+                        })
+                        .to_vec();
+                    error_bit.store(
+                        now.second().is_multiple_of(59),
+                        std::sync::atomic::Ordering::Relaxed,
+                    );
+                    self.next_fetch = Instant::now() + Duration::from_secs(59);
+                }
+                UpdateMode::Real => {
+                    match self.get_passes().await {
+                        Ok(passes) => {
+                            error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                            self.iss_passes_period.set_success();
+                            self.passes = passes;
+                        }
+                        // The passes fetched before are still good for a while
+                        Err(e) => {
+                            error!("Error getting the ISS passes: {}", e);
+                            error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                            self.iss_passes_period.set_error();
+                        }
+                    }
+                    self.next_fetch =
+                        Instant::now() + self.iss_passes_period.get_current_duration();
+                }
+            }
+        }
+        let notices = get_notices(&self.passes, self.announced, self.lead, now.timestamp());
+        if let Some(last) = notices.last() {
+            self.announced = Some(last.0);
+        }
+        notices
+            .into_iter()
+            .map(|(_, notice)| ContentUpdate::Notice(notice))
+            .collect()
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl IssPassUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let iss_passes_config = config.iss_passes.as_ref().ok_or("No ISS passes config")?;
+        if iss_passes_config.api_key.is_empty() {
+            return Err("No N2YO API key for the ISS passes".into());
+        }
+        let iss_passes_period_config = Duration::from_secs(
+            iss_passes_config
+                .update_period
+                .as_ref()
+                .ok_or("no ISS passes update period")?
+                .seconds
+                .try_into()?,
+        );
+        let iss_passes_period = ExponentialBackoff::new(
+            iss_passes_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        let lead_minutes = iss_passes_config
+            .lead_minutes
+            .unwrap_or(DEFAULT_LEAD_MINUTES);
+        Ok(IssPassUpdater {
+            update_mode,
+            client,
+            api_key: iss_passes_config.api_key.clone(),
+            latitude: iss_passes_config.latitude,
+            longitude: iss_passes_config.longitude,
+            altitude: iss_passes_config.altitude,
+            lead: i64::from(lead_minutes) * 60,
+            passes: vec![],
+            passes_mode: update_mode,
+            announced: None,
+            next_fetch: Instant::now(),
+            iss_passes_period,
+        })
+    }
+
+    async fn get_passes(&self) -> Result<Vec<Pass>, Box<dyn std::error::Error>> {
+        let url = format!(
+            "https://api.n2yo.com/rest/v1/satellite/visualpasses/{}/{}/{}/{}/{}/{}/",
+            ISS_NORAD_ID,
+            self.latitude,
+            self.longitude,
+            self.altitude,
+            DAYS_AHEAD,
+            MIN_VISIBILITY_SECONDS
+        );
+        let body = traced(
+            "fetch",
+            retry_http("ISS passes fetch", || async {
+                self.client
+                    .get(&url)
+                    .query(&[("apiKey", &self.api_key)])
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        traced_sync("parse", || parse_passes(&body))
+    }
+}
+
+fn parse_passes(body: &str) -> Result<Vec<Pass>, Box<dyn std::error::Error>> {
+    let response: Value = serde_json::from_str(body)?;
+    // e.g. a wrong key, still with a 200
+    if let Some(e) = response.get("error").and_then(Value::as_str) {
+        return Err(format!("N2YO error: {}", e).into());
+    }
+    // Left out when there's no pass at all
+    let Some(passes) = response.get("passes").and_then(Value::as_array) else {
+        return Ok(vec![]);
+    };
+    let mut parsed = vec![];
+    for pass in passes {
+        let time = |name: &str| {
+            pass.get(name)
+                .and_then(Value::as_i64)
+                .ok_or_else(|| format!("No {} in the pass: {}", name, pass))
+        };
+        let compass = |name: &str| pass.get(name).and_then(Value::as_str).unwrap_or("?");
+        parsed.push(Pass {
+            start: time("startUTC")?,
+            end: time("endUTC")?,
+            from: compass("startAzCompass").to_string(),
+            to: compass("endAzCompass").to_string(),
+        });
+    }
+    parsed.sort_by_key(|pass| pass.start);
+    Ok(parsed)
+}
+
+// The notices of the passes close enough and not announced yet, along with their starts
+fn get_notices(passes: &[Pass], announced: Option<i64>, lead: i64, now: i64) -> Vec<(i64, Notice)> {
+    passes
+        .iter()
+        .filter(|pass| announced.is_none_or(|announced| pass.start > announced))
+        .filter(|pass| pass.start - lead <= now && now < pass.end)
+        .map(|pass| {
+            let start = DateTime::from_timestamp(pass.start, 0)
+                .map(|start| start.with_timezone(&Local).format("%H:%M").to_string())
+                .unwrap_or_default();
+            let notice = Notice {
+                text: format!("ISS {} {}>{}", start, pass.from, pass.to),
+                expires_at: Some(Timestamp {
+                    seconds: pass.end,
+                    nanos: 0,
+                }),
+            };
+            (pass.start, notice)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announces_passes_ahead() {
+        let body = r#"{"info": {"satid": 25544, "satname": "SPACE STATION",
+            "transactionscount": 4, "passescount": 2}, "passes": [
+            {"startAz": 310.1, "startAzCompass": "NW", "startEl": 10.2, "startUTC": 10000,
+             "maxAz": 35.2, "maxAzCompass": "NE", "maxEl": 62.4, "maxUTC": 10180,
+             "endAz": 120.5, "endAzCompass": "ESE", "endEl": 10.1, "endUTC": 10360,
+             "mag": -3.1, "duration": 360},
+            {"startAz": 250.0, "startAzCompass": "WSW", "startUTC": 5000,
+             "endAz": 150.0, "endAzCompass": "SSE", "endUTC": 5300, "mag": -1.2}]}"#;
+        let passes = parse_passes(body).unwrap();
+        assert_eq!(passes.len(), 2);
+        assert_eq!(
+            passes[1],
+            Pass {
+                start: 10000,
+                end: 10360,
+                from: "NW".into(),
+                to: "ESE".into(),
+            }
+        );
+        assert!(parse_passes(r#"{"info": {"passescount": 0}}"#)
+            .unwrap()
+            .is_empty());
+        assert!(parse_passes(r#"{"error": "Invalid API Key!"}"#).is_err());
+
+        // Too early for the first, then its notice until it's over
+        assert!(get_notices(&passes, None, 900, 4000).is_empty());
+        let notices = get_notices(&passes, None, 900, 4200);
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].0, 5000);
+        assert!(notices[0].1.text.starts_with("ISS "));
+        assert!(notices[0].1.text.ends_with(" WSW>SSE"));
+        assert_eq!(notices[0].1.expires_at.unwrap().seconds, 5300);
+        // Only once
+        assert!(get_notices(&passes, Some(5000), 900, 4300).is_empty());
+        assert_eq!(get_notices(&passes, Some(5000), 900, 9200).len(), 1);
+        // Missed passes aren't announced late
+        assert!(get_notices(&passes, Some(5000), 900, 10400).is_empty());
+    }
+}
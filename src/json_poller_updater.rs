@@ -0,0 +1,305 @@
+//! Values picked out of any JSON API, as text widgets: each source of the config names the values
+//! it wants from its URL's response, and a template puts them together, e.g. "{temp:.1}°C" with
+//! temp at "$.current.temperature_2m".
+//!
+//! Values are found with JSON pointers (e.g. "/current/temperature_2m") or the simple JSONPaths
+//! that translate to one: children by name or index, without wildcards, slices nor filters.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::TextWidget;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::Client;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub const SOURCE: &str = "json_poller";
+// In place of the values the response doesn't have
+const MISSING: &str = "?";
+
+#[derive(Debug)]
+struct Source {
+    url: String,
+    headers: HashMap<String, String>,
+    label: String,
+    template: String,
+    // JSONPaths already turned into pointers, by name
+    pointers: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct JsonPollerUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    sources: Vec<Source>,
+    json_poller_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for JsonPollerUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(43),
+            UpdateMode::Real => Instant::now() + self.json_poller_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} JSON poller", self.update_mode);
+        let mut widgets = vec![];
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                let source = Source {
+                    url: String::new(),
+                    headers: HashMap::new(),
+                    label: "Dummy".into(),
+                    template: "{minute} past, {second:.1}s".into(),
+                    pointers: BTreeMap::from([
+                        ("minute".to_string(), "/time/minute".to_string()),
+                        ("second".to_string(), "/time/second".to_string()),
+                    ]),
+                };
+                let body = serde_json::json!({
+                    "time": {"minute": now.minute(), "second": now.second() as f64}
+                });
+                widgets.push(get_widget(&body.to_string(), &source).unwrap_or_default());
+                error_bit.store(
+                    now.second().is_multiple_of(43),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                // One failing source doesn't hide the others
+                let mut failed = false;
+                for source in &self.sources {
+                    match self.poll(source).await {
+                        Ok(widget) => widgets.push(widget),
+                        Err(e) => {
+                            error!("Error polling {}: {}", source.url, e);
+                            failed = true;
+                        }
+                    }
+                }
+                error_bit.store(failed, std::sync::atomic::Ordering::Relaxed);
+                match failed {
+                    true => self.json_poller_period.set_error(),
+                    false => self.json_poller_period.set_success(),
+                }
+            }
+        }
+        vec![ContentUpdate::TextWidgets {
+            source: SOURCE,
+            widgets,
+        }]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl JsonPollerUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let json_poller_config = config.json_poller.as_ref().ok_or("No JSON poller config")?;
+        if json_poller_config.sources.is_empty() {
+            return Err("No JSON poller sources configured".into());
+        }
+        let json_poller_period_config = Duration::from_secs(
+            json_poller_config
+                .update_period
+                .as_ref()
+                .ok_or("no JSON poller update period")?
+                .seconds
+                .try_into()?,
+        );
+        let json_poller_period = ExponentialBackoff::new(
+            json_poller_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        let mut sources = vec![];
+        for source in &json_poller_config.sources {
+            // Typos in the paths show at startup rather than as "?" on the screen
+            let mut pointers = BTreeMap::new();
+            for (name, path) in &source.values {
+                let pointer = to_json_pointer(path)
+                    .map_err(|e| format!("Bad path for {} of {}: {}", name, source.url, e))?;
+                pointers.insert(name.clone(), pointer);
+            }
+            sources.push(Source {
+                url: source.url.clone(),
+                headers: source.headers.clone(),
+                label: source.label.clone(),
+                template: source.template.clone(),
+                pointers,
+            });
+        }
+        Ok(JsonPollerUpdater {
+            update_mode,
+            client,
+            sources,
+            json_poller_period,
+        })
+    }
+
+    async fn poll(&self, source: &Source) -> Result<TextWidget, Box<dyn std::error::Error>> {
+        let body = traced(
+            "fetch",
+            retry_http("JSON poller fetch", || async {
+                let mut request = self.client.get(&source.url);
+                for (name, value) in &source.headers {
+                    request = request.header(name, value);
+                }
+                request.send().await?.error_for_status()?.text().await
+            }),
+        )
+        .await?;
+        traced_sync("parse", || get_widget(&body, source))
+    }
+}
+
+fn get_widget(body: &str, source: &Source) -> Result<TextWidget, Box<dyn std::error::Error>> {
+    let json: Value = serde_json::from_str(body)?;
+    let value = |name: &str| json.pointer(source.pointers.get(name)?);
+    let text = match source.template.is_empty() {
+        true => source
+            .pointers
+            .keys()
+            .map(|name| value(name).map_or(MISSING.to_string(), |value| to_text(value, None)))
+            .collect::<Vec<String>>()
+            .join(" "),
+        false => fill_template(&source.template, value),
+    };
+    Ok(TextWidget {
+        label: source.label.clone(),
+        text,
+        source: SOURCE.to_string(),
+    })
+}
+
+// Pointers are kept as they are, JSONPaths such as "$.hourly.time[0]" or "$['a.b']" become one
+fn to_json_pointer(path: &str) -> Result<String, String> {
+    if path.is_empty() || path.starts_with('/') {
+        return Ok(path.to_string());
+    }
+    let mut rest = path
+        .strip_prefix('$')
+        .ok_or("neither a JSON pointer nor a JSONPath")?;
+    let mut pointer = String::new();
+    while !rest.is_empty() {
+        let (token, after) = if let Some(child) = rest.strip_prefix('.') {
+            let end = child.find(['.', '[']).unwrap_or(child.len());
+            (&child[..end], &child[end..])
+        } else if let Some(quoted) = rest.strip_prefix("['").or_else(|| rest.strip_prefix("[\"")) {
+            let end = quoted.find(['\'', '"']).ok_or("unclosed quote")?;
+            let after = quoted[end + 1..]
+                .strip_prefix(']')
+                .ok_or("unclosed bracket")?;
+            (&quoted[..end], after)
+        } else if let Some(index) = rest.strip_prefix('[') {
+            let end = index.find(']').ok_or("unclosed bracket")?;
+            (&index[..end], &index[end + 1..])
+        } else {
+            return Err(format!("unexpected {:?}", rest));
+        };
+        if token.is_empty() || token.contains(['*', ':', '?', '(']) {
+            return Err(format!("unsupported step {:?}", token));
+        }
+        pointer.push('/');
+        pointer.push_str(&token.replace('~', "~0").replace('/', "~1"));
+        rest = after;
+    }
+    Ok(pointer)
+}
+
+// Replaces "{name}" with the value of that name, "{name:.2}" rounds numbers to two decimals
+fn fill_template<'a>(template: &str, value: impl Fn(&str) -> Option<&'a Value>) -> String {
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        text.push_str(&rest[..start]);
+        let placeholder = &rest[start + 1..end];
+        let (name, decimals) = match placeholder.split_once(":.") {
+            Some((name, decimals)) => (name, decimals.parse().ok()),
+            None => (placeholder, None),
+        };
+        match value(name) {
+            Some(value) => text.push_str(&to_text(value, decimals)),
+            None => text.push_str(MISSING),
+        }
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    text
+}
+
+fn to_text(value: &Value, decimals: Option<usize>) -> String {
+    match (value, decimals) {
+        (Value::String(text), _) => text.clone(),
+        (Value::Number(number), Some(decimals)) => match number.as_f64() {
+            Some(number) => format!("{:.*}", decimals, number),
+            None => number.to_string(),
+        },
+        (value, _) => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_picked_values() {
+        assert_eq!(
+            to_json_pointer("$.current['temperature_2m']").unwrap(),
+            "/current/temperature_2m"
+        );
+        assert_eq!(
+            to_json_pointer("$.hourly.time[0]").unwrap(),
+            "/hourly/time/0"
+        );
+        assert_eq!(to_json_pointer("$['a/b']").unwrap(), "/a~1b");
+        assert_eq!(
+            to_json_pointer("/already/a/pointer").unwrap(),
+            "/already/a/pointer"
+        );
+        assert!(to_json_pointer("$.items[*].name").is_err());
+        assert!(to_json_pointer("current.temperature").is_err());
+
+        let body = r#"{"current": {"temperature_2m": 12.345, "weather": "cloudy",
+            "wind_speed_10m": 8}}"#;
+        let mut source = Source {
+            url: "https://api.open-meteo.com/v1/forecast".into(),
+            headers: HashMap::new(),
+            label: "Outside".into(),
+            template: "{temp:.1}°C, {weather}, {gusts} km/h {".into(),
+            pointers: BTreeMap::from([
+                ("temp".into(), "/current/temperature_2m".into()),
+                ("weather".into(), "/current/weather".into()),
+                ("gusts".into(), "/current/wind_gusts_10m".into()),
+            ]),
+        };
+        let widget = get_widget(body, &source).unwrap();
+        assert_eq!(widget.label, "Outside");
+        assert_eq!(widget.text, "12.3°C, cloudy, ? km/h {");
+
+        // Without a template, the values in their names' order
+        source.template = String::new();
+        assert_eq!(get_widget(body, &source).unwrap().text, "? 12.345 cloudy");
+    }
+}
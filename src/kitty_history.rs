@@ -0,0 +1,170 @@
+//! Keeps the fetched kitty debts for a week, to tell whether each of them rises or falls.
+//!
+//! The history lives in a flat file (see the kitty config's history_file), one line per debt and
+//! fetch: POSIX time, group, who, whom, currency and amount, tab separated. It's rewritten after
+//! every fetch, which is cheap enough for a week of a few debts.
+
+use crate::screen_service::kitty_debt::Trend;
+use crate::screen_service::KittyDebt;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// How far back debts are kept, and compared with
+pub const HISTORY_LENGTH_SECONDS: i64 = 7 * 24 * 3600;
+
+/// Which debt is which from one fetch to the next: group, who, whom and currency
+pub type DebtKey = (String, String, String, String);
+
+pub fn debt_key(debt: &KittyDebt) -> DebtKey {
+    (
+        debt.group.clone(),
+        debt.who.clone(),
+        debt.whom.clone(),
+        debt.currency.clone(),
+    )
+}
+
+#[derive(Debug)]
+struct Entry {
+    time: i64,
+    key: DebtKey,
+    how_much: f32,
+}
+
+#[derive(Debug, Default)]
+pub struct DebtHistory {
+    // Only kept in memory without one
+    path: Option<PathBuf>,
+    // Oldest first
+    entries: Vec<Entry>,
+}
+
+impl DebtHistory {
+    /// The history saved at `path`, empty if there's none yet (or no path)
+    pub fn load(path: Option<&Path>) -> Self {
+        let Some(path) = path else {
+            return DebtHistory::default();
+        };
+        let entries: Vec<Entry> = match std::fs::read_to_string(path) {
+            Ok(text) => text.lines().filter_map(parse_line).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => {
+                warn!("Couldn't read the kitty history from {:?}: {}", path, e);
+                vec![]
+            }
+        };
+        info!("Loaded {} kitty history entries", entries.len());
+        DebtHistory {
+            path: Some(path.to_path_buf()),
+            entries,
+        }
+    }
+
+    /// Adds the debts fetched at `now` (POSIX time), forgets those older than the history length
+    /// and saves the rest
+    pub fn record(&mut self, debts: &[KittyDebt], now: i64) {
+        self.entries
+            .retain(|entry| entry.time > now - HISTORY_LENGTH_SECONDS);
+        self.entries.extend(debts.iter().map(|debt| Entry {
+            time: now,
+            key: debt_key(debt),
+            how_much: debt.how_much,
+        }));
+        if let Some(path) = &self.path {
+            if let Err(e) = self.save(path) {
+                warn!("Couldn't save the kitty history to {:?}: {}", path, e);
+            }
+        }
+    }
+
+    /// Sets whether each debt rose or fell since the oldest time we have it within the history
+    /// length before `now`. Steady if it didn't change, or if we don't know it yet.
+    pub fn set_trends(&self, debts: &mut [KittyDebt], now: i64) {
+        for debt in debts {
+            let key = debt_key(debt);
+            let oldest = self
+                .entries
+                .iter()
+                .find(|entry| entry.time > now - HISTORY_LENGTH_SECONDS && entry.key == key);
+            let trend = match oldest {
+                Some(entry) if debt.how_much > entry.how_much => Trend::Rising,
+                Some(entry) if debt.how_much < entry.how_much => Trend::Falling,
+                _ => Trend::Steady,
+            };
+            debt.set_trend(trend);
+        }
+    }
+
+    // Through a temporary file, like the content store, so a crash can't leave half a history
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let text: String = self.entries.iter().map(format_line).collect();
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, text)?;
+        std::fs::rename(temp_path, path)
+    }
+}
+
+fn format_line(entry: &Entry) -> String {
+    let (group, who, whom, currency) = &entry.key;
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\n",
+        entry.time, group, who, whom, currency, entry.how_much
+    )
+}
+
+// Skips (but logs) the lines we can't read rather than losing the whole history
+fn parse_line(line: &str) -> Option<Entry> {
+    let parse = || -> Option<Entry> {
+        let [time, group, who, whom, currency, how_much] =
+            <[&str; 6]>::try_from(line.split('\t').collect::<Vec<_>>()).ok()?;
+        Some(Entry {
+            time: time.parse().ok()?,
+            key: (group.into(), who.into(), whom.into(), currency.into()),
+            how_much: how_much.parse().ok()?,
+        })
+    };
+    let entry = parse();
+    if entry.is_none() {
+        warn!("Skipping unreadable kitty history line {:?}", line);
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debt(who: &str, how_much: f32) -> KittyDebt {
+        KittyDebt {
+            who: who.into(),
+            how_much,
+            whom: "Moses".into(),
+            currency: "CHF".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tells_trends_over_the_last_week() {
+        let dir = std::env::temp_dir().join(format!("kitty_history_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kitty_history.tsv");
+        let now = 1_720_000_000;
+
+        let mut history = DebtHistory::load(Some(&path));
+        // Sid owed a lot more than a week ago, that's forgotten
+        history.record(&[debt("Sid", 500.0)], now - HISTORY_LENGTH_SECONDS - 60);
+        history.record(&[debt("Sid", 50.0), debt("Bini", 80.0)], now - 3600);
+        history.record(&[debt("Sid", 60.0), debt("Bini", 40.0)], now - 60);
+
+        // Back from the file, like after a restart
+        std::fs::write(&path, std::fs::read_to_string(&path).unwrap() + "garbage\n").unwrap();
+        let history = DebtHistory::load(Some(&path));
+        assert_eq!(history.entries.len(), 4);
+        let mut debts = vec![debt("Sid", 72.5), debt("Bini", 40.0), debt("Nico", 5.0)];
+        history.set_trends(&mut debts, now);
+        let trends: Vec<Trend> = debts.iter().map(|d| d.trend()).collect();
+        assert_eq!(trends, [Trend::Rising, Trend::Falling, Trend::Steady]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
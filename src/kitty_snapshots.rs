@@ -0,0 +1,129 @@
+//! Keeps the kitty pages we failed to parse, so there's something to reproduce the failure with
+//! when kittysplit changes its markup.
+//!
+//! Snapshots are saved in the kitty config's snapshot_dir, named after when and which kitty they're
+//! from, and only the latest few are kept. Copy one into tests/kitty_snapshots along with the debts
+//! it should give (see below) to have the tests replay it through `extract_debts`.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// How many snapshots we keep when the config doesn't say
+pub const DEFAULT_MAX_SNAPSHOTS: usize = 10;
+// What snapshot names start with, e.g. "20240720T100000_Flat.html"
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+/// Saves the page of the kitty `label` fetched at `now`, then removes the oldest snapshots in `dir`
+/// beyond the `max_snapshots` latest
+pub fn save_snapshot(
+    dir: &Path,
+    label: &str,
+    body: &str,
+    now: DateTime<Utc>,
+    max_snapshots: usize,
+) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    // Sorting by name sorts by time
+    let label = match label.is_empty() {
+        true => "kitty".to_string(),
+        false => label.replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+    };
+    let path = dir.join(format!("{}_{}.html", now.format(TIME_FORMAT), label));
+    std::fs::write(&path, body)?;
+    info!("Saved the kitty page to {:?}", path);
+
+    let mut snapshots = list_snapshots(dir)?;
+    snapshots.sort();
+    let excess = snapshots.len().saturating_sub(max_snapshots);
+    for old in &snapshots[..excess] {
+        if let Err(e) = std::fs::remove_file(old) {
+            warn!("Couldn't remove the old kitty snapshot {:?}: {}", old, e);
+        }
+    }
+    Ok(path)
+}
+
+// The snapshots in `dir`, leaving out whatever else the directory has
+fn list_snapshots(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut snapshots = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(is_snapshot)
+        {
+            snapshots.push(path);
+        }
+    }
+    Ok(snapshots)
+}
+
+// Named like `save_snapshot` names them: the time, an underscore, the label and ".html"
+fn is_snapshot(name: &str) -> bool {
+    let Some((time, label)) = name.split_once('_') else {
+        return false;
+    };
+    NaiveDateTime::parse_from_str(time, TIME_FORMAT).is_ok()
+        && label
+            .strip_suffix(".html")
+            .is_some_and(|label| !label.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kitty_updater::{extract_debts, get_scraping};
+    use chrono::TimeZone;
+
+    #[test]
+    fn keeps_the_latest_snapshots() {
+        let dir = std::env::temp_dir().join(format!("kitty_snapshots_{}", std::process::id()));
+        let at = |minute| Utc.with_ymd_and_hms(2024, 7, 20, 10, minute, 0).unwrap();
+        // Someone else's, in a shared directory
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<body></body>").unwrap();
+        for minute in 0..4 {
+            save_snapshot(&dir, "Flat/Holidays", "<body></body>", at(minute), 3).unwrap();
+        }
+        let mut names: Vec<String> = list_snapshots(&dir)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "20240720T100100_Flat_Holidays.html",
+                "20240720T100200_Flat_Holidays.html",
+                "20240720T100300_Flat_Holidays.html"
+            ]
+        );
+        assert!(dir.join("index.html").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // Each snapshot comes with a .debts file listing the debts it should give, one per line as
+    // "who<TAB>amount<TAB>currency<TAB>whom", and none for pages that should fail to parse
+    #[test]
+    fn replays_saved_snapshots() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/kitty_snapshots");
+        let scraping = get_scraping(None).unwrap();
+        let snapshots = list_snapshots(&dir).unwrap();
+        assert!(!snapshots.is_empty());
+        for snapshot in snapshots {
+            let body = std::fs::read_to_string(&snapshot).unwrap();
+            let expected = std::fs::read_to_string(snapshot.with_extension("debts"))
+                .unwrap_or_else(|_| panic!("No expected debts for {:?}", snapshot));
+            let debts: Vec<String> = extract_debts(&body, &scraping)
+                .unwrap_or_default()
+                .iter()
+                .map(|d| format!("{}\t{}\t{}\t{}", d.who, d.how_much, d.currency, d.whom))
+                .collect();
+            let expected: Vec<&str> = expected.lines().collect();
+            assert_eq!(debts, expected, "Unexpected debts in {:?}", snapshot);
+        }
+    }
+}
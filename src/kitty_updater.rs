@@ -1,48 +1,69 @@
+use crate::config_extractor::api_config;
+use crate::config_extractor::api_config::kitty_config::{Group, Listing, Scraping};
+use crate::config_extractor::api_config::KittyConfig;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
 use crate::exponential_backoff::ExponentialBackoff;
-use crate::screen_service::{KittyDebt, ScreenContentReply};
-use crate::{config_extractor::api_config, data_updater::DataUpdater};
+use crate::kitty_history::{debt_key, DebtHistory, DebtKey};
+use crate::kitty_snapshots::{save_snapshot, DEFAULT_MAX_SNAPSHOTS};
+use crate::retry::retry_http;
+use crate::screen_service::{KittyBalance, KittyDebt};
+use crate::update_tracing::{traced, traced_sync};
 use chrono::Timelike;
-use log::{error, info, warn};
-use reqwest::Client;
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_TYPE, COOKIE};
+use reqwest::{Client, RequestBuilder};
 use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use tokio::time::{Duration, Instant};
-
-#[derive(Debug)]
-// We switch from one to the other for manual testing, but it's actually fine to keep both.
-#[allow(dead_code)]
-pub enum KittyUpdateMode {
-    Dummy,
-    Real,
-}
+use tracing::{error, info, warn};
 
 #[derive(Debug)]
 pub struct KittyUpdater {
-    update_mode: KittyUpdateMode,
+    update_mode: UpdateMode,
     client: Client,
-    kitty_url: String,
+    groups: Vec<Group>,
     kitty_period: ExponentialBackoff,
+    debt_thresholds: HashMap<String, f32>,
+    initials: HashMap<String, String>,
+    scraping: Scraping,
+    // The amounts of the last successful fetch, to tell what changed since
+    previous_debts: Option<HashMap<DebtKey, f32>>,
+    history: DebtHistory,
+    alert_webhook: String,
+    dummy_fixture: String,
+    snapshot_dir: String,
+    max_snapshots: usize,
+    listing: Listing,
+    // Whether the last update raised the alert
+    alerting: bool,
 }
 
 #[tonic::async_trait]
 impl DataUpdater for KittyUpdater {
     fn get_next_update_time(&self) -> Instant {
         match self.update_mode {
-            KittyUpdateMode::Dummy => Instant::now() + Duration::from_secs(19),
-            KittyUpdateMode::Real => Instant::now() + self.kitty_period.get_current_duration(),
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(19),
+            UpdateMode::Real => Instant::now() + self.kitty_period.get_current_duration(),
         }
     }
 
-    async fn update(
-        &mut self,
-        screen_content: &Arc<Mutex<ScreenContentReply>>,
-        error_bit: &Arc<AtomicBool>,
-    ) {
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
         info!("Updating {:?} Kitty", self.update_mode);
-        let debts;
+        let mut debts;
         match self.update_mode {
-            KittyUpdateMode::Dummy => {
+            UpdateMode::Dummy if !self.dummy_fixture.is_empty() => {
+                debts = match self.get_fixture_debts() {
+                    Ok(fixture_debts) => fixture_debts,
+                    Err(e) => {
+                        error!("Error getting Kitty debts from the fixture: {}", e);
+                        vec![]
+                    }
+                };
+                error_bit.store(debts.is_empty(), std::sync::atomic::Ordering::Relaxed);
+            }
+            UpdateMode::Dummy => {
                 let now = chrono::offset::Local::now();
                 let now_seconds =
                     // These have no rigth to fail, since 'sec' is between 0 and 59
@@ -51,43 +72,88 @@ impl DataUpdater for KittyUpdater {
                     who: "foo".into(),
                     how_much: now_seconds,
                     whom: "bar".into(),
+                    group: "Flat".into(),
+                    currency: "CHF".into(),
+                    ..Default::default()
                 }];
                 error_bit.store(now.second() % 8 == 0, std::sync::atomic::Ordering::Relaxed);
             }
-            KittyUpdateMode::Real => {
-                debts = match self.get_debts().await {
-                    Ok(returned_debts) => {
-                        // Make sure the server knows there are no errors
-                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
-                        // And potentially resume normal update cadence
-                        self.kitty_period.set_success();
-                        returned_debts
-                    }
-                    Err(e) => {
-                        error!("Error getting Kitty debts: {}", e);
-                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
-                        self.kitty_period.set_error();
-                        vec![]
+            UpdateMode::Real => {
+                // A kitty that fails doesn't hide the debts of the others
+                let mut all_debts = vec![];
+                let mut failed = false;
+                for group in &self.groups {
+                    match self.get_debts(group).await {
+                        Ok(returned_debts) => all_debts.extend(returned_debts),
+                        Err(e) => {
+                            error!("Error getting Kitty debts of '{}': {}", group.label, e);
+                            failed = true;
+                        }
                     }
                 }
+                if let Some(previous_debts) = &self.previous_debts {
+                    set_deltas(&mut all_debts, previous_debts);
+                }
+                let now = chrono::Utc::now().timestamp();
+                self.history.set_trends(&mut all_debts, now);
+                // Missing debts of a failed group aren't paid off, keep comparing to the last
+                // complete fetch
+                if !failed {
+                    self.history.record(&all_debts, now);
+                    self.previous_debts = Some(
+                        all_debts
+                            .iter()
+                            .map(|d| (debt_key(d), d.how_much))
+                            .collect(),
+                    );
+                }
+                // Let the server know whether there were errors
+                error_bit.store(failed, std::sync::atomic::Ordering::Relaxed);
+                match failed {
+                    true => self.kitty_period.set_error(),
+                    // And potentially resume normal update cadence
+                    false => self.kitty_period.set_success(),
+                }
+                debts = all_debts;
             }
         };
-        match screen_content.lock() {
-            Ok(mut content) => {
-                content.kitty_debts = debts;
-            }
-            Err(e) => error!("Poisoned lock when writing debts: {}", e),
+        set_initials(&mut debts, &self.initials);
+        // Rules step: see if anyone owes more than they should
+        let mut over_threshold = find_debts_over_threshold(&debts, &self.debt_thresholds);
+        over_threshold.extend(find_debts_over_group_threshold(&debts, &self.groups));
+        if !over_threshold.is_empty() {
+            warn!(
+                "Kitty debts over threshold for: {}",
+                over_threshold.join(", ")
+            );
+        }
+        let alert = !over_threshold.is_empty();
+        // Nag once when it starts (until the webhook gets it), the screen keeps showing it anyway
+        self.alerting = match alert && !self.alerting {
+            true => self.send_alert(&over_threshold).await,
+            false => alert,
         };
+        let balances = get_balances(&debts);
+        vec![ContentUpdate::KittyDebts {
+            debts,
+            balances,
+            alert,
+        }]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
     }
 }
 
 impl KittyUpdater {
     pub fn new(
-        update_mode: KittyUpdateMode,
+        update_mode: UpdateMode,
         config: &api_config::ApiConfig,
+        client: Client,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let kitty_config = config.kitty.as_ref().ok_or("No kitty config")?;
-        let kitty_url = kitty_config.url.clone();
+        let groups = get_groups(kitty_config)?;
         let kitty_period_config = Duration::from_secs(
             kitty_config
                 .update_period
@@ -103,40 +169,194 @@ impl KittyUpdater {
         );
         Ok(KittyUpdater {
             update_mode,
-            client: Client::new(),
-            kitty_url,
+            client,
+            groups,
             kitty_period,
+            debt_thresholds: kitty_config.debt_thresholds.clone(),
+            initials: kitty_config.initials.clone(),
+            scraping: get_scraping(kitty_config.scraping.as_ref())?,
+            previous_debts: None,
+            alert_webhook: kitty_config.alert_webhook.clone(),
+            dummy_fixture: kitty_config.dummy_fixture.clone(),
+            snapshot_dir: kitty_config.snapshot_dir.clone(),
+            listing: kitty_config.listing(),
+            max_snapshots: match kitty_config.max_snapshots {
+                Some(max) => max.try_into()?,
+                None => DEFAULT_MAX_SNAPSHOTS,
+            },
+            alerting: false,
+            history: DebtHistory::load(
+                Some(Path::new(&kitty_config.history_file)).filter(|p| !p.as_os_str().is_empty()),
+            ),
         })
     }
 
-    async fn get_debts(&self) -> Result<Vec<KittyDebt>, Box<dyn std::error::Error>> {
-        let body = self
-            .client
-            .get(&self.kitty_url)
+    // The debts of the fixture page, through the same parsing as the fetched ones
+    fn get_fixture_debts(&self) -> Result<Vec<KittyDebt>, Box<dyn std::error::Error>> {
+        let body = std::fs::read_to_string(&self.dummy_fixture)
+            .map_err(|e| format!("Couldn't read {}: {}", self.dummy_fixture, e))?;
+        extract_debts(&body, &self.scraping)
+    }
+
+    // Whether the alert got through, or there's nowhere to send it
+    async fn send_alert(&self, over_threshold: &[String]) -> bool {
+        if self.alert_webhook.is_empty() || self.update_mode == UpdateMode::Dummy {
+            return true;
+        }
+        let text = format!("The kitty needs settling: {}", over_threshold.join(", "));
+        match self
+            .post_alert(text)
             .send()
-            .await?
-            .text()
-            .await?;
+            .await
+            .and_then(|response| response.error_for_status())
+        {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Couldn't call the kitty alert webhook: {}", e);
+                false
+            }
+        }
+    }
+
+    fn post_alert(&self, text: String) -> RequestBuilder {
+        self.client
+            .post(&self.alert_webhook)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::json!({ "text": text }).to_string())
+    }
+
+    // The request for the kitty page, logged in if the group has a session
+    fn get_page(&self, group: &Group) -> RequestBuilder {
+        let mut request = self.client.get(&group.url);
+        if !group.cookie.is_empty() {
+            request = request.header(COOKIE, &group.cookie);
+        }
+        for (name, value) in &group.headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+
+    async fn get_debts(&self, group: &Group) -> Result<Vec<KittyDebt>, Box<dyn std::error::Error>> {
+        let body = traced(
+            "fetch",
+            retry_http("Kitty page fetch", || async {
+                self.get_page(group)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
 
-        extract_debts(&body).map_err(|err| format!("Error parsing Kitty debts: {:?}", err).into())
+        let debts =
+            traced_sync("parse", || extract_debts(&body, &self.scraping)).map_err(|err| {
+                // Keep the page around to reproduce the failure
+                if !self.snapshot_dir.is_empty() {
+                    let dir = Path::new(&self.snapshot_dir);
+                    let now = chrono::Utc::now();
+                    if let Err(e) = save_snapshot(dir, &group.label, &body, now, self.max_snapshots)
+                    {
+                        warn!("Couldn't save the kitty page to {:?}: {}", dir, e);
+                    }
+                }
+                format!("Error parsing Kitty debts: {:?}", err)
+            })?;
+        let debts = match self.listing {
+            Listing::Transactions => debts,
+            Listing::GreedyNetting => net_greedily(&debts),
+        };
+        Ok(debts
+            .into_iter()
+            .map(|debt| KittyDebt {
+                group: group.label.clone(),
+                ..debt
+            })
+            .collect())
+    }
+}
+
+// The kitties to fetch: the configured groups, after the unlabelled one if there's a URL
+fn get_groups(config: &KittyConfig) -> Result<Vec<Group>, Box<dyn std::error::Error>> {
+    let mut groups = vec![];
+    if !config.url.is_empty() {
+        groups.push(Group {
+            label: String::new(),
+            url: config.url.clone(),
+            cookie: config.cookie.clone(),
+            debt_threshold: config.debt_threshold,
+            ..Default::default()
+        });
+    }
+    for group in &config.groups {
+        if group.url.is_empty() {
+            return Err(format!("No URL for kitty group '{}'", group.label).into());
+        }
+        // Better find out now than have every request fail
+        HeaderValue::from_str(&group.cookie)
+            .map_err(|e| format!("Invalid cookie for kitty group '{}': {}", group.label, e))?;
+        for (name, value) in &group.headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("Invalid header name '{}': {}", name, e))?;
+            HeaderValue::from_str(value)
+                .map_err(|e| format!("Invalid value for header '{}': {}", name, e))?;
+        }
+        groups.push(group.clone());
+    }
+    if groups.is_empty() {
+        return Err("No kitty URL nor group".into());
     }
+    Ok(groups)
 }
 
-fn extract_debts(body: &String) -> Result<Vec<KittyDebt>, Box<dyn std::error::Error>> {
+// The configured way to scrape the page, with the defaults for what isn't configured. Fails on
+// invalid selectors, better now than at every update.
+pub fn get_scraping(config: Option<&Scraping>) -> Result<Scraping, Box<dyn std::error::Error>> {
+    let or_default = |configured: Option<&String>, default: &str| match configured {
+        Some(value) if !value.is_empty() => value.clone(),
+        _ => default.to_string(),
+    };
+    let scraping = Scraping {
+        transaction_selector: or_default(
+            config.map(|c| &c.transaction_selector),
+            r#"div[class="transaction-text"]"#,
+        ),
+        currency_selector: or_default(
+            config.map(|c| &c.currency_selector),
+            r#"span[class="currency-symbol"]"#,
+        ),
+        gives_marker: or_default(config.map(|c| &c.gives_marker), " gives "),
+        to_marker: or_default(config.map(|c| &c.to_marker), " to "),
+    };
+    for selector in [&scraping.transaction_selector, &scraping.currency_selector] {
+        Selector::parse(selector)
+            .map_err(|e| format!("Invalid kitty selector '{}': {}", selector, e))?;
+    }
+    Ok(scraping)
+}
+
+pub fn extract_debts(
+    body: &String,
+    scraping: &Scraping,
+) -> Result<Vec<KittyDebt>, Box<dyn std::error::Error>> {
     // Parse the body into a tree structure, returning on parsing errors
     let parsed_body = Html::parse_document(&body);
 
     // Select elements like '<div class="transaction-text">'
-    let transaction_selector = Selector::parse(r#"div[class="transaction-text"]"#)?;
+    let transaction_selector =
+        Selector::parse(&scraping.transaction_selector).map_err(|e| e.to_string())?;
+    let transactions: Vec<ElementRef> = parsed_body.select(&transaction_selector).collect();
     // Try to extract a proper debt from each of them, discarding failures (but logging them)
-    let debts: Vec<KittyDebt> = parsed_body
-        .select(&transaction_selector)
+    let debts: Vec<KittyDebt> = transactions
+        .iter()
         .filter_map(|t| {
-            extract_debt(&t)
+            extract_debt(t, scraping)
                 .inspect_err(|e| {
                     warn!(
-                        "Error extracting a debt from a 'transaction-text' div: {}",
-                        e
+                        "Error extracting a debt from a '{}' element: {}",
+                        scraping.transaction_selector, e
                     )
                 })
                 .ok()
@@ -145,8 +365,25 @@ fn extract_debts(body: &String) -> Result<Vec<KittyDebt>, Box<dyn std::error::Er
 
     // Error out if we didn't find a single debt (there should really always be two)
     if debts.is_empty() {
+        // Private kitties show a login form to those without a (valid) session
+        let password_selector = Selector::parse(r#"input[type="password"]"#)?;
+        if parsed_body.select(&password_selector).next().is_some() {
+            return Err("Got a login page, the kitty is private: set (or renew) its cookie".into());
+        }
+        // Tell which part of the scraping config no longer fits the page
+        if !transactions.is_empty() {
+            return Err(format!(
+                "No debts in the {} elements matching '{}' (markers '{}' and '{}')",
+                transactions.len(),
+                scraping.transaction_selector,
+                scraping.gives_marker,
+                scraping.to_marker
+            )
+            .into());
+        }
         return Err(format!(
-            "No debts found on page (body size: {}, parse errors: [{}])",
+            "No debts found on page, nothing matches '{}' (body size: {}, parse errors: [{}])",
+            scraping.transaction_selector,
             body.len(),
             parsed_body.errors.join(", ")
         )
@@ -156,14 +393,161 @@ fn extract_debts(body: &String) -> Result<Vec<KittyDebt>, Box<dyn std::error::Er
     Ok(debts)
 }
 
-fn extract_debt(element: &ElementRef) -> Result<KittyDebt, Box<dyn std::error::Error>> {
+// The configured initial of a person, or the first letter of their name
+fn get_initial(name: &str, initials: &HashMap<String, String>) -> String {
+    match initials.get(name) {
+        Some(initial) => initial.clone(),
+        None => name.chars().next().unwrap_or('?').to_string(),
+    }
+}
+
+fn set_initials(debts: &mut [KittyDebt], initials: &HashMap<String, String>) {
+    for debt in debts {
+        debt.who_initial = get_initial(&debt.who, initials);
+        debt.whom_initial = get_initial(&debt.whom, initials);
+    }
+}
+
+// Nets out who gives what to whom, in each currency, e.g. "S:-108 M:+150" rather than each debt.
+// Sorted by name, with the initials of the debts.
+fn get_balances(debts: &[KittyDebt]) -> Vec<KittyBalance> {
+    let mut balances = HashMap::<(&str, &str), KittyBalance>::new();
+    for debt in debts {
+        let sides = [
+            (&debt.who, &debt.who_initial, -debt.how_much),
+            (&debt.whom, &debt.whom_initial, debt.how_much),
+        ];
+        for (who, initial, amount) in sides {
+            balances
+                .entry((who, &debt.currency))
+                .or_insert_with(|| KittyBalance {
+                    who: who.clone(),
+                    balance: 0.0,
+                    currency: debt.currency.clone(),
+                    initial: initial.clone(),
+                })
+                .balance += amount;
+        }
+    }
+    let mut balances: Vec<KittyBalance> = balances.into_values().collect();
+    balances.sort_by(|a, b| (&a.who, &a.currency).cmp(&(&b.who, &b.currency)));
+    balances
+}
+
+// Transfers settling the same balances as the debts, netted greedily: in each currency, the one who
+// owes the most pays the one owed the most, until everyone's even. Usually fewer rows than the
+// debts, but not necessarily the fewest possible.
+fn net_greedily(debts: &[KittyDebt]) -> Vec<KittyDebt> {
+    let balances = get_balances(debts);
+    let mut currencies: Vec<&str> = balances.iter().map(|b| b.currency.as_str()).collect();
+    currencies.sort();
+    currencies.dedup();
+    let mut transfers = vec![];
+    for currency in currencies {
+        let mut balances: Vec<(String, f32)> = balances
+            .iter()
+            .filter(|b| b.currency == currency)
+            .map(|b| (b.who.clone(), b.balance))
+            .collect();
+        loop {
+            let by_balance = |a: &&(String, f32), b: &&(String, f32)| a.1.total_cmp(&b.1);
+            let (Some(debtor), Some(creditor)) = (
+                balances.iter().min_by(by_balance).cloned(),
+                balances.iter().max_by(by_balance).cloned(),
+            ) else {
+                break;
+            };
+            let how_much = f32::min(-debtor.1, creditor.1);
+            // Down to the cent, floats don't net out exactly
+            if how_much < 0.01 {
+                break;
+            }
+            transfers.push(KittyDebt {
+                who: debtor.0.clone(),
+                how_much,
+                whom: creditor.0.clone(),
+                currency: currency.to_string(),
+                ..Default::default()
+            });
+            for (who, balance) in balances.iter_mut() {
+                if *who == debtor.0 {
+                    *balance += how_much;
+                } else if *who == creditor.0 {
+                    *balance -= how_much;
+                }
+            }
+        }
+    }
+    transfers
+}
+
+// Sets how much each debt changed since the previous fetch, new debts changed by their whole amount
+fn set_deltas(debts: &mut [KittyDebt], previous_debts: &HashMap<DebtKey, f32>) {
+    for debt in debts {
+        let previous = previous_debts.get(&debt_key(debt)).copied().unwrap_or(0.0);
+        debt.delta = debt.how_much - previous;
+    }
+}
+
+// Sums up what each person owes across all debts in each currency, and returns the (sorted) names
+// of those with a total above their configured threshold. People without a threshold never alert.
+fn find_debts_over_threshold(
+    debts: &[KittyDebt],
+    thresholds: &HashMap<String, f32>,
+) -> Vec<String> {
+    // Francs and euros don't add up
+    let mut totals = HashMap::<(&str, &str), f32>::new();
+    for debt in debts {
+        *totals.entry((&debt.who, &debt.currency)).or_default() += debt.how_much;
+    }
+    let mut over_threshold: Vec<String> = totals
+        .into_iter()
+        .filter(|((who, _), total)| thresholds.get(*who).is_some_and(|limit| total > limit))
+        .map(|((who, _), _)| who.to_string())
+        .collect();
+    over_threshold.sort();
+    over_threshold.dedup();
+    over_threshold
+}
+
+// Describes the debts above the threshold of their kitty, e.g. "Sid>Moses 150 CHF (Holidays)"
+fn find_debts_over_group_threshold(debts: &[KittyDebt], groups: &[Group]) -> Vec<String> {
+    debts
+        .iter()
+        .filter(|debt| {
+            groups.iter().any(|group| {
+                group.label == debt.group
+                    && group.debt_threshold > 0.0
+                    && debt.how_much > group.debt_threshold
+            })
+        })
+        .map(|debt| {
+            let group = match debt.group.is_empty() {
+                true => String::new(),
+                false => format!(" ({})", debt.group),
+            };
+            format!(
+                "{}>{} {} {}{}",
+                debt.who, debt.whom, debt.how_much as i32, debt.currency, group
+            )
+        })
+        .collect()
+}
+
+fn extract_debt(
+    element: &ElementRef,
+    scraping: &Scraping,
+) -> Result<KittyDebt, Box<dyn std::error::Error>> {
     let all_texts = element.text().collect::<Vec<_>>();
 
     let who_text = all_texts
         .iter()
-        .find(|t| t.contains(" gives "))
-        .ok_or("no text node contained 'gives'")?
-        .replace(" gives ", "");
+        .find(|t| t.contains(&scraping.gives_marker))
+        .ok_or(format!(
+            "no text node contained '{}'",
+            scraping.gives_marker
+        ))?
+        .replace(&scraping.gives_marker, "");
     let who = who_text.trim().to_string();
     let how_much = all_texts
         .iter()
@@ -171,10 +555,18 @@ fn extract_debt(element: &ElementRef) -> Result<KittyDebt, Box<dyn std::error::E
         .ok_or("no text field was parseable into a float")?;
     let whom_text = all_texts
         .iter()
-        .find(|t| t.contains(" to "))
-        .ok_or("no text node contained 'to'")?
-        .replace(" to ", "");
+        .find(|t| t.contains(&scraping.to_marker))
+        .ok_or(format!("no text node contained '{}'", scraping.to_marker))?
+        .replace(&scraping.to_marker, "");
     let whom = whom_text.trim().to_string();
+    // Like '<span class="currency-symbol">CHF</span>', next to the amount
+    let currency_selector =
+        Selector::parse(&scraping.currency_selector).map_err(|e| e.to_string())?;
+    let currency = element
+        .select(&currency_selector)
+        .next()
+        .map(|symbol| symbol.text().collect::<String>().trim().to_string())
+        .unwrap_or_default();
 
     if who.is_empty() || whom.is_empty() {
         return Err(format!("either who ('{}') or whom ('{}') was empty", who, whom).into());
@@ -183,6 +575,8 @@ fn extract_debt(element: &ElementRef) -> Result<KittyDebt, Box<dyn std::error::E
         who,
         how_much,
         whom,
+        currency,
+        ..Default::default()
     })
 }
 
@@ -212,8 +606,13 @@ mod tests {
             who: "Sid".into(),
             how_much: 72.5,
             whom: "Moses".into(),
+            currency: "CHF".into(),
+            ..Default::default()
         };
-        assert_eq!(extract_debts(&body).unwrap(), vec![expected]);
+        assert_eq!(
+            extract_debts(&body, &get_scraping(None).unwrap()).unwrap(),
+            vec![expected]
+        );
     }
 
     #[test]
@@ -236,7 +635,7 @@ mod tests {
             <i class="fa-icon fas fa-certificate text-muted " aria-hidden="true"></i>
         </div>
         <div class="transaction-text">
-            Bini gives <span class="currency"><span class="currency-symbol">CHF</span>137.94</span> to Moses
+            Bini gives <span class="currency"><span class="currency-symbol">EUR</span>137.94</span> to Moses
         </div>
         <div class="transaction-action">
         </div>
@@ -248,28 +647,407 @@ mod tests {
             who: "Sid".into(),
             how_much: 72.5,
             whom: "Moses".into(),
+            currency: "CHF".into(),
+            ..Default::default()
         };
         let expected_two = KittyDebt {
             who: "Bini".into(),
             how_much: 137.94,
             whom: "Moses".into(),
+            currency: "EUR".into(),
+            ..Default::default()
         };
         assert_eq!(
-            extract_debts(&body).unwrap(),
+            extract_debts(&body, &get_scraping(None).unwrap()).unwrap(),
             vec![expected_one, expected_two]
         );
     }
 
+    #[test]
+    fn alerts_on_aggregate_debt_over_threshold() {
+        let debts = vec![
+            KittyDebt {
+                who: "Sid".into(),
+                how_much: 60.0,
+                whom: "Moses".into(),
+                ..Default::default()
+            },
+            KittyDebt {
+                who: "Sid".into(),
+                how_much: 50.0,
+                whom: "Bini".into(),
+                ..Default::default()
+            },
+            KittyDebt {
+                who: "Bini".into(),
+                how_much: 500.0,
+                whom: "Moses".into(),
+                ..Default::default()
+            },
+            KittyDebt {
+                who: "Moses".into(),
+                how_much: 10.0,
+                whom: "Bini".into(),
+                ..Default::default()
+            },
+        ];
+        // Sid is over only when summing both debts, Bini has no threshold, Moses is under
+        let thresholds = HashMap::from([("Sid".to_string(), 100.0), ("Moses".to_string(), 20.0)]);
+        assert_eq!(
+            find_debts_over_threshold(&debts, &thresholds),
+            vec!["Sid".to_string()]
+        );
+        assert!(find_debts_over_threshold(&debts, &HashMap::new()).is_empty());
+
+        // Unless they're in different currencies
+        let mut mixed = debts;
+        mixed[1].currency = "EUR".into();
+        assert!(find_debts_over_threshold(&mixed, &thresholds).is_empty());
+    }
+
+    #[test]
+    fn tells_changed_debts() {
+        let debt = |who: &str, how_much: f32| KittyDebt {
+            who: who.into(),
+            how_much,
+            whom: "Moses".into(),
+            currency: "CHF".into(),
+            ..Default::default()
+        };
+        let previous_debts = HashMap::from([
+            (debt_key(&debt("Sid", 0.0)), 72.5),
+            (debt_key(&debt("Bini", 0.0)), 10.0),
+        ]);
+        let mut debts = vec![debt("Sid", 72.5), debt("Bini", 25.0), debt("Nico", 5.0)];
+        set_deltas(&mut debts, &previous_debts);
+        let deltas: Vec<f32> = debts.iter().map(|d| d.delta).collect();
+        assert_eq!(deltas, [0.0, 15.0, 5.0]);
+    }
+
+    #[test]
+    fn tells_flatmates_apart() {
+        let initials = HashMap::from([("Sam".to_string(), "Sa".to_string())]);
+        let mut debts = vec![KittyDebt {
+            who: "Sam".into(),
+            how_much: 12.0,
+            whom: "Sid".into(),
+            ..Default::default()
+        }];
+        set_initials(&mut debts, &initials);
+        assert_eq!(debts[0].who_initial, "Sa");
+        assert_eq!(debts[0].whom_initial, "S");
+        assert_eq!(get_initial("", &initials), "?");
+    }
+
+    #[test]
+    fn nets_out_balances() {
+        let debt = |who: &str, how_much: f32, whom: &str, currency: &str| KittyDebt {
+            who: who.into(),
+            how_much,
+            whom: whom.into(),
+            currency: currency.into(),
+            who_initial: who[..1].into(),
+            whom_initial: whom[..1].into(),
+            ..Default::default()
+        };
+        let debts = vec![
+            debt("Sid", 108.0, "Moses", "CHF"),
+            debt("Bini", 42.0, "Moses", "CHF"),
+            debt("Moses", 10.0, "Sid", "EUR"),
+        ];
+        let balance = |who: &str, balance: f32, currency: &str| KittyBalance {
+            who: who.into(),
+            balance,
+            currency: currency.into(),
+            initial: who[..1].into(),
+        };
+        assert_eq!(
+            get_balances(&debts),
+            vec![
+                balance("Bini", -42.0, "CHF"),
+                balance("Moses", 150.0, "CHF"),
+                balance("Moses", -10.0, "EUR"),
+                balance("Sid", -108.0, "CHF"),
+                balance("Sid", 10.0, "EUR"),
+            ]
+        );
+    }
+
+    #[test]
+    fn nets_debts_greedily() {
+        let debt = |who: &str, how_much: f32, whom: &str, currency: &str| KittyDebt {
+            who: who.into(),
+            how_much,
+            whom: whom.into(),
+            currency: currency.into(),
+            ..Default::default()
+        };
+        let debts = vec![
+            debt("Sid", 50.0, "Moses", "CHF"),
+            debt("Moses", 30.0, "Bini", "CHF"),
+            debt("Bini", 10.0, "Sid", "CHF"),
+            debt("Sid", 20.0, "Bini", "CHF"),
+            debt("Moses", 12.5, "Sid", "EUR"),
+        ];
+        // Sid owes 60, Moses is owed 20 and Bini 40
+        assert_eq!(
+            net_greedily(&debts),
+            vec![
+                debt("Sid", 40.0, "Bini", "CHF"),
+                debt("Sid", 20.0, "Moses", "CHF"),
+                debt("Moses", 12.5, "Sid", "EUR"),
+            ]
+        );
+        assert!(net_greedily(&[
+            debt("Sid", 5.0, "Bini", "CHF"),
+            debt("Bini", 5.0, "Sid", "CHF")
+        ])
+        .is_empty());
+    }
+
+    #[test]
+    fn fetches_the_url_and_groups() {
+        let group = |label: &str, url: &str| Group {
+            label: label.into(),
+            url: url.into(),
+            ..Default::default()
+        };
+        let config = KittyConfig {
+            url: "https://kittysplit.com/flat".into(),
+            groups: vec![group("Holidays", "https://kittysplit.com/holidays")],
+            ..Default::default()
+        };
+        assert_eq!(
+            get_groups(&config).unwrap(),
+            vec![
+                group("", "https://kittysplit.com/flat"),
+                group("Holidays", "https://kittysplit.com/holidays")
+            ]
+        );
+        let groups_only = KittyConfig {
+            url: String::new(),
+            ..config.clone()
+        };
+        assert_eq!(get_groups(&groups_only).unwrap().len(), 1);
+        assert!(get_groups(&KittyConfig::default()).is_err());
+        let no_url = KittyConfig {
+            groups: vec![group("Holidays", "")],
+            ..config
+        };
+        assert!(get_groups(&no_url).is_err());
+    }
+
+    #[test]
+    fn logs_into_private_kitties() {
+        let config = api_config::ApiConfig {
+            kitty: Some(KittyConfig {
+                update_period: Some(pbjson_types::Duration {
+                    seconds: 600,
+                    nanos: 0,
+                }),
+                url: "https://kittysplit.com/flat".into(),
+                cookie: "PHPSESSID=abc123".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let updater = KittyUpdater::new(UpdateMode::Real, &config, Client::new()).unwrap();
+        let request = updater.get_page(&updater.groups[0]).build().unwrap();
+        assert_eq!(request.headers()[COOKIE], "PHPSESSID=abc123");
+
+        let group = Group {
+            url: "https://kittysplit.com/holidays".into(),
+            headers: HashMap::from([("X-Token".to_string(), "secret".to_string())]),
+            ..Default::default()
+        };
+        let request = updater.get_page(&group).build().unwrap();
+        assert_eq!(request.headers().get(COOKIE), None);
+        assert_eq!(request.headers()["x-token"], "secret");
+
+        let broken = KittyConfig {
+            groups: vec![Group {
+                headers: HashMap::from([("X Token".to_string(), "secret".to_string())]),
+                ..group
+            }],
+            ..config.kitty.unwrap()
+        };
+        assert!(get_groups(&broken).is_err());
+    }
+
+    #[test]
+    fn alerts_on_debts_over_their_kitty_threshold() {
+        let debt = |who: &str, how_much: f32, group: &str| KittyDebt {
+            who: who.into(),
+            how_much,
+            whom: "Moses".into(),
+            currency: "CHF".into(),
+            group: group.into(),
+            ..Default::default()
+        };
+        let debts = vec![
+            debt("Sid", 150.0, "Holidays"),
+            debt("Bini", 150.0, ""),
+            debt("Sid", 50.0, ""),
+        ];
+        let groups = vec![
+            Group {
+                debt_threshold: 100.0,
+                ..Default::default()
+            },
+            Group {
+                label: "Holidays".into(),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            find_debts_over_group_threshold(&debts, &groups),
+            vec!["Bini>Moses 150 CHF".to_string()]
+        );
+
+        let config = api_config::ApiConfig {
+            kitty: Some(KittyConfig {
+                update_period: Some(pbjson_types::Duration {
+                    seconds: 600,
+                    nanos: 0,
+                }),
+                url: "https://kittysplit.com/flat".into(),
+                alert_webhook: "https://chat.example/hooks/kitty".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let updater = KittyUpdater::new(UpdateMode::Real, &config, Client::new()).unwrap();
+        let request = updater.post_alert("Pay up".into()).build().unwrap();
+        assert_eq!(
+            request.body().unwrap().as_bytes(),
+            Some(r#"{"text":"Pay up"}"#.as_bytes())
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_undelivered_alerts() {
+        let config = api_config::ApiConfig {
+            kitty: Some(KittyConfig {
+                update_period: Some(pbjson_types::Duration {
+                    seconds: 600,
+                    nanos: 0,
+                }),
+                url: "https://kittysplit.com/flat".into(),
+                // Nothing listens on the discard port
+                alert_webhook: "http://127.0.0.1:9/hooks/kitty".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let over_threshold = vec!["Sid".to_string()];
+        let updater = KittyUpdater::new(UpdateMode::Real, &config, Client::new()).unwrap();
+        assert!(!updater.send_alert(&over_threshold).await);
+        // The dummy mode doesn't nag anyone
+        let updater = KittyUpdater::new(UpdateMode::Dummy, &config, Client::new()).unwrap();
+        assert!(updater.send_alert(&over_threshold).await);
+    }
+
+    #[tokio::test]
+    async fn parses_the_dummy_fixture() {
+        let dir = std::env::temp_dir().join(format!("kitty_fixture_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kitty.html");
+        std::fs::write(
+            &path,
+            r#"<div class="transaction-text">Sid gives <span class="currency"><span class="currency-symbol">CHF</span>72.50</span> to Moses</div>"#,
+        )
+        .unwrap();
+        let config = api_config::ApiConfig {
+            kitty: Some(KittyConfig {
+                update_period: Some(pbjson_types::Duration {
+                    seconds: 600,
+                    nanos: 0,
+                }),
+                url: "https://kittysplit.com/flat".into(),
+                dummy_fixture: path.to_string_lossy().into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut updater = KittyUpdater::new(UpdateMode::Dummy, &config, Client::new()).unwrap();
+        let error_bit = Arc::new(AtomicBool::new(true));
+        let updates = updater.update(&error_bit).await;
+        let ContentUpdate::KittyDebts { debts, .. } = &updates[0] else {
+            panic!("Expected kitty debts, got {:?}", updates);
+        };
+        assert_eq!(debts.len(), 1);
+        assert_eq!(debts[0].who, "Sid");
+        assert_eq!(debts[0].who_initial, "S");
+        assert!(!error_bit.load(std::sync::atomic::Ordering::Relaxed));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(updater.get_fixture_debts().is_err());
+    }
+
+    #[test]
+    fn tells_login_pages() {
+        let body =
+            r#"<body><form><input type="email"><input type="password"></form></body>"#.into();
+        let err = extract_debts(&body, &get_scraping(None).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("login page"));
+    }
+
+    #[test]
+    fn scrapes_as_configured() {
+        let body = r#"
+<body>
+<p class="debt">Sid donne <b class="money">EUR</b><span>12.50</span> à Moses</p>
+</body>
+"#
+        .into();
+        let french = Scraping {
+            transaction_selector: "p.debt".into(),
+            currency_selector: "b.money".into(),
+            gives_marker: " donne ".into(),
+            to_marker: " à ".into(),
+        };
+        let expected = KittyDebt {
+            who: "Sid".into(),
+            how_much: 12.5,
+            whom: "Moses".into(),
+            currency: "EUR".into(),
+            ..Default::default()
+        };
+        let scraping = get_scraping(Some(&french)).unwrap();
+        assert_eq!(extract_debts(&body, &scraping).unwrap(), vec![expected]);
+
+        // Errors say which part of the config doesn't fit
+        let default = get_scraping(None).unwrap();
+        let err = extract_debts(&body, &default).unwrap_err().to_string();
+        assert!(err.contains(r#"nothing matches 'div[class="transaction-text"]'"#));
+        let english_markers = get_scraping(Some(&Scraping {
+            gives_marker: String::new(),
+            to_marker: String::new(),
+            ..french.clone()
+        }))
+        .unwrap();
+        let err = extract_debts(&body, &english_markers)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("No debts in the 1 elements matching 'p.debt'"));
+
+        let broken = Scraping {
+            transaction_selector: "p[".into(),
+            ..french
+        };
+        assert!(get_scraping(Some(&broken)).is_err());
+    }
+
     #[test]
     fn doesnt_panic_on_garbled_input() {
         let body = "\\<".into();
-        assert!(extract_debts(&body).is_err());
+        assert!(extract_debts(&body, &get_scraping(None).unwrap()).is_err());
     }
 
     #[test]
     fn doesnt_panic_on_empty_page() {
         let body = "".into();
-        assert!(extract_debts(&body).is_err());
+        assert!(extract_debts(&body, &get_scraping(None).unwrap()).is_err());
     }
 
     #[test]
@@ -295,6 +1073,6 @@ mod tests {
 </body>
 "#
         .into();
-        assert!(extract_debts(&body).is_err());
+        assert!(extract_debts(&body, &get_scraping(None).unwrap()).is_err());
     }
 }
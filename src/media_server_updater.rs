@@ -0,0 +1,233 @@
+//! What a Jellyfin or Plex server is playing, as a "Playing" text widget per session.
+//!
+//! Jellyfin lists its sessions at /Sessions (see https://api.jellyfin.org), with an API key made
+//! in its dashboard. Plex lists them at /status/sessions, with the account's X-Plex-Token.
+
+use crate::config_extractor::api_config;
+use crate::config_extractor::api_config::media_server_config::Kind;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::TextWidget;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::header::ACCEPT;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub const SOURCE: &str = "media_server";
+const LABEL: &str = "Playing";
+
+#[derive(Debug)]
+pub struct MediaServerUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    kind: Kind,
+    url: String,
+    token: String,
+    media_server_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for MediaServerUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(47),
+            UpdateMode::Real => Instant::now() + self.media_server_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} media server", self.update_mode);
+        let widgets;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // A cartoon for the first half of every hour
+                widgets = match now.minute() < 30 {
+                    true => vec![widget("Bluey - Sleepytime".into())],
+                    false => vec![],
+                };
+                error_bit.store(
+                    now.second().is_multiple_of(37),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                widgets = match self.get_playing().await {
+                    Ok(titles) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.media_server_period.set_success();
+                        titles.into_iter().map(widget).collect()
+                    }
+                    Err(e) => {
+                        error!("Error getting the media server sessions: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.media_server_period.set_error();
+                        vec![]
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::TextWidgets {
+            source: SOURCE,
+            widgets,
+        }]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl MediaServerUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let media_server_config = config
+            .media_server
+            .as_ref()
+            .ok_or("No media server config")?;
+        if media_server_config.url.is_empty() {
+            return Err("No media server URL".into());
+        }
+        let media_server_period_config = Duration::from_secs(
+            media_server_config
+                .update_period
+                .as_ref()
+                .ok_or("no media server update period")?
+                .seconds
+                .try_into()?,
+        );
+        let media_server_period = ExponentialBackoff::new(
+            media_server_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(MediaServerUpdater {
+            update_mode,
+            client,
+            kind: media_server_config.kind(),
+            url: media_server_config.url.trim_end_matches('/').to_string(),
+            token: media_server_config.token.clone(),
+            media_server_period,
+        })
+    }
+
+    // The titles of what's playing (not paused) right now
+    async fn get_playing(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let (path, token_header) = match self.kind {
+            Kind::Jellyfin => ("/Sessions", "X-Emby-Token"),
+            Kind::Plex => ("/status/sessions", "X-Plex-Token"),
+        };
+        let url = format!("{}{}", self.url, path);
+        let body = traced(
+            "fetch",
+            retry_http("Media server fetch", || async {
+                self.client
+                    .get(&url)
+                    .header(token_header, &self.token)
+                    // Plex answers in XML otherwise
+                    .header(ACCEPT, "application/json")
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        traced_sync("parse", || match self.kind {
+            Kind::Jellyfin => parse_jellyfin_sessions(&body),
+            Kind::Plex => parse_plex_sessions(&body),
+        })
+    }
+}
+
+fn widget(title: String) -> TextWidget {
+    TextWidget {
+        label: LABEL.to_string(),
+        text: title,
+        source: SOURCE.to_string(),
+    }
+}
+
+// e.g. "Bluey - Sleepytime" for episodes (or tracks, with their artist), just the name otherwise
+fn with_parent(parent: Option<&str>, name: &str) -> String {
+    match parent.filter(|parent| !parent.is_empty()) {
+        Some(parent) => format!("{} - {}", parent, name),
+        None => name.to_string(),
+    }
+}
+
+// The sessions list all the clients, only those with a NowPlayingItem are playing
+fn parse_jellyfin_sessions(body: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let sessions: Vec<Value> = serde_json::from_str(body)?;
+    Ok(sessions
+        .iter()
+        .filter(|session| session.pointer("/PlayState/IsPaused") != Some(&Value::Bool(true)))
+        .filter_map(|session| {
+            let item = session.get("NowPlayingItem")?;
+            let parent = item
+                .get("SeriesName")
+                .or_else(|| item.get("AlbumArtist"))
+                .and_then(Value::as_str);
+            Some(with_parent(parent, item.get("Name")?.as_str()?))
+        })
+        .collect())
+}
+
+fn parse_plex_sessions(body: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let sessions: Value = serde_json::from_str(body)?;
+    let container = sessions
+        .get("MediaContainer")
+        .ok_or("No MediaContainer in the sessions")?;
+    // Plex leaves the list out when there's nothing
+    let Some(items) = container.get("Metadata").and_then(Value::as_array) else {
+        return Ok(vec![]);
+    };
+    Ok(items
+        .iter()
+        .filter(|item| item.pointer("/Player/state").and_then(Value::as_str) != Some("paused"))
+        .filter_map(|item| {
+            let parent = item.get("grandparentTitle").and_then(Value::as_str);
+            Some(with_parent(parent, item.get("title")?.as_str()?))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sessions() {
+        let jellyfin = r#"[
+            {"UserName": "kids", "DeviceName": "Living room TV",
+             "NowPlayingItem": {"Name": "Sleepytime", "SeriesName": "Bluey", "Type": "Episode"},
+             "PlayState": {"IsPaused": false}},
+            {"UserName": "parents", "NowPlayingItem": {"Name": "Alien", "Type": "Movie"},
+             "PlayState": {"IsPaused": true}},
+            {"UserName": "parents", "DeviceName": "Phone", "PlayState": {}}
+        ]"#;
+        assert_eq!(
+            parse_jellyfin_sessions(jellyfin).unwrap(),
+            ["Bluey - Sleepytime"]
+        );
+
+        let plex = r#"{"MediaContainer": {"size": 2, "Metadata": [
+            {"type": "movie", "title": "Alien", "Player": {"state": "playing"}},
+            {"type": "episode", "title": "Sleepytime", "grandparentTitle": "Bluey",
+             "Player": {"state": "paused"}}
+        ]}}"#;
+        assert_eq!(parse_plex_sessions(plex).unwrap(), ["Alien"]);
+        let idle = r#"{"MediaContainer": {"size": 0}}"#;
+        assert!(parse_plex_sessions(idle).unwrap().is_empty());
+    }
+}
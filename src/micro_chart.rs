@@ -0,0 +1,159 @@
+//! Tiny charts for the numbers of the screen content (e.g. the temperature trend). There's no room
+//! for axes or labels on the panel, so the values just span the whole height of their chart, from
+//! the smallest at the bottom to the largest at the top.
+
+use crate::config_extractor::api_config::{chart::Kind, Chart};
+use crate::theme::parse_color;
+use embedded_graphics::{
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle},
+};
+use log::warn;
+
+#[derive(Debug)]
+pub struct MicroChart {
+    source: String,
+    kind: Kind,
+    area: Rectangle,
+    // At full brightness
+    color: [u8; 3],
+}
+
+impl MicroChart {
+    pub fn new(config: &Chart) -> Self {
+        let color = parse_color(&config.color).unwrap_or_else(|| {
+            if !config.color.is_empty() {
+                warn!(
+                    "Invalid color {:?} for the {} chart, drawing it white",
+                    config.color, config.source
+                );
+            }
+            [0xff, 0xff, 0xff]
+        });
+        MicroChart {
+            source: config.source.clone(),
+            kind: config.kind(),
+            area: Rectangle::new(
+                Point::new(config.x, config.y),
+                Size::new(config.width, config.height),
+            ),
+            color,
+        }
+    }
+
+    /// The name of the numbers this chart draws
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Draws the values in the chart's area, leaving out those that don't fit its width
+    pub fn draw<D>(&self, values: &[f32], brightness: f32, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb888>,
+    {
+        let Size { width, height } = self.area.size;
+        let values = &values[..values.len().min(width as usize)];
+        if values.is_empty() || height == 0 {
+            return Ok(());
+        }
+        let [r, g, b] = self.color.map(|c| (f32::from(c) * brightness) as u8);
+        let color = Rgb888::new(r, g, b);
+        let left = self.area.top_left.x;
+        let bottom = self.area.top_left.y + height as i32 - 1;
+        let heights = scale(values, height);
+        match self.kind {
+            Kind::Sparkline => {
+                let points: Vec<Point> = columns(values.len(), width)
+                    .into_iter()
+                    .zip(heights)
+                    .map(|(x, h)| Point::new(left + x as i32, bottom - h as i32))
+                    .collect();
+                match points.as_slice() {
+                    [point] => Pixel(*point, color).draw(target)?,
+                    _ => {
+                        for pair in points.windows(2) {
+                            Line::new(pair[0], pair[1])
+                                .into_styled(PrimitiveStyle::with_stroke(color, 1))
+                                .draw(target)?;
+                        }
+                    }
+                }
+            }
+            Kind::Bar => {
+                // Leave a gap between bars when there's room for it
+                let slot = width / values.len() as u32;
+                let bar_width = if slot > 2 { slot - 1 } else { slot };
+                for (i, h) in heights.into_iter().enumerate() {
+                    // Even the smallest value gets a pixel, so bars are never missing
+                    let bar_height = h + 1;
+                    Rectangle::new(
+                        Point::new(
+                            left + (i as u32 * slot) as i32,
+                            bottom + 1 - bar_height as i32,
+                        ),
+                        Size::new(bar_width, bar_height),
+                    )
+                    .into_styled(PrimitiveStyle::with_fill(color))
+                    .draw(target)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// How high each value goes above the bottom of a `height` tall chart, from 0 for the smallest to
+// `height - 1` for the largest. Flat (or single) values sit in the middle.
+fn scale(values: &[f32], height: u32) -> Vec<u32> {
+    let finite = || values.iter().copied().filter(|v| v.is_finite());
+    let min = finite().fold(f32::INFINITY, f32::min);
+    let max = finite().fold(f32::NEG_INFINITY, f32::max);
+    let top = height.saturating_sub(1);
+    values
+        .iter()
+        .map(|v| {
+            if !v.is_finite() {
+                0
+            } else if max > min {
+                ((v - min) / (max - min) * top as f32).round() as u32
+            } else {
+                top / 2
+            }
+        })
+        .collect()
+}
+
+// Spreads `count` values over `width` columns, the first and last ones on the edges
+fn columns(count: usize, width: u32) -> Vec<u32> {
+    match count {
+        0 => vec![],
+        1 => vec![0],
+        _ => (0..count as u32)
+            .map(|i| i * width.saturating_sub(1) / (count as u32 - 1))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_values_to_the_height() {
+        assert_eq!(scale(&[10.0, 15.0, 20.0], 5), vec![0, 2, 4]);
+        assert_eq!(scale(&[-3.0, 1.0], 8), vec![0, 7]);
+        // No trend to show
+        assert_eq!(scale(&[12.0, 12.0], 8), vec![3, 3]);
+        assert_eq!(scale(&[12.0], 1), vec![0]);
+        assert_eq!(scale(&[1.0, f32::NAN, 3.0], 3), vec![0, 0, 2]);
+    }
+
+    #[test]
+    fn spreads_values_over_the_width() {
+        assert_eq!(columns(0, 10), Vec::<u32>::new());
+        assert_eq!(columns(1, 10), vec![0]);
+        assert_eq!(columns(3, 11), vec![0, 5, 10]);
+        assert_eq!(columns(4, 4), vec![0, 1, 2, 3]);
+    }
+}
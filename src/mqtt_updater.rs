@@ -0,0 +1,271 @@
+//! Values from an MQTT broker, shown as text widgets: the config maps each topic to a label, and
+//! the latest value published on it (or picked out of its JSON payload) becomes the widget's text.
+//!
+//! Unlike the other updaters, there's nothing to poll: an update waits for messages to come in,
+//! and the next one starts right after it, so the widgets change as soon as the values do.
+
+use crate::config_extractor::api_config::{self, MqttTopic};
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::screen_service::TextWidget;
+use chrono::Timelike;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS, SubscribeFilter};
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, error, info};
+
+pub const SOURCE: &str = "mqtt";
+const DEFAULT_PORT: u16 = 1883;
+// How long an update waits for messages before showing what it has anyway
+const MAX_WAIT: Duration = Duration::from_secs(60);
+// Messages tend to come in bursts (e.g. retained ones on subscribing), which make one update
+const BURST_WAIT: Duration = Duration::from_millis(200);
+
+pub struct MqttUpdater {
+    update_mode: UpdateMode,
+    client: AsyncClient,
+    event_loop: EventLoop,
+    topics: Vec<MqttTopic>,
+    // The latest value of each topic, if any came in since we connected
+    values: Vec<Option<String>>,
+    reconnect_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for MqttUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(17),
+            // Right away, unless the connection failed
+            UpdateMode::Real => Instant::now() + self.reconnect_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        debug!("Updating {:?} MQTT", self.update_mode);
+        let widgets;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                let door = match now.minute() % 10 < 3 {
+                    true => "open",
+                    false => "closed",
+                };
+                widgets = vec![
+                    widget("Garage", door),
+                    widget("Washer", &format!("{} min", 60 - now.minute())),
+                ];
+                error_bit.store(
+                    now.second().is_multiple_of(23),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => match self.receive().await {
+                Ok(()) => {
+                    error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                    self.reconnect_period.set_success();
+                    widgets = self.get_widgets();
+                }
+                Err(e) => {
+                    error!("Error receiving from the MQTT broker: {}", e);
+                    error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                    self.reconnect_period.set_error();
+                    // They may change while we're away, the broker sends them again on reconnecting
+                    self.values.fill(None);
+                    widgets = vec![];
+                }
+            },
+        }
+        vec![ContentUpdate::TextWidgets {
+            source: SOURCE,
+            widgets,
+        }]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl MqttUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mqtt_config = config.mqtt.as_ref().ok_or("No MQTT config")?;
+        if mqtt_config.host.is_empty() {
+            return Err("No MQTT broker host".into());
+        }
+        if let Some(invalid) = mqtt_config
+            .topics
+            .iter()
+            .find(|t| !rumqttc::valid_filter(&t.topic))
+        {
+            return Err(format!("Invalid MQTT topic {:?}", invalid.topic).into());
+        }
+        let port = match mqtt_config.port {
+            Some(port) => port.try_into()?,
+            None => DEFAULT_PORT,
+        };
+        // Two screens on the same broker would kick each other out with the same id
+        let client_id = format!("rpi-screen-service-{}", std::process::id());
+        let mut options = MqttOptions::new(client_id, &mqtt_config.host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if !mqtt_config.username.is_empty() {
+            options.set_credentials(&mqtt_config.username, &mqtt_config.password);
+        }
+        // Connecting only happens once the event loop is polled, i.e. on the first update
+        let (client, event_loop) = AsyncClient::new(options, 10);
+        Ok(MqttUpdater {
+            update_mode,
+            client,
+            event_loop,
+            topics: mqtt_config.topics.clone(),
+            values: vec![None; mqtt_config.topics.len()],
+            reconnect_period: ExponentialBackoff::new(
+                Duration::ZERO,
+                Duration::from_secs(60), // 1 min
+                Duration::from_secs(1200), // 20 min
+            ),
+        })
+    }
+
+    // Drives the connection until some values came in, or for MAX_WAIT if none do. Right after
+    // subscribing, it only waits for the retained values, so a first update doesn't hold the
+    // server's warm-up when there are none. Polling again after an error reconnects.
+    async fn receive(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut deadline = Instant::now() + MAX_WAIT;
+        loop {
+            let event = match tokio::time::timeout_at(deadline, self.event_loop.poll()).await {
+                Ok(event) => event?,
+                Err(_) => return Ok(()),
+            };
+            match event {
+                // Subscriptions don't survive reconnecting, since the session is clean
+                Event::Incoming(Packet::ConnAck(_)) => {
+                    info!("Connected to the MQTT broker, subscribing");
+                    let filters = self
+                        .topics
+                        .iter()
+                        .map(|t| SubscribeFilter::new(t.topic.clone(), QoS::AtMostOnce));
+                    self.client.try_subscribe_many(filters)?;
+                }
+                // The broker sends the retained values right after acknowledging
+                Event::Incoming(Packet::SubAck(_)) => {
+                    debug!("Subscribed to the MQTT topics");
+                    deadline = deadline.min(Instant::now() + BURST_WAIT);
+                }
+                Event::Incoming(Packet::Publish(publish))
+                    if record_message(
+                        &self.topics,
+                        &mut self.values,
+                        &publish.topic,
+                        &publish.payload,
+                    ) =>
+                {
+                    deadline = deadline.min(Instant::now() + BURST_WAIT);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // In the config's order, leaving out the topics nothing came in on yet
+    fn get_widgets(&self) -> Vec<TextWidget> {
+        self.topics
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(topic, value)| Some(widget(&topic.label, value.as_ref()?)))
+            .collect()
+    }
+}
+
+fn widget(label: &str, text: &str) -> TextWidget {
+    TextWidget {
+        label: label.to_string(),
+        text: text.to_string(),
+        source: SOURCE.to_string(),
+    }
+}
+
+// Keeps the value of a message for each topic it matches, returning whether any did
+fn record_message(
+    topics: &[MqttTopic],
+    values: &mut [Option<String>],
+    topic: &str,
+    payload: &[u8],
+) -> bool {
+    let mut recorded = false;
+    for (config, value) in topics.iter().zip(values.iter_mut()) {
+        if !rumqttc::matches(topic, &config.topic) {
+            continue;
+        }
+        match get_value(payload, &config.json_pointer) {
+            Some(text) => {
+                *value = Some(text);
+                recorded = true;
+            }
+            None => debug!("No value for {} in {:?}", config.label, payload),
+        }
+    }
+    recorded
+}
+
+// The whole payload as text, or what's at `json_pointer` in it
+fn get_value(payload: &[u8], json_pointer: &str) -> Option<String> {
+    let payload = String::from_utf8_lossy(payload);
+    if json_pointer.is_empty() {
+        return Some(payload.trim().to_string());
+    }
+    let json: Value = serde_json::from_str(&payload).ok()?;
+    match json.pointer(json_pointer)? {
+        Value::String(text) => Some(text.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_topic_values() {
+        let topic = |topic: &str, label: &str, json_pointer: &str| MqttTopic {
+            topic: topic.into(),
+            label: label.into(),
+            json_pointer: json_pointer.into(),
+        };
+        let topics = [
+            topic("garage/door", "Garage", ""),
+            topic("zigbee2mqtt/+/sensor", "Temp", "/temperature"),
+        ];
+        let mut values = vec![None; 2];
+        assert!(record_message(
+            &topics,
+            &mut values,
+            "garage/door",
+            b"open\n"
+        ));
+        assert!(!record_message(&topics, &mut values, "garage/light", b"on"));
+        assert!(record_message(
+            &topics,
+            &mut values,
+            "zigbee2mqtt/kitchen/sensor",
+            br#"{"temperature": 21.5, "battery": 90}"#
+        ));
+        // Not the value we're after
+        assert!(!record_message(
+            &topics,
+            &mut values,
+            "zigbee2mqtt/kitchen/sensor",
+            br#"{"battery": 89}"#
+        ));
+        assert_eq!(values, [Some("open".into()), Some("21.5".into())]);
+        assert_eq!(
+            get_value(br#"{"state": "ON"}"#, "/state"),
+            Some("ON".into())
+        );
+    }
+}
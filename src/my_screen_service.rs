@@ -1,144 +1,367 @@
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
-use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
 
-use crate::config_extractor::api_config::ApiConfig;
-use crate::data_updater::DataUpdater;
+use crate::admin_auth::require_admin;
+use crate::astronomy_updater::AstronomyUpdater;
+use crate::bike_sharing_updater::BikeSharingUpdater;
+use crate::chores_updater::ChoresUpdater;
+use crate::circuit_breaker::{CircuitBreaker, DEFAULT_COOL_DOWN, DEFAULT_FAILURE_THRESHOLD};
+use crate::config_extractor::api_config::{ApiConfig, AstronomyConfig, WeatherConfig};
+use crate::connectivity_updater::ConnectivityUpdater;
+use crate::content_aggregator;
+use crate::content_review::UnderReview;
+use crate::content_store;
+use crate::countdown_updater::CountdownUpdater;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::ev_charger_updater::EvChargerUpdater;
 use crate::gcal_updater::GcalUpdater;
+use crate::home_assistant_updater::HomeAssistantUpdater;
+use crate::http_client;
+use crate::iss_pass_updater::IssPassUpdater;
+use crate::json_poller_updater::JsonPollerUpdater;
 use crate::kitty_updater::KittyUpdater;
+use crate::media_server_updater::MediaServerUpdater;
+use crate::mqtt_updater::MqttUpdater;
+use crate::octoprint_updater::OctoPrintUpdater;
+use crate::openings_updater::OpeningsUpdater;
+use crate::race_calendar_updater::RaceCalendarUpdater;
+use crate::rss_updater::RssUpdater;
+use crate::screen_service::calendar_event::DateHint;
 use crate::screen_service::screen_service_server::ScreenService;
 use crate::screen_service::{
+    AcknowledgeReply, AcknowledgeRequest, ApproveSourceReply, ApproveSourceRequest, Astronomy,
+    CalendarEvent, CompleteChoreReply, CompleteChoreRequest, Departure, LogTailReply,
+    LogTailRequest, Notice, PushMessageReply, PushMessageRequest, RefreshReply, RefreshRequest,
     ScreenContentReply, ScreenContentRequest, ScreenHashReply, ScreenHashRequest,
+    SetBrightnessReply, SetBrightnessRequest, SetUpdaterModeReply, SetUpdaterModeRequest,
+    StatusReply, StatusRequest, UpdaterStatus, Weather,
 };
+use crate::sensor_updater::SensorUpdater;
+use crate::shopping_list_updater::ShoppingListUpdater;
+use crate::smart_plug_updater::SmartPlugUpdater;
+use crate::solar_updater::SolarUpdater;
+use crate::sports_updater::SportsUpdater;
+use crate::system_stats_updater::SystemStatsUpdater;
+use crate::ticker_updater::TickerUpdater;
+use crate::todoist_updater::TodoistUpdater;
 use crate::transport_updater::TransportUpdater;
-use chrono::Timelike;
-use log::{debug, error, warn};
+use crate::unraid_updater::UnraidUpdater;
+use crate::update_schedule::{Accelerated, OnSchedule, UpdateSchedule};
+use crate::update_scheduler::UpdateScheduler;
+use crate::updater_registry::{UpdaterCommand, UpdaterHandle, UpdaterRegistry};
+use crate::waste_collection_updater::WasteCollectionUpdater;
+use crate::weather_updater::WeatherUpdater;
+use chrono::{DateTime, Local, NaiveDate, Timelike};
 use prost::Message;
+use prost_types::Timestamp;
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::watch;
 use tonic::{Request, Response, Status};
+use tracing::{debug, error, info, warn};
 
+// Where log4rs_config.yml writes its logs
+const DEFAULT_LOG_FILE: &str = "log/screen_service.log";
+const DEFAULT_STARTED_EVENT_EXPIRY_HOURS: u32 = 2;
+// How long to hold off serving while the first updates come in
+const WARM_UP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Cheap to clone: all clones share the same content and state
+#[derive(Clone)]
 pub struct MyScreenService {
     config: ApiConfig,
-    screen_content_container: Arc<Mutex<ScreenContentReply>>,
-    error_statuses: Vec<Arc<AtomicBool>>,
+    // The latest content from the aggregator, and where to send it updates
+    screen_content: watch::Receiver<ScreenContentReply>,
+    content_updates: UnboundedSender<ContentUpdate>,
+    // The handles of the updaters the scheduler runs, by name
+    updaters: UpdaterRegistry,
+    // Set from the admin console, takes precedence over the config's brightness map
+    brightness_override: Arc<Mutex<Option<f32>>>,
+    // How many times faster than they ask for the updaters run, 1 outside of soak tests
+    update_speedup: u32,
 }
 
 impl MyScreenService {
+    // Needs a tokio runtime, since it starts the content aggregator
     pub fn new(config: &ApiConfig) -> Self {
-        let screen_content_container = Arc::new(Mutex::new(ScreenContentReply::default()));
+        // Start from what we had before a restart, if we can
+        let initial_content = match get_cache_file(config) {
+            Some(cache_file) => content_store::load(Path::new(cache_file)).unwrap_or_else(|e| {
+                warn!("Couldn't load cached content from {}: {}", cache_file, e);
+                ScreenContentReply::default()
+            }),
+            None => ScreenContentReply::default(),
+        };
+        let (content_updates, aggregator, screen_content) =
+            content_aggregator::pipeline(initial_content);
+        tokio::spawn(aggregator.run());
         MyScreenService {
             config: config.clone(),
-            screen_content_container,
-            error_statuses: vec![],
+            screen_content,
+            content_updates,
+            updaters: UpdaterRegistry::default(),
+            brightness_override: Arc::new(Mutex::new(None)),
+            update_speedup: 1,
         }
     }
 
-    pub fn start_backgound_updates(&mut self) {
-        // Start the updaters in dummy mode, to avoid spamming the server if we got something wrong
-        self.start_kitty_updates(crate::kitty_updater::KittyUpdateMode::Real);
-        self.start_gcal_updates(crate::gcal_updater::GcalUpdateMode::Real);
-        self.start_transport_updates(crate::transport_updater::TransportUpdateMode::Real);
-    }
-
-    fn start_kitty_updates(&mut self, update_mode: crate::kitty_updater::KittyUpdateMode) {
-        let config_copy = self.config.clone();
-        let container = Arc::clone(&self.screen_content_container);
-        self.error_statuses.push(Arc::new(AtomicBool::new(false)));
-        let error_bit = self
-            .error_statuses
-            .last()
-            .expect("No error bit in vec when we just pushed one?")
-            .clone();
-        tokio::spawn(async move {
-            let mut kitty_updater = KittyUpdater::new(update_mode, &config_copy)
-                .expect("Error creating the Kitty updater");
-            loop {
-                kitty_updater.update(&container, &error_bit).await;
-                tokio::time::sleep_until(kitty_updater.get_next_update_time()).await;
-            }
-        });
+    // This is only used by the soak test, so the server compilation complains that we never use it.
+    #[allow(dead_code)]
+    pub fn with_update_speedup(mut self, update_speedup: u32) -> Self {
+        self.update_speedup = update_speedup;
+        self
     }
 
-    fn start_gcal_updates(&mut self, update_mode: crate::gcal_updater::GcalUpdateMode) {
-        let config_copy = self.config.clone();
-        let container = Arc::clone(&self.screen_content_container);
-        self.error_statuses.push(Arc::new(AtomicBool::new(false)));
-        let error_bit = self
-            .error_statuses
-            .last()
-            .expect("No error bit in vec when we just pushed one?")
-            .clone();
-        tokio::spawn(async move {
-            let mut gcal_updater = GcalUpdater::new(update_mode, &config_copy)
-                .expect("Error creating the gcal updater");
-            loop {
-                gcal_updater.update(&container, &error_bit).await;
-                tokio::time::sleep_until(gcal_updater.get_next_update_time()).await;
-            }
-        });
+    /// Fails without starting anything when the HTTP config is wrong, rather than have every
+    /// updater fail for an unrelated reason (e.g. bypassing the only proxy out)
+    pub fn start_backgound_updates(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut scheduler = UpdateScheduler::new();
+        // One client for all the updaters, so they share its proxy, certificates and connections
+        let client = http_client::build(&self.config)
+            .map_err(|e| format!("Error setting up the HTTP client: {}", e))?;
+        // Starts the updater named after the given config field when that field is set and not
+        // disabled, in Real mode unless the config asks for Dummy. The arguments after the
+        // constructor are cloned into it, after the mode and config.
+        macro_rules! start_updater {
+            ($field:ident, $new:path $(, $arg:ident)*) => {{
+                let name = stringify!($field);
+                let settings = self.config.$field.as_ref().map(|config| {
+                    (config.enabled, config.dummy_mode, config.schedule.clone())
+                });
+                if let Some((enabled, dummy_mode, schedule)) = settings {
+                    if is_enabled(name, enabled) {
+                        let mode = UpdateMode::from_dummy(dummy_mode);
+                        let updater = $new(mode, &self.config $(, $arg.clone())*);
+                        self.add_updater(&mut scheduler, name, updater, Some(schedule));
+                    }
+                }
+            }};
+        }
+        start_updater!(kitty, KittyUpdater::new, client);
+        start_updater!(gcal, GcalUpdater::new, client);
+        start_updater!(transport, TransportUpdater::new, client);
+        start_updater!(weather, WeatherUpdater::new, client);
+        start_updater!(astronomy, AstronomyUpdater::new);
+        start_updater!(countdowns, CountdownUpdater::new);
+        start_updater!(chores, ChoresUpdater::new);
+        start_updater!(sensors, SensorUpdater::new);
+        start_updater!(system_stats, SystemStatsUpdater::new);
+        start_updater!(unraid, UnraidUpdater::new, client);
+        start_updater!(connectivity, ConnectivityUpdater::new);
+        start_updater!(mqtt, MqttUpdater::new);
+        start_updater!(home_assistant, HomeAssistantUpdater::new, client);
+        start_updater!(media_server, MediaServerUpdater::new, client);
+        start_updater!(solar, SolarUpdater::new, client);
+        start_updater!(tickers, TickerUpdater::new, client);
+        start_updater!(rss, RssUpdater::new, client);
+        start_updater!(waste_collection, WasteCollectionUpdater::new, client);
+        start_updater!(todoist, TodoistUpdater::new, client);
+        start_updater!(shopping_list, ShoppingListUpdater::new, client);
+        start_updater!(bike_sharing, BikeSharingUpdater::new, client);
+        start_updater!(ev_charger, EvChargerUpdater::new, client);
+        start_updater!(sports, SportsUpdater::new, client);
+        start_updater!(race_calendar, RaceCalendarUpdater::new, client);
+        start_updater!(json_poller, JsonPollerUpdater::new, client);
+        start_updater!(smart_plugs, SmartPlugUpdater::new, client);
+        start_updater!(openings, OpeningsUpdater::new, client);
+        start_updater!(octoprint, OctoPrintUpdater::new, client);
+        start_updater!(iss_passes, IssPassUpdater::new, client);
+
+        tokio::spawn(scheduler.run(self.content_updates.clone()));
+        if let Some(cache_file) = get_cache_file(&self.config) {
+            tokio::spawn(content_store::save_periodically(
+                cache_file.to_string(),
+                self.screen_content.clone(),
+            ));
+        }
+        Ok(())
     }
 
-    fn start_transport_updates(
+    /// Waits for the first round of updates started by `start_backgound_updates`, so that we don't
+    /// serve empty content right after boot
+    pub async fn warm_up(&self) {
+        let start = std::time::Instant::now();
+        match self.updaters.wait_for_first_updates(WARM_UP_TIMEOUT).await {
+            true => info!("Warmed up in {:?}", start.elapsed()),
+            false => warn!(
+                "Some updaters are still busy with their first update after {:?}, serving anyway",
+                WARM_UP_TIMEOUT
+            ),
+        }
+    }
+
+    // Hands the updater (behind a circuit breaker, its review and schedule if any) over to the
+    // scheduler and registers its handle, or logs why it couldn't be created
+    fn add_updater<U: DataUpdater + 'static>(
         &mut self,
-        update_mode: crate::transport_updater::TransportUpdateMode,
+        scheduler: &mut UpdateScheduler,
+        name: &'static str,
+        updater: Result<U, Box<dyn std::error::Error>>,
+        schedule: Option<String>,
     ) {
-        let config_copy = self.config.clone();
-        let container = Arc::clone(&self.screen_content_container);
-        self.error_statuses.push(Arc::new(AtomicBool::new(false)));
-        let error_bit = self
-            .error_statuses
-            .last()
-            .expect("No error bit in vec when we just pushed one?")
-            .clone();
-        tokio::spawn(async move {
-            let mut transport_updater = TransportUpdater::new(update_mode, &config_copy)
-                .expect("Error creating the transport updater");
-            loop {
-                transport_updater.update(&container, &error_bit).await;
-                tokio::time::sleep_until(transport_updater.get_next_update_time()).await;
+        let schedule = match schedule
+            .filter(|s| !s.is_empty())
+            .map(|s| UpdateSchedule::parse(&s))
+        {
+            None => None,
+            Some(Ok(schedule)) => Some(schedule),
+            Some(Err(e)) => {
+                error!("Error reading the {} schedule: {}", name, e);
+                return;
             }
-        });
+        };
+        match updater {
+            Ok(updater) => {
+                let mut updater: Box<dyn DataUpdater> = Box::new(CircuitBreaker::new(
+                    name,
+                    Box::new(updater),
+                    DEFAULT_FAILURE_THRESHOLD,
+                    DEFAULT_COOL_DOWN,
+                ));
+                if self.is_under_review(name) {
+                    info!("Holding {} content back until it's approved", name);
+                    updater = Box::new(UnderReview::new(name, updater));
+                }
+                if let Some(schedule) = schedule {
+                    updater = Box::new(OnSchedule::new(updater, schedule));
+                }
+                if self.update_speedup > 1 {
+                    updater = Box::new(Accelerated::new(updater, self.update_speedup));
+                }
+                self.updaters.register(scheduler.add(name, updater));
+            }
+            Err(e) => error!("Error creating the {} updater: {}", name, e),
+        }
+    }
+
+    fn is_under_review(&self, name: &str) -> bool {
+        self.config
+            .server
+            .as_ref()
+            .is_some_and(|server| server.review_sources.iter().any(|source| source == name))
+    }
+
+    // Shows the notice until it expires, along with the others
+    pub fn push_notice(&self, notice: Notice) -> Result<(), String> {
+        self.content_updates
+            .send(ContentUpdate::Notice(notice))
+            .map_err(|e| format!("Content aggregator is gone: {}", e))
+    }
+
+    // Returns a snapshot of the content proto, with its brightness, error and notices fields updated
+    pub fn get_composed_content(&self) -> ScreenContentReply {
+        let mut content = self.screen_content.borrow().clone();
+        // Update the brightness according to now
+        let now = chrono::offset::Local::now();
+        let is_night = is_night(content.astronomy.as_ref(), now.timestamp());
+        content.brightness = self
+            .get_brightness(now.hour(), content.weather.as_ref(), is_night)
+            .unwrap_or(1.0);
+        let today = now.date_naive();
+        // Update the error bit
+        content.error = self.updaters.any_error();
+        // Drop the notices that are done showing
+        let now = Timestamp::from(SystemTime::now());
+        remove_expired_notices(&mut content.notices, &now);
+        // And what went stale since the last updates (e.g. during an API outage)
+        remove_past_departures(&mut content.bus_departures, &now);
+        let event_expiry_hours = self
+            .config
+            .gcal
+            .as_ref()
+            .and_then(|gcal| gcal.started_event_expiry_hours)
+            .unwrap_or(DEFAULT_STARTED_EVENT_EXPIRY_HOURS);
+        let expiry_seconds = i64::from(event_expiry_hours) * 3600;
+        content
+            .upcoming_events
+            .retain(|event| !started_long_ago(event, &now, expiry_seconds));
+        remove_long_started_event(&mut content.next_upcoming_event, &now, expiry_seconds);
+        // The next event may be done showing while the ones after it aren't
+        if let Some(first) = content.upcoming_events.first() {
+            content.next_upcoming_event = Some(first.clone());
+        }
+        let countdown = self.config.gcal.as_ref().is_some_and(|gcal| gcal.countdown);
+        let events = content.upcoming_events.iter_mut();
+        for event in events.chain(content.next_upcoming_event.iter_mut()) {
+            event.in_progress = is_in_progress(event, &now);
+            if countdown {
+                set_countdown(event, today);
+            }
+        }
+        content
     }
 
     // Computes the hash of the content proto **after updating its brightness and error fields**
-    fn get_hash<'a>(
-        &'a self,
-        content: &'a Arc<Mutex<ScreenContentReply>>,
-    ) -> Result<u64, Box<dyn std::error::Error + '_>> {
+    pub fn get_hash(&self) -> Result<u64, Box<dyn std::error::Error + '_>> {
         let mut hasher = std::hash::DefaultHasher::new();
         let mut buf = prost::bytes::BytesMut::new();
 
-        {
-            let mut content = content.lock()?;
-            // Update the brightness according to now
-            let now = chrono::offset::Local::now();
-            content.brightness = self.get_brightness(now.hour()).unwrap_or(1.0);
-            // Update the error bit
-            content.error = self
-                .error_statuses
-                .iter()
-                .any(|e| e.load(std::sync::atomic::Ordering::Relaxed));
-            // Serialize the latest proto into our bytes buffer
-            content.encode(&mut buf)?;
-        }
+        // Serialize the latest proto into our bytes buffer
+        self.get_composed_content().encode(&mut buf)?;
 
         // Hash the proto bytes
         buf.hash(&mut hasher);
         Ok(hasher.finish())
     }
 
-    fn get_brightness(&self, hour: u32) -> Option<f32> {
+    fn get_brightness(&self, hour: u32, weather: Option<&Weather>, is_night: bool) -> Option<f32> {
+        match self.brightness_override.lock() {
+            Ok(brightness_override) => {
+                if brightness_override.is_some() {
+                    return *brightness_override;
+                }
+            }
+            Err(e) => error!("Poisoned lock when reading brightness override: {}", e),
+        }
         let brightness_map = &self.config.server.as_ref()?.brightness_map;
-        get_brightness_impl(brightness_map, hour).or_else(|| {
+        let brightness = get_brightness_impl(brightness_map, hour).or_else(|| {
             warn!(
                 "Couldn't find a brightness from the config map for hour {}",
                 hour
             );
             None
-        })
+        })?;
+        let brightness = compensate_for_weather(brightness, weather, self.config.weather.as_ref());
+        Some(limit_at_night(
+            brightness,
+            is_night,
+            self.config.astronomy.as_ref(),
+        ))
     }
 }
 
+fn to_status(handle: &UpdaterHandle) -> UpdaterStatus {
+    let stats = handle.stats();
+    UpdaterStatus {
+        name: handle.name().to_string(),
+        error: handle.has_error(),
+        dummy_mode: stats.dummy_mode,
+        updates: stats.updates,
+        failures: stats.failures,
+        last_update: stats.last_update.map(Timestamp::from),
+        last_duration_ms: u32::try_from(stats.last_duration.as_millis()).unwrap_or(u32::MAX),
+        staged_content: stats.staged,
+    }
+}
+
+fn get_cache_file(config: &ApiConfig) -> Option<&str> {
+    config.server.as_ref()?.cache_file.as_deref()
+}
+
+// Updaters are enabled unless their config explicitly says otherwise
+fn is_enabled(name: &str, enabled: Option<bool>) -> bool {
+    let enabled = enabled.unwrap_or(true);
+    if !enabled {
+        info!(
+            "The {} updater is disabled in the config, not starting it",
+            name
+        );
+    }
+    enabled
+}
+
 fn get_brightness_impl(brightness_map: &HashMap<u32, f32>, hour: u32) -> Option<f32> {
     let (mut best_hour, mut best_brightness) = (None, None);
     for (h, b) in brightness_map {
@@ -147,7 +370,124 @@ fn get_brightness_impl(brightness_map: &HashMap<u32, f32>, hour: u32) -> Option<
             best_brightness = Some(*b);
         }
     }
-    best_brightness
+    // Before the day's first entry, the previous day's last one still applies
+    best_brightness.or_else(|| {
+        brightness_map
+            .iter()
+            .max_by_key(|(h, _)| **h)
+            .map(|(_, b)| *b)
+    })
+}
+
+// Raises daytime brightness under a clear sky and lowers it under clouds, within the config's limits
+fn compensate_for_weather(
+    brightness: f32,
+    weather: Option<&Weather>,
+    config: Option<&WeatherConfig>,
+) -> f32 {
+    let (Some(weather), Some(config)) = (weather, config) else {
+        return brightness;
+    };
+    if !weather.is_day {
+        return brightness;
+    }
+    let clear_sky = 1.0 - (weather.cloud_cover / 100.0).clamp(0.0, 1.0);
+    let gain = config.max_boost * clear_sky - config.max_reduction * (1.0 - clear_sky);
+    (brightness * (1.0 + gain)).clamp(0.0, 1.0)
+}
+
+// Between sunset and sunrise, as far as we know
+fn is_night(astronomy: Option<&Astronomy>, now: i64) -> bool {
+    let Some(astronomy) = astronomy else {
+        return false;
+    };
+    match (astronomy.sunrise, astronomy.sunset) {
+        (Some(sunrise), Some(sunset)) => now < sunrise.seconds || sunset.seconds <= now,
+        // Polar days and nights, the brightness map knows better
+        _ => false,
+    }
+}
+
+// Whatever the hour, the night needs no more than the config's night brightness
+fn limit_at_night(brightness: f32, is_night: bool, config: Option<&AstronomyConfig>) -> f32 {
+    match config.and_then(|config| config.night_brightness) {
+        Some(night_brightness) if is_night => brightness.min(night_brightness),
+        _ => brightness,
+    }
+}
+
+fn remove_expired_notices(notices: &mut Vec<Notice>, now: &Timestamp) {
+    notices.retain(|notice| {
+        notice
+            .expires_at
+            .is_some_and(|expiry| (expiry.seconds, expiry.nanos) > (now.seconds, now.nanos))
+    });
+}
+
+fn remove_past_departures(departures: &mut Vec<Departure>, now: &Timestamp) {
+    departures.retain(|departure| {
+        departure
+            .departure_time
+            .is_some_and(|time| (time.seconds, time.nanos) >= (now.seconds, now.nanos))
+    });
+}
+
+// Started, and not over according to its end time (if it has one)
+fn is_in_progress(event: &CalendarEvent, now: &Timestamp) -> bool {
+    event
+        .event_start
+        .is_some_and(|start| start.seconds <= now.seconds)
+        && event.event_end.is_some_and(|end| now.seconds < end.seconds)
+}
+
+// In local days, an event tonight is today even when it's already tomorrow in UTC
+fn set_countdown(event: &mut CalendarEvent, today: NaiveDate) {
+    let Some(start) = event
+        .event_start
+        .and_then(|start| DateTime::from_timestamp(start.seconds, 0))
+    else {
+        return;
+    };
+    let day = start.with_timezone(&Local).date_naive();
+    event.set_date_hint(DateHint::Countdown);
+    event.days_until = (day - today).num_days() as i32;
+}
+
+// Events in progress stay, however long ago they started
+fn started_long_ago(event: &CalendarEvent, now: &Timestamp, expiry_seconds: i64) -> bool {
+    if is_in_progress(event, now) {
+        return false;
+    }
+    // All-day events start at midnight, and are on for the whole day
+    let expiry_seconds = match event.all_day {
+        true => expiry_seconds.max(24 * 3600),
+        false => expiry_seconds,
+    };
+    event
+        .event_start
+        .is_some_and(|start| start.seconds + expiry_seconds < now.seconds)
+}
+
+fn remove_long_started_event(
+    event: &mut Option<CalendarEvent>,
+    now: &Timestamp,
+    expiry_seconds: i64,
+) {
+    if event
+        .as_ref()
+        .is_some_and(|event| started_long_ago(event, now, expiry_seconds))
+    {
+        *event = None;
+    }
+}
+
+// Returns (at most) the last `count` lines of the given text
+fn last_lines(text: &str, count: usize) -> Vec<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    lines[lines.len().saturating_sub(count)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
 }
 
 #[tonic::async_trait]
@@ -158,18 +498,7 @@ impl ScreenService for MyScreenService {
         _request: Request<ScreenContentRequest>,
     ) -> Result<Response<ScreenContentReply>, Status> {
         debug!("Serving /GetScreenContent");
-        // Try to lock and clone our screen content to return it
-        let reply: ScreenContentReply = match self.screen_content_container.lock() {
-            Ok(content) => content.clone(),
-            Err(e) => {
-                error!("Poisoned lock when reading content for serving: {}", e);
-                let mut reply = ScreenContentReply::default();
-                reply.error = true;
-                reply
-            }
-        };
-
-        Ok(Response::new(reply))
+        Ok(Response::new(self.get_composed_content()))
     }
 
     async fn get_screen_hash(
@@ -177,7 +506,7 @@ impl ScreenService for MyScreenService {
         _request: Request<ScreenHashRequest>,
     ) -> Result<Response<ScreenHashReply>, Status> {
         debug!("Serving /GetScreenHash");
-        let reply = match self.get_hash(&self.screen_content_container) {
+        let reply = match self.get_hash() {
             Ok(hash) => ScreenHashReply { hash },
             Err(e) => {
                 error!("Error computing hash: {:#?}", e);
@@ -186,6 +515,174 @@ impl ScreenService for MyScreenService {
         };
         Ok(Response::new(reply))
     }
+
+    async fn get_status(
+        &self,
+        _request: Request<StatusRequest>,
+    ) -> Result<Response<StatusReply>, Status> {
+        debug!("Serving /GetStatus");
+        let hash = self
+            .get_hash()
+            .map_err(|e| Status::internal(format!("Error computing hash: {}", e)))?;
+        let brightness = self.get_composed_content().brightness;
+        let brightness_overridden = self
+            .brightness_override
+            .lock()
+            .map_err(|e| Status::internal(format!("Poisoned brightness lock: {}", e)))?
+            .is_some();
+        let updaters = self.updaters.handles().iter().map(to_status).collect();
+        Ok(Response::new(StatusReply {
+            updaters,
+            brightness,
+            brightness_overridden,
+            hash,
+        }))
+    }
+
+    async fn refresh_now(
+        &self,
+        request: Request<RefreshRequest>,
+    ) -> Result<Response<RefreshReply>, Status> {
+        require_admin(&request)?;
+        let source = request.into_inner().source;
+        info!("Serving /RefreshNow for {}", source);
+        self.updaters
+            .get(&source)
+            .ok_or_else(|| Status::not_found(format!("No updater named '{}'", source)))?
+            .send(UpdaterCommand::Refresh)
+            .map_err(Status::unavailable)?;
+        Ok(Response::new(RefreshReply {}))
+    }
+
+    async fn set_updater_mode(
+        &self,
+        request: Request<SetUpdaterModeRequest>,
+    ) -> Result<Response<SetUpdaterModeReply>, Status> {
+        require_admin(&request)?;
+        let request = request.into_inner();
+        info!("Serving /SetUpdaterMode with {:?}", request);
+        self.updaters
+            .get(&request.source)
+            .ok_or_else(|| Status::not_found(format!("No updater named '{}'", request.source)))?
+            .send(UpdaterCommand::SetDummyMode(request.dummy_mode))
+            .map_err(Status::unavailable)?;
+        Ok(Response::new(SetUpdaterModeReply {}))
+    }
+
+    async fn approve_source(
+        &self,
+        request: Request<ApproveSourceRequest>,
+    ) -> Result<Response<ApproveSourceReply>, Status> {
+        require_admin(&request)?;
+        let source = request.into_inner().source;
+        info!("Serving /ApproveSource for {}", source);
+        self.updaters
+            .get(&source)
+            .ok_or_else(|| Status::not_found(format!("No updater named '{}'", source)))?
+            .send(UpdaterCommand::Approve)
+            .map_err(Status::unavailable)?;
+        Ok(Response::new(ApproveSourceReply {}))
+    }
+
+    async fn acknowledge(
+        &self,
+        request: Request<AcknowledgeRequest>,
+    ) -> Result<Response<AcknowledgeReply>, Status> {
+        require_admin(&request)?;
+        let source = request.into_inner().source;
+        info!("Serving /Acknowledge for {}", source);
+        self.updaters
+            .get(&source)
+            .ok_or_else(|| Status::not_found(format!("No updater named '{}'", source)))?
+            .send(UpdaterCommand::Acknowledge)
+            .map_err(Status::unavailable)?;
+        Ok(Response::new(AcknowledgeReply {}))
+    }
+
+    async fn complete_chore(
+        &self,
+        request: Request<CompleteChoreRequest>,
+    ) -> Result<Response<CompleteChoreReply>, Status> {
+        require_admin(&request)?;
+        let chore = request.into_inner().chore;
+        info!("Serving /CompleteChore for {}", chore);
+        // Typos get told off here, the updater couldn't
+        let known = self.config.chores.as_ref().is_some_and(|chores| {
+            chores
+                .chores
+                .iter()
+                .any(|known| known.label.eq_ignore_ascii_case(&chore))
+        });
+        if !known {
+            return Err(Status::not_found(format!("No chore named '{}'", chore)));
+        }
+        self.updaters
+            .get("chores")
+            .ok_or_else(|| Status::not_found("The chores updater isn't running"))?
+            .send(UpdaterCommand::Complete(chore))
+            .map_err(Status::unavailable)?;
+        Ok(Response::new(CompleteChoreReply {}))
+    }
+
+    async fn set_brightness(
+        &self,
+        request: Request<SetBrightnessRequest>,
+    ) -> Result<Response<SetBrightnessReply>, Status> {
+        require_admin(&request)?;
+        let request = request.into_inner();
+        info!("Serving /SetBrightness with {:?}", request);
+        if !request.clear_override && !(0.0..=1.0).contains(&request.brightness) {
+            return Err(Status::invalid_argument(
+                "Brightness must be between 0.0 and 1.0",
+            ));
+        }
+        let mut brightness_override = self
+            .brightness_override
+            .lock()
+            .map_err(|e| Status::internal(format!("Poisoned brightness lock: {}", e)))?;
+        *brightness_override = match request.clear_override {
+            true => None,
+            false => Some(request.brightness),
+        };
+        Ok(Response::new(SetBrightnessReply {}))
+    }
+
+    async fn push_message(
+        &self,
+        request: Request<PushMessageRequest>,
+    ) -> Result<Response<PushMessageReply>, Status> {
+        require_admin(&request)?;
+        let request = request.into_inner();
+        info!("Serving /PushMessage with {:?}", request);
+        let expires_at =
+            SystemTime::now() + std::time::Duration::from_secs(request.duration_seconds.into());
+        self.push_notice(Notice {
+            text: request.text,
+            expires_at: Some(Timestamp::from(expires_at)),
+        })
+        .map_err(Status::unavailable)?;
+        Ok(Response::new(PushMessageReply {}))
+    }
+
+    async fn get_log_tail(
+        &self,
+        request: Request<LogTailRequest>,
+    ) -> Result<Response<LogTailReply>, Status> {
+        require_admin(&request)?;
+        debug!("Serving /GetLogTail");
+        let log_file = self
+            .config
+            .server
+            .as_ref()
+            .and_then(|server| server.log_file.as_deref())
+            .unwrap_or(DEFAULT_LOG_FILE);
+        let logs = std::fs::read_to_string(log_file).map_err(|e| {
+            Status::not_found(format!("Couldn't read log file '{}': {}", log_file, e))
+        })?;
+        Ok(Response::new(LogTailReply {
+            lines: last_lines(&logs, request.into_inner().lines as usize),
+        }))
+    }
 }
 
 #[cfg(test)]
@@ -205,4 +702,216 @@ mod tests {
         assert_eq!(get_brightness_impl(&map, 12), Some(1.0));
         assert_eq!(get_brightness_impl(&map, 14), Some(1.0));
     }
+
+    #[test]
+    fn computes_brightness_on_dst_nights() {
+        use chrono::{TimeZone, Utc};
+        use chrono_tz::Europe::Zurich;
+        let map = HashMap::from([(0, 0.0), (2, 0.5), (3, 0.8)]);
+        let local_hour = |utc_hour| {
+            let at = |month, day| Utc.with_ymd_and_hms(2024, month, day, utc_hour, 30, 0);
+            (
+                at(3, 31).unwrap().with_timezone(&Zurich).hour(),
+                at(10, 27).unwrap().with_timezone(&Zurich).hour(),
+            )
+        };
+
+        // Spring: 01:30 CET is followed by 03:30 CEST, the 2 o'clock entry is skipped
+        assert_eq!(local_hour(0), (1, 2));
+        assert_eq!(get_brightness_impl(&map, local_hour(0).0), Some(0.0));
+        assert_eq!(get_brightness_impl(&map, local_hour(1).0), Some(0.8));
+        // Autumn: 02:30 CEST is followed by 02:30 CET, both get the same brightness
+        assert_eq!(local_hour(0).1, local_hour(1).1);
+        assert_eq!(get_brightness_impl(&map, local_hour(1).1), Some(0.5));
+    }
+
+    #[test]
+    fn computes_brightness_across_midnight() {
+        let map = HashMap::from([(7, 1.0), (22, 0.1)]);
+        // Past midnight, the evening's brightness carries over until the morning entry
+        assert_eq!(get_brightness_impl(&map, 23), Some(0.1));
+        assert_eq!(get_brightness_impl(&map, 0), Some(0.1));
+        assert_eq!(get_brightness_impl(&map, 6), Some(0.1));
+        assert_eq!(get_brightness_impl(&map, 7), Some(1.0));
+        assert_eq!(get_brightness_impl(&HashMap::new(), 7), None);
+    }
+
+    #[test]
+    fn compensates_brightness_for_weather() {
+        let config = WeatherConfig {
+            max_boost: 0.2,
+            max_reduction: 0.4,
+            ..Default::default()
+        };
+        let weather = |cloud_cover, is_day| Weather {
+            cloud_cover,
+            is_day,
+            ..Default::default()
+        };
+        let compensate = |weather| compensate_for_weather(0.5, Some(&weather), Some(&config));
+
+        assert_eq!(compensate(weather(0.0, true)), 0.6);
+        assert_eq!(compensate(weather(100.0, true)), 0.3);
+        assert_eq!(compensate(weather(100.0 / 3.0, true)), 0.5);
+        // Nothing to compensate at night, without weather, or without config
+        assert_eq!(compensate(weather(0.0, false)), 0.5);
+        assert_eq!(compensate_for_weather(0.5, None, Some(&config)), 0.5);
+        assert_eq!(
+            compensate_for_weather(0.5, Some(&weather(0.0, true)), None),
+            0.5
+        );
+        // Never beyond full brightness
+        assert_eq!(
+            compensate_for_weather(0.95, Some(&weather(0.0, true)), Some(&config)),
+            1.0
+        );
+    }
+
+    #[test]
+    fn limits_brightness_at_night() {
+        let astronomy = Astronomy {
+            sunrise: Some(Timestamp {
+                seconds: 6_000,
+                nanos: 0,
+            }),
+            sunset: Some(Timestamp {
+                seconds: 60_000,
+                nanos: 0,
+            }),
+            ..Default::default()
+        };
+        assert!(is_night(Some(&astronomy), 5_000));
+        assert!(!is_night(Some(&astronomy), 6_000));
+        assert!(is_night(Some(&astronomy), 60_000));
+        assert!(!is_night(None, 5_000));
+        assert!(!is_night(Some(&Astronomy::default()), 5_000));
+
+        let config = AstronomyConfig {
+            night_brightness: Some(0.2),
+            ..Default::default()
+        };
+        assert_eq!(limit_at_night(0.8, true, Some(&config)), 0.2);
+        assert_eq!(limit_at_night(0.1, true, Some(&config)), 0.1);
+        assert_eq!(limit_at_night(0.8, false, Some(&config)), 0.8);
+        assert_eq!(limit_at_night(0.8, true, None), 0.8);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_logs_from_non_admins() {
+        let service = MyScreenService::new(&ApiConfig::default());
+        let request = Request::new(LogTailRequest { lines: 10 });
+        let status = service.get_log_tail(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn enables_updaters_by_default() {
+        assert!(is_enabled("foo", None));
+        assert!(is_enabled("foo", Some(true)));
+        assert!(!is_enabled("foo", Some(false)));
+    }
+
+    #[test]
+    fn removes_expired_notices() {
+        let notice = |text: &str, seconds| Notice {
+            text: text.into(),
+            expires_at: Some(Timestamp { seconds, nanos: 0 }),
+        };
+        let mut notices = vec![
+            notice("past", 100),
+            notice("future", 300),
+            Notice {
+                text: "no expiry".into(),
+                expires_at: None,
+            },
+        ];
+        remove_expired_notices(
+            &mut notices,
+            &Timestamp {
+                seconds: 200,
+                nanos: 0,
+            },
+        );
+        assert_eq!(notices, vec![notice("future", 300)]);
+    }
+
+    #[test]
+    fn removes_past_departures() {
+        let departure = |seconds| Departure {
+            departure_time: Some(Timestamp { seconds, nanos: 0 }),
+            ..Default::default()
+        };
+        let mut departures = vec![departure(100), departure(200), departure(300)];
+        remove_past_departures(
+            &mut departures,
+            &Timestamp {
+                seconds: 200,
+                nanos: 0,
+            },
+        );
+        assert_eq!(departures, vec![departure(200), departure(300)]);
+    }
+
+    #[test]
+    fn removes_long_started_event() {
+        let now = Timestamp {
+            seconds: 10_000,
+            nanos: 0,
+        };
+        let event = |seconds| {
+            Some(CalendarEvent {
+                event_start: Some(Timestamp { seconds, nanos: 0 }),
+                event_title: "Dinner".into(),
+                ..Default::default()
+            })
+        };
+        // Started an hour ago, still shown with a 2h expiry
+        let mut recent = event(10_000 - 3600);
+        remove_long_started_event(&mut recent, &now, 7200);
+        assert_eq!(recent, event(10_000 - 3600));
+        // Started 3 hours ago
+        let mut old = event(10_000 - 3 * 3600);
+        remove_long_started_event(&mut old, &now, 7200);
+        assert_eq!(old, None);
+        // Unless it's on all day
+        let mut all_day = event(10_000 - 3 * 3600);
+        all_day.as_mut().unwrap().all_day = true;
+        remove_long_started_event(&mut all_day, &now, 7200);
+        assert!(all_day.is_some());
+        // Or still going on
+        let mut long = event(10_000 - 3 * 3600);
+        long.as_mut().unwrap().event_end = Some(Timestamp {
+            seconds: 10_000 + 60,
+            nanos: 0,
+        });
+        assert!(is_in_progress(long.as_ref().unwrap(), &now));
+        remove_long_started_event(&mut long, &now, 7200);
+        assert!(long.is_some());
+        assert!(!is_in_progress(&event(10_000 + 60).unwrap(), &now));
+    }
+
+    #[test]
+    fn counts_down_to_events() {
+        use chrono::TimeZone;
+        let start = Local.with_ymd_and_hms(2024, 7, 23, 12, 0, 0).unwrap();
+        let mut event = CalendarEvent {
+            event_start: Some(Timestamp::from(SystemTime::from(start))),
+            ..Default::default()
+        };
+        set_countdown(&mut event, NaiveDate::from_ymd_opt(2024, 7, 20).unwrap());
+        assert_eq!(event.date_hint(), DateHint::Countdown);
+        assert_eq!(event.days_until, 3);
+        // Nothing to count down to
+        let mut timeless = CalendarEvent::default();
+        set_countdown(&mut timeless, NaiveDate::from_ymd_opt(2024, 7, 20).unwrap());
+        assert_eq!(timeless.date_hint(), DateHint::Date);
+    }
+
+    #[test]
+    fn takes_last_lines() {
+        let text = "one\ntwo\nthree\n";
+        assert_eq!(last_lines(text, 2), vec!["two", "three"]);
+        assert_eq!(last_lines(text, 10), vec!["one", "two", "three"]);
+        assert!(last_lines(text, 0).is_empty());
+    }
 }
@@ -0,0 +1,199 @@
+//! How far the 3D printer is into its job, from OctoPrint's /api/job: the progress and when it
+//! should be done, only while printing (or paused).
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::Printer;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use prost_types::Timestamp;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+#[derive(Debug)]
+pub struct OctoPrintUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    url: String,
+    api_key: String,
+    octoprint_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for OctoPrintUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(41),
+            UpdateMode::Real => Instant::now() + self.octoprint_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} OctoPrint", self.update_mode);
+        let printer;
+        let now = chrono::offset::Local::now();
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                // A print over the first 50 minutes of every hour, idle after that
+                let minute = now.minute() as i64;
+                printer = (minute < 50).then(|| Printer {
+                    file: "benchy.gcode".into(),
+                    progress: minute as f32 * 2.0,
+                    eta: Some(Timestamp {
+                        seconds: now.timestamp() + (50 - minute) * 60,
+                        nanos: 0,
+                    }),
+                    paused: false,
+                });
+                error_bit.store(
+                    now.second().is_multiple_of(41),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                printer = match self.get_printer(now.timestamp()).await {
+                    Ok(printer) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.octoprint_period.set_success();
+                        printer
+                    }
+                    Err(e) => {
+                        error!("Error getting the OctoPrint job: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.octoprint_period.set_error();
+                        None
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Printer(printer)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl OctoPrintUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let octoprint_config = config.octoprint.as_ref().ok_or("No OctoPrint config")?;
+        if octoprint_config.url.is_empty() {
+            return Err("No OctoPrint URL".into());
+        }
+        let octoprint_period_config = Duration::from_secs(
+            octoprint_config
+                .update_period
+                .as_ref()
+                .ok_or("no OctoPrint update period")?
+                .seconds
+                .try_into()?,
+        );
+        let octoprint_period = ExponentialBackoff::new(
+            octoprint_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(OctoPrintUpdater {
+            update_mode,
+            client,
+            url: octoprint_config.url.trim_end_matches('/').to_string(),
+            api_key: octoprint_config.api_key.clone(),
+            octoprint_period,
+        })
+    }
+
+    async fn get_printer(&self, now: i64) -> Result<Option<Printer>, Box<dyn std::error::Error>> {
+        let url = format!("{}/api/job", self.url);
+        let body = traced(
+            "fetch",
+            retry_http("OctoPrint fetch", || async {
+                self.client
+                    .get(&url)
+                    .header("X-Api-Key", &self.api_key)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        traced_sync("parse", || parse_job(&body, now))
+    }
+}
+
+// None unless a job is under way, e.g. when the printer is idle ("Operational") or disconnected
+// ("Offline")
+fn parse_job(body: &str, now: i64) -> Result<Option<Printer>, Box<dyn std::error::Error>> {
+    let job: Value = serde_json::from_str(body)?;
+    let state = job
+        .get("state")
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("No state in the OctoPrint job: {}", body))?;
+    // "Printing from SD" too
+    let paused = match state {
+        "Paused" | "Pausing" => true,
+        "Starting" | "Resuming" | "Finishing" => false,
+        state if state.starts_with("Printing") => false,
+        _ => return Ok(None),
+    };
+    let number = |pointer: &str| job.pointer(pointer).and_then(Value::as_f64);
+    // Null until OctoPrint has an estimate, e.g. right after starting
+    let eta = number("/progress/printTimeLeft").map(|left| Timestamp {
+        seconds: now + left.round() as i64,
+        nanos: 0,
+    });
+    Ok(Some(Printer {
+        file: job
+            .pointer("/job/file/display")
+            .or_else(|| job.pointer("/job/file/name"))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        progress: number("/progress/completion")
+            .unwrap_or(0.0)
+            .clamp(0.0, 100.0) as f32,
+        eta,
+        paused,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_job_progress() {
+        let printing = r#"{"job": {"file": {"name": "benchy_0.2mm.gcode",
+            "display": "benchy 0.2mm.gcode", "origin": "local"}, "estimatedPrintTime": 8811},
+            "progress": {"completion": 22.1478, "filepos": 337942, "printTime": 276,
+            "printTimeLeft": 912}, "state": "Printing"}"#;
+        let printer = parse_job(printing, 1000).unwrap().unwrap();
+        assert_eq!(printer.file, "benchy 0.2mm.gcode");
+        assert_eq!(printer.progress, 22.1478);
+        assert_eq!(printer.eta.unwrap().seconds, 1912);
+        assert!(!printer.paused);
+
+        let paused = r#"{"job": {"file": {"name": "vase.gcode"}}, "state": "Paused",
+            "progress": {"completion": 80.0, "printTimeLeft": null}}"#;
+        let printer = parse_job(paused, 1000).unwrap().unwrap();
+        assert_eq!(printer.file, "vase.gcode");
+        assert!(printer.paused);
+        assert!(printer.eta.is_none());
+
+        let idle = r#"{"job": {"file": {"name": null}}, "progress": {"completion": null},
+            "state": "Operational"}"#;
+        assert!(parse_job(idle, 1000).unwrap().is_none());
+        assert!(parse_job("{}", 1000).is_err());
+    }
+}
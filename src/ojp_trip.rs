@@ -0,0 +1,354 @@
+//! Plans the configured trips with OJP trip requests, and reads their next connections out of the
+//! responses as departures: towards the trip's destination, leaving when it's time to leave the
+//! origin, with the lines of all the legs.
+//!
+//! See https://opentransportdata.swiss/en/cookbook/ojptriprequest/
+
+use crate::config_extractor::api_config::transport_config::trip::Place;
+use crate::config_extractor::api_config::transport_config::Trip;
+use crate::screen_service::Departure;
+use crate::time_util;
+use crate::transport_updater::{get_time, next_event, read_flag, read_inner_text};
+use prost_types::Timestamp;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use tokio::io::AsyncBufRead;
+use tracing::{debug, error, info};
+
+/// The OJP trip request for the connections of the trip from `departures_from` on
+pub fn create_trip_request(
+    trip: &Trip,
+    number_of_results: u32,
+    departures_from: &chrono::DateTime<chrono::Utc>,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> String {
+    let now_utc_string = now.format("%Y-%m-%dT%H:%M:%S%.3fZ");
+    let from_utc_string = departures_from.format("%Y-%m-%dT%H:%M:%S%.3fZ");
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<OJP xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:xsd="http://www.w3.org/2001/XMLSchema" xmlns="http://www.siri.org.uk/siri" version="1.0" xmlns:ojp="http://www.vdv.de/ojp" xsi:schemaLocation="http://www.siri.org.uk/siri ../ojp-xsd-v1.0/OJP.xsd">
+    <OJPRequest>
+        <ServiceRequest>
+            <RequestTimestamp>{}</RequestTimestamp>
+            <RequestorRef>raspi-screen-server</RequestorRef>
+            <ojp:OJPTripRequest>
+                <RequestTimestamp>{}</RequestTimestamp>
+                <ojp:Origin>
+                    {}
+                    <ojp:DepArrTime>{}</ojp:DepArrTime>
+                </ojp:Origin>
+                <ojp:Destination>
+                    {}
+                </ojp:Destination>
+                <ojp:Params>
+                    <ojp:NumberOfResults>{}</ojp:NumberOfResults>
+                    <ojp:IncludeTrackSections>false</ojp:IncludeTrackSections>
+                    <ojp:IncludeLegProjection>false</ojp:IncludeLegProjection>
+                    <ojp:IncludeIntermediateStops>false</ojp:IncludeIntermediateStops>
+                </ojp:Params>
+            </ojp:OJPTripRequest>
+        </ServiceRequest>
+    </OJPRequest>
+</OJP>
+"#,
+        now_utc_string,
+        now_utc_string,
+        create_place_ref(&trip.origin.unwrap_or_default()),
+        from_utc_string,
+        create_place_ref(&trip.destination.unwrap_or_default()),
+        number_of_results,
+    )
+}
+
+fn create_place_ref(place: &Place) -> String {
+    let place = match place.stop_id {
+        0 => format!(
+            "<ojp:GeoPosition><Longitude>{}</Longitude><Latitude>{}</Latitude>\
+             </ojp:GeoPosition>",
+            place.longitude, place.latitude
+        ),
+        stop_id => format!("<StopPlaceRef>{}</StopPlaceRef>", stop_id),
+    };
+    format!(
+        "<ojp:PlaceRef>{}<ojp:LocationName><ojp:Text>ignored</ojp:Text></ojp:LocationName>\
+         </ojp:PlaceRef>",
+        place
+    )
+}
+
+#[derive(Debug, Default)]
+struct ConnectionBuilder {
+    // When to leave the origin, which may be a walk away from the first stop
+    start_time: Option<Timestamp>,
+    // At the first stop, only when there's real-time data for the estimated time
+    timetabled_time: Option<Timestamp>,
+    estimated_time: Option<Timestamp>,
+    quay: Option<String>,
+    lines: Vec<String>,
+    // Some leg doesn't run, or the connection can't be made anymore
+    cancelled: bool,
+    deviation: bool,
+}
+
+/// Reads the next connections of the trip out of a trip response, as departures towards its
+/// destination
+pub async fn extract_connections<R: AsyncBufRead + Unpin>(
+    body: R,
+    trip: &Trip,
+) -> Result<Vec<Departure>, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_reader(body);
+    reader.config_mut().trim_text(true);
+    let mut buf = vec![];
+    let mut inner_buf = vec![];
+
+    let mut connections = vec![];
+    let mut connection = ConnectionBuilder::default();
+    let mut timed_legs = 0;
+    let mut in_first_board = false;
+    loop {
+        buf.clear();
+        match reader.read_event_into_async(&mut buf).await {
+            Err(quick_xml::Error::Io(e)) => {
+                return Err(format!("Couldn't read the OJP response: {}", e).into());
+            }
+            Err(e) => {
+                error!("Error at position {}: {:?}", reader.error_position(), e);
+                break;
+            }
+            Ok(Event::Eof) => break,
+            Ok(Event::Start(e)) => match e.name().as_ref() {
+                b"ojp:Trip" => {
+                    connection = ConnectionBuilder::default();
+                    timed_legs = 0;
+                }
+                b"ojp:StartTime" if connection.start_time.is_none() => {
+                    if let Ok(Event::Text(t)) = next_event(&mut reader, &mut inner_buf).await {
+                        connection.start_time = Some(get_time(&t)?);
+                    }
+                }
+                b"ojp:TimedLeg" => timed_legs += 1,
+                // Only the first stop tells when the connection leaves
+                b"ojp:LegBoard" => in_first_board = timed_legs == 1,
+                b"ojp:TimetabledTime" if in_first_board => {
+                    if let Ok(Event::Text(t)) = next_event(&mut reader, &mut inner_buf).await {
+                        connection.timetabled_time = Some(get_time(&t)?);
+                    }
+                }
+                b"ojp:EstimatedTime" if in_first_board => {
+                    if let Ok(Event::Text(t)) = next_event(&mut reader, &mut inner_buf).await {
+                        connection.estimated_time = Some(get_time(&t)?);
+                    }
+                }
+                b"ojp:PlannedQuay" | b"ojp:EstimatedQuay" if in_first_board => {
+                    if let Some(quay) = read_inner_text(&mut reader, &mut inner_buf, "Quay").await {
+                        connection.quay = Some(quay);
+                    }
+                }
+                b"ojp:PublishedLineName" => {
+                    if let Some(line) =
+                        read_inner_text(&mut reader, &mut inner_buf, "Line is").await
+                    {
+                        connection.lines.push(line);
+                    }
+                }
+                b"ojp:Cancelled" | b"ojp:NotServicedStop" | b"ojp:Infeasible" => {
+                    connection.cancelled |=
+                        read_flag(&mut reader, &mut inner_buf, "Cancelled").await;
+                }
+                b"ojp:Deviation" | b"ojp:Unplanned" => {
+                    connection.deviation |=
+                        read_flag(&mut reader, &mut inner_buf, "Deviation").await;
+                }
+                _ => (),
+            },
+            Ok(Event::End(e)) => match e.name().as_ref() {
+                b"ojp:LegBoard" => in_first_board = false,
+                b"ojp:Trip" => match &connection {
+                    ConnectionBuilder {
+                        cancelled: true, ..
+                    } => info!("Skipping unviable connection {:?}", &connection),
+                    ConnectionBuilder {
+                        timetabled_time: Some(timetabled_ts),
+                        ..
+                    } => {
+                        let departure = to_departure(&connection, timetabled_ts, trip);
+                        debug!("Found {:?}", departure);
+                        connections.push(departure);
+                    }
+                    // Walking all the way, nothing to catch
+                    _ => debug!("Skipping connection without any service {:?}", &connection),
+                },
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+    Ok(connections)
+}
+
+fn to_departure(
+    connection: &ConnectionBuilder,
+    timetabled_ts: &Timestamp,
+    trip: &Trip,
+) -> Departure {
+    // The walk to the first stop takes as long whatever the delay
+    let walk_seconds = connection
+        .start_time
+        .map_or(0, |start| (timetabled_ts.seconds - start.seconds).max(0));
+    let depart_ts = connection.estimated_time.unwrap_or(*timetabled_ts);
+    let leave_at = |time: &Timestamp| Timestamp {
+        seconds: time.seconds - walk_seconds,
+        nanos: time.nanos,
+    };
+    Departure {
+        destination: trip.destination_name.clone(),
+        departure_time: Some(leave_at(&depart_ts)),
+        timetabled_time: Some(leave_at(timetabled_ts)),
+        delay_minutes: time_util::delay_minutes(timetabled_ts.seconds, depart_ts.seconds),
+        line: connection.lines.join(">"),
+        platform: connection.quay.clone().unwrap_or_default(),
+        deviation: connection.deviation,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trip() -> Trip {
+        Trip {
+            origin: Some(Place {
+                latitude: 46.53,
+                longitude: 6.58,
+                ..Default::default()
+            }),
+            destination: Some(Place {
+                stop_id: 8501118,
+                ..Default::default()
+            }),
+            destination_name: "RENENS".into(),
+        }
+    }
+
+    #[test]
+    fn makes_request() {
+        let now = chrono::Utc::now();
+        let request = create_trip_request(&trip(), 3, &now, &now);
+        assert!(request.contains(
+            "<ojp:Origin>
+                    <ojp:PlaceRef><ojp:GeoPosition><Longitude>6.58</Longitude><Latitude>46.53</Latitude></ojp:GeoPosition>"
+        ));
+        assert!(request.contains(
+            "<ojp:Destination>
+                    <ojp:PlaceRef><StopPlaceRef>8501118</StopPlaceRef>"
+        ));
+        assert!(request.contains("<ojp:NumberOfResults>3</ojp:NumberOfResults>"));
+    }
+
+    #[tokio::test]
+    async fn extracts_connections() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<siri:OJP xmlns:siri="http://www.siri.org.uk/siri" xmlns:ojp="http://www.vdv.de/ojp" version="1.0">
+    <siri:OJPResponse>
+        <siri:ServiceDelivery>
+            <ojp:OJPTripDelivery>
+                <ojp:TripResult>
+                    <ojp:Trip>
+                        <ojp:StartTime>2024-07-23T10:55:00Z</ojp:StartTime>
+                        <ojp:Transfers>1</ojp:Transfers>
+                        <ojp:TripLeg>
+                            <ojp:ContinuousLeg>
+                                <ojp:TimeWindowStart>2024-07-23T10:55:00Z</ojp:TimeWindowStart>
+                            </ojp:ContinuousLeg>
+                        </ojp:TripLeg>
+                        <ojp:TripLeg>
+                            <ojp:TimedLeg>
+                                <ojp:LegBoard>
+                                    <siri:StopPointRef>8588845</siri:StopPointRef>
+                                    <ojp:PlannedQuay>
+                                        <ojp:Text>A</ojp:Text>
+                                    </ojp:PlannedQuay>
+                                    <ojp:ServiceDeparture>
+                                        <ojp:TimetabledTime>2024-07-23T11:00:00Z</ojp:TimetabledTime>
+                                        <ojp:EstimatedTime>2024-07-23T11:02:00Z</ojp:EstimatedTime>
+                                    </ojp:ServiceDeparture>
+                                </ojp:LegBoard>
+                                <ojp:LegAlight>
+                                    <ojp:ServiceArrival>
+                                        <ojp:TimetabledTime>2024-07-23T11:10:00Z</ojp:TimetabledTime>
+                                    </ojp:ServiceArrival>
+                                </ojp:LegAlight>
+                                <ojp:Service>
+                                    <ojp:PublishedLineName>
+                                        <ojp:Text>7</ojp:Text>
+                                    </ojp:PublishedLineName>
+                                </ojp:Service>
+                            </ojp:TimedLeg>
+                        </ojp:TripLeg>
+                        <ojp:TripLeg>
+                            <ojp:TimedLeg>
+                                <ojp:LegBoard>
+                                    <ojp:PlannedQuay>
+                                        <ojp:Text>2</ojp:Text>
+                                    </ojp:PlannedQuay>
+                                    <ojp:ServiceDeparture>
+                                        <ojp:TimetabledTime>2024-07-23T11:15:00Z</ojp:TimetabledTime>
+                                    </ojp:ServiceDeparture>
+                                </ojp:LegBoard>
+                                <ojp:Service>
+                                    <ojp:PublishedLineName>
+                                        <ojp:Text>M1</ojp:Text>
+                                    </ojp:PublishedLineName>
+                                </ojp:Service>
+                            </ojp:TimedLeg>
+                        </ojp:TripLeg>
+                    </ojp:Trip>
+                </ojp:TripResult>
+                <ojp:TripResult>
+                    <ojp:Trip>
+                        <ojp:StartTime>2024-07-23T11:05:00Z</ojp:StartTime>
+                        <ojp:TripLeg>
+                            <ojp:TimedLeg>
+                                <ojp:LegBoard>
+                                    <ojp:ServiceDeparture>
+                                        <ojp:TimetabledTime>2024-07-23T11:10:00Z</ojp:TimetabledTime>
+                                    </ojp:ServiceDeparture>
+                                </ojp:LegBoard>
+                                <ojp:Service>
+                                    <ojp:PublishedLineName>
+                                        <ojp:Text>33</ojp:Text>
+                                    </ojp:PublishedLineName>
+                                    <ojp:Cancelled>true</ojp:Cancelled>
+                                </ojp:Service>
+                            </ojp:TimedLeg>
+                        </ojp:TripLeg>
+                    </ojp:Trip>
+                </ojp:TripResult>
+            </ojp:OJPTripDelivery>
+        </siri:ServiceDelivery>
+    </siri:OJPResponse>
+</siri:OJP>
+"#;
+        let connections = extract_connections(body.as_bytes(), &trip()).await.unwrap();
+        assert_eq!(
+            connections,
+            vec![Departure {
+                destination: "RENENS".into(),
+                // Leave 5 minutes before the delayed bus
+                departure_time: Some(Timestamp {
+                    seconds: 1721732220,
+                    nanos: 0
+                }),
+                timetabled_time: Some(Timestamp {
+                    seconds: 1721732100,
+                    nanos: 0
+                }),
+                delay_minutes: 2,
+                line: "7>M1".into(),
+                platform: "A".into(),
+                ..Default::default()
+            }]
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Which doors and windows are open, from Home Assistant's contact sensors, for the screen to warn
+//! e.g. "2 windows open" before leaving.
+//!
+//! A single call to Home Assistant's /api/states gets them all: the binary sensors whose device
+//! class is a door or a window (or the ones listed in the config), "on" meaning open.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::Openings;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::header::AUTHORIZATION;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+// The device classes of contact sensors, "opening" being the generic one
+const DOOR_CLASSES: [&str; 3] = ["door", "garage_door", "opening"];
+const WINDOW_CLASSES: [&str; 1] = ["window"];
+
+#[derive(Debug)]
+pub struct OpeningsUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    url: String,
+    token: String,
+    entity_ids: Vec<String>,
+    openings_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for OpeningsUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(29),
+            UpdateMode::Real => Instant::now() + self.openings_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} openings", self.update_mode);
+        let openings;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // Airing the kitchen over the first ten minutes of every quarter hour
+                let open: Vec<String> = match now.minute() % 15 < 10 {
+                    true => vec!["Kitchen".into(), "Bathroom".into()],
+                    false => vec![],
+                };
+                openings = Some(Openings {
+                    doors: 0,
+                    windows: open.len() as u32,
+                    open,
+                });
+                error_bit.store(
+                    now.second().is_multiple_of(29),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                openings = match self.get_openings().await {
+                    Ok(fetched) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.openings_period.set_success();
+                        Some(fetched)
+                    }
+                    Err(e) => {
+                        error!("Error getting the open doors and windows: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.openings_period.set_error();
+                        None
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Openings(openings)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl OpeningsUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let openings_config = config.openings.as_ref().ok_or("No openings config")?;
+        if openings_config.url.is_empty() {
+            return Err("No Home Assistant URL for the openings".into());
+        }
+        let openings_period_config = Duration::from_secs(
+            openings_config
+                .update_period
+                .as_ref()
+                .ok_or("no openings update period")?
+                .seconds
+                .try_into()?,
+        );
+        let openings_period = ExponentialBackoff::new(
+            openings_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(OpeningsUpdater {
+            update_mode,
+            client,
+            url: openings_config.url.trim_end_matches('/').to_string(),
+            token: openings_config.token.clone(),
+            entity_ids: openings_config.entity_ids.clone(),
+            openings_period,
+        })
+    }
+
+    async fn get_openings(&self) -> Result<Openings, Box<dyn std::error::Error>> {
+        let url = format!("{}/api/states", self.url);
+        let body = traced(
+            "fetch",
+            retry_http("Openings fetch", || async {
+                self.client
+                    .get(&url)
+                    .header(AUTHORIZATION, format!("Bearer {}", self.token))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        traced_sync("parse", || parse_openings(&body, &self.entity_ids))
+    }
+}
+
+// The listed sensors only count as windows when their device class says so, doors otherwise
+fn parse_openings(
+    body: &str,
+    entity_ids: &[String],
+) -> Result<Openings, Box<dyn std::error::Error>> {
+    let states: Value = serde_json::from_str(body)?;
+    let states = states.as_array().ok_or("No states from Home Assistant")?;
+    let mut openings = Openings::default();
+    for state in states {
+        let text = |pointer: &str| state.pointer(pointer).and_then(Value::as_str);
+        let Some(entity_id) = text("/entity_id") else {
+            continue;
+        };
+        let class = text("/attributes/device_class").unwrap_or_default();
+        let watched = match entity_ids.is_empty() {
+            true => {
+                entity_id.starts_with("binary_sensor.")
+                    && (DOOR_CLASSES.contains(&class) || WINDOW_CLASSES.contains(&class))
+            }
+            false => entity_ids.iter().any(|watched| watched == entity_id),
+        };
+        if !watched || text("/state") != Some("on") {
+            continue;
+        }
+        match WINDOW_CLASSES.contains(&class) {
+            true => openings.windows += 1,
+            false => openings.doors += 1,
+        }
+        let name = text("/attributes/friendly_name").unwrap_or(entity_id);
+        openings.open.push(name.to_string());
+    }
+    Ok(openings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_open_doors_and_windows() {
+        let body = r#"[
+            {"entity_id": "binary_sensor.kitchen_window", "state": "on",
+             "attributes": {"device_class": "window", "friendly_name": "Kitchen"}},
+            {"entity_id": "binary_sensor.bathroom_window", "state": "off",
+             "attributes": {"device_class": "window", "friendly_name": "Bathroom"}},
+            {"entity_id": "binary_sensor.front_door", "state": "on",
+             "attributes": {"device_class": "door", "friendly_name": "Front door"}},
+            {"entity_id": "binary_sensor.hallway_motion", "state": "on",
+             "attributes": {"device_class": "motion", "friendly_name": "Hallway"}},
+            {"entity_id": "sensor.living_room_temperature", "state": "21.5",
+             "attributes": {"device_class": "temperature"}}
+        ]"#;
+        let openings = parse_openings(body, &[]).unwrap();
+        assert_eq!(
+            openings,
+            Openings {
+                doors: 1,
+                windows: 1,
+                open: vec!["Kitchen".into(), "Front door".into()],
+            }
+        );
+
+        let watched = ["binary_sensor.kitchen_window".to_string()];
+        let openings = parse_openings(body, &watched).unwrap();
+        assert_eq!((openings.doors, openings.windows), (0, 1));
+    }
+}
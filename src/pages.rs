@@ -0,0 +1,157 @@
+//! Several pages of sections (clock, departures, charts, ...) for the client to flip through,
+//! rather than cramming everything onto a single 64x32 layout. Pages change every page period,
+//! on presses of a GPIO button, or both, whatever the content polling is up to.
+
+use crate::config_extractor::api_config::{page::Section, Client};
+use log::{debug, info, warn};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::{Duration, Instant};
+
+// Short enough not to miss a press, long enough for the contacts to settle
+const BUTTON_POLL_PERIOD: Duration = Duration::from_millis(50);
+
+const ALL_SECTIONS: [Section; 8] = [
+    Section::Clock,
+    Section::Debts,
+    Section::Departures,
+    Section::BottomLine,
+    Section::Charts,
+    Section::Moon,
+    Section::Diagnostics,
+    Section::Printer,
+];
+
+#[derive(Debug)]
+pub struct PageScheduler {
+    pages: Vec<Vec<Section>>,
+    // None to only change pages with the button
+    period: Option<Duration>,
+    current: usize,
+    next_switch: Instant,
+}
+
+impl PageScheduler {
+    pub fn new(config: Option<&Client>) -> Self {
+        let mut pages: Vec<Vec<Section>> = config
+            .iter()
+            .flat_map(|client| client.pages.iter())
+            .map(|page| page.sections().collect())
+            .filter(|sections: &Vec<Section>| {
+                if sections.is_empty() {
+                    warn!("Skipping a page without sections");
+                }
+                !sections.is_empty()
+            })
+            .collect();
+        // Today's layout, with everything at once
+        if pages.is_empty() {
+            pages.push(ALL_SECTIONS.to_vec());
+        }
+        let period = config
+            .and_then(|client| client.page_period.as_ref())
+            .and_then(|period| u64::try_from(period.seconds).ok())
+            .filter(|seconds| *seconds > 0)
+            .map(Duration::from_secs);
+        info!("{} page(s), changing every {:?}", pages.len(), period);
+        PageScheduler {
+            pages,
+            period,
+            current: 0,
+            next_switch: Instant::now() + period.unwrap_or_default(),
+        }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn sections(&self) -> &[Section] {
+        &self.pages[self.current]
+    }
+
+    /// When the current page times out, None if it never does (e.g. with a single page)
+    pub fn next_switch(&self) -> Option<Instant> {
+        match (self.pages.len() > 1, self.period) {
+            (true, Some(_)) => Some(self.next_switch),
+            _ => None,
+        }
+    }
+
+    /// On to the next page, which gets a full period
+    pub fn advance(&mut self) {
+        self.current = (self.current + 1) % self.pages.len();
+        self.next_switch = Instant::now() + self.period.unwrap_or_default();
+        debug!("Showing page {}", self.current);
+    }
+}
+
+/// Sends on `presses` whenever the button on the `path` GPIO value file gets pressed, i.e. goes
+/// from 1 to 0, until the receiver is dropped
+pub fn watch_button(path: String, presses: UnboundedSender<()>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(BUTTON_POLL_PERIOD);
+        let mut was_pressed = false;
+        loop {
+            interval.tick().await;
+            // The file is tiny and lives in memory, no need for async reads
+            let pressed = match std::fs::read_to_string(&path) {
+                Ok(value) => value.trim() == "0",
+                Err(e) => {
+                    warn!(
+                        "Couldn't read the page button {}: {}, giving up on it",
+                        path, e
+                    );
+                    return;
+                }
+            };
+            if pressed && !was_pressed && presses.send(()).is_err() {
+                return;
+            }
+            was_pressed = pressed;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_extractor::api_config::Page;
+
+    #[test]
+    fn flips_through_pages() {
+        // Everything on a single page by default
+        let pages = PageScheduler::new(None);
+        assert_eq!(pages.sections(), ALL_SECTIONS);
+        assert!(pages.next_switch().is_none());
+
+        let mut config = Client {
+            pages: vec![
+                Page {
+                    sections: vec![Section::Clock as i32, Section::Departures as i32],
+                },
+                Page { sections: vec![] },
+                Page {
+                    sections: vec![Section::Charts as i32],
+                },
+            ],
+            ..Default::default()
+        };
+        let mut pages = PageScheduler::new(Some(&config));
+        assert_eq!(pages.sections(), [Section::Clock, Section::Departures]);
+        // Button presses only
+        assert!(pages.next_switch().is_none());
+        pages.advance();
+        assert_eq!(pages.current(), 1);
+        assert_eq!(pages.sections(), [Section::Charts]);
+        pages.advance();
+        assert_eq!(pages.current(), 0);
+
+        config.page_period = Some(pbjson_types::Duration {
+            seconds: 10,
+            nanos: 0,
+        });
+        let pages = PageScheduler::new(Some(&config));
+        let next_switch = pages.next_switch().unwrap();
+        assert!(next_switch > Instant::now() + Duration::from_secs(9));
+    }
+}
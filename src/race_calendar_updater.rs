@@ -0,0 +1,303 @@
+//! A countdown to the next session of a racing series, e.g. "F1 Quali 2d3h", hidden while it's
+//! more than a few days away.
+//!
+//! F1's calendar comes from Jolpica (https://github.com/jolpica/jolpica-f1), which took over the
+//! Ergast API and lists every session of the season's weekends. Other series (e.g. MotoGP) have no
+//! such API, an ICS calendar with an event per session does the job instead.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::gcal_updater;
+use crate::retry::retry_http;
+use crate::screen_service::RaceSession;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::{DateTime, Local, Timelike};
+use prost_types::Timestamp;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+const F1_CALENDAR_API: &str = "https://api.jolpi.ca/ergast/f1/current.json";
+const DEFAULT_LABEL: &str = "F1";
+const DEFAULT_MAX_DAYS: u32 = 7;
+// The sessions of a weekend as Jolpica names them, and shorter for the screen
+const F1_SESSIONS: [(&str, &str); 6] = [
+    ("FirstPractice", "FP1"),
+    ("SecondPractice", "FP2"),
+    ("ThirdPractice", "FP3"),
+    ("SprintQualifying", "Sprint quali"),
+    ("Sprint", "Sprint"),
+    ("Qualifying", "Quali"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Session {
+    // e.g. "Japanese Grand Prix", empty for ICS calendars
+    event: String,
+    // e.g. "Quali"
+    name: String,
+    start: i64,
+}
+
+#[derive(Debug)]
+pub struct RaceCalendarUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    label: String,
+    ics_url: String,
+    max_days: u32,
+    race_calendar_period: ExponentialBackoff,
+    // Soonest first, kept when a fetch fails since calendars hardly change
+    sessions: Vec<Session>,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for RaceCalendarUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(41),
+            UpdateMode::Real => {
+                let period = self.race_calendar_period.get_current_duration();
+                if self.race_calendar_period.get_is_error() {
+                    return Instant::now() + period;
+                }
+                // Also when the next session starts or comes close enough to show
+                let now = Local::now().timestamp();
+                let max_seconds = self.max_days as i64 * 86400;
+                let until_change = self
+                    .sessions
+                    .iter()
+                    .flat_map(|session| [session.start - max_seconds, session.start])
+                    .map(|change| change - now)
+                    .filter(|&seconds| seconds > 0)
+                    .min()
+                    .map(|seconds| Duration::from_secs(seconds as u64));
+                Instant::now() + until_change.map_or(period, |until| until.min(period))
+            }
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} race calendar", self.update_mode);
+        let now = chrono::offset::Local::now();
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                // Qualifying at every full hour
+                let start = now.timestamp() + 60 * (60 - now.minute() as i64);
+                self.sessions = vec![Session {
+                    event: "Dummy Grand Prix".into(),
+                    name: "Quali".into(),
+                    start,
+                }];
+                error_bit.store(
+                    now.second().is_multiple_of(41),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => match self.get_sessions().await {
+                Ok(fetched) => {
+                    error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                    self.race_calendar_period.set_success();
+                    self.sessions = fetched;
+                }
+                Err(e) => {
+                    error!("Error getting the race calendar: {}", e);
+                    error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                    self.race_calendar_period.set_error();
+                }
+            },
+        }
+        vec![ContentUpdate::RaceSession(get_next_session(
+            &self.sessions,
+            &self.label,
+            self.max_days,
+            now.timestamp(),
+        ))]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl RaceCalendarUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let race_calendar_config = config
+            .race_calendar
+            .as_ref()
+            .ok_or("No race calendar config")?;
+        let race_calendar_period_config = Duration::from_secs(
+            race_calendar_config
+                .update_period
+                .as_ref()
+                .ok_or("no race calendar update period")?
+                .seconds
+                .try_into()?,
+        );
+        let race_calendar_period = ExponentialBackoff::new(
+            race_calendar_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        let label = match race_calendar_config.label.is_empty() {
+            true => DEFAULT_LABEL,
+            false => race_calendar_config.label.as_str(),
+        };
+        Ok(RaceCalendarUpdater {
+            update_mode,
+            client,
+            label: label.to_string(),
+            ics_url: race_calendar_config.ics_url.clone(),
+            max_days: race_calendar_config.max_days.unwrap_or(DEFAULT_MAX_DAYS),
+            race_calendar_period,
+            sessions: vec![],
+        })
+    }
+
+    async fn get_sessions(&self) -> Result<Vec<Session>, Box<dyn std::error::Error>> {
+        let url = match self.ics_url.is_empty() {
+            true => F1_CALENDAR_API,
+            false => self.ics_url.as_str(),
+        };
+        let body = traced(
+            "fetch",
+            retry_http("Race calendar fetch", || async {
+                self.client
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        let mut sessions = traced_sync("parse", || match self.ics_url.is_empty() {
+            true => parse_f1_sessions(&body),
+            false => parse_ics_sessions(body),
+        })?;
+        sessions.sort_by_key(|session| session.start);
+        Ok(sessions)
+    }
+}
+
+fn parse_f1_sessions(body: &str) -> Result<Vec<Session>, Box<dyn std::error::Error>> {
+    let calendar: Value = serde_json::from_str(body)?;
+    let races = calendar
+        .pointer("/MRData/RaceTable/Races")
+        .and_then(Value::as_array)
+        .ok_or("No races in the F1 calendar")?;
+    let mut sessions = vec![];
+    for race in races {
+        let event = race.get("raceName").and_then(Value::as_str).unwrap_or("?");
+        // The race itself is dated at the top level, its other sessions each in their own object
+        let weekend = F1_SESSIONS
+            .iter()
+            .filter_map(|(key, name)| Some((race.get(*key)?, *name)))
+            .chain([(race, "Race")]);
+        for (session, name) in weekend {
+            let Some(start) = parse_f1_start(session) else {
+                continue;
+            };
+            sessions.push(Session {
+                event: event.to_string(),
+                name: name.to_string(),
+                start,
+            });
+        }
+    }
+    Ok(sessions)
+}
+
+// e.g. {"date": "2024-10-27", "time": "20:00:00Z"}, the time is missing for old seasons
+fn parse_f1_start(session: &Value) -> Option<i64> {
+    let date = session.get("date")?.as_str()?;
+    let time = session.get("time")?.as_str()?;
+    let start = DateTime::parse_from_rfc3339(&format!("{}T{}", date, time)).ok()?;
+    Some(start.timestamp())
+}
+
+fn parse_ics_sessions(ics: String) -> Result<Vec<Session>, Box<dyn std::error::Error>> {
+    Ok(gcal_updater::parse_events(ics)?
+        .into_iter()
+        .filter_map(|event| {
+            Some(Session {
+                event: String::new(),
+                name: event.event_title,
+                start: event.event_start?.seconds,
+            })
+        })
+        .collect())
+}
+
+// The first session yet to start, unless it's more than `max_days` away
+fn get_next_session(
+    sessions: &[Session],
+    label: &str,
+    max_days: u32,
+    now: i64,
+) -> Option<RaceSession> {
+    let next = sessions.iter().find(|session| session.start > now)?;
+    if next.start - now > max_days as i64 * 86400 {
+        return None;
+    }
+    Some(RaceSession {
+        series: label.to_string(),
+        event: next.event.clone(),
+        session: next.name.clone(),
+        start: Some(Timestamp {
+            seconds: next.start,
+            nanos: 0,
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_next_close_session() {
+        let body = r#"{"MRData": {"series": "f1", "RaceTable": {"season": "2024", "Races": [
+            {"season": "2024", "round": "20", "raceName": "Mexico City Grand Prix",
+             "date": "2024-10-27", "time": "20:00:00Z",
+             "FirstPractice": {"date": "2024-10-25", "time": "18:30:00Z"},
+             "SecondPractice": {"date": "2024-10-25", "time": "22:00:00Z"},
+             "ThirdPractice": {"date": "2024-10-26", "time": "17:30:00Z"},
+             "Qualifying": {"date": "2024-10-26", "time": "21:00:00Z"}},
+            {"season": "2024", "round": "21", "raceName": "São Paulo Grand Prix",
+             "date": "2024-11-03", "time": "17:00:00Z",
+             "FirstPractice": {"date": "2024-11-01", "time": "14:30:00Z"},
+             "SprintQualifying": {"date": "2024-11-01", "time": "18:30:00Z"},
+             "Sprint": {"date": "2024-11-02", "time": "14:00:00Z"},
+             "Qualifying": {"date": "2024-11-02"}}
+        ]}}}"#;
+        let sessions = parse_f1_sessions(body).unwrap();
+        // Without the qualifying that has no time
+        assert_eq!(sessions.len(), 9);
+        assert_eq!(sessions[4].name, "Race");
+
+        // 2024-10-26 12:00 UTC
+        let now = 1729944000;
+        let next = get_next_session(&sessions, "F1", 7, now).unwrap();
+        assert_eq!(
+            (next.event.as_str(), next.session.as_str()),
+            ("Mexico City Grand Prix", "FP3")
+        );
+        assert_eq!(next.start.unwrap().seconds, now + 5 * 3600 + 1800);
+
+        // Between the two weekends, with the next one too far to show
+        let after_mexico = 1730073600;
+        assert!(get_next_session(&sessions, "F1", 2, after_mexico).is_none());
+        let next = get_next_session(&sessions, "F1", 7, after_mexico).unwrap();
+        assert_eq!(next.session, "FP1");
+    }
+}
@@ -0,0 +1,123 @@
+use crate::exponential_backoff::ExponentialBackoff;
+use std::fmt::Display;
+use std::future::Future;
+use tokio::time::Duration;
+use tracing::warn;
+
+// What the updaters use for their HTTP requests: 3 attempts, 1s then 2s apart
+const HTTP_ATTEMPTS: u32 = 3;
+const HTTP_FIRST_DELAY: Duration = Duration::from_secs(1);
+
+/// Runs the operation up to `attempts` times, waiting exponentially longer between attempts,
+/// as long as the errors it returns are transient. Returns the first success or the last error.
+pub async fn retry<T, E, F, Fut>(
+    what: &str,
+    attempts: u32,
+    first_delay: Duration,
+    is_transient: impl Fn(&E) -> bool,
+    mut operation: F,
+) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut delays = ExponentialBackoff::new(Duration::ZERO, first_delay, first_delay * 8);
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Ok(result) => return Ok(result),
+            Err(e) if attempt < attempts && is_transient(&e) => {
+                delays.set_error();
+                warn!(
+                    "{} failed (attempt {}/{}), retrying in {:?}: {}",
+                    what,
+                    attempt,
+                    attempts,
+                    delays.get_current_duration(),
+                    e
+                );
+                tokio::time::sleep(delays.get_current_duration()).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Retries HTTP requests that failed in a way that could go away by itself
+pub async fn retry_http<T, F, Fut>(what: &str, operation: F) -> Result<T, reqwest::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, reqwest::Error>>,
+{
+    retry(
+        what,
+        HTTP_ATTEMPTS,
+        HTTP_FIRST_DELAY,
+        is_transient_http_error,
+        operation,
+    )
+    .await
+}
+
+// Timeouts, connection issues and 5xx are worth retrying, other errors won't fix themselves
+fn is_transient_http_error(error: &reqwest::Error) -> bool {
+    error.is_timeout()
+        || error.is_connect()
+        || error
+            .status()
+            .is_some_and(|status| status.is_server_error())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn is_transient(error: &String) -> bool {
+        error == "transient"
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors_until_success() {
+        let calls = Cell::new(0);
+        let result = retry("test", 3, Duration::from_millis(1), is_transient, || {
+            calls.set(calls.get() + 1);
+            let result = match calls.get() {
+                3 => Ok(42),
+                _ => Err("transient".to_string()),
+            };
+            async move { result }
+        })
+        .await;
+        assert_eq!(result, Ok(42));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let calls = Cell::new(0);
+        let result: Result<(), String> =
+            retry("test", 3, Duration::from_millis(1), is_transient, || {
+                calls.set(calls.get() + 1);
+                async { Err("transient".to_string()) }
+            })
+            .await;
+        assert_eq!(result, Err("transient".to_string()));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn doesnt_retry_permanent_errors() {
+        let calls = Cell::new(0);
+        let result: Result<(), String> =
+            retry("test", 3, Duration::from_millis(1), is_transient, || {
+                calls.set(calls.get() + 1);
+                async { Err("permanent".to_string()) }
+            })
+            .await;
+        assert_eq!(result, Err("permanent".to_string()));
+        assert_eq!(calls.get(), 1);
+    }
+}
@@ -1,78 +1,180 @@
 /// Example showing some basic usage of the C++ library.
+mod bdf_font;
+mod blanking;
 mod config_extractor;
+mod event_format;
+mod glyph_fallback;
+mod hash_beacon;
+mod micro_chart;
+mod pages;
+mod theme;
+mod time_util;
 
+// Clients don't use every message (e.g. the ones for constrained clients)
+#[allow(dead_code)]
 pub mod screen_service {
     tonic::include_proto!("screen_service"); // The string specified here must match the proto package name
 }
 
 use crate::config_extractor::cli;
+use bdf_font::BdfFont;
+use blanking::Blanking;
 use chrono::{DateTime, Datelike, Local, Timelike};
-use config_extractor::api_config::ApiConfig;
+use config_extractor::api_config::page::Section;
+use config_extractor::api_config::{ApiConfig, Client};
 use config_extractor::extract_config;
 use embedded_graphics::{
-    mono_font::{ascii::FONT_4X6, ascii::FONT_5X7, ascii::FONT_9X15_BOLD, MonoTextStyle},
+    image::ImageRaw,
+    mono_font::{
+        ascii::FONT_4X6, ascii::FONT_5X7, ascii::FONT_9X15_BOLD, mapping::GlyphMapping,
+        DecorationDimensions, MonoFont, MonoTextStyle,
+    },
     pixelcolor::Rgb888,
     prelude::*,
+    primitives::{Circle, PrimitiveStyle, Rectangle, Triangle},
     text::Text,
 };
+use glyph_fallback::{Coverage, GlyphFallback};
 use log::{debug, error, info, warn};
+use micro_chart::MicroChart;
+use pages::PageScheduler;
 use rpi_led_matrix::{LedCanvas, LedMatrix, LedMatrixOptions, LedRuntimeOptions};
 use screen_service::{
-    screen_service_client::ScreenServiceClient, ScreenContentReply, ScreenContentRequest,
-    ScreenHashRequest,
+    appliance::State as ApplianceState, calendar_event::DateHint, ev_charger::State,
+    kitty_debt::Trend, screen_service_client::ScreenServiceClient, Appliance, CalendarEvent,
+    Diagnostics, EvCharger, Headline, Match, Openings, Printer, Quote, RaceSession,
+    ScreenContentReply, ScreenContentRequest, ScreenHashRequest, Solar, Tasks, TextWidget,
 };
+use std::collections::HashMap;
+use std::path::Path;
+use theme::{Palette, Themes, DEFAULT_PALETTE};
 use tonic::transport::Channel;
 
-// Styles used by the drawing operations.
-fn clock_style(b: f32) -> MonoTextStyle<'static, Rgb888> {
-    MonoTextStyle::new(
-        &FONT_9X15_BOLD,
-        Rgb888::new(
-            (f32::from(0xff as u8) * b) as u8,
-            (f32::from(0xff as u8) * b) as u8,
-            (f32::from(0xff as u8) * b) as u8,
-        ),
-    )
+// In pixels per second, for disruptions, headlines and the text too wide for the panel
+const DEFAULT_SCROLL_SPEED: u32 = 16;
+// How long the text too wide for the panel rests at its start and end
+const DEFAULT_SCROLL_PAUSE: tokio::time::Duration = tokio::time::Duration::from_secs(2);
+// Finished appliances blink on the scroll ticks, about twice a second at the default speed
+const BLINK_TICKS: u32 = 8;
+// Over this, the room needs airing
+const STALE_AIR_CO2_PPM: f32 = 1000.0;
+// Where the diagnostics gauges turn red: the Pi throttles from 80°C, and services start failing
+// on full disks
+const CRITICAL_CPU_TEMPERATURE: f32 = 80.0;
+const CRITICAL_USAGE_PERCENT: f32 = 90.0;
+// How many departure lines fit between the clock and the bottom line
+const BUS_LINES: usize = 2;
+
+// The style of the drawing operations' texts, `color` dimmed to the brightness `b`
+fn text_style(
+    font: &'static MonoFont<'static>,
+    color: [u8; 3],
+    b: f32,
+) -> MonoTextStyle<'static, Rgb888> {
+    let [red, green, blue] = color.map(|channel| (f32::from(channel) * b) as u8);
+    MonoTextStyle::new(font, Rgb888::new(red, green, blue))
 }
-fn debt_style(b: f32) -> MonoTextStyle<'static, Rgb888> {
-    MonoTextStyle::new(
-        &FONT_5X7,
-        Rgb888::new(
-            (f32::from(0xcd as u8) * b) as u8,
-            (f32::from(0xcd as u8) * b) as u8,
-            (f32::from(0xf1 as u8) * b) as u8,
-        ),
-    )
+
+// What the sections with text are drawn with: the compiled-in fonts, unless the config has BDF
+// fonts for them
+struct Fonts {
+    clock: &'static MonoFont<'static>,
+    debts: &'static MonoFont<'static>,
+    departures: &'static MonoFont<'static>,
+    bottom_line: &'static MonoFont<'static>,
+    // The cells of the loaded fonts, by section. The usual clock font also has its usual spot,
+    // loaded ones get centered.
+    loaded: HashMap<Section, &'static HashMap<char, usize>>,
 }
-fn bus_style(b: f32) -> MonoTextStyle<'static, Rgb888> {
-    MonoTextStyle::new(
-        &FONT_5X7,
-        Rgb888::new(
-            (f32::from(0xff as u8) * b) as u8,
-            (f32::from(0xe6 as u8) * b) as u8,
-            (f32::from(0x89 as u8) * b) as u8,
-        ),
-    )
+
+impl Fonts {
+    fn new(client_config: Option<&Client>) -> Self {
+        let mut fonts = Fonts {
+            clock: &FONT_9X15_BOLD,
+            debts: &FONT_5X7,
+            departures: &FONT_5X7,
+            bottom_line: &FONT_4X6,
+            loaded: HashMap::new(),
+        };
+        for font in client_config.iter().flat_map(|client| client.fonts.iter()) {
+            let section = font.section();
+            let slot = match section {
+                Section::Clock => &mut fonts.clock,
+                Section::Debts => &mut fonts.debts,
+                Section::Departures => &mut fonts.departures,
+                Section::BottomLine => &mut fonts.bottom_line,
+                _ => {
+                    warn!(
+                        "No text in the {:?} section to draw with {}",
+                        section, font.path
+                    );
+                    continue;
+                }
+            };
+            match BdfFont::load(Path::new(&font.path)) {
+                Ok(loaded) => {
+                    info!("Drawing the {:?} section with {}", section, font.path);
+                    let (mono_font, cells) = to_mono_font(loaded);
+                    *slot = mono_font;
+                    fonts.loaded.insert(section, cells);
+                }
+                Err(e) => warn!(
+                    "Couldn't load the font {} for the {:?} section, keeping the usual one: {}",
+                    font.path, section, e
+                ),
+            }
+        }
+        fonts
+    }
+
+    // What the section's font can draw
+    fn coverage(&self, section: Section) -> Coverage<'static> {
+        match self.loaded.get(&section).copied() {
+            Some(cells) => Coverage::Loaded(cells),
+            None => Coverage::BuiltIn,
+        }
+    }
 }
-fn cal_style(b: f32) -> MonoTextStyle<'static, Rgb888> {
-    MonoTextStyle::new(
-        &FONT_4X6,
-        Rgb888::new(
-            (f32::from(0xd4 as u8) * b) as u8,
-            (f32::from(0xfd as u8) * b) as u8,
-            (f32::from(0xc7 as u8) * b) as u8,
-        ),
-    )
+
+// Which cell of a loaded font each character is drawn from
+struct CellMapping {
+    cells: HashMap<char, usize>,
+    // For the characters the font doesn't have
+    replacement: usize,
 }
-fn err_style(b: f32) -> MonoTextStyle<'static, Rgb888> {
-    MonoTextStyle::new(
-        &FONT_4X6,
-        Rgb888::new(
-            (f32::from(0xff as u8) * b) as u8,
-            (f32::from(0x00 as u8) * b) as u8,
-            (f32::from(0x00 as u8) * b) as u8,
-        ),
-    )
+
+impl GlyphMapping for CellMapping {
+    fn index(&self, c: char) -> usize {
+        self.cells.get(&c).copied().unwrap_or(self.replacement)
+    }
+}
+
+// Loaded once for the whole run, so leaked to live as long as the compiled-in fonts. Also returns
+// the font's cells, to know which characters it has.
+fn to_mono_font(font: BdfFont) -> (&'static MonoFont<'static>, &'static HashMap<char, usize>) {
+    let cells: HashMap<char, usize> = font
+        .chars
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (*c, i))
+        .collect();
+    let replacement = cells
+        .get(&glyph_fallback::DEFAULT_REPLACEMENT)
+        .copied()
+        .unwrap_or_default();
+    let glyph_mapping: &'static CellMapping =
+        Box::leak(Box::new(CellMapping { cells, replacement }));
+    let image: &'static [u8] = font.image.leak();
+    let mono_font = Box::leak(Box::new(MonoFont {
+        image: ImageRaw::new(image, font.image_width),
+        glyph_mapping,
+        character_size: Size::new(font.cell_width, font.cell_height),
+        character_spacing: 0,
+        baseline: font.baseline,
+        strikethrough: DecorationDimensions::new(font.cell_height / 2, 1),
+        underline: DecorationDimensions::new(font.baseline + 1, 1),
+    }));
+    (mono_font, &glyph_mapping.cells)
 }
 
 fn get_options_from_config(api_config: &ApiConfig) -> (LedMatrixOptions, LedRuntimeOptions) {
@@ -164,118 +266,674 @@ async fn make_full_request(client: &mut ScreenServiceClient<Channel>) -> ScreenC
 }
 
 fn print_error_bit(canvas: &mut LedCanvas) {
-    Text::new(".", Point::new(0, 0), err_style(0.5))
-        .draw(canvas)
-        .inspect_err(|e| error!("Can't even print the error bit: {:?}\nI'm giving up.", e))
-        .expect("Can't even print the error bit, I'm giving up.");
+    Text::new(
+        ".",
+        Point::new(0, 0),
+        text_style(&FONT_4X6, DEFAULT_PALETTE.error, 0.5),
+    )
+    .draw(canvas)
+    .inspect_err(|e| error!("Can't even print the error bit: {:?}\nI'm giving up.", e))
+    .expect("Can't even print the error bit, I'm giving up.");
 }
 
-fn draw_content_onto_canvas(
+// The numbers of the content charts can draw, by the name their config refers to them with
+fn get_chart_values<'a>(content: &'a ScreenContentReply, source: &str) -> Option<&'a [f32]> {
+    match source {
+        "temperature_trend" => Some(
+            content
+                .weather
+                .as_ref()
+                .map(|weather| weather.temperature_trend.as_slice())
+                .unwrap_or_default(),
+        ),
+        _ => None,
+    }
+}
+
+// A tiny moon in the top right corner, as bright as it is full
+fn draw_moon(
     canvas: &mut LedCanvas,
-    content: &ScreenContentReply,
+    moon_phase: f32,
+    b: f32,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Consider graceful handling of the expect calls below
-    canvas.clear();
-    let now = Local::now();
+    let lit = (1.0 - (moon_phase * std::f32::consts::TAU).cos()) / 2.0;
+    let color = |lit: f32| {
+        Rgb888::new(
+            (f32::from(0xf0 as u8) * b * lit) as u8,
+            (f32::from(0xe0 as u8) * b * lit) as u8,
+            (f32::from(0x90 as u8) * b * lit) as u8,
+        )
+    };
+    let top_left = Point::new(canvas.size().width as i32 - 6, 1);
+    // The outline stays a little visible at new moon
+    Circle::new(top_left, 5)
+        .into_styled(PrimitiveStyle::with_fill(color(lit.max(0.15))))
+        .draw(canvas)?;
+    Ok(())
+}
 
-    //let time_text = "19:24";
-    let time_text = format!("{}", now.format("%H:%M")); // pls help me
-    Text::new(
-        &time_text,
-        Point::new(9, 9),
-        clock_style(content.brightness),
+// The 3D printer's progress as a 1px bar along the top row, dimmer while paused
+fn draw_printer(
+    canvas: &mut LedCanvas,
+    printer: &Printer,
+    b: f32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let b = match printer.paused {
+        true => b * 0.4,
+        false => b,
+    };
+    let width = canvas.size().width;
+    let done = ((printer.progress / 100.0).clamp(0.0, 1.0) * width as f32).round() as u32;
+    Rectangle::new(Point::new(0, 0), Size::new(done, 1))
+        .into_styled(PrimitiveStyle::with_fill(Rgb888::new(
+            0,
+            (f32::from(0x90 as u8) * b) as u8,
+            (f32::from(0xff as u8) * b) as u8,
+        )))
+        .draw(canvas)?;
+    // The rest of the row faintly, to tell where the bar ends
+    Rectangle::new(Point::new(done as i32, 0), Size::new(width - done, 1))
+        .into_styled(PrimitiveStyle::with_fill(Rgb888::new(
+            0,
+            (f32::from(0x10 as u8) * b) as u8,
+            (f32::from(0x20 as u8) * b) as u8,
+        )))
+        .draw(canvas)?;
+    Ok(())
+}
+
+// Three 1px wide gauges on the right edge, below the moon: CPU temperature (up to 100°C), memory
+// and disk usage. Dim green, red when critical (or throttled, for the temperature).
+fn draw_diagnostics(
+    canvas: &mut LedCanvas,
+    diagnostics: &Diagnostics,
+    b: f32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    const HEIGHT: u32 = 8;
+    const TOP: i32 = 8;
+    let temperature = diagnostics.cpu_temperature.unwrap_or_default();
+    let gauges = [
+        (
+            temperature,
+            diagnostics.throttled || temperature >= CRITICAL_CPU_TEMPERATURE,
+        ),
+        (
+            diagnostics.memory_used,
+            diagnostics.memory_used >= CRITICAL_USAGE_PERCENT,
+        ),
+        (
+            diagnostics.disk_used,
+            diagnostics.disk_used >= CRITICAL_USAGE_PERCENT,
+        ),
+    ];
+    let right = canvas.size().width as i32 - 1;
+    for (i, (percent, critical)) in gauges.into_iter().enumerate() {
+        let color = match critical {
+            true => Rgb888::new((f32::from(0xff as u8) * b) as u8, 0, 0),
+            false => Rgb888::new(0, (f32::from(0x60 as u8) * b) as u8, 0),
+        };
+        let height = ((percent / 100.0).clamp(0.0, 1.0) * HEIGHT as f32).round() as u32;
+        let x = right - 2 + i as i32;
+        Rectangle::new(
+            Point::new(x, TOP + (HEIGHT - height) as i32),
+            Size::new(1, height),
+        )
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(canvas)?;
+    }
+    Ok(())
+}
+
+// What's down, if the server can't reach the internet
+fn get_outage(content: &ScreenContentReply) -> Option<&'static str> {
+    let connectivity = content.connectivity.as_ref()?;
+    match (connectivity.lan_down, connectivity.internet_down) {
+        (true, _) => Some("No network"),
+        (false, true) => Some("No internet"),
+        (false, false) => None,
+    }
+}
+
+// The CO2 level when it's high enough to air the room
+
+fn get_stale_air(content: &ScreenContentReply) -> Option<f32> {
+    let co2 = content.indoor.as_ref()?.co2?;
+    (co2 > STALE_AIR_CO2_PPM).then_some(co2)
+}
+
+// The initial the server picked for a name, or its first letter for servers that don't
+fn debt_initial(initial: &str, name: &str) -> String {
+    if !initial.is_empty() {
+        return initial.to_string();
+    }
+    name.chars()
+        .next()
+        .unwrap_or_else(|| {
+            error!("No first char in debt's name");
+            '?'
+        })
+        .to_string()
+}
+
+// What takes turns on the bottom line, a minute each
+#[derive(Clone, Copy)]
+enum Rotating<'a> {
+    Event(&'a CalendarEvent),
+    Widget(&'a TextWidget),
+    Solar(&'a Solar),
+    Quote(&'a Quote),
+    Headline(&'a Headline),
+    Tasks(&'a Tasks),
+    EvCharger(&'a EvCharger),
+    Match(&'a Match),
+    Race(&'a RaceSession),
+    Openings(&'a Openings),
+}
+
+// The upcoming events first (older servers only send the next one), then the text widgets, the
+// solar production, the quotes, the headlines, the tasks (unless there are none left) and the EV
+// charger (while there's a car to tell about), then the matches being played, the next race
+// session and the doors and windows left open
+fn get_rotating(content: &ScreenContentReply, minute: u32) -> Option<Rotating<'_>> {
+    let events = match content.upcoming_events.is_empty() {
+        true => content.next_upcoming_event.as_slice(),
+        false => content.upcoming_events.as_slice(),
+    };
+    let rotating: Vec<Rotating> = events
+        .iter()
+        .map(Rotating::Event)
+        .chain(content.text_widgets.iter().map(Rotating::Widget))
+        .chain(content.solar.iter().map(Rotating::Solar))
+        .chain(content.quotes.iter().map(Rotating::Quote))
+        .chain(content.headlines.iter().map(Rotating::Headline))
+        .chain(
+            content
+                .tasks
+                .iter()
+                .filter(|tasks| tasks.due_today > 0)
+                .map(Rotating::Tasks),
+        )
+        .chain(
+            content
+                .ev_charger
+                .iter()
+                .filter(|charger| {
+                    matches!(
+                        charger.state(),
+                        State::Charging | State::Complete | State::Error
+                    )
+                })
+                .map(Rotating::EvCharger),
+        )
+        .chain(
+            content
+                .matches
+                .iter()
+                .filter(|m| m.live)
+                .map(Rotating::Match),
+        )
+        .chain(content.race_session.iter().map(Rotating::Race))
+        .chain(
+            content
+                .openings
+                .iter()
+                .filter(|openings| !openings.open.is_empty())
+                .map(Rotating::Openings),
+        )
+        .collect();
+    match rotating.len() {
+        0 => None,
+        n => Some(rotating[minute as usize % n]),
+    }
+}
+
+// e.g. "2 windows open", or "1 door, 2 windows" when there are both
+fn openings_summary(openings: &Openings) -> String {
+    let count = |n: u32, what: &str| match n {
+        1 => format!("1 {}", what),
+        n => format!("{} {}s", n, what),
+    };
+    match (openings.doors, openings.windows) {
+        (doors, 0) => format!("{} open", count(doors, "door")),
+        (0, windows) => format!("{} open", count(windows, "window")),
+        (doors, windows) => format!("{}, {}", count(doors, "door"), count(windows, "window")),
+    }
+}
+
+fn get_finished_appliance(content: &ScreenContentReply) -> Option<&Appliance> {
+    content
+        .appliances
+        .iter()
+        .find(|appliance| appliance.state() == ApplianceState::Finished)
+}
+
+// Watts below a kW, otherwise kW with a decimal, e.g. "850W" or "3.2kW"
+fn compact_watts(watts: f32) -> String {
+    match watts.abs() < 1000.0 {
+        true => format!("{:.0}W", watts),
+        false => format!("{:.1}kW", watts / 1000.0),
+    }
+}
+
+// e.g. "3.2kW /1.1kW 80%": the production in the sun's color, then what the house uses and how
+// full the battery is, when the inverter tells
+fn draw_solar(
+    canvas: &mut LedCanvas,
+    solar: &Solar,
+    font: &'static MonoFont<'static>,
+    palette: &Palette,
+    b: f32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let production = compact_watts(solar.production);
+    let mut end = Text::new(
+        &production,
+        Point::new(0, 30),
+        text_style(font, palette.solar, b),
     )
     .draw(canvas)?;
+    if let Some(consumption) = solar.consumption {
+        let consumption = format!(" /{}", compact_watts(consumption));
+        end =
+            Text::new(&consumption, end, text_style(font, palette.bottom_line, b)).draw(canvas)?;
+    }
+    if let Some(battery) = solar.battery {
+        Text::new(
+            &format!(" {:.0}%", battery),
+            end,
+            text_style(font, palette.battery, b),
+        )
+        .draw(canvas)?;
+    }
+    Ok(())
+}
+
+// Right to left from the right edge, over and over
+fn draw_scrolling(
+    canvas: &mut LedCanvas,
+    text: &str,
+    style: MonoTextStyle<'static, Rgb888>,
+    scroll: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let char_width = style.font.character_size.width + style.font.character_spacing;
+    let screen_width = canvas.size().width;
+    let text_width = text.chars().count() as u32 * char_width;
+    let x = screen_width as i32 - (scroll % (text_width + screen_width)) as i32;
+    Text::new(text, Point::new(x, 30), style).draw(canvas)?;
+    Ok(())
+}
 
-    //let debt_text = "S>B:108\nM>B:42";
-    let debt_text = content
+// Draws the text on the bottom line as is if it fits, otherwise as a marquee: still at its start
+// for `pause_ticks`, then scrolling left until its end shows, still again, and back to the start.
+// Returns whether it's a marquee, which needs the scroll ticks.
+fn draw_marquee(
+    canvas: &mut LedCanvas,
+    text: &str,
+    style: MonoTextStyle<'static, Rgb888>,
+    scroll: u32,
+    pause_ticks: u32,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let char_width = style.font.character_size.width + style.font.character_spacing;
+    let text_width =
+        (text.chars().count() as u32 * char_width).saturating_sub(style.font.character_spacing);
+    let overflow = text_width.saturating_sub(canvas.size().width);
+    let offset = match overflow {
+        0 => 0,
+        _ => (scroll % (2 * pause_ticks + overflow))
+            .saturating_sub(pause_ticks)
+            .min(overflow),
+    };
+    Text::new(text, Point::new(-(offset as i32), 30), style).draw(canvas)?;
+    Ok(overflow > 0)
+}
+
+// Who owes what to whom in the kitties, below the clock
+fn draw_debts(
+    canvas: &mut LedCanvas,
+    content: &ScreenContentReply,
+    glyphs: &mut GlyphFallback,
+    fonts: &Fonts,
+    palette: &Palette,
+) -> Result<(), Box<dyn std::error::Error>> {
+    //let debt_lines = ["S>B:108", "M>B:42"];
+    let debt_lines = content
         .kitty_debts
         .iter()
         .map(|debt| {
+            // Francs go without saying, other currencies follow the amount
+            let currency = match debt.currency.as_str() {
+                "CHF" => "",
+                other => other,
+            };
             format!(
-                "{}>{}:{}",
-                debt.who
-                    .chars()
-                    .next()
-                    .or_else(|| {
-                        error!("No first char in debt's who");
-                        Some('?')
-                    })
-                    .unwrap(),
-                debt.whom
-                    .chars()
-                    .next()
-                    .or_else(|| {
-                        error!("No first char in debt's whom");
-                        Some('?')
-                    })
-                    .unwrap(),
-                debt.how_much as i32
+                "{}>{}:{}{}",
+                debt_initial(&debt.who_initial, &debt.who),
+                debt_initial(&debt.whom_initial, &debt.whom),
+                debt.how_much as i32,
+                currency
             )
         })
-        .collect::<Vec<String>>()
-        .join("\n");
-    Text::new(
-        &debt_text,
-        Point::new(0, 17),
-        debt_style(content.brightness),
-    )
-    .draw(canvas)?;
+        .collect::<Vec<String>>();
+    for (i, (debt, debt_line)) in content.kitty_debts.iter().zip(&debt_lines).enumerate() {
+        // Someone owes too much: make it visible, and so are amounts that changed since the last
+        // fetch (e.g. someone added an expense)
+        let style = if content.kitty_alert {
+            text_style(fonts.debts, palette.debt_alert, content.brightness)
+        } else if debt.delta != 0.0 {
+            text_style(fonts.debts, palette.debt_change, content.brightness)
+        } else {
+            text_style(fonts.debts, palette.debts, content.brightness)
+        };
+        let y = 17 + i as i32 * fonts.debts.character_size.height as i32;
+        let text = glyphs.cover(debt_line, fonts.coverage(Section::Debts));
+        let end = Text::new(&text, Point::new(0, y), style).draw(canvas)?;
+        // A tiny arrow after the amount for how it went over the last week, the font has none
+        let arrow = match debt.trend() {
+            Trend::Steady => None,
+            Trend::Rising => Some([(0, -2), (4, -2), (2, -4)]),
+            Trend::Falling => Some([(0, -4), (4, -4), (2, -2)]),
+        };
+        if let (Some([a, b, c]), Some(color)) = (arrow, style.text_color) {
+            let point = |(dx, dy): (i32, i32)| end + Point::new(1 + dx, dy);
+            Triangle::new(point(a), point(b), point(c))
+                .into_styled(PrimitiveStyle::with_fill(color))
+                .draw(canvas)?;
+        }
+    }
+    Ok(())
+}
 
+// The next departures (and the bikes nearby, room permitting), right of the debts
+fn draw_departures(
+    canvas: &mut LedCanvas,
+    content: &ScreenContentReply,
+    glyphs: &mut GlyphFallback,
+    fonts: &Fonts,
+    palette: &Palette,
+    now: &DateTime<Local>,
+) -> Result<(), Box<dyn std::error::Error>> {
     //let bus_text = "18:12'\n32: 7'";
-    // Sort the departures, so at least when all present they show on the same line
+    // Sort the departures, so at least when all present they show on the same line (the sort is
+    // stable, so those to the same destination stay earliest first)
     let mut departures = content.bus_departures.clone();
-    departures.sort_by_key(|departure| departure.destination_enum().as_str_name());
-    let bus_text = departures
-        .iter()
-        .map(|dep| {
-            let proto_ts = dep
-                .departure_time
+    departures.sort_by_key(|departure| departure.destination.clone());
+    // Each line has the next departure, its delay if any, then the ones after it
+    let mut bus_lines: Vec<(String, i32, String)> = vec![];
+    let mut last_destination = None;
+    for dep in &departures {
+        let proto_ts = dep
+            .departure_time
+            .or_else(|| {
+                error!("Departure without a time");
+                Some(
+                    prost_types::Timestamp::date(2000, 01, 01)
+                        .expect("Can't even make a hardcoded proto"),
+                )
+            })
+            .unwrap();
+        let mut departure_minutes_from_now = time_util::minutes_until(&proto_ts, now)?;
+        if departure_minutes_from_now < 0 {
+            warn!(
+                "Got a departure {} minutes in the past, clamping to 0",
+                departure_minutes_from_now
+            );
+            departure_minutes_from_now = 0;
+            print_error_bit(canvas);
+        }
+        let mark = match (dep.deviation, dep.scheduled_only) {
+            (true, _) => '!',
+            (false, true) => '~',
+            (false, false) => '\'',
+        };
+        // The ones after the next only add their minutes, e.g. "7F:3'B,9'"
+        let destination = Some((dep.destination.clone(), dep.arrival));
+        if destination == last_destination {
+            if let Some((_, _, after)) = bus_lines.last_mut() {
+                after.push_str(&format!(",{}{}", departure_minutes_from_now, mark));
+            }
+            continue;
+        }
+        last_destination = destination;
+        // The line tells apart the departures to the same destination, and the platform where
+        // to wait for them, e.g. "7F:3'B" (or "7F>3'B" for an arrival, "7F:3!B" for a detour,
+        // "7F:3~B" when only the timetable is known)
+        let bus_text = format!(
+            "{}{}{}{}{}{}",
+            dep.line,
+            dep.destination
+                .chars()
+                .next()
                 .or_else(|| {
-                    error!("Departure without a time");
-                    Some(
-                        prost_types::Timestamp::date(2000, 01, 01)
-                            .expect("Can't even make a hardcoded proto"),
-                    )
+                    error!("No first char in departure");
+                    Some('?')
                 })
-                .unwrap();
-            let departure_time: DateTime<Local> = DateTime::from_timestamp(
-                proto_ts.seconds,
-                proto_ts.nanos.try_into().expect("Invalid TS nanos"),
-            )
-            // We can't use `?` here because the function (we're in the lambda) doesn't return a Result
-            .expect("Unable to convert departure proto TS into DateTime")
-            .into();
-            let mut departure_minutes_from_now =
-                departure_time.signed_duration_since(now).num_minutes();
-            if departure_minutes_from_now < 0 {
-                warn!(
-                    "Got a departure {} minutes in the past, clamping to 0",
-                    departure_minutes_from_now
-                );
-                departure_minutes_from_now = 0;
-                print_error_bit(canvas);
-            }
-            format!(
-                "{}:{}'",
-                dep.destination_enum()
-                    .as_str_name()
-                    .chars()
-                    .next()
-                    .or_else(|| {
-                        error!("No first char in departure");
-                        Some('?')
-                    })
-                    .unwrap(),
-                departure_minutes_from_now
+                .unwrap(),
+            if dep.arrival { '>' } else { ':' },
+            departure_minutes_from_now,
+            mark,
+            dep.platform
+        );
+        bus_lines.push((bus_text, dep.delay_minutes, String::new()));
+    }
+    // The bikes left at the stations nearby, in the lines the departures leave free, e.g. "PB:3+2e"
+    for station in &content.bike_stations {
+        if bus_lines.len() >= BUS_LINES {
+            break;
+        }
+        let bike_text = match station.ebikes {
+            0 => format!("{}:{}", station.label, station.bikes),
+            ebikes => format!("{}:{}+{}e", station.label, station.bikes, ebikes),
+        };
+        bus_lines.push((bike_text, 0, String::new()));
+    }
+    for (i, (bus_text, delay_minutes, after)) in bus_lines.iter().enumerate() {
+        let line_height = fonts.departures.character_size.height as i32;
+        let position = Point::new(36, 17 + i as i32 * line_height);
+        // Line and platform names come from the operators, so they may need covering too
+        let end = Text::new(
+            &glyphs.cover(bus_text, fonts.coverage(Section::Departures)),
+            position,
+            text_style(fonts.departures, palette.departures, content.brightness),
+        )
+        .draw(canvas)?;
+        // Drawn in its own color right after the departure, e.g. "+3'"
+        let end = match delay_minutes {
+            0 => end,
+            _ => Text::new(
+                &format!("{:+}'", delay_minutes),
+                end,
+                text_style(fonts.departures, palette.delay, content.brightness),
             )
-        })
-        .collect::<Vec<String>>()
-        .join("\n");
-    Text::new(&bus_text, Point::new(36, 17), bus_style(content.brightness)).draw(canvas)?;
+            .draw(canvas)?,
+        };
+        Text::new(
+            after,
+            end,
+            text_style(fonts.departures, palette.departures, content.brightness),
+        )
+        .draw(canvas)?;
+    }
+    Ok(())
+}
 
+// Returns whether something moves, for the caller to keep the scroll ticks coming
+fn draw_bottom_line(
+    canvas: &mut LedCanvas,
+    content: &ScreenContentReply,
+    glyphs: &mut GlyphFallback,
+    fonts: &Fonts,
+    palette: &Palette,
+    now: &DateTime<Local>,
+    scroll: u32,
+    marquee_pause: u32,
+) -> Result<bool, Box<dyn std::error::Error>> {
     //let cal_text = "23.10: Escape game";
-    if let Some(event) = &content.next_upcoming_event {
+    let rotating = get_rotating(content, now.minute());
+    let coverage = fonts.coverage(Section::BottomLine);
+    let mut moving = false;
+    if let Some(notice) = content.notices.first() {
+        // Pushed notices are short-lived, so they take precedence over the calendar
+        moving = draw_marquee(
+            canvas,
+            &glyphs.cover(&notice.text, coverage),
+            text_style(fonts.bottom_line, palette.bottom_line, content.brightness),
+            scroll,
+            marquee_pause,
+        )?;
+    } else if let Some(appliance) = get_finished_appliance(content) {
+        // Blinks until acknowledged, for someone to go empty the washer
+        moving = true;
+        if scroll / BLINK_TICKS % 2 == 0 {
+            Text::new(
+                &glyphs.cover(&format!("{} done!", appliance.label), coverage),
+                Point::new(0, 30),
+                text_style(fonts.bottom_line, palette.change(true), content.brightness),
+            )
+            .draw(canvas)?;
+        }
+    } else if let Some(outage) = get_outage(content) {
+        // Everything else is stale then, say why rather than leave it to the error bit
+        Text::new(
+            outage,
+            Point::new(0, 30),
+            text_style(fonts.bottom_line, palette.error, content.brightness),
+        )
+        .draw(canvas)?;
+    } else if let Some(co2) = get_stale_air(content) {
+        // Stale air only calls for opening a window, but better do it before anything else
+        Text::new(
+            &format!("Air! CO2:{:.0}ppm", co2),
+            Point::new(0, 30),
+            text_style(fonts.bottom_line, palette.disruption, content.brightness),
+        )
+        .draw(canvas)?;
+    } else if let Some(nas) = content.nas.as_ref().filter(|nas| !nas.warning.is_empty()) {
+        // A degraded array is no emergency either, but it won't fix itself
+        moving = draw_marquee(
+            canvas,
+            &glyphs.cover(&nas.warning, coverage),
+            text_style(fonts.bottom_line, palette.disruption, content.brightness),
+            scroll,
+            marquee_pause,
+        )?;
+    } else if !content.disruptions.is_empty() {
+        let disruptions = content.disruptions.join(" - ");
+        let text = glyphs.cover(&disruptions, coverage);
+        draw_scrolling(
+            canvas,
+            &text,
+            text_style(fonts.bottom_line, palette.disruption, content.brightness),
+            scroll,
+        )?;
+        moving = true;
+    } else if let Some(Rotating::Widget(widget)) = rotating {
+        moving = draw_marquee(
+            canvas,
+            &glyphs.cover(&format!("{}: {}", widget.label, widget.text), coverage),
+            text_style(fonts.bottom_line, palette.bottom_line, content.brightness),
+            scroll,
+            marquee_pause,
+        )?;
+    } else if let Some(Rotating::Solar(solar)) = rotating {
+        draw_solar(
+            canvas,
+            solar,
+            fonts.bottom_line,
+            palette,
+            content.brightness,
+        )?;
+    } else if let Some(Rotating::Quote(quote)) = rotating {
+        // e.g. "AAPL $190.12 +1.2%", the change in green or red
+        let text = glyphs.cover(&format!("{} {} ", quote.label, quote.price_text), coverage);
+        let style = text_style(fonts.bottom_line, palette.bottom_line, content.brightness);
+        let end = Text::new(&text, Point::new(0, 30), style).draw(canvas)?;
+        let change_style = text_style(
+            fonts.bottom_line,
+            palette.change(quote.change >= 0.0),
+            content.brightness,
+        );
+        Text::new(&format!("{:+.1}%", quote.change), end, change_style).draw(canvas)?;
+    } else if let Some(Rotating::Headline(headline)) = rotating {
+        // Too long to fit, e.g. "BBC: Lake Geneva freezes over for the first time since 1963"
+        let text = glyphs.cover(
+            &format!("{}: {}", headline.source, headline.title),
+            coverage,
+        );
+        draw_scrolling(
+            canvas,
+            &text,
+            text_style(fonts.bottom_line, palette.bottom_line, content.brightness),
+            scroll,
+        )?;
+        moving = true;
+    } else if let Some(Rotating::Tasks(tasks)) = rotating {
+        // e.g. "Household 3: Water the plants"
+        let text = format!("{} {}: {}", tasks.label, tasks.due_today, tasks.top_task);
+        moving = draw_marquee(
+            canvas,
+            &glyphs.cover(&text, coverage),
+            text_style(fonts.bottom_line, palette.bottom_line, content.brightness),
+            scroll,
+            marquee_pause,
+        )?;
+    } else if let Some(Rotating::EvCharger(charger)) = rotating {
+        // e.g. "EV 11kW ~18:30" while charging, the finish time only when the updater can tell
+        let finish = charger
+            .estimated_finish
+            .and_then(|finish| DateTime::from_timestamp(finish.seconds, 0))
+            .map(|finish| format!(" ~{}", finish.with_timezone(&Local).format("%H:%M")));
+        let (text, style) = match charger.state() {
+            State::Complete => (
+                "EV ready".to_string(),
+                text_style(fonts.bottom_line, palette.change(true), content.brightness),
+            ),
+            State::Error => (
+                "EV error".to_string(),
+                text_style(fonts.bottom_line, palette.error, content.brightness),
+            ),
+            _ => (
+                format!(
+                    "EV {}{}",
+                    compact_watts(charger.power),
+                    finish.unwrap_or_default()
+                ),
+                text_style(fonts.bottom_line, palette.bottom_line, content.brightness),
+            ),
+        };
+        Text::new(&text, Point::new(0, 30), style).draw(canvas)?;
+    } else if let Some(Rotating::Match(m)) = rotating {
+        // Scrolls like the headlines, for the score to catch the eye
+        let text = glyphs.cover(&m.text, coverage);
+        draw_scrolling(
+            canvas,
+            &text,
+            text_style(fonts.bottom_line, palette.bottom_line, content.brightness),
+            scroll,
+        )?;
+        moving = true;
+    } else if let Some(Rotating::Race(session)) = rotating {
+        // e.g. "F1 Quali 2d3h"
+        let start = session.start.map_or(0, |start| start.seconds);
+        let text = format!(
+            "{} {} {}",
+            session.series,
+            session.session,
+            event_format::until(start - Local::now().timestamp())
+        );
+        Text::new(
+            &glyphs.cover(&text, coverage),
+            Point::new(0, 30),
+            text_style(fonts.bottom_line, palette.bottom_line, content.brightness),
+        )
+        .draw(canvas)?;
+    } else if let Some(Rotating::Openings(openings)) = rotating {
+        // A reminder for when leaving rather than an alert, so it only takes its turn
+        Text::new(
+            &openings_summary(openings),
+            Point::new(0, 30),
+            text_style(fonts.bottom_line, palette.disruption, content.brightness),
+        )
+        .draw(canvas)?;
+    } else if let Some(Rotating::Event(event)) = rotating {
         let proto_ts = event
             .event_start
             .or_else(|| {
@@ -292,20 +950,136 @@ fn draw_content_onto_canvas(
         )
         .ok_or("Unable to convert event proto TS into DateTime")?
         .into();
-        let cal_text = format!(
-            "{}.{}: {}",
-            event_time.day(),
-            event_time.month(),
-            event.event_title
-        );
-        Text::new(&cal_text, Point::new(0, 30), cal_style(content.brightness)).draw(canvas)?;
+        // A start time already past says less than that it's going on
+        let cal_text = match (event.in_progress, event.date_hint()) {
+            (true, _) => format!("now: {}", event.event_title),
+            (false, DateHint::Countdown) => format!(
+                "{}: {}",
+                event_format::countdown(event.days_until),
+                event.event_title
+            ),
+            (false, DateHint::Date) => format!(
+                "{}.{}: {}",
+                event_time.day(),
+                event_time.month(),
+                event.event_title
+            ),
+        };
+        let char_width =
+            fonts.bottom_line.character_size.width + fonts.bottom_line.character_spacing;
+        let cal_text = with_location(cal_text, &event.location, canvas.size().width / char_width);
+        // Long titles scroll rather than run off the edge
+        moving = draw_marquee(
+            canvas,
+            &glyphs.cover(&cal_text, coverage),
+            text_style(fonts.bottom_line, palette.bottom_line, content.brightness),
+            scroll,
+            marquee_pause,
+        )?;
+    }
+    Ok(moving)
+}
+
+// Draws the page's sections, returns whether something moves on the bottom line for the caller
+// to keep the scroll ticks coming
+fn draw_content_onto_canvas(
+    canvas: &mut LedCanvas,
+    content: &ScreenContentReply,
+    glyphs: &mut GlyphFallback,
+    fonts: &Fonts,
+    themes: &Themes,
+    charts: &[MicroChart],
+    diagnostics_widget: bool,
+    page: &[Section],
+    scroll: u32,
+    marquee_pause: u32,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    // Consider graceful handling of the expect calls below
+    canvas.clear();
+    let now = Local::now();
+    let palette = themes.palette(content.brightness);
+
+    if page.contains(&Section::Clock) {
+        //let time_text = "19:24";
+        let time_text = format!("{}", now.format("%H:%M")); // pls help me
+        let position = match fonts.loaded.contains_key(&Section::Clock) {
+            false => Point::new(9, 9),
+            // Centered, with the top of the cells on the first row
+            true => {
+                let char_width = fonts.clock.character_size.width + fonts.clock.character_spacing;
+                let text_width = time_text.len() as i32 * char_width as i32;
+                let x = (canvas.size().width as i32 - text_width) / 2;
+                Point::new(x, fonts.clock.baseline as i32)
+            }
+        };
+        Text::new(
+            &time_text,
+            position,
+            text_style(fonts.clock, palette.clock, content.brightness),
+        )
+        .draw(canvas)?;
+    }
+    if page.contains(&Section::Debts) {
+        draw_debts(canvas, content, glyphs, fonts, palette)?;
+    }
+    if page.contains(&Section::Departures) {
+        draw_departures(canvas, content, glyphs, fonts, palette, &now)?;
+    }
+    let moving = match page.contains(&Section::BottomLine) {
+        true => draw_bottom_line(
+            canvas,
+            content,
+            glyphs,
+            fonts,
+            palette,
+            &now,
+            scroll,
+            marquee_pause,
+        )?,
+        false => false,
+    };
+
+    if page.contains(&Section::Charts) {
+        for chart in charts {
+            if let Some(values) = get_chart_values(content, chart.source()) {
+                chart.draw(values, content.brightness, canvas)?;
+            }
+        }
+    }
+
+    if let (true, Some(astronomy)) = (page.contains(&Section::Moon), &content.astronomy) {
+        draw_moon(canvas, astronomy.moon_phase, content.brightness)?;
+    }
+    let diagnostics_widget = diagnostics_widget && page.contains(&Section::Diagnostics);
+    if let (true, Some(diagnostics)) = (diagnostics_widget, &content.diagnostics) {
+        draw_diagnostics(canvas, diagnostics, content.brightness)?;
+    }
+    if let (true, Some(printer)) = (page.contains(&Section::Printer), &content.printer) {
+        draw_printer(canvas, printer, content.brightness)?;
     }
 
     if content.error {
         print_error_bit(canvas);
     }
 
-    Ok(())
+    Ok(moving)
+}
+
+// Appends where the event takes place, only if it all fits in `max_chars`: the title matters more
+fn with_location(text: String, location: &str, max_chars: u32) -> String {
+    let with_location = format!("{} - {}", text, location);
+    match !location.is_empty() && with_location.chars().count() as u32 <= max_chars {
+        true => with_location,
+        false => text,
+    }
+}
+
+// How long until the wall clock's minute changes, plus a few ms so we wake up in the new minute
+fn until_next_minute(now: DateTime<Local>) -> tokio::time::Duration {
+    // Leap seconds show up as more than 1000ms into the second, cap them
+    let millis_into_minute =
+        u64::from(now.second()) * 1000 + u64::from(now.timestamp_subsec_millis().min(999));
+    tokio::time::Duration::from_millis(60_000 - millis_into_minute + 20)
 }
 
 #[tokio::main]
@@ -335,23 +1109,165 @@ async fn main() {
             .expect("Invalid client update period"),
     );
     info!("update interval: {:?}", update_interval);
+    // Without a beacon we poll the hash from the server at every tick
+    let beacon = match &api_config.beacon {
+        Some(beacon_config) => Some(
+            hash_beacon::listen(beacon_config)
+                .await
+                .expect("Couldn't listen for hash beacons"),
+        ),
+        None => None,
+    };
     let mut interval = tokio::time::interval(update_interval);
+    let scroll_speed = api_config
+        .client
+        .as_ref()
+        .and_then(|client| client.scroll_speed)
+        .unwrap_or(DEFAULT_SCROLL_SPEED)
+        .max(1);
+    let scroll_period = tokio::time::Duration::from_secs(1) / scroll_speed;
+    let scroll_pause = api_config
+        .client
+        .as_ref()
+        .and_then(|client| client.scroll_pause.as_ref())
+        .and_then(|pause| u64::try_from(pause.seconds).ok())
+        .map_or(DEFAULT_SCROLL_PAUSE, tokio::time::Duration::from_secs);
+    // In scroll ticks, as the marquees count their pauses
+    let marquee_pause = (scroll_pause.as_millis() / scroll_period.as_millis().max(1)) as u32;
+    let mut scroll_interval = tokio::time::interval(scroll_period);
+    let mut scroll: u32 = 0;
+    // Whether the last drawing moves, e.g. scrolling disruptions or a blinking appliance
+    let mut scrolling = false;
     let mut hash: u64 = 0;
     let mut minutes: u32 = Local::now().minute();
-    let mut content: ScreenContentReply;
+    let mut content = ScreenContentReply::default();
+    let mut glyphs = GlyphFallback::new(
+        api_config
+            .client
+            .as_ref()
+            .and_then(|client| client.replacement_glyph.as_deref()),
+    );
+    let fonts = Fonts::new(api_config.client.as_ref());
+    let themes = Themes::new(api_config.client.as_ref());
+    let charts: Vec<MicroChart> = api_config
+        .client
+        .iter()
+        .flat_map(|client| client.charts.iter())
+        .map(MicroChart::new)
+        .collect();
+    let diagnostics_widget = api_config
+        .client
+        .as_ref()
+        .is_some_and(|client| client.diagnostics_widget);
+    // Flipped through on their own timer and button, whatever the hash polling is up to
+    let mut pages = PageScheduler::new(api_config.client.as_ref());
+    let mut page = pages.current();
+    let (press_sender, mut presses) = tokio::sync::mpsc::unbounded_channel();
+    match api_config.client.as_ref().map(|client| &client.page_button) {
+        Some(button) if !button.is_empty() => pages::watch_button(button.clone(), press_sender),
+        _ => drop(press_sender),
+    }
+    for chart in &charts {
+        if get_chart_values(&content, chart.source()).is_none() {
+            warn!(
+                "Unknown chart source {:?}, it won't be drawn",
+                chart.source()
+            );
+        }
+    }
+    let blanking = Blanking::new(api_config.client.as_ref());
+    let mut blanked = false;
     loop {
-        interval.tick().await;
-        let new_hash = make_hash_request(&mut client).await;
-        if hash != new_hash || minutes != Local::now().minute() {
-            debug!("new hash or minute change, querying full content");
-            hash = new_hash;
+        // Blank hours end on the hour, there's nothing to poll nor draw until then
+        if blanking.is_some_and(|blanking| blanking.is_blank(Local::now().hour())) {
+            if !blanked {
+                info!("Blanking the panel");
+                canvas.clear();
+                canvas = matrix.swap(canvas);
+                blanked = true;
+            }
+            tokio::time::sleep(until_next_minute(Local::now())).await;
+            continue;
+        }
+        if blanked {
+            info!("Done blanking the panel");
+            blanked = false;
+            // Whatever we had is stale by now, start over as if we just started
+            hash = 0;
+            content = ScreenContentReply::default();
+            interval.reset_immediately();
+        }
+        let next_minute = tokio::time::Instant::now() + until_next_minute(Local::now());
+        let next_page = pages.next_switch();
+        // Nothing's shown at zero brightness (e.g. at night), once we got our first content
+        let screen_off = hash != 0 && content.brightness == 0.0;
+        let new_hash = match &beacon {
+            // Stop polling and redrawing altogether so the Wi-Fi can power-save, the beacon tells
+            // us when the content (brightness included) changes
+            Some(socket) if screen_off => {
+                debug!("Screen is off, waiting for the beacon to announce new content");
+                let known_hash = hash_beacon::wait_for_change(socket, hash).await;
+                // Don't burst through the ticks we missed while sleeping
+                interval.reset();
+                match known_hash {
+                    Some(hash) => hash,
+                    None => make_hash_request(&mut client).await,
+                }
+            }
+            _ => tokio::select! {
+                known_hash = hash_beacon::wait_for_hash(beacon.as_ref(), &mut interval, hash) => {
+                    match known_hash {
+                        Some(hash) => hash,
+                        None => make_hash_request(&mut client).await,
+                    }
+                }
+                // Redraw the clock right as the minute changes, whatever the phase of the poll interval
+                _ = tokio::time::sleep_until(next_minute) => hash,
+                _ = scroll_interval.tick(), if scrolling => hash,
+                _ = tokio::time::sleep_until(next_page.unwrap_or(next_minute)),
+                    if next_page.is_some() => {
+                    pages.advance();
+                    hash
+                }
+                Some(()) = presses.recv() => {
+                    pages.advance();
+                    hash
+                }
+            },
+        };
+        let page_changed = page != pages.current();
+        if hash != new_hash || minutes != Local::now().minute() || scrolling || page_changed {
+            // The marquees of a new page start over
+            scroll = match scrolling && !page_changed {
+                true => scroll.wrapping_add(1),
+                false => 0,
+            };
+            // Only the clock needs redrawing on minute changes, we can reuse the content we have
+            if hash != new_hash {
+                debug!("new hash, querying full content");
+                hash = new_hash;
+                content = make_full_request(&mut client).await;
+                debug!("full content: {:?}", &content);
+            }
             minutes = Local::now().minute();
-            content = make_full_request(&mut client).await;
-            debug!("full content: {:?}", &content);
-            let _ = draw_content_onto_canvas(&mut canvas, &content).inspect_err(|e| {
+            page = pages.current();
+            scrolling = draw_content_onto_canvas(
+                &mut canvas,
+                &content,
+                &mut glyphs,
+                &fonts,
+                &themes,
+                &charts,
+                diagnostics_widget,
+                pages.sections(),
+                scroll,
+                marquee_pause,
+            )
+            .inspect_err(|e| {
                 warn!("Error drawing things on the canvas: {}", e);
                 print_error_bit(&mut canvas);
-            });
+            })
+            .unwrap_or(false);
             canvas = matrix.swap(canvas);
         }
     }
@@ -0,0 +1,295 @@
+//! The latest headlines of a few RSS or Atom feeds, each fetched at its own pace and filtered by
+//! its own keywords.
+
+use crate::config_extractor::api_config;
+use crate::config_extractor::api_config::rss_config::Feed;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::Headline;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::{DateTime, Timelike};
+use prost_types::Timestamp;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::Client;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+const DEFAULT_FEED_PERIOD: Duration = Duration::from_secs(900);
+const DEFAULT_MAX_HEADLINES: u32 = 1;
+
+#[derive(Debug)]
+struct FeedState {
+    feed: Feed,
+    period: ExponentialBackoff,
+    next_fetch: Instant,
+    // The latest ones that passed the filters, from the last fetch
+    headlines: Vec<Headline>,
+}
+
+#[derive(Debug)]
+pub struct RssUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    feeds: Vec<FeedState>,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for RssUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(61),
+            // Whenever the next feed is due
+            UpdateMode::Real => self
+                .feeds
+                .iter()
+                .map(|state| state.next_fetch)
+                .min()
+                .unwrap_or_else(|| Instant::now() + DEFAULT_FEED_PERIOD),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} RSS", self.update_mode);
+        let headlines;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                headlines = vec![Headline {
+                    title: format!(
+                        "Local man watches his screen for {} minutes straight",
+                        now.minute()
+                    ),
+                    source: "Dummy news".into(),
+                    published: Some(Timestamp {
+                        seconds: now.timestamp(),
+                        nanos: 0,
+                    }),
+                }];
+                error_bit.store(
+                    now.second().is_multiple_of(47),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                let mut failed = false;
+                let now = Instant::now();
+                for state in self
+                    .feeds
+                    .iter_mut()
+                    .filter(|state| state.next_fetch <= now)
+                {
+                    match get_headlines(&self.client, &state.feed).await {
+                        Ok(fetched) => {
+                            state.period.set_success();
+                            state.headlines = fetched;
+                        }
+                        Err(e) => {
+                            error!("Error getting the {} feed: {}", state.feed.url, e);
+                            state.period.set_error();
+                            state.headlines.clear();
+                            failed = true;
+                        }
+                    }
+                    state.next_fetch = Instant::now() + state.period.get_current_duration();
+                }
+                error_bit.store(failed, std::sync::atomic::Ordering::Relaxed);
+                headlines = self
+                    .feeds
+                    .iter()
+                    .flat_map(|state| state.headlines.clone())
+                    .collect();
+            }
+        }
+        vec![ContentUpdate::Headlines(headlines)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl RssUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let rss_config = config.rss.as_ref().ok_or("No RSS config")?;
+        if rss_config.feeds.is_empty() {
+            return Err("No RSS feeds configured".into());
+        }
+        let mut feeds = vec![];
+        for feed in &rss_config.feeds {
+            let period = match &feed.update_period {
+                Some(period) => Duration::from_secs(period.seconds.try_into()?),
+                None => DEFAULT_FEED_PERIOD,
+            };
+            feeds.push(FeedState {
+                feed: feed.clone(),
+                period: ExponentialBackoff::new(
+                    period,
+                    Duration::from_secs(60), // 1 min
+                    Duration::from_secs(1200), // 20 min
+                ),
+                next_fetch: Instant::now(),
+                headlines: vec![],
+            });
+        }
+        Ok(RssUpdater {
+            update_mode,
+            client,
+            feeds,
+        })
+    }
+}
+
+async fn get_headlines(
+    client: &Client,
+    feed: &Feed,
+) -> Result<Vec<Headline>, Box<dyn std::error::Error>> {
+    let body = traced(
+        "fetch",
+        retry_http("RSS fetch", || async {
+            client
+                .get(&feed.url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }),
+    )
+    .await?;
+    traced_sync("parse", || {
+        let headlines = parse_feed(&body, &feed.label)?;
+        Ok(select_headlines(headlines, feed))
+    })
+}
+
+// The items of an RSS feed, or the entries of an Atom one, in the feed's order
+fn parse_feed(body: &str, source: &str) -> Result<Vec<Headline>, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_str(body);
+    reader.config_mut().trim_text(true);
+    let mut headlines = vec![];
+    let mut headline: Option<Headline> = None;
+    // The element of the item whose text we're reading
+    let mut field: Option<Vec<u8>> = None;
+    let mut text = String::new();
+    loop {
+        match reader.read_event()? {
+            Event::Eof => break,
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"item" | b"entry" => {
+                    headline = Some(Headline {
+                        source: source.to_string(),
+                        ..Default::default()
+                    })
+                }
+                name @ (b"title" | b"pubDate" | b"published" | b"updated")
+                    if headline.is_some() =>
+                {
+                    field = Some(name.to_vec());
+                    text.clear();
+                }
+                _ => (),
+            },
+            Event::Text(t) if field.is_some() => text.push_str(&t.unescape()?),
+            Event::CData(c) if field.is_some() => text.push_str(&String::from_utf8_lossy(&c)),
+            Event::End(e) => match (e.local_name().as_ref(), headline.as_mut()) {
+                (b"item" | b"entry", Some(_)) => headlines.extend(headline.take()),
+                (name, Some(headline)) if field.as_deref() == Some(name) => {
+                    match name {
+                        b"title" => headline.title = text.trim().to_string(),
+                        // Atom entries may have both, the publication is what we're after
+                        b"updated" if headline.published.is_some() => (),
+                        _ => headline.published = parse_date(text.trim()),
+                    }
+                    field = None;
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+    Ok(headlines)
+}
+
+// RSS has RFC 2822 dates (e.g. "Wed, 02 Oct 2024 13:00:00 GMT"), Atom RFC 3339 ones
+fn parse_date(text: &str) -> Option<Timestamp> {
+    let date = DateTime::parse_from_rfc2822(text)
+        .or_else(|_| DateTime::parse_from_rfc3339(text))
+        .ok()?;
+    Some(Timestamp {
+        seconds: date.timestamp(),
+        nanos: 0,
+    })
+}
+
+// The latest headlines passing the feed's keyword filters, newest first. Those without a date
+// keep the feed's order, which is usually newest first too.
+fn select_headlines(mut headlines: Vec<Headline>, feed: &Feed) -> Vec<Headline> {
+    let contains_any = |title: &str, keywords: &[String]| {
+        let title = title.to_lowercase();
+        keywords
+            .iter()
+            .any(|keyword| title.contains(&keyword.to_lowercase()))
+    };
+    headlines.retain(|headline| {
+        !headline.title.is_empty()
+            && (feed.keywords.is_empty() || contains_any(&headline.title, &feed.keywords))
+            && !contains_any(&headline.title, &feed.excluded_keywords)
+    });
+    headlines.sort_by_key(|headline| {
+        std::cmp::Reverse(headline.published.map(|published| published.seconds))
+    });
+    let max_headlines = feed.max_headlines.unwrap_or(DEFAULT_MAX_HEADLINES);
+    headlines.truncate(max_headlines as usize);
+    headlines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_feed_headlines() {
+        let rss = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0"><channel><title>Local news</title>
+  <item><title>Lake Geneva freezes over</title><pubDate>Wed, 02 Oct 2024 09:00:00 GMT</pubDate></item>
+  <item><title><![CDATA[Bus line 7 & 8 merged]]></title><pubDate>Wed, 02 Oct 2024 13:00:00 +0200</pubDate></item>
+  <item><title>Sports: the lake team wins</title><pubDate>Wed, 02 Oct 2024 12:00:00 GMT</pubDate></item>
+</channel></rss>"#;
+        let headlines = parse_feed(rss, "News").unwrap();
+        assert_eq!(headlines.len(), 3);
+        assert_eq!(headlines[1].title, "Bus line 7 & 8 merged");
+        assert_eq!(headlines[1].published.unwrap().seconds, 1727866800);
+
+        let feed = Feed {
+            keywords: vec!["LAKE".into(), "bus".into()],
+            excluded_keywords: vec!["sports".into()],
+            max_headlines: Some(2),
+            ..Default::default()
+        };
+        let titles: Vec<String> = select_headlines(headlines, &feed)
+            .into_iter()
+            .map(|headline| headline.title)
+            .collect();
+        assert_eq!(
+            titles,
+            ["Bus line 7 & 8 merged", "Lake Geneva freezes over"]
+        );
+
+        let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>Blog</title>
+  <entry><title type="html">Rust 2024 &amp; beyond</title><updated>2024-10-03T08:00:00Z</updated>
+    <published>2024-10-01T08:00:00Z</published></entry>
+</feed>"#;
+        let headlines = parse_feed(atom, "Blog").unwrap();
+        assert_eq!(headlines[0].title, "Rust 2024 & beyond");
+        assert_eq!(headlines[0].source, "Blog");
+        assert_eq!(headlines[0].published.unwrap().seconds, 1727769600);
+    }
+}
@@ -0,0 +1,204 @@
+//! Indoor temperature, humidity and CO2 from sensors attached to the Pi, e.g. a BME280 or an SCD40
+//! on its I2C bus.
+//!
+//! We don't talk I2C ourselves: the kernel has drivers for these (bmp280 and scd4x), which expose
+//! the compensated measurements as files under /sys/bus/iio/devices once the sensor is declared,
+//! e.g. with `dtoverlay=i2c-sensor,bme280` in the Pi's config.txt. So there's nothing to build
+//! differently for the Pi, elsewhere there are just no sensors to be found.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::screen_service::Indoor;
+use chrono::Timelike;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, error, info};
+
+pub const IIO_DEVICES_DIR: &str = "/sys/bus/iio/devices";
+
+#[derive(Debug)]
+pub struct SensorUpdater {
+    update_mode: UpdateMode,
+    // All the IIO devices there are if empty
+    devices: Vec<PathBuf>,
+    sensor_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for SensorUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(23),
+            UpdateMode::Real => Instant::now() + self.sensor_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} sensors", self.update_mode);
+        let indoor;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                indoor = Some(Indoor {
+                    temperature: Some(21.0 + now.minute() as f32 / 30.0),
+                    humidity: Some(45.0),
+                    // Stale air for a few minutes every hour
+                    co2: Some(500.0 + 20.0 * now.minute() as f32),
+                });
+                error_bit.store(
+                    now.second().is_multiple_of(13),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                indoor = match self.read_sensors() {
+                    Ok(read_indoor) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.sensor_period.set_success();
+                        Some(read_indoor)
+                    }
+                    Err(e) => {
+                        error!("Error reading the sensors: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.sensor_period.set_error();
+                        None
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Indoor(indoor)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl SensorUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let sensors_config = config.sensors.as_ref().ok_or("No sensors config")?;
+        let sensor_period_config = Duration::from_secs(
+            sensors_config
+                .update_period
+                .as_ref()
+                .ok_or("no sensors update period")?
+                .seconds
+                .try_into()?,
+        );
+        let sensor_period = ExponentialBackoff::new(
+            sensor_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(SensorUpdater {
+            update_mode,
+            devices: sensors_config.devices.iter().map(PathBuf::from).collect(),
+            sensor_period,
+        })
+    }
+
+    fn read_sensors(&self) -> Result<Indoor, Box<dyn std::error::Error>> {
+        let devices = match self.devices.is_empty() {
+            true => list_devices(Path::new(IIO_DEVICES_DIR))?,
+            false => self.devices.clone(),
+        };
+        read_indoor(&devices)
+    }
+}
+
+// The IIO devices in `dir`, e.g. /sys/bus/iio/devices/iio:device0
+fn list_devices(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut devices = vec![];
+    for entry in std::fs::read_dir(dir).map_err(|e| format!("Can't list {:?}: {}", dir, e))? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("iio:device"))
+        {
+            devices.push(path);
+        }
+    }
+    devices.sort();
+    Ok(devices)
+}
+
+// Each measurement from the first of the devices that has it
+fn read_indoor(devices: &[PathBuf]) -> Result<Indoor, Box<dyn std::error::Error>> {
+    let first = |channel: &str| {
+        devices
+            .iter()
+            .find_map(|device| read_channel(device, channel))
+    };
+    let indoor = Indoor {
+        // Milli degrees Celsius, and milli percents
+        temperature: first("temp").map(|t| (t / 1000.0) as f32),
+        humidity: first("humidityrelative").map(|h| (h / 1000.0) as f32),
+        // In ppm straight from the scd4x driver, with no scale
+        co2: first("concentration_co2").map(|c| c as f32),
+    };
+    if indoor == Indoor::default() {
+        return Err(format!("No measurement from any of the devices {:?}", devices).into());
+    }
+    Ok(indoor)
+}
+
+// The channel's measurement, from the input file for the drivers that compensate it themselves,
+// otherwise from the raw value with its offset and scale
+fn read_channel(device: &Path, channel: &str) -> Option<f64> {
+    let read = |suffix: &str| -> Option<f64> {
+        let path = device.join(format!("in_{}_{}", channel, suffix));
+        let text = std::fs::read_to_string(&path).ok()?;
+        let value = text.trim().parse().ok();
+        if value.is_none() {
+            debug!("Unreadable {:?}: {:?}", path, text);
+        }
+        value
+    };
+    if let Some(input) = read("input") {
+        return Some(input);
+    }
+    let raw = read("raw")?;
+    Some((raw + read("offset").unwrap_or(0.0)) * read("scale").unwrap_or(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_iio_devices() {
+        let dir = std::env::temp_dir().join(format!("sensor_updater_{}", std::process::id()));
+        let write = |device: &str, file: &str, value: &str| {
+            std::fs::create_dir_all(dir.join(device)).unwrap();
+            std::fs::write(dir.join(device).join(file), value).unwrap();
+        };
+        // A BME280, compensated by its driver
+        write("iio:device0", "in_temp_input", "21480\n");
+        write("iio:device0", "in_humidityrelative_input", "45123\n");
+        write("iio:device0", "in_pressure_input", "96.5\n");
+        // And an SCD40, whose temperature is raw and only read if the first sensor has none
+        write("iio:device1", "in_temp_raw", "25000\n");
+        write("iio:device1", "in_temp_offset", "-16852\n");
+        write("iio:device1", "in_temp_scale", "2.670288\n");
+        write("iio:device1", "in_concentration_co2_raw", "812\n");
+        write("trigger0", "name", "not a device\n");
+
+        let devices = list_devices(&dir).unwrap();
+        assert_eq!(devices.len(), 2);
+        let indoor = read_indoor(&devices).unwrap();
+        assert_eq!(indoor.temperature, Some(21.48));
+        assert_eq!(indoor.humidity, Some(45.123));
+        assert_eq!(indoor.co2, Some(812.0));
+        let scd40_temperature = read_channel(&devices[1], "temp").unwrap();
+        assert!((scd40_temperature / 1000.0 - 21.76).abs() < 0.01);
+
+        assert!(read_indoor(&[dir.join("trigger0")]).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
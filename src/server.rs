@@ -1,15 +1,9 @@
-mod config_extractor;
-mod data_updater;
-mod dummy_client;
-mod gcal_updater;
-mod kitty_updater;
-mod my_screen_service;
-mod transport_updater;
-mod exponential_backoff;
+mod server_modules;
 
-use log::debug;
 use screen_service::screen_service_server::ScreenServiceServer;
+use server_modules::*;
 use tonic::transport::Server;
+use tracing::{debug, error};
 
 pub mod screen_service {
     tonic::include_proto!("screen_service"); // The string specified here must match the proto package name
@@ -29,16 +23,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Create the service, and tell it to start the content updates
     let mut screen_service = my_screen_service::MyScreenService::new(&config);
-    screen_service.start_backgound_updates();
+    screen_service
+        .start_backgound_updates()
+        .expect("Error starting the updaters");
+    // All updaters start at once, only start serving when they're done (or taking too long)
+    screen_service.warm_up().await;
 
     // Start the actual serving, always from localhost ('[::1]' or '127.0.0.1' or '0.0.0.0')
     // (The address in the config is for clients)
     let server_config = config.server.as_ref().expect("No server config found");
+    if let Some(compact_port) = server_config.compact_port {
+        let compact_service = screen_service.clone();
+        tokio::spawn(async move {
+            if let Err(e) = content_encoder::serve_compact(compact_service, compact_port).await {
+                error!("Stopped serving constrained clients: {}", e);
+            }
+        });
+    }
+    if let Some(webhook_port) = server_config.webhook_port {
+        let webhook_service = screen_service.clone();
+        let token = server_config.webhook_token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = webhook::serve_webhook(webhook_service, webhook_port, token).await {
+                error!("Stopped taking notices over HTTP: {}", e);
+            }
+        });
+    }
+    if let Some(beacon_config) = config.beacon.clone() {
+        let beacon_service = screen_service.clone();
+        tokio::spawn(async move {
+            let get_hash = || beacon_service.get_hash().ok();
+            if let Err(e) = hash_beacon::broadcast(&beacon_config, get_hash).await {
+                error!("Stopped broadcasting the content hash: {}", e);
+            }
+        });
+    }
     let address = format!("0.0.0.0:{}", server_config.port)
         .parse()
         .expect("Couldn't parse the config port to an address");
+    // Anyone on the LAN gets the content, only admins get to change it
+    let admin_interceptor = admin_auth::AdminInterceptor::new(server_config.admin_token.clone());
     Server::builder()
-        .add_service(ScreenServiceServer::new(screen_service))
+        .add_service(ScreenServiceServer::with_interceptor(
+            screen_service,
+            admin_interceptor,
+        ))
         .serve(address)
         .await
         .expect("Error while starting or executing the server");
@@ -0,0 +1,117 @@
+//! The server's modules, declared once for both the server and the soak test binaries, which
+//! `use` them all from their root so the modules keep reaching each other through `crate::`
+
+#[path = "admin_auth.rs"]
+pub mod admin_auth;
+#[path = "astronomy_updater.rs"]
+pub mod astronomy_updater;
+#[path = "bike_sharing_updater.rs"]
+pub mod bike_sharing_updater;
+#[path = "chores_updater.rs"]
+pub mod chores_updater;
+#[path = "circuit_breaker.rs"]
+pub mod circuit_breaker;
+#[path = "config_extractor.rs"]
+pub mod config_extractor;
+#[path = "connectivity_updater.rs"]
+pub mod connectivity_updater;
+#[path = "content_aggregator.rs"]
+pub mod content_aggregator;
+#[path = "content_encoder.rs"]
+pub mod content_encoder;
+#[path = "content_review.rs"]
+pub mod content_review;
+#[path = "content_store.rs"]
+pub mod content_store;
+#[path = "countdown_updater.rs"]
+pub mod countdown_updater;
+#[path = "data_updater.rs"]
+pub mod data_updater;
+#[path = "destinations.rs"]
+pub mod destinations;
+#[path = "dummy_client.rs"]
+pub mod dummy_client;
+#[path = "ev_charger_updater.rs"]
+pub mod ev_charger_updater;
+#[path = "event_format.rs"]
+pub mod event_format;
+#[path = "exponential_backoff.rs"]
+pub mod exponential_backoff;
+#[path = "gcal_updater.rs"]
+pub mod gcal_updater;
+#[path = "gtfs_realtime.rs"]
+pub mod gtfs_realtime;
+#[path = "gtfs_static.rs"]
+pub mod gtfs_static;
+#[path = "hash_beacon.rs"]
+pub mod hash_beacon;
+#[path = "home_assistant_updater.rs"]
+pub mod home_assistant_updater;
+#[path = "http_client.rs"]
+pub mod http_client;
+#[path = "iss_pass_updater.rs"]
+pub mod iss_pass_updater;
+#[path = "json_poller_updater.rs"]
+pub mod json_poller_updater;
+#[path = "kitty_history.rs"]
+pub mod kitty_history;
+#[path = "kitty_snapshots.rs"]
+pub mod kitty_snapshots;
+#[path = "kitty_updater.rs"]
+pub mod kitty_updater;
+#[path = "media_server_updater.rs"]
+pub mod media_server_updater;
+#[path = "mqtt_updater.rs"]
+pub mod mqtt_updater;
+#[path = "my_screen_service.rs"]
+pub mod my_screen_service;
+#[path = "octoprint_updater.rs"]
+pub mod octoprint_updater;
+#[path = "ojp_trip.rs"]
+pub mod ojp_trip;
+#[path = "openings_updater.rs"]
+pub mod openings_updater;
+#[path = "race_calendar_updater.rs"]
+pub mod race_calendar_updater;
+#[path = "retry.rs"]
+pub mod retry;
+#[path = "rss_updater.rs"]
+pub mod rss_updater;
+#[path = "sensor_updater.rs"]
+pub mod sensor_updater;
+#[path = "shopping_list_updater.rs"]
+pub mod shopping_list_updater;
+#[path = "smart_plug_updater.rs"]
+pub mod smart_plug_updater;
+#[path = "solar_updater.rs"]
+pub mod solar_updater;
+#[path = "sports_updater.rs"]
+pub mod sports_updater;
+#[path = "system_stats_updater.rs"]
+pub mod system_stats_updater;
+#[path = "ticker_updater.rs"]
+pub mod ticker_updater;
+#[path = "time_util.rs"]
+pub mod time_util;
+#[path = "todoist_updater.rs"]
+pub mod todoist_updater;
+#[path = "transport_opendata.rs"]
+pub mod transport_opendata;
+#[path = "transport_updater.rs"]
+pub mod transport_updater;
+#[path = "unraid_updater.rs"]
+pub mod unraid_updater;
+#[path = "update_schedule.rs"]
+pub mod update_schedule;
+#[path = "update_scheduler.rs"]
+pub mod update_scheduler;
+#[path = "update_tracing.rs"]
+pub mod update_tracing;
+#[path = "updater_registry.rs"]
+pub mod updater_registry;
+#[path = "waste_collection_updater.rs"]
+pub mod waste_collection_updater;
+#[path = "weather_updater.rs"]
+pub mod weather_updater;
+#[path = "webhook.rs"]
+pub mod webhook;
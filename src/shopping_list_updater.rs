@@ -0,0 +1,271 @@
+//! How many items are on a shared Bring! shopping list, as a text widget that only shows while
+//! there's something to buy.
+//!
+//! Bring! has no public API, this talks to the one its web app uses: a login with the account's
+//! email and password gives a token and the default list, whose items are under "purchase".
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::TextWidget;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::header::AUTHORIZATION;
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub const SOURCE: &str = "shopping_list";
+const BRING_API: &str = "https://api.getbring.com/rest/v2";
+// The web app's, which every Bring! integration out there uses
+const BRING_API_KEY: &str = "cof4Nc6D8saplXjE3h3HXqHH8m7VU2i1Gs0g85Sp";
+const DEFAULT_LABEL: &str = "Shopping";
+
+#[derive(Debug)]
+struct Session {
+    access_token: String,
+    list_uuid: String,
+}
+
+#[derive(Debug)]
+pub struct ShoppingListUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    email: String,
+    password: String,
+    list_uuid: String,
+    label: String,
+    show_latest: bool,
+    shopping_list_period: ExponentialBackoff,
+    // Logged in once, until Bring! doesn't take the token anymore
+    session: Option<Session>,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for ShoppingListUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(37),
+            UpdateMode::Real => Instant::now() + self.shopping_list_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} shopping list", self.update_mode);
+        let items;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // One more item every ten minutes
+                let dummy_items = ["Milk", "Bread", "Eggs", "Coffee", "Apples"];
+                items = dummy_items[..(now.minute() as usize / 10).min(dummy_items.len())]
+                    .iter()
+                    .rev()
+                    .map(|item| item.to_string())
+                    .collect();
+                error_bit.store(
+                    now.second().is_multiple_of(37),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                items = match self.get_items().await {
+                    Ok(fetched) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.shopping_list_period.set_success();
+                        fetched
+                    }
+                    Err(e) => {
+                        error!("Error getting the shopping list: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.shopping_list_period.set_error();
+                        vec![]
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::TextWidgets {
+            source: SOURCE,
+            widgets: get_widget(&items, &self.label, self.show_latest)
+                .into_iter()
+                .collect(),
+        }]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl ShoppingListUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let shopping_list_config = config
+            .shopping_list
+            .as_ref()
+            .ok_or("No shopping list config")?;
+        if shopping_list_config.email.is_empty() {
+            return Err("No Bring! email".into());
+        }
+        let shopping_list_period_config = Duration::from_secs(
+            shopping_list_config
+                .update_period
+                .as_ref()
+                .ok_or("no shopping list update period")?
+                .seconds
+                .try_into()?,
+        );
+        let shopping_list_period = ExponentialBackoff::new(
+            shopping_list_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        let label = match shopping_list_config.label.is_empty() {
+            true => DEFAULT_LABEL,
+            false => shopping_list_config.label.as_str(),
+        };
+        Ok(ShoppingListUpdater {
+            update_mode,
+            client,
+            email: shopping_list_config.email.clone(),
+            password: shopping_list_config.password.clone(),
+            list_uuid: shopping_list_config.list_uuid.clone(),
+            label: label.to_string(),
+            show_latest: shopping_list_config.show_latest,
+            shopping_list_period,
+            session: None,
+        })
+    }
+
+    // The names of the items to buy, latest first
+    async fn get_items(&mut self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        if self.session.is_none() {
+            self.session = Some(self.login().await?);
+        }
+        let session = self.session.as_ref().ok_or("No Bring! session")?;
+        let url = format!("{}/bringlists/{}", BRING_API, session.list_uuid);
+        let response = traced(
+            "fetch",
+            retry_http("Shopping list fetch", || async {
+                let response = self
+                    .client
+                    .get(&url)
+                    .header(AUTHORIZATION, format!("Bearer {}", session.access_token))
+                    .header("X-BRING-API-KEY", BRING_API_KEY)
+                    .send()
+                    .await?;
+                Ok((response.status(), response.text().await?))
+            }),
+        )
+        .await?;
+        match response {
+            (StatusCode::UNAUTHORIZED, _) => {
+                // Expired, log in again next time
+                self.session = None;
+                Err("Bring! didn't take the token".into())
+            }
+            (status, _) if !status.is_success() => {
+                Err(format!("Bring! answered {} for the list", status).into())
+            }
+            (_, body) => traced_sync("parse", || parse_items(&body)),
+        }
+    }
+
+    async fn login(&self) -> Result<Session, Box<dyn std::error::Error>> {
+        let url = format!("{}/bringauth", BRING_API);
+        let body = traced(
+            "login",
+            retry_http("Bring! login", || async {
+                self.client
+                    .post(&url)
+                    .form(&[("email", &self.email), ("password", &self.password)])
+                    .header("X-BRING-API-KEY", BRING_API_KEY)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        let login: Value = serde_json::from_str(&body)?;
+        let text = |name: &str| login.get(name).and_then(Value::as_str);
+        let access_token = text("access_token").ok_or("No token in the Bring! login")?;
+        // The account's default list unless the config picks another
+        let list_uuid = match self.list_uuid.is_empty() {
+            true => text("bringListUUID").ok_or("No default list in the Bring! login")?,
+            false => self.list_uuid.as_str(),
+        };
+        Ok(Session {
+            access_token: access_token.to_string(),
+            list_uuid: list_uuid.to_string(),
+        })
+    }
+}
+
+// Bring! puts the latest additions first
+fn parse_items(body: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let list: Value = serde_json::from_str(body)?;
+    let purchase = list
+        .get("purchase")
+        .and_then(Value::as_array)
+        .ok_or("No purchase in the shopping list")?;
+    Ok(purchase
+        .iter()
+        .filter_map(|item| item.get("name")?.as_str())
+        .map(str::to_string)
+        .collect())
+}
+
+// e.g. "Shopping: 3 items, Milk", nothing when the list is empty
+fn get_widget(items: &[String], label: &str, show_latest: bool) -> Option<TextWidget> {
+    let count = match items.len() {
+        0 => return None,
+        1 => "1 item".to_string(),
+        n => format!("{} items", n),
+    };
+    let text = match (show_latest, items.first()) {
+        (true, Some(latest)) => format!("{}, {}", count, latest),
+        _ => count,
+    };
+    Some(TextWidget {
+        label: label.to_string(),
+        text,
+        source: SOURCE.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_list_items() {
+        let body = r#"{"uuid": "9b3ba561-02ad-4744-a737-c43k7e5b93ec", "status": "SHARED",
+            "purchase": [
+                {"specification": "2L", "name": "Milk"},
+                {"specification": "", "name": "Bread"}
+            ],
+            "recently": [{"specification": "", "name": "Eggs"}]}"#;
+        let items = parse_items(body).unwrap();
+        assert_eq!(items, ["Milk", "Bread"]);
+
+        let widget = get_widget(&items, "Shopping", true).unwrap();
+        assert_eq!(
+            (widget.label.as_str(), widget.text.as_str()),
+            ("Shopping", "2 items, Milk")
+        );
+        assert_eq!(
+            get_widget(&items[1..], "Shopping", false).unwrap().text,
+            "1 item"
+        );
+        assert!(get_widget(&[], "Shopping", true).is_none());
+    }
+}
@@ -0,0 +1,283 @@
+//! What the appliances behind a few smart plugs are up to, from their power draw: running above a
+//! threshold, then finished once they've stayed under it for a while (a washing machine pauses
+//! between its cycles). Finished appliances stay so until acknowledged, see the Acknowledge RPC.
+//!
+//! This reads the plugs' local HTTP APIs: Shelly's /meter/0 (Gen1), /rpc/Switch.GetStatus (Gen2
+//! and later), and Tasmota's sensor status.
+
+use crate::config_extractor::api_config;
+use crate::config_extractor::api_config::smart_plugs_config::{plug::Kind, Plug};
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::appliance::State;
+use crate::screen_service::Appliance;
+use crate::update_tracing::{traced, traced_sync};
+use crate::updater_registry::UpdaterCommand;
+use chrono::Timelike;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+const DEFAULT_RUNNING_WATTS: f32 = 10.0;
+const DEFAULT_FINISHED_AFTER: i64 = 180;
+
+// Where an appliance is at, kept across updates
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tracker {
+    state: State,
+    power: f32,
+    // When the power went under the threshold while running
+    quiet_since: Option<i64>,
+}
+
+impl Tracker {
+    fn new() -> Self {
+        Tracker {
+            state: State::Idle,
+            power: 0.0,
+            quiet_since: None,
+        }
+    }
+
+    // Running as soon as the power goes over `running_watts`, finished once it's been under it
+    // for `finished_after` seconds
+    fn record(&mut self, power: f32, running_watts: f32, finished_after: i64, now: i64) {
+        self.power = power;
+        if power >= running_watts {
+            self.state = State::Running;
+            self.quiet_since = None;
+        } else if self.state == State::Running {
+            let quiet_since = *self.quiet_since.get_or_insert(now);
+            if now - quiet_since >= finished_after {
+                self.state = State::Finished;
+                self.quiet_since = None;
+            }
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        if self.state == State::Finished {
+            self.state = State::Idle;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SmartPlugUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    plugs: Vec<Plug>,
+    // One per plug, in the same order
+    trackers: Vec<Tracker>,
+    // The mode the trackers follow
+    trackers_mode: UpdateMode,
+    smart_plugs_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for SmartPlugUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(53),
+            UpdateMode::Real => Instant::now() + self.smart_plugs_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} smart plugs", self.update_mode);
+        // Each mode tracks its own appliances
+        if self.trackers_mode != self.update_mode {
+            self.trackers = vec![Tracker::new(); self.plugs.len()];
+            self.trackers_mode = self.update_mode;
+        }
+        let now = chrono::offset::Local::now();
+        let appliances = match self.update_mode {
+            UpdateMode::Dummy => {
+                // Washing over the first 20 minutes of every hour, finished a minute after
+                let power = match now.minute() < 20 {
+                    true => 450.0,
+                    false => 1.5,
+                };
+                self.trackers.resize(1, Tracker::new());
+                self.trackers[0].record(power, DEFAULT_RUNNING_WATTS, 60, now.timestamp());
+                error_bit.store(
+                    now.second().is_multiple_of(53),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+                vec![get_appliance("Washer", &self.trackers[0])]
+            }
+            UpdateMode::Real => {
+                // Plugs that can't be reached keep their last state
+                let mut failed = false;
+                for (plug, tracker) in self.plugs.iter().zip(self.trackers.iter_mut()) {
+                    match get_power(&self.client, plug).await {
+                        Ok(power) => {
+                            let running_watts = plug.running_watts.unwrap_or(DEFAULT_RUNNING_WATTS);
+                            let finished_after = plug
+                                .finished_after
+                                .as_ref()
+                                .map_or(DEFAULT_FINISHED_AFTER, |duration| duration.seconds);
+                            tracker.record(power, running_watts, finished_after, now.timestamp());
+                        }
+                        Err(e) => {
+                            error!("Error getting the power of {}: {}", plug.label, e);
+                            failed = true;
+                        }
+                    }
+                }
+                error_bit.store(failed, std::sync::atomic::Ordering::Relaxed);
+                match failed {
+                    true => self.smart_plugs_period.set_error(),
+                    false => self.smart_plugs_period.set_success(),
+                }
+                self.plugs
+                    .iter()
+                    .zip(&self.trackers)
+                    .map(|(plug, tracker)| get_appliance(&plug.label, tracker))
+                    .collect()
+            }
+        };
+        vec![ContentUpdate::Appliances(appliances)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+
+    fn command(&mut self, command: UpdaterCommand) -> Result<(), String> {
+        match command {
+            UpdaterCommand::Acknowledge => {
+                self.trackers.iter_mut().for_each(Tracker::acknowledge);
+                Ok(())
+            }
+            command => Err(format!("{:?} isn't supported", command)),
+        }
+    }
+}
+
+impl SmartPlugUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let smart_plugs_config = config.smart_plugs.as_ref().ok_or("No smart plugs config")?;
+        if smart_plugs_config.plugs.is_empty() {
+            return Err("No smart plugs configured".into());
+        }
+        let smart_plugs_period_config = Duration::from_secs(
+            smart_plugs_config
+                .update_period
+                .as_ref()
+                .ok_or("no smart plugs update period")?
+                .seconds
+                .try_into()?,
+        );
+        let smart_plugs_period = ExponentialBackoff::new(
+            smart_plugs_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        let mut plugs = smart_plugs_config.plugs.clone();
+        for plug in &mut plugs {
+            plug.url = plug.url.trim_end_matches('/').to_string();
+        }
+        Ok(SmartPlugUpdater {
+            update_mode,
+            client,
+            trackers: vec![Tracker::new(); plugs.len()],
+            trackers_mode: update_mode,
+            plugs,
+            smart_plugs_period,
+        })
+    }
+}
+
+// In W
+async fn get_power(client: &Client, plug: &Plug) -> Result<f32, Box<dyn std::error::Error>> {
+    let url = match plug.kind() {
+        Kind::Shelly => format!("{}/meter/0", plug.url),
+        Kind::ShellyGen2 => format!("{}/rpc/Switch.GetStatus?id=0", plug.url),
+        Kind::Tasmota => format!("{}/cm?cmnd=Status%208", plug.url),
+    };
+    let body = traced(
+        "fetch",
+        retry_http("Smart plug fetch", || async {
+            client
+                .get(&url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }),
+    )
+    .await?;
+    traced_sync("parse", || parse_power(&body, plug.kind()))
+}
+
+fn parse_power(body: &str, kind: Kind) -> Result<f32, Box<dyn std::error::Error>> {
+    let status: Value = serde_json::from_str(body)?;
+    let pointer = match kind {
+        Kind::Shelly => "/power",
+        Kind::ShellyGen2 => "/apower",
+        Kind::Tasmota => "/StatusSNS/ENERGY/Power",
+    };
+    let power = status
+        .pointer(pointer)
+        .and_then(Value::as_f64)
+        .ok_or_else(|| format!("No power in the plug's status: {}", body))?;
+    Ok(power as f32)
+}
+
+fn get_appliance(label: &str, tracker: &Tracker) -> Appliance {
+    let mut appliance = Appliance {
+        label: label.to_string(),
+        power: tracker.power,
+        ..Default::default()
+    };
+    appliance.set_state(tracker.state);
+    appliance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_appliance_cycles() {
+        let shelly = r#"{"power": 452.31, "overpower": 0.00, "is_valid": true,
+            "timestamp": 1729600000, "counters": [7.5, 7.4, 7.6], "total": 4321}"#;
+        assert_eq!(parse_power(shelly, Kind::Shelly).unwrap(), 452.31);
+        let shelly_gen2 = r#"{"id": 0, "source": "HTTP", "output": true, "apower": 8.9,
+            "voltage": 231.2, "current": 0.05, "aenergy": {"total": 1234.5}}"#;
+        assert_eq!(parse_power(shelly_gen2, Kind::ShellyGen2).unwrap(), 8.9);
+        let tasmota = r#"{"StatusSNS": {"Time": "2024-10-22T18:45:00",
+            "ENERGY": {"Total": 12.3, "Power": 1200, "Voltage": 230}}}"#;
+        assert_eq!(parse_power(tasmota, Kind::Tasmota).unwrap(), 1200.0);
+        assert!(parse_power(tasmota, Kind::Shelly).is_err());
+
+        let mut tracker = Tracker::new();
+        tracker.record(2.0, 10.0, 180, 0);
+        assert_eq!(tracker.state, State::Idle);
+        tracker.record(450.0, 10.0, 180, 60);
+        assert_eq!(tracker.state, State::Running);
+        // Pausing between cycles
+        tracker.record(3.0, 10.0, 180, 120);
+        tracker.record(400.0, 10.0, 180, 240);
+        tracker.record(3.0, 10.0, 180, 300);
+        tracker.record(3.0, 10.0, 180, 420);
+        assert_eq!(tracker.state, State::Running);
+        tracker.record(3.0, 10.0, 180, 480);
+        assert_eq!(tracker.state, State::Finished);
+        // Until acknowledged
+        tracker.record(3.0, 10.0, 180, 3600);
+        assert_eq!(tracker.state, State::Finished);
+        tracker.acknowledge();
+        assert_eq!(tracker.state, State::Idle);
+    }
+}
@@ -0,0 +1,522 @@
+// The server binary is the one flagging dead code: the soak test leaves out the compact and
+// webhook ports and the dummy client
+#[allow(dead_code)]
+mod server_modules;
+
+use clap::{Arg, ArgMatches};
+use config_extractor::api_config::bike_sharing_config::Station;
+use config_extractor::api_config::rss_config::Feed;
+use config_extractor::api_config::smart_plugs_config::Plug;
+use config_extractor::api_config::tickers_config::Ticker;
+use config_extractor::api_config::{
+    ApiConfig, AstronomyConfig, BikeSharingConfig, ChoresConfig, ConnectivityConfig,
+    CountdownsConfig, EvChargerConfig, HomeAssistantConfig, IssPassesConfig, JsonPollerConfig,
+    JsonSource, MediaServerConfig, MqttConfig, OctoPrintConfig, OpeningsConfig, RaceCalendarConfig,
+    RssConfig, SensorsConfig, ShoppingListConfig, SmartPlugsConfig, SolarConfig, SportsConfig,
+    SystemStatsConfig, TickersConfig, TodoistConfig, UnraidConfig, WasteCollectionConfig,
+    WeatherConfig,
+};
+use screen_service::screen_service_client::ScreenServiceClient;
+use screen_service::screen_service_server::ScreenServiceServer;
+use screen_service::{ScreenContentRequest, ScreenHashRequest};
+use server_modules::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tonic::transport::Server;
+use tracing::{error, info, warn};
+
+pub mod screen_service {
+    tonic::include_proto!("screen_service"); // The string specified here must match the proto package name
+}
+
+const REPORT_PERIOD: Duration = Duration::from_secs(60);
+
+// What the simulated clients saw, shared between all of them
+#[derive(Default)]
+struct Counters {
+    hash_requests: AtomicU64,
+    hash_changes: AtomicU64,
+    errors: AtomicU64,
+    // Slowest request since the last report, a symptom of lock contention
+    max_latency_micros: AtomicU64,
+}
+
+fn cli() -> clap::Command {
+    config_extractor::cli()
+        .about("Runs the server with dummy updaters and polling clients for a long time, tracking its memory")
+        .arg(
+            Arg::new("hours")
+                .long("hours")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("4")
+                .help("How long to run for"),
+        )
+        .arg(
+            Arg::new("clients")
+                .long("clients")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("4")
+                .help("How many clients poll the server"),
+        )
+        .arg(
+            Arg::new("poll_ms")
+                .long("poll_ms")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("200")
+                .help("How often each client polls the hash, in ms"),
+        )
+        .arg(
+            Arg::new("speedup")
+                .long("speedup")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("10")
+                .help("How many times faster than usual the updaters run"),
+        )
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("50099")
+                .help("Where to serve, away from the real server's port"),
+        )
+        .arg(
+            Arg::new("max_rss_growth_mb")
+                .long("max_rss_growth_mb")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("32")
+                .help("Fail if the resident memory grows more than this over the run"),
+        )
+}
+
+// Runs every updater in dummy mode, on its own port, without touching the cache, beacon or
+// compact port of the real server
+fn to_soak_config(mut config: ApiConfig, port: u32) -> ApiConfig {
+    if let Some(kitty) = config.kitty.as_mut() {
+        kitty.dummy_mode = true;
+    }
+    if let Some(gcal) = config.gcal.as_mut() {
+        gcal.dummy_mode = true;
+    }
+    if let Some(transport) = config.transport.as_mut() {
+        transport.dummy_mode = true;
+    }
+    let weather = config.weather.get_or_insert_with(|| WeatherConfig {
+        update_period: Some(pbjson_types::Duration {
+            seconds: 600,
+            nanos: 0,
+        }),
+        ..Default::default()
+    });
+    weather.dummy_mode = true;
+    weather.enabled = Some(true);
+    let astronomy = config
+        .astronomy
+        .get_or_insert_with(AstronomyConfig::default);
+    astronomy.dummy_mode = true;
+    astronomy.enabled = Some(true);
+    let countdowns = config
+        .countdowns
+        .get_or_insert_with(CountdownsConfig::default);
+    countdowns.dummy_mode = true;
+    countdowns.enabled = Some(true);
+    let sensors = config.sensors.get_or_insert_with(|| SensorsConfig {
+        update_period: Some(pbjson_types::Duration {
+            seconds: 60,
+            nanos: 0,
+        }),
+        ..Default::default()
+    });
+    sensors.dummy_mode = true;
+    sensors.enabled = Some(true);
+    let system_stats = config
+        .system_stats
+        .get_or_insert_with(|| SystemStatsConfig {
+            update_period: Some(pbjson_types::Duration {
+                seconds: 60,
+                nanos: 0,
+            }),
+            ..Default::default()
+        });
+    system_stats.dummy_mode = true;
+    system_stats.enabled = Some(true);
+    let unraid = config.unraid.get_or_insert_with(|| UnraidConfig {
+        update_period: Some(pbjson_types::Duration {
+            seconds: 60,
+            nanos: 0,
+        }),
+        url: "http://tower.lan".into(),
+        ..Default::default()
+    });
+    unraid.dummy_mode = true;
+    unraid.enabled = Some(true);
+    let connectivity = config
+        .connectivity
+        .get_or_insert_with(|| ConnectivityConfig {
+            update_period: Some(pbjson_types::Duration {
+                seconds: 60,
+                nanos: 0,
+            }),
+            ..Default::default()
+        });
+    connectivity.dummy_mode = true;
+    connectivity.enabled = Some(true);
+    let mqtt = config.mqtt.get_or_insert_with(|| MqttConfig {
+        host: "broker.lan".into(),
+        ..Default::default()
+    });
+    mqtt.dummy_mode = true;
+    mqtt.enabled = Some(true);
+    let home_assistant = config
+        .home_assistant
+        .get_or_insert_with(|| HomeAssistantConfig {
+            update_period: Some(pbjson_types::Duration {
+                seconds: 60,
+                nanos: 0,
+            }),
+            url: "http://homeassistant.local:8123".into(),
+            ..Default::default()
+        });
+    home_assistant.dummy_mode = true;
+    home_assistant.enabled = Some(true);
+    let media_server = config
+        .media_server
+        .get_or_insert_with(|| MediaServerConfig {
+            update_period: Some(pbjson_types::Duration {
+                seconds: 60,
+                nanos: 0,
+            }),
+            url: "http://jellyfin.lan:8096".into(),
+            ..Default::default()
+        });
+    media_server.dummy_mode = true;
+    media_server.enabled = Some(true);
+    let solar = config.solar.get_or_insert_with(|| SolarConfig {
+        update_period: Some(pbjson_types::Duration {
+            seconds: 60,
+            nanos: 0,
+        }),
+        url: "http://192.168.1.50".into(),
+        ..Default::default()
+    });
+    solar.dummy_mode = true;
+    solar.enabled = Some(true);
+    let tickers = config.tickers.get_or_insert_with(|| TickersConfig {
+        update_period: Some(pbjson_types::Duration {
+            seconds: 60,
+            nanos: 0,
+        }),
+        tickers: vec![Ticker {
+            symbol: "AAPL".into(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    });
+    tickers.dummy_mode = true;
+    tickers.enabled = Some(true);
+    let rss = config.rss.get_or_insert_with(|| RssConfig {
+        feeds: vec![Feed {
+            url: "https://example.com/feed.xml".into(),
+            label: "News".into(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    });
+    rss.dummy_mode = true;
+    rss.enabled = Some(true);
+    let waste_collection = config
+        .waste_collection
+        .get_or_insert_with(|| WasteCollectionConfig {
+            update_period: Some(pbjson_types::Duration {
+                seconds: 3600,
+                nanos: 0,
+            }),
+            ics_url: "https://example.com/waste.ics".into(),
+            ..Default::default()
+        });
+    waste_collection.dummy_mode = true;
+    waste_collection.enabled = Some(true);
+    let todoist = config.todoist.get_or_insert_with(|| TodoistConfig {
+        token: "dummy".into(),
+        update_period: Some(pbjson_types::Duration {
+            seconds: 300,
+            nanos: 0,
+        }),
+        ..Default::default()
+    });
+    todoist.dummy_mode = true;
+    todoist.enabled = Some(true);
+    let shopping_list = config
+        .shopping_list
+        .get_or_insert_with(|| ShoppingListConfig {
+            email: "dummy@example.com".into(),
+            update_period: Some(pbjson_types::Duration {
+                seconds: 300,
+                nanos: 0,
+            }),
+            show_latest: true,
+            ..Default::default()
+        });
+    shopping_list.dummy_mode = true;
+    shopping_list.enabled = Some(true);
+    let bike_sharing = config
+        .bike_sharing
+        .get_or_insert_with(|| BikeSharingConfig {
+            station_status_url: "https://example.com/gbfs/station_status".into(),
+            stations: vec![Station {
+                station_id: "230".into(),
+                label: "PB".into(),
+            }],
+            update_period: Some(pbjson_types::Duration {
+                seconds: 60,
+                nanos: 0,
+            }),
+            ..Default::default()
+        });
+    bike_sharing.dummy_mode = true;
+    bike_sharing.enabled = Some(true);
+    let ev_charger = config.ev_charger.get_or_insert_with(|| EvChargerConfig {
+        url: "http://192.168.1.60".into(),
+        update_period: Some(pbjson_types::Duration {
+            seconds: 60,
+            nanos: 0,
+        }),
+        ..Default::default()
+    });
+    ev_charger.dummy_mode = true;
+    ev_charger.enabled = Some(true);
+    let sports = config.sports.get_or_insert_with(|| SportsConfig {
+        team_ids: vec![1871],
+        update_period: Some(pbjson_types::Duration {
+            seconds: 60,
+            nanos: 0,
+        }),
+        ..Default::default()
+    });
+    sports.dummy_mode = true;
+    sports.enabled = Some(true);
+    let race_calendar = config
+        .race_calendar
+        .get_or_insert_with(|| RaceCalendarConfig {
+            update_period: Some(pbjson_types::Duration {
+                seconds: 60,
+                nanos: 0,
+            }),
+            ..Default::default()
+        });
+    race_calendar.dummy_mode = true;
+    race_calendar.enabled = Some(true);
+    let json_poller = config.json_poller.get_or_insert_with(|| JsonPollerConfig {
+        update_period: Some(pbjson_types::Duration {
+            seconds: 60,
+            nanos: 0,
+        }),
+        sources: vec![JsonSource {
+            url: "https://example.com/api.json".into(),
+            label: "Example".into(),
+            values: [("value".to_string(), "$.value".to_string())].into(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    });
+    json_poller.dummy_mode = true;
+    json_poller.enabled = Some(true);
+    let smart_plugs = config.smart_plugs.get_or_insert_with(|| SmartPlugsConfig {
+        update_period: Some(pbjson_types::Duration {
+            seconds: 60,
+            nanos: 0,
+        }),
+        plugs: vec![Plug {
+            url: "http://192.168.1.70".into(),
+            label: "Washer".into(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    });
+    smart_plugs.dummy_mode = true;
+    smart_plugs.enabled = Some(true);
+    let openings = config.openings.get_or_insert_with(|| OpeningsConfig {
+        url: "http://homeassistant.local:8123".into(),
+        update_period: Some(pbjson_types::Duration {
+            seconds: 60,
+            nanos: 0,
+        }),
+        ..Default::default()
+    });
+    openings.dummy_mode = true;
+    openings.enabled = Some(true);
+    let octoprint = config.octoprint.get_or_insert_with(|| OctoPrintConfig {
+        url: "http://octopi.local".into(),
+        update_period: Some(pbjson_types::Duration {
+            seconds: 60,
+            nanos: 0,
+        }),
+        ..Default::default()
+    });
+    octoprint.dummy_mode = true;
+    octoprint.enabled = Some(true);
+    let chores = config.chores.get_or_insert_with(ChoresConfig::default);
+    chores.dummy_mode = true;
+    chores.enabled = Some(true);
+    let iss_passes = config.iss_passes.get_or_insert_with(|| IssPassesConfig {
+        api_key: "soak".into(),
+        update_period: Some(pbjson_types::Duration {
+            seconds: 6 * 3600,
+            nanos: 0,
+        }),
+        ..Default::default()
+    });
+    iss_passes.dummy_mode = true;
+    iss_passes.enabled = Some(true);
+    config.beacon = None;
+    if let Some(server) = config.server.as_mut() {
+        server.address = "127.0.0.1".into();
+        server.port = port;
+        server.compact_port = None;
+        server.webhook_port = None;
+        server.cache_file = None;
+    }
+    config
+}
+
+// The resident set size of this process, in kB (only on Linux)
+fn get_rss_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+async fn poll(config: ApiConfig, poll_period: Duration, counters: Arc<Counters>) {
+    let address = config_extractor::get_server_address(&config);
+    let mut client = loop {
+        match ScreenServiceClient::connect(address.clone()).await {
+            Ok(client) => break client,
+            Err(e) => {
+                warn!("Client couldn't connect yet: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    };
+    let mut interval = tokio::time::interval(poll_period);
+    let mut hash = 0;
+    loop {
+        interval.tick().await;
+        let start = Instant::now();
+        let new_hash = match client.get_screen_hash(ScreenHashRequest {}).await {
+            Ok(reply) => reply.into_inner().hash,
+            Err(e) => {
+                error!("Hash request failed: {}", e);
+                counters.errors.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+        counters.hash_requests.fetch_add(1, Ordering::Relaxed);
+        if new_hash != hash {
+            hash = new_hash;
+            counters.hash_changes.fetch_add(1, Ordering::Relaxed);
+            if let Err(e) = client.get_screen_content(ScreenContentRequest {}).await {
+                error!("Content request failed: {}", e);
+                counters.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let latency = u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX);
+        counters
+            .max_latency_micros
+            .fetch_max(latency, Ordering::Relaxed);
+    }
+}
+
+async fn soak(matches: &ArgMatches, config: ApiConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let hours: f64 = *matches.get_one("hours").ok_or("Missing hours")?;
+    let clients: usize = *matches.get_one("clients").ok_or("Missing clients")?;
+    let poll_ms: u64 = *matches.get_one("poll_ms").ok_or("Missing poll_ms")?;
+    let speedup: u32 = *matches.get_one("speedup").ok_or("Missing speedup")?;
+    let max_rss_growth_mb: u64 = *matches
+        .get_one("max_rss_growth_mb")
+        .ok_or("Missing max_rss_growth_mb")?;
+    let duration = Duration::try_from_secs_f64(hours * 3600.0)?;
+    info!(
+        "Soaking for {:?} with {} clients polling every {}ms, updaters {}x faster",
+        duration, clients, poll_ms, speedup
+    );
+
+    let mut screen_service =
+        my_screen_service::MyScreenService::new(&config).with_update_speedup(speedup);
+    screen_service.start_backgound_updates()?;
+    screen_service.warm_up().await;
+    let server_config = config.server.as_ref().ok_or("No server config found")?;
+    let address = format!("127.0.0.1:{}", server_config.port).parse()?;
+    let admin_interceptor = admin_auth::AdminInterceptor::new(server_config.admin_token.clone());
+    tokio::spawn(async move {
+        if let Err(e) = Server::builder()
+            .add_service(ScreenServiceServer::with_interceptor(
+                screen_service,
+                admin_interceptor,
+            ))
+            .serve(address)
+            .await
+        {
+            error!("Soaked server stopped: {}", e);
+        }
+    });
+
+    let counters = Arc::new(Counters::default());
+    for _ in 0..clients {
+        tokio::spawn(poll(
+            config.clone(),
+            Duration::from_millis(poll_ms),
+            Arc::clone(&counters),
+        ));
+    }
+
+    // Let the server and the first updates settle before taking the baseline
+    tokio::time::sleep(REPORT_PERIOD.min(duration)).await;
+    let start_rss_kb = get_rss_kb();
+    let end = Instant::now() + duration;
+    let mut interval = tokio::time::interval_at(Instant::now() + REPORT_PERIOD, REPORT_PERIOD);
+    while Instant::now() < end {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = tokio::time::sleep_until(end) => (),
+        }
+        info!(
+            "RSS: {}kB (started at {}kB), hash requests: {}, hash changes: {}, errors: {}, slowest poll: {}us",
+            get_rss_kb().unwrap_or_default(),
+            start_rss_kb.unwrap_or_default(),
+            counters.hash_requests.load(Ordering::Relaxed),
+            counters.hash_changes.load(Ordering::Relaxed),
+            counters.errors.load(Ordering::Relaxed),
+            counters.max_latency_micros.swap(0, Ordering::Relaxed)
+        );
+    }
+
+    let (Some(start_rss_kb), Some(end_rss_kb)) = (start_rss_kb, get_rss_kb()) else {
+        warn!("Couldn't read the resident memory, not checking for leaks");
+        return Ok(());
+    };
+    let growth_kb = end_rss_kb.saturating_sub(start_rss_kb);
+    info!("Resident memory grew by {}kB over the run", growth_kb);
+    if growth_kb > max_rss_growth_mb * 1024 {
+        return Err(format!(
+            "Resident memory grew by {}kB, more than the allowed {}MB",
+            growth_kb, max_rss_growth_mb
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = cli().get_matches();
+    config_extractor::init_logging(&matches).expect("Error setting up logging");
+    let config = config_extractor::extract_config(&matches).expect("Error reading config");
+    let port: u32 = *matches.get_one("port").expect("Missing port");
+    soak(&matches, to_soak_config(config, port)).await
+}
@@ -0,0 +1,308 @@
+//! What the solar panels produce, what the house uses and how full its battery is, from the PV
+//! inverter. Each brand has its own API:
+//! - Fronius inverters answer on the LAN with their Solar API (GetPowerFlowRealtimeData)
+//! - SolarEdge only has its cloud monitoring API, which allows 300 requests a day per site
+//! - SMA inverters answer on the LAN with their web UI's API, which needs logging in, and doesn't
+//!   tell the consumption
+
+use crate::config_extractor::api_config;
+use crate::config_extractor::api_config::solar_config::Inverter;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::Solar;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+const SOLAREDGE_API: &str = "https://monitoringapi.solaredge.com";
+// The SMA values we read: the AC power fed by the inverter, and the battery's state of charge
+const SMA_POWER_KEY: &str = "6100_40263F00";
+const SMA_BATTERY_KEY: &str = "6100_00295A00";
+
+#[derive(Debug)]
+pub struct SolarUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    inverter: Inverter,
+    url: String,
+    api_key: String,
+    site_id: String,
+    solar_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for SolarUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(53),
+            UpdateMode::Real => Instant::now() + self.solar_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} solar", self.update_mode);
+        let solar;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // The sun rises and sets every hour, and the battery fills up with it
+                let sun = (now.minute() as f32 / 60.0 * std::f32::consts::PI).sin();
+                solar = Some(Solar {
+                    production: 4000.0 * sun,
+                    consumption: Some(600.0 + 10.0 * now.second() as f32),
+                    battery: Some(20.0 + 80.0 * now.minute() as f32 / 60.0),
+                });
+                error_bit.store(
+                    now.second().is_multiple_of(41),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                solar = match self.get_solar().await {
+                    Ok(fetched) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.solar_period.set_success();
+                        Some(fetched)
+                    }
+                    Err(e) => {
+                        error!("Error getting the solar production: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.solar_period.set_error();
+                        None
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Solar(solar)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl SolarUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let solar_config = config.solar.as_ref().ok_or("No solar config")?;
+        let inverter = solar_config.inverter();
+        match inverter {
+            Inverter::SolarEdge if solar_config.site_id.is_empty() => {
+                return Err("No SolarEdge site id".into())
+            }
+            Inverter::Fronius | Inverter::Sma if solar_config.url.is_empty() => {
+                return Err("No inverter URL".into())
+            }
+            _ => (),
+        }
+        let solar_period_config = Duration::from_secs(
+            solar_config
+                .update_period
+                .as_ref()
+                .ok_or("no solar update period")?
+                .seconds
+                .try_into()?,
+        );
+        let solar_period = ExponentialBackoff::new(
+            solar_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(SolarUpdater {
+            update_mode,
+            client,
+            inverter,
+            url: solar_config.url.trim_end_matches('/').to_string(),
+            api_key: solar_config.api_key.clone(),
+            site_id: solar_config.site_id.clone(),
+            solar_period,
+        })
+    }
+
+    async fn get_solar(&self) -> Result<Solar, Box<dyn std::error::Error>> {
+        match self.inverter {
+            Inverter::Fronius => {
+                let url = format!("{}/solar_api/v1/GetPowerFlowRealtimeData.fcgi", self.url);
+                let body = self.fetch(self.client.get(&url)).await?;
+                traced_sync("parse", || parse_fronius(&body))
+            }
+            Inverter::SolarEdge => {
+                let url = format!("{}/site/{}/currentPowerFlow", SOLAREDGE_API, self.site_id);
+                let request = self.client.get(&url).query(&[("api_key", &self.api_key)]);
+                let body = self.fetch(request).await?;
+                traced_sync("parse", || parse_solaredge(&body))
+            }
+            Inverter::Sma => self.get_sma_solar().await,
+        }
+    }
+
+    // The inverter only allows a few sessions at once, so we log out each time
+    async fn get_sma_solar(&self) -> Result<Solar, Box<dyn std::error::Error>> {
+        let login = serde_json::json!({ "right": "usr", "pass": self.api_key }).to_string();
+        let body = self.fetch(self.sma_post("login", None, login)).await?;
+        let login: Value = serde_json::from_str(&body)?;
+        let sid = login
+            .pointer("/result/sid")
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("SMA login failed: {}", body))?;
+        let keys = serde_json::json!({ "destDev": [], "keys": [SMA_POWER_KEY, SMA_BATTERY_KEY] });
+        // Logging out either way, without holding on to the (non Send) error meanwhile
+        let values = self
+            .fetch(self.sma_post("getValues", Some(sid), keys.to_string()))
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(e) = self
+            .fetch(self.sma_post("logout", Some(sid), "{}".into()))
+            .await
+        {
+            warn!("Couldn't log out of the SMA inverter: {}", e);
+        }
+        let values = values?;
+        traced_sync("parse", || parse_sma_values(&values))
+    }
+
+    fn sma_post(&self, call: &str, sid: Option<&str>, body: String) -> reqwest::RequestBuilder {
+        let mut request = self
+            .client
+            .post(format!("{}/dyn/{}.json", self.url, call))
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(sid) = sid {
+            request = request.query(&[("sid", sid)]);
+        }
+        request
+    }
+
+    async fn fetch(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let body = traced(
+            "fetch",
+            retry_http("Solar fetch", || async {
+                request
+                    .try_clone()
+                    .expect("Solar requests have no streamed bodies")
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        Ok(body)
+    }
+}
+
+// The site's power flow: P_PV is null at night, and the load is negative when consuming
+fn parse_fronius(body: &str) -> Result<Solar, Box<dyn std::error::Error>> {
+    let response: Value = serde_json::from_str(body)?;
+    let site = response
+        .pointer("/Body/Data/Site")
+        .ok_or("No site in the power flow")?;
+    let battery = response
+        .pointer("/Body/Data/Inverters")
+        .and_then(Value::as_object)
+        .and_then(|inverters| inverters.values().find_map(|i| i.get("SOC")?.as_f64()));
+    Ok(Solar {
+        production: site.get("P_PV").and_then(Value::as_f64).unwrap_or_default() as f32,
+        consumption: site
+            .get("P_Load")
+            .and_then(Value::as_f64)
+            .map(|load| load.abs() as f32),
+        battery: battery.map(|soc| soc as f32),
+    })
+}
+
+// In the unit the response says, kW usually
+fn parse_solaredge(body: &str) -> Result<Solar, Box<dyn std::error::Error>> {
+    let response: Value = serde_json::from_str(body)?;
+    let flow = response
+        .get("siteCurrentPowerFlow")
+        .ok_or("No power flow in the response")?;
+    let scale = match flow.get("unit").and_then(Value::as_str) {
+        Some("kW") => 1000.0,
+        Some("W") | None => 1.0,
+        Some(other) => return Err(format!("Unknown SolarEdge unit {}", other).into()),
+    };
+    let power = |pointer: &str| Some((flow.pointer(pointer)?.as_f64()? * scale) as f32);
+    Ok(Solar {
+        production: power("/PV/currentPower").unwrap_or_default(),
+        consumption: power("/LOAD/currentPower"),
+        battery: flow
+            .pointer("/STORAGE/chargeLevel")
+            .and_then(Value::as_f64)
+            .map(|level| level as f32),
+    })
+}
+
+// e.g. {"result": {"0199-xxxxx": {"6100_40263F00": {"1": [{"val": 1234}]}}}}, the value being
+// null when the inverter sleeps
+fn parse_sma_values(body: &str) -> Result<Solar, Box<dyn std::error::Error>> {
+    let response: Value = serde_json::from_str(body)?;
+    let device = response
+        .get("result")
+        .and_then(Value::as_object)
+        .and_then(|devices| devices.values().next())
+        .ok_or_else(|| format!("No SMA device in {}", body))?;
+    let value = |key: &str| -> Option<f32> {
+        let channels = device.get(key)?.as_object()?;
+        Some(channels.values().next()?.get(0)?.get("val")?.as_f64()? as f32)
+    };
+    Ok(Solar {
+        production: value(SMA_POWER_KEY).unwrap_or_default(),
+        consumption: None,
+        battery: value(SMA_BATTERY_KEY),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_power_flows() {
+        let fronius = r#"{"Body": {"Data": {
+            "Site": {"P_PV": 3210.5, "P_Load": -1100.2, "P_Grid": -2110.3, "P_Akku": null},
+            "Inverters": {"1": {"DT": 1, "P": 3210.5, "SOC": 80.5}}
+        }}}"#;
+        let solar = parse_fronius(fronius).unwrap();
+        assert_eq!(solar.production, 3210.5);
+        assert_eq!(solar.consumption, Some(1100.2));
+        assert_eq!(solar.battery, Some(80.5));
+        let night = r#"{"Body": {"Data": {"Site": {"P_PV": null, "P_Load": -300},
+            "Inverters": {"1": {"DT": 1, "P": 0}}}}}"#;
+        let solar = parse_fronius(night).unwrap();
+        assert_eq!((solar.production, solar.battery), (0.0, None));
+
+        let solaredge = r#"{"siteCurrentPowerFlow": {"updateRefreshRate": 3, "unit": "kW",
+            "GRID": {"status": "Active", "currentPower": 2.1},
+            "LOAD": {"status": "Active", "currentPower": 1.1},
+            "PV": {"status": "Active", "currentPower": 3.2},
+            "STORAGE": {"status": "Idle", "currentPower": 0.0, "chargeLevel": 46}}}"#;
+        let solar = parse_solaredge(solaredge).unwrap();
+        assert_eq!(solar.production, 3200.0);
+        assert_eq!(solar.consumption, Some(1100.0));
+        assert_eq!(solar.battery, Some(46.0));
+
+        let sma = r#"{"result": {"0199-70012345": {
+            "6100_40263F00": {"1": [{"val": 2450}]},
+            "6100_00295A00": {"7": [{"val": 63}]}}}}"#;
+        let solar = parse_sma_values(sma).unwrap();
+        assert_eq!((solar.production, solar.battery), (2450.0, Some(63.0)));
+        let asleep = r#"{"result": {"0199-70012345": {"6100_40263F00": {"1": [{"val": null}]}}}}"#;
+        assert_eq!(parse_sma_values(asleep).unwrap().production, 0.0);
+        assert!(parse_sma_values(r#"{"err": 401}"#).is_err());
+    }
+}
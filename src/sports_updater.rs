@@ -0,0 +1,288 @@
+//! The live score or next fixture of a few football teams, e.g. "LS 2-1 GC (63')".
+//!
+//! See https://www.football-data.org/documentation/api for the API, its free tier (with a token
+//! from signing up) covers the major leagues but has no match minute, which is then estimated
+//! from the kick-off.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::Match;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::{DateTime, Days, Local, Timelike};
+use prost_types::Timestamp;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+const MATCHES_API: &str = "https://api.football-data.org/v4/teams";
+// How far ahead to look for the next fixture
+const FIXTURE_DAYS: u64 = 14;
+// Between updates while no match is on, unless one kicks off before
+const IDLE_PERIOD: Duration = Duration::from_secs(3600);
+
+#[derive(Debug)]
+pub struct SportsUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    token: String,
+    team_ids: Vec<u32>,
+    sports_period: ExponentialBackoff,
+    // Of the last update, to update often only during match time
+    matches: Vec<Match>,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for SportsUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(30),
+            UpdateMode::Real => {
+                let period = self.sports_period.get_current_duration();
+                if self.matches.iter().any(|m| m.live) || self.sports_period.get_is_error() {
+                    return Instant::now() + period;
+                }
+                let now = Local::now().timestamp();
+                let until_kickoff = self
+                    .matches
+                    .iter()
+                    .filter_map(|m| m.kickoff)
+                    .map(|kickoff| kickoff.seconds - now)
+                    .filter(|&seconds| seconds > 0)
+                    .min()
+                    .map(|seconds| Duration::from_secs(seconds as u64));
+                Instant::now() + until_kickoff.map_or(IDLE_PERIOD, |until| until.min(IDLE_PERIOD))
+            }
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} sports", self.update_mode);
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // A match over the first half of every hour, the next one's fixture otherwise
+                let minute = now.minute() as i64;
+                let kickoff = match minute < 30 {
+                    true => now.timestamp() - 60 * minute,
+                    false => now.timestamp() + 60 * (60 - minute),
+                };
+                let status = match minute < 30 {
+                    true => "IN_PLAY",
+                    false => "TIMED",
+                };
+                let kickoff = DateTime::from_timestamp(kickoff, 0).unwrap_or_default();
+                let dummy = serde_json::json!({
+                    "utcDate": kickoff.to_rfc3339(),
+                    "status": status,
+                    "homeTeam": {"tla": "LS"},
+                    "awayTeam": {"tla": "GC"},
+                    "score": {"fullTime": {"home": minute / 10, "away": minute / 20}},
+                });
+                self.matches = get_match(&dummy, now.timestamp()).into_iter().collect();
+                error_bit.store(
+                    now.second().is_multiple_of(59),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                self.matches = match self.get_matches().await {
+                    Ok(fetched) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.sports_period.set_success();
+                        fetched
+                    }
+                    Err(e) => {
+                        error!("Error getting the matches: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.sports_period.set_error();
+                        vec![]
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Matches(self.matches.clone())]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl SportsUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let sports_config = config.sports.as_ref().ok_or("No sports config")?;
+        if sports_config.team_ids.is_empty() {
+            return Err("No teams configured".into());
+        }
+        let sports_period_config = Duration::from_secs(
+            sports_config
+                .update_period
+                .as_ref()
+                .ok_or("no sports update period")?
+                .seconds
+                .try_into()?,
+        );
+        let sports_period = ExponentialBackoff::new(
+            sports_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(SportsUpdater {
+            update_mode,
+            client,
+            token: sports_config.token.clone(),
+            team_ids: sports_config.team_ids.clone(),
+            sports_period,
+            matches: vec![],
+        })
+    }
+
+    // One per team with a match on or coming, in the config's order (twice when two configured
+    // teams play each other)
+    async fn get_matches(&self) -> Result<Vec<Match>, Box<dyn std::error::Error>> {
+        let today = Local::now().date_naive();
+        let date_from = today.format("%Y-%m-%d").to_string();
+        let date_to = (today + Days::new(FIXTURE_DAYS))
+            .format("%Y-%m-%d")
+            .to_string();
+        let mut matches = vec![];
+        for team_id in &self.team_ids {
+            let url = format!("{}/{}/matches", MATCHES_API, team_id);
+            let body = traced(
+                "fetch",
+                retry_http("Matches fetch", || async {
+                    self.client
+                        .get(&url)
+                        .query(&[("dateFrom", &date_from), ("dateTo", &date_to)])
+                        .header("X-Auth-Token", &self.token)
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .text()
+                        .await
+                }),
+            )
+            .await
+            .map_err(|e| format!("team {}: {}", team_id, e))?;
+            let now = Local::now().timestamp();
+            matches.extend(traced_sync("parse", || parse_matches(&body, now))?);
+        }
+        Ok(matches)
+    }
+}
+
+// The match being played, or else the next one
+fn parse_matches(body: &str, now: i64) -> Result<Option<Match>, Box<dyn std::error::Error>> {
+    let response: Value = serde_json::from_str(body)?;
+    let matches = response
+        .get("matches")
+        .and_then(Value::as_array)
+        .ok_or("No matches in the response")?;
+    let with_status = |statuses: &[&str]| {
+        matches.iter().find(|m| {
+            let status = m.get("status").and_then(Value::as_str);
+            status.is_some_and(|status| statuses.contains(&status))
+        })
+    };
+    let next = with_status(&["IN_PLAY", "PAUSED"]).or_else(|| with_status(&["SCHEDULED", "TIMED"]));
+    Ok(next.and_then(|m| get_match(m, now)))
+}
+
+// e.g. "LS 2-1 GC (63')" while live, "LS - GC" before
+fn get_match(m: &Value, now: i64) -> Option<Match> {
+    let text = |pointer: &str| m.pointer(pointer).and_then(Value::as_str);
+    let team = |side: &str| {
+        text(&format!("/{}/tla", side))
+            .or_else(|| text(&format!("/{}/shortName", side)))
+            .unwrap_or("?")
+    };
+    let kickoff = DateTime::parse_from_rfc3339(text("/utcDate")?).ok()?;
+    let (home, away) = (team("homeTeam"), team("awayTeam"));
+    let live = matches!(text("/status"), Some("IN_PLAY" | "PAUSED"));
+    let text = match live {
+        true => {
+            let goals = |side: &str| {
+                m.pointer(&format!("/score/fullTime/{}", side))
+                    .and_then(Value::as_u64)
+                    .unwrap_or(0)
+            };
+            let minute = match (text("/status"), m.get("minute").and_then(Value::as_u64)) {
+                (Some("PAUSED"), _) => "HT".to_string(),
+                (_, Some(minute)) => format!("{}'", minute),
+                (_, None) => estimate_minute((now - kickoff.timestamp()) / 60),
+            };
+            format!(
+                "{} {}-{} {} ({})",
+                home,
+                goals("home"),
+                goals("away"),
+                away,
+                minute
+            )
+        }
+        false => format!("{} - {}", home, away),
+    };
+    Some(Match {
+        text,
+        live,
+        kickoff: Some(Timestamp {
+            seconds: kickoff.timestamp(),
+            nanos: 0,
+        }),
+    })
+}
+
+// From the minutes since kick-off, assuming a 15 minutes break and stoppage time where the halves
+// would be over
+fn estimate_minute(elapsed: i64) -> String {
+    match elapsed {
+        ..=0 => "1'".to_string(),
+        1..=45 => format!("{}'", elapsed),
+        46..=60 => "45+".to_string(),
+        61..=105 => format!("{}'", elapsed - 15),
+        _ => "90+".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_live_and_next_matches() {
+        // 2024-10-22 18:45 UTC
+        let kickoff = 1729622700;
+        let body = r#"{"filters": {}, "resultSet": {"count": 2}, "matches": [
+            {"utcDate": "2024-10-22T18:45:00Z", "status": "IN_PLAY",
+             "homeTeam": {"id": 1871, "shortName": "Lausanne-Sport", "tla": "LS"},
+             "awayTeam": {"id": 1870, "shortName": "Grasshoppers", "tla": "GC"},
+             "score": {"winner": null, "fullTime": {"home": 2, "away": 1}}},
+            {"utcDate": "2024-10-29T18:45:00Z", "status": "TIMED",
+             "homeTeam": {"shortName": "Servette"}, "awayTeam": {"tla": "LS"},
+             "score": {"fullTime": {"home": null, "away": null}}}
+        ]}"#;
+        let live = parse_matches(body, kickoff + 78 * 60).unwrap().unwrap();
+        assert_eq!(live.text, "LS 2-1 GC (63')");
+        assert!(live.live);
+        assert_eq!(live.kickoff.unwrap().seconds, kickoff);
+
+        let finished = body.replace("IN_PLAY", "FINISHED");
+        let next = parse_matches(&finished, kickoff).unwrap().unwrap();
+        assert_eq!(next.text, "Servette - LS");
+        assert!(!next.live);
+
+        assert_eq!(estimate_minute(50), "45+");
+        assert_eq!(estimate_minute(120), "90+");
+        let none = r#"{"matches": []}"#;
+        assert!(parse_matches(none, kickoff).unwrap().is_none());
+    }
+}
@@ -0,0 +1,216 @@
+//! How the host running the server is doing: CPU temperature and throttling, load, memory and
+//! disk usage, to notice a Pi overheating or its SD card filling up before the service dies of it.
+//!
+//! All of it comes from Linux's /proc and /sys (the throttling from the Pi's firmware), and the
+//! disk usage from `df`. What a host doesn't have is left out rather than failing the update.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::screen_service::Diagnostics;
+use chrono::Timelike;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+const CPU_TEMPERATURE_FILE: &str = "/sys/class/thermal/thermal_zone0/temp";
+// Only on Raspberry Pis, see `vcgencmd get_throttled`
+const THROTTLED_FILE: &str = "/sys/devices/platform/soc/soc:firmware/get_throttled";
+const DEFAULT_DISK_PATH: &str = "/";
+// Under-voltage, capped frequency, throttled or at the soft temperature limit right now. The bits
+// above 16 say it happened since boot, which we don't care for.
+const THROTTLED_NOW_MASK: u32 = 0xf;
+
+#[derive(Debug)]
+pub struct SystemStatsUpdater {
+    update_mode: UpdateMode,
+    disk_path: String,
+    stats_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for SystemStatsUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(19),
+            UpdateMode::Real => Instant::now() + self.stats_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} system stats", self.update_mode);
+        let diagnostics;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                diagnostics = Some(Diagnostics {
+                    cpu_temperature: Some(50.0 + now.minute() as f32 / 2.0),
+                    load: now.second() as f32 / 15.0,
+                    memory_used: 40.0,
+                    disk_used: 50.0 + now.minute() as f32 * 0.8,
+                    throttled: now.minute() > 55,
+                });
+                error_bit.store(
+                    now.second().is_multiple_of(17),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                diagnostics = match self.get_diagnostics().await {
+                    Ok(read_diagnostics) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.stats_period.set_success();
+                        Some(read_diagnostics)
+                    }
+                    Err(e) => {
+                        error!("Error getting the system stats: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.stats_period.set_error();
+                        None
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Diagnostics(diagnostics)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl SystemStatsUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let stats_config = config
+            .system_stats
+            .as_ref()
+            .ok_or("No system stats config")?;
+        let stats_period_config = Duration::from_secs(
+            stats_config
+                .update_period
+                .as_ref()
+                .ok_or("no system stats update period")?
+                .seconds
+                .try_into()?,
+        );
+        let stats_period = ExponentialBackoff::new(
+            stats_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(SystemStatsUpdater {
+            update_mode,
+            disk_path: match stats_config.disk_path.is_empty() {
+                true => DEFAULT_DISK_PATH.to_string(),
+                false => stats_config.disk_path.clone(),
+            },
+            stats_period,
+        })
+    }
+
+    async fn get_diagnostics(&self) -> Result<Diagnostics, Box<dyn std::error::Error>> {
+        let read =
+            |path: &str| std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e));
+        let df = tokio::process::Command::new("df")
+            .args(["-Pk", &self.disk_path])
+            .output()
+            .await?;
+        if !df.status.success() {
+            return Err(
+                format!("df failed: {}", String::from_utf8_lossy(&df.stderr).trim()).into(),
+            );
+        }
+        Ok(Diagnostics {
+            // Some hosts have no thermal zone, and only Pis tell about throttling
+            cpu_temperature: read(CPU_TEMPERATURE_FILE)
+                .ok()
+                .and_then(|text| text.trim().parse::<f32>().ok())
+                .map(|milli_degrees| milli_degrees / 1000.0),
+            load: parse_load(&read("/proc/loadavg")?)?,
+            memory_used: parse_memory_used(&read("/proc/meminfo")?)?,
+            disk_used: parse_disk_used(&String::from_utf8_lossy(&df.stdout))?,
+            throttled: read(THROTTLED_FILE).is_ok_and(|text| is_throttled(&text)),
+        })
+    }
+}
+
+// From the firmware's flags, in hex (e.g. "50005" when under-voltage and throttled)
+fn is_throttled(flags: &str) -> bool {
+    let flags = flags.trim().trim_start_matches("0x");
+    u32::from_str_radix(flags, 16).is_ok_and(|flags| flags & THROTTLED_NOW_MASK != 0)
+}
+
+// The 1 minute load average, the first of /proc/loadavg (e.g. "0.42 0.35 0.30 1/123 4567")
+fn parse_load(loadavg: &str) -> Result<f32, Box<dyn std::error::Error>> {
+    let load = loadavg.split_whitespace().next().ok_or("Empty loadavg")?;
+    Ok(load.parse()?)
+}
+
+// How much of the memory isn't available, in percent, from /proc/meminfo
+fn parse_memory_used(meminfo: &str) -> Result<f32, Box<dyn std::error::Error>> {
+    let field = |name: &str| -> Result<f32, Box<dyn std::error::Error>> {
+        let line = meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .ok_or_else(|| format!("No {} in meminfo", name))?;
+        Ok(line.trim().trim_end_matches("kB").trim().parse()?)
+    };
+    let total = field("MemTotal")?;
+    if total == 0.0 {
+        return Err("No memory in meminfo".into());
+    }
+    Ok(100.0 * (1.0 - field("MemAvailable")? / total))
+}
+
+// The used space in percent from `df -Pk`'s second line, like its capacity column (which leaves
+// out the space reserved for root)
+fn parse_disk_used(df: &str) -> Result<f32, Box<dyn std::error::Error>> {
+    let fields: Vec<&str> = df
+        .lines()
+        .nth(1)
+        .ok_or("No filesystem in df's output")?
+        .split_whitespace()
+        .collect();
+    let [_, _, used, available, ..] = fields.as_slice() else {
+        return Err(format!("Unexpected df output {:?}", df).into());
+    };
+    let (used, available): (f32, f32) = (used.parse()?, available.parse()?);
+    if used + available == 0.0 {
+        return Err("Empty filesystem".into());
+    }
+    Ok(100.0 * used / (used + available))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_system_stats() {
+        assert_eq!(parse_load("0.42 0.35 0.30 1/123 4567\n").unwrap(), 0.42);
+        assert!(parse_load("").is_err());
+
+        let meminfo = "MemTotal:        1000000 kB
+MemFree:          100000 kB
+MemAvailable:     250000 kB
+Buffers:           50000 kB
+";
+        assert_eq!(parse_memory_used(meminfo).unwrap(), 75.0);
+        assert!(parse_memory_used("MemTotal: 1000 kB\n").is_err());
+
+        let df = "Filesystem     1024-blocks    Used Available Capacity Mounted on
+/dev/root         29000000 9000000  21000000      30% /
+";
+        assert_eq!(parse_disk_used(df).unwrap(), 30.0);
+        assert!(parse_disk_used("df: /nope: No such file or directory\n").is_err());
+
+        assert!(is_throttled("50005\n"));
+        // Throttled earlier, fine now
+        assert!(!is_throttled("50000\n"));
+        assert!(!is_throttled("0\n"));
+    }
+}
@@ -0,0 +1,245 @@
+//! The colors of the client's texts, by role (the clock, the departures, the bottom line...), from
+//! named themes in the config rather than compiled in. There's a built-in "default" theme, and a
+//! dark red "night" one that's easier on the eyes in a dark hallway.
+//!
+//! The theme can change with the server's brightness: below the configured night brightness, the
+//! texts are drawn with the night theme (when one is set).
+
+use crate::config_extractor::api_config::{Client, Theme};
+use log::{info, warn};
+
+pub const DEFAULT_THEME: &str = "default";
+pub const NIGHT_THEME: &str = "night";
+const DEFAULT_NIGHT_BRIGHTNESS: f32 = 0.2;
+
+// At full brightness, the server's dims them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub clock: [u8; 3],
+    pub debts: [u8; 3],
+    // Someone owes too much
+    pub debt_alert: [u8; 3],
+    // The amount changed since the last fetch
+    pub debt_change: [u8; 3],
+    pub departures: [u8; 3],
+    // Stands out from the departures it follows
+    pub delay: [u8; 3],
+    // The calendar, widgets and the rest of the bottom line
+    pub bottom_line: [u8; 3],
+    pub solar: [u8; 3],
+    pub battery: [u8; 3],
+    // Quotes going up (and finished appliances), and going down
+    pub rising: [u8; 3],
+    pub falling: [u8; 3],
+    // Warm, so it reads as a warning without looking like an error
+    pub disruption: [u8; 3],
+    pub error: [u8; 3],
+}
+
+pub const DEFAULT_PALETTE: Palette = Palette {
+    clock: [0xff, 0xff, 0xff],
+    debts: [0xcd, 0xcd, 0xf1],
+    debt_alert: [0xff, 0x60, 0x60],
+    debt_change: [0x80, 0xff, 0x80],
+    departures: [0xff, 0xe6, 0x89],
+    delay: [0xff, 0x80, 0x40],
+    bottom_line: [0xd4, 0xfd, 0xc7],
+    solar: [0xff, 0xd0, 0x20],
+    battery: [0x80, 0xff, 0x80],
+    rising: [0x40, 0xff, 0x40],
+    falling: [0xff, 0x40, 0x40],
+    disruption: [0xff, 0xb0, 0x30],
+    error: [0xff, 0x00, 0x00],
+};
+
+// Only reds, told apart by how bright they are
+pub const NIGHT_PALETTE: Palette = Palette {
+    clock: [0x90, 0x00, 0x00],
+    debts: [0x60, 0x00, 0x00],
+    debt_alert: [0xc0, 0x10, 0x00],
+    debt_change: [0x90, 0x20, 0x00],
+    departures: [0x80, 0x08, 0x00],
+    delay: [0xb0, 0x20, 0x00],
+    bottom_line: [0x70, 0x00, 0x00],
+    solar: [0x90, 0x18, 0x00],
+    battery: [0x60, 0x10, 0x00],
+    rising: [0x80, 0x20, 0x00],
+    falling: [0xc0, 0x00, 0x00],
+    disruption: [0xa0, 0x18, 0x00],
+    error: [0xff, 0x00, 0x00],
+};
+
+impl Palette {
+    // The colors the theme sets replace ours, invalid ones are left out
+    fn with(mut self, name: &str, theme: &Theme) -> Self {
+        let roles = [
+            (&mut self.clock, &theme.clock, "clock"),
+            (&mut self.debts, &theme.debts, "debts"),
+            (&mut self.debt_alert, &theme.debt_alert, "debt_alert"),
+            (&mut self.debt_change, &theme.debt_change, "debt_change"),
+            (&mut self.departures, &theme.departures, "departures"),
+            (&mut self.delay, &theme.delay, "delay"),
+            (&mut self.bottom_line, &theme.bottom_line, "bottom_line"),
+            (&mut self.solar, &theme.solar, "solar"),
+            (&mut self.battery, &theme.battery, "battery"),
+            (&mut self.rising, &theme.rising, "rising"),
+            (&mut self.falling, &theme.falling, "falling"),
+            (&mut self.disruption, &theme.disruption, "disruption"),
+            (&mut self.error, &theme.error, "error"),
+        ];
+        for (color, configured, role) in roles {
+            if configured.is_empty() {
+                continue;
+            }
+            match parse_color(configured) {
+                Some(parsed) => *color = parsed,
+                None => warn!(
+                    "Invalid {} color {:?} in the {} theme, keeping {:02x?}",
+                    role, configured, name, color
+                ),
+            }
+        }
+        self
+    }
+
+    pub fn change(&self, rising: bool) -> [u8; 3] {
+        match rising {
+            true => self.rising,
+            false => self.falling,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Themes {
+    day: Palette,
+    // With the brightness it applies at and below
+    night: Option<(Palette, f32)>,
+}
+
+impl Themes {
+    pub fn new(config: Option<&Client>) -> Self {
+        let palette = |name: &str| {
+            let built_in = match name {
+                NIGHT_THEME => Some(NIGHT_PALETTE),
+                DEFAULT_THEME => Some(DEFAULT_PALETTE),
+                _ => None,
+            };
+            let configured = config.and_then(|client| client.themes.get(name));
+            match (built_in, configured) {
+                (built_in, Some(theme)) => {
+                    Some(built_in.unwrap_or(DEFAULT_PALETTE).with(name, theme))
+                }
+                (built_in, None) => built_in,
+            }
+        };
+        let day_name = config
+            .map(|client| client.theme.as_str())
+            .filter(|name| !name.is_empty())
+            .unwrap_or(DEFAULT_THEME);
+        let day = palette(day_name).unwrap_or_else(|| {
+            warn!("No {} theme, drawing with the default one", day_name);
+            DEFAULT_PALETTE
+        });
+        let night_name = config
+            .map(|client| client.night_theme.as_str())
+            .filter(|name| !name.is_empty());
+        let night = night_name.and_then(|name| {
+            let night = palette(name);
+            if night.is_none() {
+                warn!(
+                    "No {} theme for the night, keeping the {} one",
+                    name, day_name
+                );
+            }
+            night
+        });
+        let night_brightness = config
+            .and_then(|client| client.night_brightness)
+            .unwrap_or(DEFAULT_NIGHT_BRIGHTNESS);
+        info!(
+            "Drawing with the {} theme, {:?} at brightness {} and below",
+            day_name, night_name, night_brightness
+        );
+        Themes {
+            day,
+            night: night.map(|night| (night, night_brightness)),
+        }
+    }
+
+    /// What to draw with at the server's `brightness`
+    pub fn palette(&self, brightness: f32) -> &Palette {
+        match &self.night {
+            Some((night, night_brightness)) if brightness <= *night_brightness => night,
+            _ => &self.day,
+        }
+    }
+}
+
+// Parses "rrggbb" (with or without a leading '#')
+pub fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok();
+    Some([channel(0)?, channel(1)?, channel(2)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("ffe689"), Some([0xff, 0xe6, 0x89]));
+        assert_eq!(parse_color("#00FF10"), Some([0x00, 0xff, 0x10]));
+        assert_eq!(parse_color(""), None);
+        assert_eq!(parse_color("fff"), None);
+        assert_eq!(parse_color("gg0000"), None);
+        assert_eq!(parse_color("é0000"), None);
+    }
+
+    #[test]
+    fn picks_the_theme_by_brightness() {
+        let themes = Themes::new(None);
+        assert_eq!(*themes.palette(0.05), DEFAULT_PALETTE);
+
+        let mut config = Client {
+            theme: "ocean".into(),
+            night_theme: NIGHT_THEME.into(),
+            ..Default::default()
+        };
+        config.themes.insert(
+            "ocean".into(),
+            Theme {
+                clock: "#40a0ff".into(),
+                departures: "not a color".into(),
+                ..Default::default()
+            },
+        );
+        config.themes.insert(
+            NIGHT_THEME.into(),
+            Theme {
+                clock: "400000".into(),
+                ..Default::default()
+            },
+        );
+        let themes = Themes::new(Some(&config));
+        // Only the colors set change
+        let day = themes.palette(0.8);
+        assert_eq!(day.clock, [0x40, 0xa0, 0xff]);
+        assert_eq!(day.departures, DEFAULT_PALETTE.departures);
+        assert_eq!(day.debts, DEFAULT_PALETTE.debts);
+        // On top of the built-in night theme
+        let night = themes.palette(0.2);
+        assert_eq!(night.clock, [0x40, 0x00, 0x00]);
+        assert_eq!(night.debts, NIGHT_PALETTE.debts);
+
+        // Unknown themes fall back to the default one, and to no night theme
+        config.theme = "sunset".into();
+        config.night_theme = "moonlight".into();
+        let themes = Themes::new(Some(&config));
+        assert_eq!(*themes.palette(0.0), DEFAULT_PALETTE);
+    }
+}
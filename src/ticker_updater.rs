@@ -0,0 +1,235 @@
+//! Prices of a few stocks or cryptocurrencies, along with how they changed since the previous
+//! close, formatted as each ticker's config says.
+//!
+//! Quotes come from Yahoo Finance's chart API, which needs no key and knows cryptocurrencies as
+//! e.g. "BTC-USD". It turns away requests without a browser-like user agent.
+
+use crate::config_extractor::api_config;
+use crate::config_extractor::api_config::tickers_config::Ticker;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::Quote;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::header::USER_AGENT;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+const QUOTES_API: &str = "https://query1.finance.yahoo.com/v8/finance/chart";
+const BROWSER_USER_AGENT: &str = "Mozilla/5.0 (X11; Linux aarch64; rv:128.0) Gecko/20100101";
+const DEFAULT_DECIMALS: u32 = 2;
+
+#[derive(Debug)]
+pub struct TickerUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    tickers: Vec<Ticker>,
+    ticker_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for TickerUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(59),
+            UpdateMode::Real => Instant::now() + self.ticker_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} tickers", self.update_mode);
+        let quotes;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                let minute = now.minute() as f64;
+                let dummy_ticker = |symbol: &str, prefix: &str, decimals| Ticker {
+                    symbol: symbol.into(),
+                    prefix: prefix.into(),
+                    decimals: Some(decimals),
+                    ..Default::default()
+                };
+                // One going up over the hour, the other down
+                quotes = vec![
+                    get_quote(&dummy_ticker("AAPL", "$", 2), 190.0 + minute / 10.0, 190.0),
+                    get_quote(
+                        &dummy_ticker("BTC-USD", "$", 0),
+                        64000.0 - 50.0 * minute,
+                        64000.0,
+                    ),
+                ];
+                error_bit.store(
+                    now.second().is_multiple_of(43),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                quotes = match self.get_quotes().await {
+                    Ok(fetched) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.ticker_period.set_success();
+                        fetched
+                    }
+                    Err(e) => {
+                        error!("Error getting the quotes: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.ticker_period.set_error();
+                        vec![]
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Quotes(quotes)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl TickerUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let tickers_config = config.tickers.as_ref().ok_or("No tickers config")?;
+        if tickers_config.tickers.is_empty() {
+            return Err("No tickers configured".into());
+        }
+        let ticker_period_config = Duration::from_secs(
+            tickers_config
+                .update_period
+                .as_ref()
+                .ok_or("no tickers update period")?
+                .seconds
+                .try_into()?,
+        );
+        let ticker_period = ExponentialBackoff::new(
+            ticker_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(TickerUpdater {
+            update_mode,
+            client,
+            tickers: tickers_config.tickers.clone(),
+            ticker_period,
+        })
+    }
+
+    // In the config's order
+    async fn get_quotes(&self) -> Result<Vec<Quote>, Box<dyn std::error::Error>> {
+        let mut quotes = vec![];
+        for ticker in &self.tickers {
+            let url = format!("{}/{}", QUOTES_API, ticker.symbol);
+            let body = traced(
+                "fetch",
+                retry_http("Quotes fetch", || async {
+                    self.client
+                        .get(&url)
+                        .query(&[("range", "1d"), ("interval", "1d")])
+                        .header(USER_AGENT, BROWSER_USER_AGENT)
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .text()
+                        .await
+                }),
+            )
+            .await
+            .map_err(|e| format!("{}: {}", ticker.symbol, e))?;
+            let (price, previous_close) = traced_sync("parse", || parse_chart(&body))?;
+            quotes.push(get_quote(ticker, price, previous_close));
+        }
+        Ok(quotes)
+    }
+}
+
+// The current price and the previous close out of the chart's meta
+fn parse_chart(body: &str) -> Result<(f64, f64), Box<dyn std::error::Error>> {
+    let chart: Value = serde_json::from_str(body)?;
+    if let Some(description) = chart
+        .pointer("/chart/error/description")
+        .and_then(Value::as_str)
+    {
+        return Err(format!("Quotes API error: {}", description).into());
+    }
+    let meta = chart
+        .pointer("/chart/result/0/meta")
+        .ok_or("No meta in the chart")?;
+    let number = |name: &str| meta.get(name).and_then(Value::as_f64);
+    let price = number("regularMarketPrice").ok_or("No price in the chart")?;
+    let previous_close = number("chartPreviousClose")
+        .or_else(|| number("previousClose"))
+        .ok_or("No previous close in the chart")?;
+    Ok((price, previous_close))
+}
+
+// Labeled and formatted as the ticker's config says, e.g. "$190.12"
+fn get_quote(ticker: &Ticker, price: f64, previous_close: f64) -> Quote {
+    let decimals = ticker.decimals.unwrap_or(DEFAULT_DECIMALS) as usize;
+    let change = if previous_close == 0.0 {
+        0.0
+    } else {
+        100.0 * (price - previous_close) / previous_close
+    };
+    Quote {
+        label: match ticker.label.is_empty() {
+            true => ticker.symbol.clone(),
+            false => ticker.label.clone(),
+        },
+        price,
+        change: change as f32,
+        price_text: format!("{}{:.*}{}", ticker.prefix, decimals, price, ticker.suffix),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_quotes() {
+        let body = r#"{"chart": {"result": [{"meta": {"currency": "CHF", "symbol": "NESN.SW",
+            "regularMarketPrice": 84.2, "chartPreviousClose": 80.2, "previousClose": 80.2}}],
+            "error": null}}"#;
+        let (price, previous_close) = parse_chart(body).unwrap();
+        let ticker = Ticker {
+            symbol: "NESN.SW".into(),
+            label: "Nestle".into(),
+            decimals: Some(1),
+            suffix: " CHF".into(),
+            ..Default::default()
+        };
+        let quote = get_quote(&ticker, price, previous_close);
+        assert_eq!(quote.label, "Nestle");
+        assert_eq!(quote.price_text, "84.2 CHF");
+        assert!((quote.change - 4.99).abs() < 0.01);
+
+        let ticker = Ticker {
+            symbol: "BTC-USD".into(),
+            prefix: "$".into(),
+            decimals: Some(0),
+            ..Default::default()
+        };
+        let quote = get_quote(&ticker, 64123.4, 65000.0);
+        assert_eq!(
+            (quote.label.as_str(), quote.price_text.as_str()),
+            ("BTC-USD", "$64123")
+        );
+        assert!(quote.change < 0.0);
+
+        let error = r#"{"chart": {"result": null, "error": {"code": "Not Found",
+            "description": "No data found, symbol may be delisted"}}}"#;
+        assert!(parse_chart(error)
+            .unwrap_err()
+            .to_string()
+            .contains("delisted"));
+    }
+}
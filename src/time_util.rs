@@ -0,0 +1,240 @@
+// The clients only use part of this (they're built with the server feature too in workspace builds)
+#![allow(dead_code)]
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone};
+use prost_types::Timestamp;
+
+fn to_timestamp<Tz: TimeZone>(time: &DateTime<Tz>) -> Timestamp {
+    Timestamp {
+        seconds: time.timestamp(),
+        // Always below 2e9, even for leap seconds
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+// Wall-clock times that fall into a DST gap don't exist, and those in the repeated hour are
+// ambiguous: take the earliest valid reading, which is what calendar apps do
+fn from_local<Tz: TimeZone>(
+    local: &NaiveDateTime,
+    tz: &Tz,
+) -> Result<DateTime<Tz>, Box<dyn std::error::Error>> {
+    tz.from_local_datetime(local)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(*local + chrono::Duration::hours(1)))
+                .earliest()
+        })
+        .ok_or_else(|| format!("{} doesn't exist in this timezone", local).into())
+}
+
+/// Parses an ISO-8601 timestamp such as OJP's. Those with an offset (`Z`, `+01:00`...) are
+/// absolute, those without one are read as wall-clock time in `local_tz`.
+pub fn parse_iso8601<Tz: TimeZone>(
+    text: &str,
+    local_tz: &Tz,
+) -> Result<Timestamp, Box<dyn std::error::Error>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(to_timestamp(&time));
+    }
+    let local = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f").map_err(|e| {
+        format!(
+            "ISO-8601 timestamp parsing error {:?} parsing '{}'",
+            e, text
+        )
+    })?;
+    Ok(to_timestamp(&from_local(&local, local_tz)?))
+}
+
+/// Whether an ICS date-time property (from its parameters on, like for `parse_ics_datetime`) is a
+/// whole day, e.g. `;VALUE=DATE:20240720` for all-day events
+pub fn is_ics_date(property: &str) -> bool {
+    let params = property.rsplit_once(':').map_or("", |(params, _)| params);
+    params.split(';').any(|param| param == "VALUE=DATE")
+}
+
+/// Parses an ICS date-time property from its parameters on, e.g. `:20240720T110000Z`,
+/// `;TZID=Europe/Zurich:20240720T130000` or `;VALUE=DATE:20240720` (the midnight starting that
+/// day). Floating times without a TZID are read as wall-clock time in `local_tz`.
+pub fn parse_ics_datetime<Tz: TimeZone>(
+    property: &str,
+    local_tz: &Tz,
+) -> Result<Timestamp, Box<dyn std::error::Error>> {
+    let (params, value) = property
+        .rsplit_once(':')
+        .ok_or_else(|| format!("No value in ICS property '{}'", property))?;
+    let tzid = params
+        .split(';')
+        .find_map(|param| param.strip_prefix("TZID="));
+
+    if let Some(utc) = value.strip_suffix('Z') {
+        let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+            .map_err(|e| format!("ICS timestamp parsing error {:?} parsing '{}'", e, value))?;
+        return Ok(to_timestamp(&time.and_utc()));
+    }
+    let local = match NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        Ok(time) => time,
+        Err(_) => NaiveDate::parse_from_str(value, "%Y%m%d")
+            .map_err(|e| format!("ICS timestamp parsing error {:?} parsing '{}'", e, value))?
+            .and_time(chrono::NaiveTime::MIN),
+    };
+    match tzid {
+        Some(tzid) => {
+            let tz: chrono_tz::Tz = tzid
+                .parse()
+                .map_err(|e| format!("Unknown ICS timezone '{}': {}", tzid, e))?;
+            Ok(to_timestamp(&from_local(&local, &tz)?))
+        }
+        None => Ok(to_timestamp(&from_local(&local, local_tz)?)),
+    }
+}
+
+/// Whole minutes from `now` until the given proto timestamp (negative if it's in the past).
+/// Both are absolute instants, so changes of the display timezone's offset (DST) don't matter.
+pub fn minutes_until<Tz: TimeZone>(
+    time: &Timestamp,
+    now: &DateTime<Tz>,
+) -> Result<i64, Box<dyn std::error::Error>> {
+    let time = DateTime::from_timestamp(time.seconds, time.nanos.try_into()?)
+        .ok_or("Timestamp out of range")?;
+    Ok(time.signed_duration_since(now).num_minutes())
+}
+
+/// Whole minutes between the timetabled and the actual time of a departure, negative if early
+pub fn delay_minutes(timetabled_seconds: i64, actual_seconds: i64) -> i32 {
+    ((actual_seconds - timetabled_seconds) / 60)
+        .try_into()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use chrono_tz::Europe::Zurich;
+
+    fn timestamp(rfc3339: &str) -> Timestamp {
+        let time = DateTime::parse_from_rfc3339(rfc3339).unwrap();
+        Timestamp {
+            seconds: time.timestamp(),
+            nanos: 0,
+        }
+    }
+
+    #[test]
+    fn counts_minutes_across_dst_end() {
+        // 02:50 CEST, then clocks go back from 03:00 CEST to 02:00 CET
+        let now = Utc
+            .with_ymd_and_hms(2024, 10, 27, 0, 50, 0)
+            .unwrap()
+            .with_timezone(&Zurich);
+        // Leaves at 02:05 CET, which reads as "earlier" on the wall clock
+        let departure = timestamp("2024-10-27T02:05:00+01:00");
+        assert_eq!(minutes_until(&departure, &now).unwrap(), 15);
+    }
+
+    #[test]
+    fn counts_minutes_across_dst_start() {
+        // 01:55 CET, then clocks jump from 02:00 CET to 03:00 CEST
+        let now = Zurich.with_ymd_and_hms(2024, 3, 31, 1, 55, 0).unwrap();
+        let departure = timestamp("2024-03-31T03:05:00+02:00");
+        assert_eq!(minutes_until(&departure, &now).unwrap(), 10);
+    }
+
+    #[test]
+    fn counts_minutes_across_new_year() {
+        let now = Zurich.with_ymd_and_hms(2024, 12, 31, 23, 58, 0).unwrap();
+        let departure = timestamp("2025-01-01T00:03:00+01:00");
+        assert_eq!(minutes_until(&departure, &now).unwrap(), 5);
+    }
+
+    #[test]
+    fn counts_past_departures_as_negative() {
+        let now = Zurich.with_ymd_and_hms(2024, 7, 23, 12, 0, 0).unwrap();
+        let departure = timestamp("2024-07-23T11:30:00+02:00");
+        assert_eq!(minutes_until(&departure, &now).unwrap(), -30);
+    }
+
+    #[test]
+    fn parses_iso8601_with_offsets() {
+        let expected = timestamp("2024-07-23T11:02:30Z");
+        assert_eq!(
+            parse_iso8601("2024-07-23T11:02:30Z", &Utc).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_iso8601("2024-07-23T13:02:30+02:00", &Utc).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_iso8601("2024-07-23T11:02:30.250Z", &Utc).unwrap(),
+            Timestamp {
+                nanos: 250_000_000,
+                ..expected
+            }
+        );
+        // Without an offset, it's Zurich wall-clock time (CEST in the summer)
+        assert_eq!(
+            parse_iso8601("2024-07-23T13:02:30", &Zurich).unwrap(),
+            expected
+        );
+        assert!(parse_iso8601("23.07.2024 13:02", &Zurich).is_err());
+    }
+
+    #[test]
+    fn parses_iso8601_around_dst() {
+        // The repeated hour in the autumn reads as its first occurrence (CEST)
+        assert_eq!(
+            parse_iso8601("2024-10-27T02:30:00", &Zurich).unwrap(),
+            timestamp("2024-10-27T00:30:00Z")
+        );
+        // The skipped hour in the spring gets pushed past the gap
+        assert_eq!(
+            parse_iso8601("2024-03-31T02:30:00", &Zurich).unwrap(),
+            timestamp("2024-03-31T01:30:00Z")
+        );
+    }
+
+    #[test]
+    fn parses_ics_datetimes() {
+        assert_eq!(
+            parse_ics_datetime(":20240720T110000Z", &Zurich).unwrap(),
+            timestamp("2024-07-20T11:00:00Z")
+        );
+        assert_eq!(
+            parse_ics_datetime(";TZID=Europe/Zurich:20240720T130000", &Utc).unwrap(),
+            timestamp("2024-07-20T11:00:00Z")
+        );
+        assert_eq!(
+            parse_ics_datetime(";TZID=Europe/Zurich:20241220T130000", &Utc).unwrap(),
+            timestamp("2024-12-20T12:00:00Z")
+        );
+        assert_eq!(
+            parse_ics_datetime(";VALUE=DATE:20240720", &Zurich).unwrap(),
+            timestamp("2024-07-19T22:00:00Z")
+        );
+        assert_eq!(
+            parse_ics_datetime(":20240720T130000", &Zurich).unwrap(),
+            timestamp("2024-07-20T11:00:00Z")
+        );
+        assert!(is_ics_date(";VALUE=DATE:20240720"));
+        assert!(!is_ics_date(";VALUE=DATE-TIME:20240720T130000"));
+        assert!(!is_ics_date(":20240720T110000Z"));
+    }
+
+    #[test]
+    fn rejects_invalid_ics_datetimes() {
+        assert!(parse_ics_datetime("20240720T110000Z", &Utc).is_err());
+        assert!(parse_ics_datetime(":2024-07-20", &Utc).is_err());
+        assert!(parse_ics_datetime(";TZID=Mars/Olympus:20240720T130000", &Utc).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        let now = Utc::now();
+        let invalid = Timestamp {
+            seconds: 0,
+            nanos: -1,
+        };
+        assert!(minutes_until(&invalid, &now).is_err());
+    }
+}
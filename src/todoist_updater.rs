@@ -0,0 +1,215 @@
+//! How many tasks are due today in Todoist, and which one to do first.
+//!
+//! See https://developer.todoist.com/rest/v2/#get-active-tasks, with an API token from the
+//! account's integrations settings. Tasks are picked with a Todoist filter, optionally within one
+//! project (e.g. a shared "Household" one).
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::Tasks;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::header::AUTHORIZATION;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+const TASKS_API: &str = "https://api.todoist.com/rest/v2/tasks";
+const DEFAULT_FILTER: &str = "today";
+const DEFAULT_LABEL: &str = "Todo";
+
+#[derive(Debug)]
+pub struct TodoistUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    token: String,
+    filter: String,
+    label: String,
+    todoist_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for TodoistUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(41),
+            UpdateMode::Real => Instant::now() + self.todoist_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} Todoist", self.update_mode);
+        let tasks;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // One task less every ten minutes
+                let due_today = 5 - now.minute() / 10;
+                tasks = Some(Tasks {
+                    label: self.label.clone(),
+                    due_today,
+                    top_task: match due_today {
+                        0 => String::new(),
+                        _ => "Water the plants".into(),
+                    },
+                });
+                error_bit.store(
+                    now.second().is_multiple_of(41),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                tasks = match self.get_tasks().await {
+                    Ok(fetched) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.todoist_period.set_success();
+                        Some(fetched)
+                    }
+                    Err(e) => {
+                        error!("Error getting the Todoist tasks: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.todoist_period.set_error();
+                        None
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Tasks(tasks)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl TodoistUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let todoist_config = config.todoist.as_ref().ok_or("No Todoist config")?;
+        if todoist_config.token.is_empty() {
+            return Err("No Todoist token".into());
+        }
+        let todoist_period_config = Duration::from_secs(
+            todoist_config
+                .update_period
+                .as_ref()
+                .ok_or("no Todoist update period")?
+                .seconds
+                .try_into()?,
+        );
+        let todoist_period = ExponentialBackoff::new(
+            todoist_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        let filter = match todoist_config.filter.is_empty() {
+            true => DEFAULT_FILTER,
+            false => todoist_config.filter.as_str(),
+        };
+        let label = match todoist_config.label.is_empty() {
+            true => DEFAULT_LABEL,
+            false => todoist_config.label.as_str(),
+        };
+        Ok(TodoistUpdater {
+            update_mode,
+            client,
+            token: todoist_config.token.clone(),
+            filter: get_filter(filter, &todoist_config.project),
+            label: label.to_string(),
+            todoist_period,
+        })
+    }
+
+    async fn get_tasks(&self) -> Result<Tasks, Box<dyn std::error::Error>> {
+        let body = traced(
+            "fetch",
+            retry_http("Todoist fetch", || async {
+                self.client
+                    .get(TASKS_API)
+                    .query(&[("filter", &self.filter)])
+                    .header(AUTHORIZATION, format!("Bearer {}", self.token))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        traced_sync("parse", || parse_tasks(&body, &self.label))
+    }
+}
+
+// The API ignores its project_id parameter along with a filter, so the project goes in the filter
+// itself, e.g. "##Household & (today)" (the double hash includes its subprojects)
+fn get_filter(filter: &str, project: &str) -> String {
+    match project.is_empty() {
+        true => filter.to_string(),
+        false => format!("##{} & ({})", project, filter),
+    }
+}
+
+// The top task is the most urgent one (Todoist's priority 4 is what its apps call p1), then the
+// one due the earliest, then the first in the project's order
+fn parse_tasks(body: &str, label: &str) -> Result<Tasks, Box<dyn std::error::Error>> {
+    let tasks: Vec<Value> = serde_json::from_str(body)?;
+    let top_task = tasks
+        .iter()
+        .min_by_key(|task| {
+            let priority = task.get("priority").and_then(Value::as_i64).unwrap_or(1);
+            // Dates and date-times sort right as they are, those without come last
+            let due = task
+                .pointer("/due/datetime")
+                .or_else(|| task.pointer("/due/date"))
+                .and_then(Value::as_str)
+                .unwrap_or("~");
+            let order = task.get("order").and_then(Value::as_i64).unwrap_or(0);
+            (-priority, due, order)
+        })
+        .and_then(|task| task.get("content"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    Ok(Tasks {
+        label: label.to_string(),
+        due_today: tasks.len().try_into()?,
+        top_task: top_task.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_top_task() {
+        let body = r#"[
+            {"id": "1", "content": "Take the bins out", "priority": 1, "order": 1,
+             "due": {"date": "2024-10-22", "is_recurring": true, "string": "every tuesday"}},
+            {"id": "2", "content": "Call the plumber", "priority": 3, "order": 2,
+             "due": {"date": "2024-10-22", "datetime": "2024-10-22T16:00:00Z"}},
+            {"id": "3", "content": "Water the plants", "priority": 3, "order": 3,
+             "due": {"date": "2024-10-22", "datetime": "2024-10-22T09:00:00Z"}}
+        ]"#;
+        let tasks = parse_tasks(body, "Household").unwrap();
+        assert_eq!(tasks.label, "Household");
+        assert_eq!(tasks.due_today, 3);
+        assert_eq!(tasks.top_task, "Water the plants");
+
+        let tasks = parse_tasks("[]", "Todo").unwrap();
+        assert_eq!((tasks.due_today, tasks.top_task.as_str()), (0, ""));
+
+        assert_eq!(
+            get_filter("today | overdue", "Household"),
+            "##Household & (today | overdue)"
+        );
+        assert_eq!(get_filter("today", ""), "today");
+    }
+}
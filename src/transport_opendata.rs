@@ -0,0 +1,248 @@
+//! Reads departures out of the transport.opendata.ch stationboard, a Swiss alternative to OJP that
+//! needs no API key and answers in plain JSON. Stops pick it with their backend, e.g. to keep the
+//! departures coming while OJP is down.
+//!
+//! See https://transport.opendata.ch/docs.html#stationboard
+
+use crate::config_extractor::api_config::transport_config::stop::{EventType, Mode};
+use crate::config_extractor::api_config::transport_config::Stop;
+use crate::destinations::{get_destination, get_destination_by_text};
+use crate::gtfs_realtime::station_of;
+use crate::screen_service::Departure;
+use crate::time_util;
+use chrono::{DateTime, TimeZone};
+use prost_types::Timestamp;
+use serde_json::Value;
+use tracing::debug;
+
+pub const STATIONBOARD_URL: &str = "https://transport.opendata.ch/v1/stationboard";
+
+/// The stationboard query for the departures (or arrivals) at the stop from `from` on
+pub fn create_query<Tz: TimeZone>(
+    stop: &Stop,
+    limit: u32,
+    from: &DateTime<Tz>,
+) -> Vec<(&'static str, String)>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    vec![
+        ("id", stop.stop_id.to_string()),
+        ("limit", limit.to_string()),
+        (
+            "type",
+            match stop.event_type() {
+                EventType::Departure => "departure",
+                EventType::Arrival => "arrival",
+            }
+            .to_string(),
+        ),
+        // Wall-clock time at the stop, without any offset
+        ("datetime", from.format("%Y-%m-%d %H:%M").to_string()),
+    ]
+}
+
+/// Reads the departures (or arrivals, depending on the stop's event type) out of a stationboard
+/// response, towards the stop's destinations
+pub fn extract_departures(
+    body: &str,
+    stop: &Stop,
+) -> Result<Vec<Departure>, Box<dyn std::error::Error>> {
+    let response: Value = serde_json::from_str(body)?;
+    let stationboard = response
+        .get("stationboard")
+        .and_then(Value::as_array)
+        .ok_or("No stationboard in the response")?;
+    let arrival = stop.event_type() == EventType::Arrival;
+    let mut departures = vec![];
+    for journey in stationboard {
+        let Some((time, timetabled)) = get_times(journey, arrival) else {
+            debug!("No time for journey {:?}", journey.get("name"));
+            continue;
+        };
+        let category = journey.get("category").and_then(Value::as_str);
+        if let Some(mode) = category.and_then(get_mode) {
+            if !stop.modes.is_empty() && !stop.modes().any(|wanted| wanted == mode) {
+                debug!("Skipping journey {:?} by {:?}", journey.get("name"), mode);
+                continue;
+            }
+        }
+        // The pass list starts with our stop, the destination is wherever it goes next (or where
+        // it says it goes, for the stops we don't know)
+        let Some(destination) = journey
+            .get("passList")
+            .and_then(Value::as_array)
+            .and_then(|calls| {
+                calls.iter().skip(1).find_map(|call| {
+                    let id = call.pointer("/station/id")?.as_str()?;
+                    get_destination(station_of(id)?, stop)
+                })
+            })
+            .or_else(|| {
+                let to = journey.get("to")?.as_str()?;
+                get_destination_by_text(to, stop)
+            })
+        else {
+            debug!(
+                "Journey {:?} goes to none of our destinations",
+                journey.get("name")
+            );
+            continue;
+        };
+        let text = |pointer: &str| journey.pointer(pointer).and_then(Value::as_str);
+        departures.push(Departure {
+            destination,
+            departure_time: Some(Timestamp {
+                seconds: time,
+                nanos: 0,
+            }),
+            timetabled_time: Some(Timestamp {
+                seconds: timetabled,
+                nanos: 0,
+            }),
+            delay_minutes: time_util::delay_minutes(timetabled, time),
+            line: text("/number").unwrap_or_default().to_string(),
+            // The forecast platform replaces the planned one, like OJP's estimated quay
+            platform: text("/stop/prognosis/platform")
+                .or(text("/stop/platform"))
+                .unwrap_or_default()
+                .to_string(),
+            arrival,
+            ..Default::default()
+        });
+    }
+    Ok(departures)
+}
+
+// The mode of a journey's category (e.g. "B" for bus, "IR" for InterRegio trains), if we know it
+fn get_mode(category: &str) -> Option<Mode> {
+    match category {
+        "B" | "BUS" | "EXB" | "KB" | "NFB" | "TX" => Some(Mode::Bus),
+        "T" | "TRAM" | "NFT" => Some(Mode::Tram),
+        "M" => Some(Mode::Metro),
+        "S" | "SN" | "R" | "RE" | "IR" | "IC" | "ICE" | "EC" | "EN" | "TGV" | "RJX" | "PE" => {
+            Some(Mode::Rail)
+        }
+        "BAT" | "BAV" | "FAE" => Some(Mode::Water),
+        "GB" | "PB" | "SL" | "FUN" => Some(Mode::Telecabin),
+        _ => None,
+    }
+}
+
+// When the journey gets to (or leaves) our stop, forecast if there's one, and when it should
+fn get_times(journey: &Value, arrival: bool) -> Option<(i64, i64)> {
+    let (prognosis, timestamp) = match arrival {
+        true => ("/stop/prognosis/arrival", "/stop/arrivalTimestamp"),
+        false => ("/stop/prognosis/departure", "/stop/departureTimestamp"),
+    };
+    let forecast = journey
+        .pointer(prognosis)
+        .and_then(Value::as_str)
+        .and_then(|time| DateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S%z").ok())
+        .map(|time| time.timestamp());
+    let timetabled = journey.pointer(timestamp)?.as_i64()?;
+    Some((forecast.unwrap_or(timetabled), timetabled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_extractor::api_config::transport_config::DestinationPoints;
+
+    #[test]
+    fn extracts_departures() {
+        let stop = Stop {
+            stop_id: 8588845,
+            destination_points: vec![DestinationPoints {
+                stops: vec![8592050],
+                destination_name: "FLON".into(),
+                ..Default::default()
+            }],
+            event_type: EventType::Departure.into(),
+            ..Default::default()
+        };
+        let body = r#"{"station": {"id": "8588845", "name": "Renens VD, Timonet"},
+            "stationboard": [
+                {"name": "B 7", "number": "7", "to": "Lausanne, Flon",
+                 "stop": {"station": {"id": "8588845"}, "departure": "2024-07-20T10:05:00+0200",
+                          "departureTimestamp": 1721462700, "platform": "A",
+                          "prognosis": {"platform": "B", "departure": "2024-07-20T10:07:00+0200"}},
+                 "passList": [{"station": {"id": "8588845"}}, {"station": {"id": "8592050"}}]},
+                {"name": "B 33", "number": "33", "to": "Renens VD, gare",
+                 "stop": {"station": {"id": "8588845"}, "departureTimestamp": 1721462800,
+                          "prognosis": {"platform": null, "departure": null}},
+                 "passList": [{"station": {"id": "8588845"}}, {"station": {"id": "8501118"}}]},
+                {"name": "B 7", "number": "7", "to": "Lausanne, Flon",
+                 "stop": {"station": {"id": "8588845"}, "departureTimestamp": 1721463300,
+                          "platform": "A", "prognosis": {"platform": null, "departure": null}},
+                 "passList": [{"station": {"id": "8588845"}}, {"station": {"id": "8592050"}}]}
+            ]}"#;
+        assert_eq!(
+            extract_departures(body, &stop).unwrap(),
+            vec![
+                Departure {
+                    destination: "FLON".into(),
+                    departure_time: Some(Timestamp {
+                        seconds: 1721462820,
+                        nanos: 0
+                    }),
+                    timetabled_time: Some(Timestamp {
+                        seconds: 1721462700,
+                        nanos: 0
+                    }),
+                    delay_minutes: 2,
+                    line: "7".into(),
+                    platform: "B".into(),
+                    ..Default::default()
+                },
+                Departure {
+                    destination: "FLON".into(),
+                    departure_time: Some(Timestamp {
+                        seconds: 1721463300,
+                        nanos: 0
+                    }),
+                    timetabled_time: Some(Timestamp {
+                        seconds: 1721463300,
+                        nanos: 0
+                    }),
+                    line: "7".into(),
+                    platform: "A".into(),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert!(extract_departures("{}", &stop).is_err());
+    }
+
+    #[test]
+    fn keeps_the_stop_modes() {
+        let stop = Stop {
+            stop_id: 8501118,
+            destination_points: vec![DestinationPoints {
+                stops: vec![8501120],
+                destination_name: "LAUSANNE".into(),
+                ..Default::default()
+            }],
+            modes: vec![Mode::Rail.into(), Mode::Metro.into()],
+            ..Default::default()
+        };
+        let body = r#"{"stationboard": [
+                {"name": "IR 15", "category": "IR", "number": "15",
+                 "stop": {"departureTimestamp": 1721462700},
+                 "passList": [{"station": {"id": "8501118"}}, {"station": {"id": "8501120"}}]},
+                {"name": "B 33", "category": "B", "number": "33",
+                 "stop": {"departureTimestamp": 1721462800},
+                 "passList": [{"station": {"id": "8501118"}}, {"station": {"id": "8501120"}}]},
+                {"name": "X 1", "category": "X", "number": "1",
+                 "stop": {"departureTimestamp": 1721462900},
+                 "passList": [{"station": {"id": "8501118"}}, {"station": {"id": "8501120"}}]}
+            ]}"#;
+        let lines: Vec<_> = extract_departures(body, &stop)
+            .unwrap()
+            .into_iter()
+            .map(|departure| departure.line)
+            .collect();
+        // Unknown categories aren't filtered out
+        assert_eq!(lines, ["15", "1"]);
+    }
+}
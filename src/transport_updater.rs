@@ -1,32 +1,61 @@
+use crate::config_extractor::api_config;
+use crate::config_extractor::api_config::transport_config::stop::{EventType, Mode};
+use crate::config_extractor::api_config::transport_config::Backend;
+use crate::config_extractor::api_config::transport_config::{Stop, Trip};
 use crate::config_extractor::api_config::TransportConfig;
-use crate::dummy_client::screen_service::departure::DestinationEnum;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::destinations::{self, get_destination, get_destination_by_text};
 use crate::exponential_backoff::ExponentialBackoff;
-use crate::screen_service::{Departure, ScreenContentReply};
-use crate::{config_extractor::api_config, data_updater::DataUpdater};
-use chrono::{Datelike, NaiveDateTime, Timelike};
-use log::{debug, error, info, warn};
+use crate::gtfs_realtime;
+use crate::gtfs_static::Timetable;
+use crate::ojp_trip;
+use crate::retry::retry_http;
+use crate::screen_service::Departure;
+use crate::time_util;
+use crate::transport_opendata;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::{Local, Timelike};
+use futures_util::TryStreamExt;
 use prost_types::Timestamp;
 use quick_xml::events::{BytesText, Event};
 use quick_xml::Reader;
-use reqwest::Client;
+use reqwest::header::{ACCEPT_ENCODING, CONTENT_TYPE};
+use reqwest::{Client, RequestBuilder};
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use tokio::io::AsyncBufRead;
 use tokio::time::{Duration, Instant};
+use tokio_util::io::StreamReader;
+use tracing::{debug, error, info, warn};
 
-#[derive(Debug)]
-// We switch from one to the other for manual testing, but it's actually fine to keep both.
-#[allow(dead_code)]
-pub enum TransportUpdateMode {
-    Dummy,
-    Real,
-}
+const DEFAULT_NUMBER_OF_RESULTS: u32 = 10;
+const DEFAULT_DEPARTURES_MAX_AGE: Duration = Duration::from_secs(300);
+const DEFAULT_MIN_UPDATE_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_UPDATE_INTERVAL: Duration = Duration::from_secs(1200);
 
 #[derive(Debug)]
 pub struct TransportUpdater {
-    update_mode: TransportUpdateMode,
+    update_mode: UpdateMode,
     client: Client,
     config: TransportConfig,
+    stops: Vec<Stop>,
+    number_of_results: u32,
+    per_destination: usize,
+    departure_offset: chrono::Duration,
+    // Where departures come from while the API is down, if configured
+    timetable: Option<Timetable>,
+    // Every departure of the last API call, and how many of them it showed, to show the next ones
+    // from there until `cache_expiry`
+    cached_departures: Vec<Departure>,
+    cached_count: usize,
+    cache_expiry: Instant,
+    // Along with the cached departures, as long as they're shown
+    disruptions: Vec<String>,
+    departures_max_age: Duration,
+    min_update_interval: Duration,
+    max_update_interval: Duration,
     transport_next_update: Instant,
     backoff_handler: ExponentialBackoff,
 }
@@ -35,8 +64,8 @@ pub struct TransportUpdater {
 impl DataUpdater for TransportUpdater {
     fn get_next_update_time(&self) -> Instant {
         match self.update_mode {
-            TransportUpdateMode::Dummy => Instant::now() + Duration::from_secs(21),
-            TransportUpdateMode::Real => {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(21),
+            UpdateMode::Real => {
                 if self.backoff_handler.get_is_error() {
                     warn!(
                         "Transport updater in error mode, performing exponential backoff. Current: {:?}",
@@ -53,18 +82,22 @@ impl DataUpdater for TransportUpdater {
         }
     }
 
-    async fn update(
-        &mut self,
-        screen_content: &Arc<Mutex<ScreenContentReply>>,
-        error_bit: &Arc<AtomicBool>,
-    ) {
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
         info!("Updating {:?} transport", self.update_mode);
         let destinations;
+        let disruptions;
         match self.update_mode {
-            TransportUpdateMode::Dummy => {
+            UpdateMode::Dummy => {
                 let now = chrono::offset::Local::now();
                 destinations = vec![Departure {
-                    destination_enum: DestinationEnum::Flon.into(),
+                    destination: "FLON".into(),
+                    line: "7".into(),
+                    platform: "B".into(),
+                    arrival: false,
+                    deviation: false,
+                    scheduled_only: false,
+                    timetabled_time: None,
+                    delay_minutes: 0,
                     departure_time: Some(prost_types::Timestamp::from(
                         std::time::SystemTime::from(
                             now + chrono::Duration::minutes(now.second().into()),
@@ -72,14 +105,26 @@ impl DataUpdater for TransportUpdater {
                     )),
                 }];
                 error_bit.store(now.second() % 9 == 0, std::sync::atomic::Ordering::Relaxed);
+                disruptions = match now.second() % 3 {
+                    0 => vec!["Line 7 diverted".to_string()],
+                    _ => vec![],
+                };
             }
-            TransportUpdateMode::Real => {
-                destinations = match self.get_departures().await {
+            UpdateMode::Real => {
+                let departures = match self.get_cached_departures() {
+                    Some(departures) => {
+                        debug!("Reusing {} cached departures", departures.len());
+                        Ok(departures)
+                    }
+                    None => self.fetch_departures().await,
+                };
+                destinations = match departures {
                     Ok(mut departures) => {
                         // Compute next update time based on result, or enter error mode
                         self.set_next_update_time(&mut departures);
                         // Make sure the server knows there are no errors
                         error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        disruptions = self.disruptions.clone();
                         departures
                     }
                     Err(e) => {
@@ -88,24 +133,30 @@ impl DataUpdater for TransportUpdater {
                         self.backoff_handler.set_error();
                         // Not needed, but for regression safety (we should see the error bit in backoff_handler and use its duration instead)
                         self.transport_next_update = Instant::now() + Duration::from_secs(600);
-                        vec![]
+                        // Whatever they were, we can't tell whether they're still going on
+                        self.disruptions.clear();
+                        disruptions = vec![];
+                        self.get_scheduled_departures()
                     }
                 }
             }
         };
-        match screen_content.lock() {
-            Ok(mut content) => {
-                content.bus_departures = destinations;
-            }
-            Err(e) => error!("Poisoned lock when writing debts: {}", e),
-        };
+        vec![
+            ContentUpdate::Departures(destinations),
+            ContentUpdate::Disruptions(disruptions),
+        ]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
     }
 }
 
 impl TransportUpdater {
     pub fn new(
-        update_mode: TransportUpdateMode,
+        update_mode: UpdateMode,
         config: &api_config::ApiConfig,
+        client: Client,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let transport_config = config.transport.as_ref().ok_or("No transport config")?;
         let backoff_handler = ExponentialBackoff::new(
@@ -113,32 +164,284 @@ impl TransportUpdater {
             Duration::from_secs(30),
             Duration::from_secs(1200), // 20 min
         );
+        let min_update_interval = to_duration(
+            transport_config.min_update_interval.as_ref(),
+            DEFAULT_MIN_UPDATE_INTERVAL,
+        );
+        let max_update_interval = to_duration(
+            transport_config.max_update_interval.as_ref(),
+            DEFAULT_MAX_UPDATE_INTERVAL,
+        );
+        if min_update_interval > max_update_interval {
+            return Err(format!(
+                "Min update interval {:?} is above the max {:?}",
+                min_update_interval, max_update_interval
+            )
+            .into());
+        }
+        if !transport_config.trips.is_empty() && transport_config.backend() != Backend::Ojp {
+            return Err("Trips are planned with OJP, the backend must be OJP".into());
+        }
+        let stops = transport_config.stops.clone();
+        destinations::check_regexes(&stops)?;
+        let timetable = match transport_config.gtfs_static_path.as_str() {
+            "" => None,
+            path => Timetable::load(Path::new(path), &stops)
+                .inspect_err(|e| error!("Couldn't load the timetable from {}: {}", path, e))
+                .ok(),
+        };
         // This will get set after each update to match the next departure
         Ok(TransportUpdater {
             update_mode,
-            client: Client::new(),
+            client,
             config: transport_config.to_owned(),
+            stops,
+            number_of_results: transport_config
+                .number_of_results
+                .unwrap_or(DEFAULT_NUMBER_OF_RESULTS),
+            per_destination: transport_config.departures_per_destination.unwrap_or(1) as usize,
+            departure_offset: chrono::Duration::seconds(
+                transport_config
+                    .departure_offset
+                    .as_ref()
+                    .map_or(0, |offset| offset.seconds),
+            ),
+            timetable,
+            cached_departures: vec![],
+            cached_count: 0,
+            cache_expiry: Instant::now(),
+            disruptions: vec![],
+            departures_max_age: to_duration(
+                transport_config.departures_max_age.as_ref(),
+                DEFAULT_DEPARTURES_MAX_AGE,
+            ),
+            min_update_interval,
+            max_update_interval,
             transport_next_update: Instant::now() + Duration::from_secs(600), // Technically not needed
             backoff_handler,
         })
     }
 
-    async fn get_departures(&self) -> Result<Vec<Departure>, Box<dyn std::error::Error>> {
-        let api_url = &self.config.url;
+    // Calls the API, keeping all the departures for the next updates
+    async fn fetch_departures(&mut self) -> Result<Vec<Departure>, Box<dyn std::error::Error>> {
+        (self.cached_departures, self.disruptions) = self.get_departures().await?;
+        self.cache_expiry = Instant::now() + self.departures_max_age;
+        let departures = self.get_upcoming_departures();
+        self.cached_count = departures.len();
+        Ok(departures)
+    }
+
+    // The next departures out of the last API call's, as long as it's recent enough and they
+    // still fill the screen like they did then: once those of a destination run out, only a new
+    // call tells what comes after them
+    fn get_cached_departures(&self) -> Option<Vec<Departure>> {
+        if Instant::now() >= self.cache_expiry {
+            return None;
+        }
+        let departures = self.get_upcoming_departures();
+        (!departures.is_empty() && departures.len() == self.cached_count).then_some(departures)
+    }
+
+    fn get_upcoming_departures(&self) -> Vec<Departure> {
+        upcoming_departures(
+            &self.cached_departures,
+            (chrono::Utc::now() + self.departure_offset).timestamp(),
+            self.per_destination,
+        )
+    }
+
+    // All the departures of all the stops, left for `upcoming_departures` to merge, and the
+    // disruptions they have (OJP only)
+    async fn get_departures(
+        &self,
+    ) -> Result<(Vec<Departure>, Vec<String>), Box<dyn std::error::Error>> {
+        let mut departures = vec![];
+        let mut disruptions = vec![];
+        // The feed covers the whole network, a single download serves all the stops using it
+        let mut feed = None;
+        for stop in &self.stops {
+            let backend = stop
+                .backend
+                .map_or(self.config.backend(), |_| stop.backend());
+            match backend {
+                Backend::Ojp => {
+                    let (stop_departures, stop_disruptions) = self.get_ojp_departures(stop).await?;
+                    departures.extend(stop_departures);
+                    // Disruptions on a line often affect several of our stops
+                    for disruption in stop_disruptions {
+                        if !disruptions.contains(&disruption) {
+                            disruptions.push(disruption);
+                        }
+                    }
+                }
+                Backend::GtfsRealtime => {
+                    if feed.is_none() {
+                        feed = Some(self.get_gtfs_feed().await?);
+                    }
+                    let departures_from = (chrono::Utc::now() + self.departure_offset).timestamp();
+                    departures.extend(gtfs_realtime::extract_departures(
+                        feed.as_ref().ok_or("No GTFS-RT feed")?,
+                        stop,
+                        departures_from,
+                    ));
+                }
+                Backend::TransportOpendata => {
+                    departures.extend(self.get_stationboard_departures(stop).await?)
+                }
+            }
+        }
+        for trip in &self.config.trips {
+            departures.extend(self.get_trip_connections(trip).await?);
+        }
+        Ok((departures, disruptions))
+    }
+
+    async fn get_trip_connections(
+        &self,
+        trip: &Trip,
+    ) -> Result<Vec<Departure>, Box<dyn std::error::Error>> {
+        let now = chrono::Utc::now();
+        let request_body = ojp_trip::create_trip_request(
+            trip,
+            self.number_of_results,
+            &(now + self.departure_offset),
+            &now,
+        );
+        let response = traced(
+            "fetch",
+            retry_http("OJP trip request", || async {
+                self.post_ojp(request_body.clone())
+                    .send()
+                    .await?
+                    .error_for_status()
+            }),
+        )
+        .await?;
+
+        let body = StreamReader::new(response.bytes_stream().map_err(std::io::Error::other));
+        traced("parse", ojp_trip::extract_connections(Box::pin(body), trip)).await
+    }
+
+    async fn get_stationboard_departures(
+        &self,
+        stop: &Stop,
+    ) -> Result<Vec<Departure>, Box<dyn std::error::Error>> {
+        let query = transport_opendata::create_query(
+            stop,
+            self.number_of_results,
+            &(Local::now() + self.departure_offset),
+        );
+        let response_body = traced(
+            "fetch",
+            retry_http("Stationboard request", || async {
+                self.client
+                    .get(transport_opendata::STATIONBOARD_URL)
+                    .query(&query)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+
+        debug!("Received stationboard response: {:?}", response_body);
+        traced_sync("parse", || {
+            transport_opendata::extract_departures(&response_body, stop)
+        })
+    }
+
+    // What the timetable has, for when the API errors out (nothing if there's no timetable)
+    fn get_scheduled_departures(&self) -> Vec<Departure> {
+        let Some(timetable) = &self.timetable else {
+            return vec![];
+        };
+        let from = Local::now() + self.departure_offset;
+        let departures = merge_departures(
+            self.stops
+                .iter()
+                .flat_map(|stop| timetable.departures(stop, &from))
+                .collect(),
+            self.per_destination,
+        );
+        info!(
+            "Falling back to {} scheduled departures from the timetable",
+            departures.len()
+        );
+        departures
+    }
+
+    async fn get_gtfs_feed(
+        &self,
+    ) -> Result<gtfs_realtime::transit_realtime::FeedMessage, Box<dyn std::error::Error>> {
         let api_key = &self.config.api_key;
-        let request_body = create_ojp_request(&self.config, &chrono::Utc::now());
+        let response_body = traced(
+            "fetch",
+            retry_http("GTFS-RT request", || async {
+                let mut request = self.client.get(&self.config.url);
+                if !api_key.is_empty() {
+                    request = request.bearer_auth(api_key);
+                }
+                request.send().await?.error_for_status()?.bytes().await
+            }),
+        )
+        .await?;
 
+        debug!("Received a {} bytes GTFS-RT feed", response_body.len());
+        traced_sync("parse", || gtfs_realtime::decode_feed(&response_body))
+    }
+
+    // Responses are gzipped unless the config says otherwise, and transparently decompressed
+    fn post_ojp(&self, body: String) -> RequestBuilder {
         let request = self
             .client
-            .post(api_url)
-            .header("Content-Type", "application/xml")
-            .bearer_auth(api_key)
-            .body(request_body);
+            .post(&self.config.url)
+            .header(CONTENT_TYPE, "application/xml")
+            .bearer_auth(&self.config.api_key)
+            .body(body);
+        match self.config.gzip.unwrap_or(true) {
+            true => request,
+            // The client asks for gzip by default, unless told otherwise
+            false => request.header(ACCEPT_ENCODING, "identity"),
+        }
+    }
+
+    async fn get_ojp_departures(
+        &self,
+        stop: &Stop,
+    ) -> Result<(Vec<Departure>, Vec<String>), Box<dyn std::error::Error>> {
+        let now = chrono::Utc::now();
+        let request_body = create_ojp_request(
+            stop,
+            self.number_of_results,
+            &(now + self.departure_offset),
+            &now,
+        );
 
-        let response_body = request.send().await?.text().await?;
+        // Only up to the response headers, the body gets read while parsing
+        let response = traced(
+            "fetch",
+            retry_http("OJP request", || async {
+                self.post_ojp(request_body.clone())
+                    .send()
+                    .await?
+                    .error_for_status()
+            }),
+        )
+        .await?;
 
-        debug!("Received transport response: {:?}", response_body);
-        extract_departures(&response_body, &self.config)
+        debug!(
+            "Receiving transport response ({:?} bytes)",
+            response.content_length()
+        );
+        let body = StreamReader::new(response.bytes_stream().map_err(std::io::Error::other));
+        // Those after the shown ones are kept for when the first ones leave
+        traced(
+            "parse",
+            extract_departures(Box::pin(body), stop, usize::MAX),
+        )
+        .await
     }
 
     fn set_next_update_time(&mut self, departures: &mut Vec<Departure>) {
@@ -188,22 +491,90 @@ impl TransportUpdater {
             .flatten()
             .map(|ts| ts.seconds)
             .ok_or("No next departure")?;
+        // It leaves the screen once it's closer than the departure offset (e.g. the walk to the
+        // stop), not when the bus actually leaves
+        let leaves_screen_sec = departure_utc_sec - self.departure_offset.num_seconds();
         let now_utc_sec = chrono::offset::Utc::now().timestamp();
 
-        if departure_utc_sec - now_utc_sec < 0 && departure_utc_sec - now_utc_sec > -60 {
-            debug!("Supposed next departure is ~now; retying in one minute");
-            return Ok(Instant::now() + Duration::from_secs(60));
-        }
-
-        // This is probably bogous as hell, with all the Unix TS and timezones shenanigans
+        // Both are POSIX times, so this holds whatever the local timezone and its DST
         Ok(Instant::now()
-            + Duration::from_secs(u64::try_from(departure_utc_sec - now_utc_sec)?)
-            + offset)
+            + get_update_delay(
+                leaves_screen_sec - now_utc_sec,
+                offset,
+                self.min_update_interval,
+                self.max_update_interval,
+            )?)
     }
 }
 
-fn create_ojp_request(config: &TransportConfig, now: &chrono::DateTime<chrono::Utc>) -> String {
+// How long to wait for the next update, for a first departure leaving the screen in `seconds`:
+// just after it does, within the bounds
+fn get_update_delay(
+    seconds: i64,
+    offset: Duration,
+    min: Duration,
+    max: Duration,
+) -> Result<Duration, Box<dyn std::error::Error>> {
+    if seconds < -60 {
+        return Err(format!("Next departure left {} seconds ago", -seconds).into());
+    }
+    if seconds < 0 {
+        debug!("Supposed next departure is ~now; retrying after the min interval");
+    }
+    let delay = Duration::from_secs(seconds.max(0).unsigned_abs()) + offset;
+    Ok(delay.clamp(min, max))
+}
+
+fn to_duration(duration: Option<&pbjson_types::Duration>, default: Duration) -> Duration {
+    duration.map_or(default, |duration| {
+        Duration::from_secs(duration.seconds.try_into().unwrap_or_default())
+    })
+}
+
+// Keeps the `per_destination` earliest departures towards each destination, wherever they leave
+// from (and the earliest arrivals, apart from departures), earliest first
+fn merge_departures(mut departures: Vec<Departure>, per_destination: usize) -> Vec<Departure> {
+    departures.sort_by_key(|d| d.departure_time.map_or(i64::MAX, |t| t.seconds));
+    let mut kept = HashMap::<(String, bool), usize>::default();
+    departures.retain(|departure| {
+        let count = kept
+            .entry((departure.destination.clone(), departure.arrival))
+            .or_default();
+        *count += 1;
+        *count <= per_destination
+    });
+    departures
+}
+
+// The departures still to come from `departures_from` (POSIX time) on, merged like
+// `merge_departures` does
+fn upcoming_departures(
+    departures: &[Departure],
+    departures_from: i64,
+    per_destination: usize,
+) -> Vec<Departure> {
+    let upcoming = departures
+        .iter()
+        .filter(|d| {
+            d.departure_time
+                .is_some_and(|t| t.seconds >= departures_from)
+        })
+        .cloned()
+        .collect();
+    merge_departures(upcoming, per_destination)
+}
+
+// OJP 1.0 has no way to ask only for what changed since a previous response, so we keep each
+// response down to the calls at our stop instead: the previous and onward calls of every service
+// made up most of the payload, and their times would be mistaken for departures from our stop.
+fn create_ojp_request(
+    stop: &Stop,
+    number_of_results: u32,
+    departures_from: &chrono::DateTime<chrono::Utc>,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> String {
     let now_utc_string = now.format("%Y-%m-%dT%H:%M:%S%.3fZ");
+    let from_utc_string = departures_from.format("%Y-%m-%dT%H:%M:%S%.3fZ");
     format!(
         r#"<?xml version="1.0" encoding="UTF-8"?>
 <OJP xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:xsd="http://www.w3.org/2001/XMLSchema" xmlns="http://www.siri.org.uk/siri" version="1.0" xmlns:ojp="http://www.vdv.de/ojp" xsi:schemaLocation="http://www.siri.org.uk/siri ../ojp-xsd-v1.0/OJP.xsd">
@@ -222,9 +593,12 @@ fn create_ojp_request(config: &TransportConfig, now: &chrono::DateTime<chrono::U
                     </ojp:PlaceRef>
                     <ojp:DepArrTime>{}</ojp:DepArrTime>
                 </ojp:Location>
-                <ojp:Params>
-                    <ojp:NumberOfResults>10</ojp:NumberOfResults>
-                    <ojp:StopEventType>departure</ojp:StopEventType>
+                <ojp:Params>{}
+                    <ojp:NumberOfResults>{}</ojp:NumberOfResults>
+                    <ojp:StopEventType>{}</ojp:StopEventType>
+                    <ojp:IncludePreviousCalls>false</ojp:IncludePreviousCalls>
+                    <ojp:IncludeOnwardCalls>false</ojp:IncludeOnwardCalls>
+                    <ojp:IncludeOperatingDays>false</ojp:IncludeOperatingDays>
                     <ojp:IncludeRealtimeData>true</ojp:IncludeRealtimeData>
                 </ojp:Params>
             </ojp:OJPStopEventRequest>
@@ -232,28 +606,112 @@ fn create_ojp_request(config: &TransportConfig, now: &chrono::DateTime<chrono::U
     </OJPRequest>
 </OJP>
 "#,
-        now_utc_string, now_utc_string, config.stop_id, now_utc_string
+        now_utc_string,
+        now_utc_string,
+        stop.stop_id,
+        from_utc_string,
+        create_mode_filter(stop),
+        number_of_results,
+        match stop.event_type() {
+            EventType::Departure => "departure",
+            EventType::Arrival => "arrival",
+        }
     )
 }
 
+// OJP's name for a mode, in its PtModeFilter as in the PtMode of services
+fn get_ojp_mode(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Bus => "bus",
+        Mode::Tram => "tram",
+        Mode::Metro => "metro",
+        Mode::Rail => "rail",
+        Mode::Water => "water",
+        Mode::Telecabin => "telecabin",
+    }
+}
+
+// Has OJP only return the stop's modes, if it has any
+fn create_mode_filter(stop: &Stop) -> String {
+    if stop.modes.is_empty() {
+        return String::new();
+    }
+    let modes: String = stop
+        .modes()
+        .map(|mode| {
+            format!(
+                "\n                        <ojp:PtMode>{}</ojp:PtMode>",
+                get_ojp_mode(mode)
+            )
+        })
+        .collect();
+    format!(
+        r#"
+                    <ojp:PtModeFilter>
+                        <ojp:Exclude>false</ojp:Exclude>{}
+                    </ojp:PtModeFilter>"#,
+        modes
+    )
+}
+
+// Whether the stop shows services of this OJP mode, in case the API didn't filter them all out
+fn wants_mode(stop: &Stop, ojp_mode: &str) -> bool {
+    stop.modes.is_empty() || stop.modes().any(|mode| get_ojp_mode(mode) == ojp_mode)
+}
+
 #[derive(Debug, Default)]
 struct DepartureBuilder {
-    departure_time: Option<Timestamp>,
+    timetabled_time: Option<Timestamp>,
+    // Only when there's real-time data, the departure time then
+    estimated_time: Option<Timestamp>,
     dest_id: Option<u32>,
+    dest_text: Option<String>,
+    line: Option<String>,
+    mode: Option<String>,
+    quay: Option<String>,
+    // The whole service, or just its stop at ours
+    cancelled: bool,
+    deviation: bool,
 }
 
-fn extract_departures(
-    body: &str,
-    config: &TransportConfig,
-) -> Result<Vec<Departure>, Box<dyn std::error::Error>> {
-    let mut reader = Reader::from_str(body);
+// Reads the departures (or arrivals, depending on the stop's event type) out of an OJP response,
+// keeping the next `per_destination` ones towards each destination, along with the summaries of
+// the disruptions (situations) affecting them. The body is parsed as it's read, so only those (and
+// not the whole response) stay in memory.
+async fn extract_departures<R: AsyncBufRead + Unpin>(
+    body: R,
+    stop: &Stop,
+    per_destination: usize,
+) -> Result<(Vec<Departure>, Vec<String>), Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_reader(body);
     reader.config_mut().trim_text(true);
+    // Events borrow from these, the outer one stays alive while the inner ones get read
+    let mut buf = vec![];
+    let mut inner_buf = vec![];
 
-    let mut departures = HashMap::<i32, Departure>::default();
+    let mut departures = vec![];
     let mut departure = DepartureBuilder::default();
+    // Calls may have both an arrival and a departure time, only read the one we asked for
+    let arrival = stop.event_type() == EventType::Arrival;
+    let wanted_service: &[u8] = match arrival {
+        true => b"ojp:ServiceArrival",
+        false => b"ojp:ServiceDeparture",
+    };
+    let mut in_wanted_service = false;
+    // Why the current event's time couldn't be read, if it couldn't: it's dropped at its end
+    let mut bad_time = None;
+    let mut disruptions = vec![];
+    // Situations have their summary in several languages, the first one will do
+    let mut in_situation = false;
+    let mut situation_summary = None;
     // The `Reader` does not implement `Iterator` because it outputs borrowed data (`Cow`s)
     loop {
-        match reader.read_event() {
+        buf.clear();
+        match reader.read_event_into_async(&mut buf).await {
+            // The connection broke off, what we got so far may miss the earliest departures
+            Err(quick_xml::Error::Io(e)) => {
+                return Err(format!("Couldn't read the OJP response: {}", e).into());
+            }
             Err(e) => {
                 error!("Error at position {}: {:?}", reader.error_position(), e);
                 break;
@@ -263,15 +721,35 @@ fn extract_departures(
             // Handle the start of interesting tags
             Ok(Event::Start(e)) => {
                 match e.name().as_ref() {
-                    b"ojp:StopEventResult" => debug!("Found stop event..."),
-                    b"ojp:TimetabledTime" => {
-                        let text = reader.read_event();
+                    b"ojp:StopEventResult" => {
+                        debug!("Found stop event...");
+                        // Don't let a previous event's fields leak into this one
+                        departure = DepartureBuilder::default();
+                        bad_time = None;
+                    }
+                    name if name == wanted_service => in_wanted_service = true,
+                    b"ojp:PtSituation" => {
+                        in_situation = true;
+                        situation_summary = None;
+                    }
+                    // SIRI-SX 2.0 has it in its publishing actions, older versions right there
+                    b"siri:Summary" | b"siri:SummaryText"
+                        if in_situation && situation_summary.is_none() =>
+                    {
+                        let text = next_event(&mut reader, &mut inner_buf).await;
+                        debug_print(&text, "Disruption");
+                        if let Ok(Event::Text(t)) = text {
+                            situation_summary = t.unescape().ok().map(|text| text.into_owned());
+                        }
+                    }
+                    b"ojp:TimetabledTime" if in_wanted_service => {
+                        let text = next_event(&mut reader, &mut inner_buf).await;
                         debug_print(&text, "Departure time (timetable)");
                         match text {
-                            Ok(Event::Text(t)) => {
-                                let time = get_time(&t)?;
-                                departure.departure_time = Some(time);
-                            }
+                            Ok(Event::Text(t)) => match get_time(&t) {
+                                Ok(time) => departure.timetabled_time = Some(time),
+                                Err(e) => bad_time = Some(e.to_string()),
+                            },
                             other => {
                                 error!(
                                     "Expected text type after 'TimetabledTime', got {:?}",
@@ -280,24 +758,21 @@ fn extract_departures(
                             }
                         };
                     }
-                    b"ojp:EstimatedTime" => {
-                        let text = reader.read_event();
+                    b"ojp:EstimatedTime" if in_wanted_service => {
+                        let text = next_event(&mut reader, &mut inner_buf).await;
                         debug_print(&text, "Departure time (estimated)");
                         match text {
-                            Ok(Event::Text(t)) => {
-                                let time = get_time(&t)?;
-                                departure.departure_time = Some(time);
-                            }
+                            Ok(Event::Text(t)) => match get_time(&t) {
+                                Ok(time) => departure.estimated_time = Some(time),
+                                Err(e) => bad_time = Some(e.to_string()),
+                            },
                             other => {
-                                error!(
-                                    "Expected text type after 'TimetabledTime', got {:?}",
-                                    other
-                                );
+                                error!("Expected text type after 'EstimatedTime', got {:?}", other);
                             }
                         };
                     }
                     b"ojp:DestinationStopPointRef" => {
-                        let text = reader.read_event();
+                        let text = next_event(&mut reader, &mut inner_buf).await;
                         debug_print(&text, "Destination ID");
                         match text {
                             Ok(Event::Text(t)) => {
@@ -307,12 +782,13 @@ fn extract_departures(
                                     break;
                                 };
                                 debug!("  Towards: {:?}", text);
-                                let Ok(dest_id) = text.parse::<u32>().inspect_err(|e| {
-                                    error!("Couldn't parse stop point ID into a number: {}", e)
-                                }) else {
-                                    break;
-                                };
-                                departure.dest_id = Some(dest_id);
+                                // Left to the destination text if it's not a number
+                                departure.dest_id = text
+                                    .parse::<u32>()
+                                    .inspect_err(|e| {
+                                        warn!("Couldn't parse stop point ID into a number: {}", e)
+                                    })
+                                    .ok();
                             }
                             other => {
                                 error!(
@@ -322,17 +798,36 @@ fn extract_departures(
                             }
                         };
                     }
+                    b"ojp:PtMode" => {
+                        let text = next_event(&mut reader, &mut inner_buf).await;
+                        debug_print(&text, "Mode");
+                        if let Ok(Event::Text(t)) = text {
+                            departure.mode = t.unescape().ok().map(|mode| mode.into_owned());
+                        }
+                    }
                     b"ojp:PublishedLineName" => {
-                        // Just for debug purposes, to see the destination name
-                        let _inner = reader.read_event();
-                        let text = reader.read_event();
-                        debug_print(&text, "Line is");
+                        departure.line =
+                            read_inner_text(&mut reader, &mut inner_buf, "Line is").await;
+                    }
+                    // The planned quay comes first, the estimated one (if any) replaces it
+                    b"ojp:PlannedQuay" | b"ojp:EstimatedQuay" => {
+                        if let Some(quay) =
+                            read_inner_text(&mut reader, &mut inner_buf, "Quay").await
+                        {
+                            departure.quay = Some(quay);
+                        }
+                    }
+                    b"ojp:Cancelled" | b"ojp:NotServicedStop" => {
+                        departure.cancelled |=
+                            read_flag(&mut reader, &mut inner_buf, "Cancelled").await;
+                    }
+                    b"ojp:Deviation" | b"ojp:Unplanned" => {
+                        departure.deviation |=
+                            read_flag(&mut reader, &mut inner_buf, "Deviation").await;
                     }
                     b"ojp:DestinationText" => {
-                        // Just for debug purposes, to see the destination name
-                        let _inner = reader.read_event();
-                        let text = reader.read_event();
-                        debug_print(&text, "Destination name");
+                        departure.dest_text =
+                            read_inner_text(&mut reader, &mut inner_buf, "Destination name").await;
                     }
                     // We don't care about the start of other tags
                     _ => (),
@@ -341,61 +836,67 @@ fn extract_departures(
             // Handle the end of interesting tags
             Ok(Event::End(e)) => {
                 match e.name().as_ref() {
+                    name if name == wanted_service => in_wanted_service = false,
+                    b"ojp:PtSituation" => {
+                        in_situation = false;
+                        match situation_summary.take() {
+                            Some(summary) if !disruptions.contains(&summary) => {
+                                disruptions.push(summary)
+                            }
+                            Some(_) => (),
+                            None => warn!("Found a disruption without any summary"),
+                        }
+                    }
                     b"ojp:StopEventResult" => {
                         debug!("Found event end, inspecting constructed departure");
+                        // The other departures of the stop are still good
+                        if let Some(e) = bad_time.take() {
+                            warn!("Skipping departure {:?}: {}", &departure, e);
+                            continue;
+                        }
                         match &departure {
+                            // Better show the next bus that does come
+                            DepartureBuilder {
+                                cancelled: true, ..
+                            } => info!("Skipping cancelled departure {:?}", &departure),
+                            DepartureBuilder {
+                                mode: Some(mode), ..
+                            } if !wants_mode(stop, mode) => {
+                                debug!("Skipping departure by {}: {:?}", mode, &departure)
+                            }
                             DepartureBuilder {
-                                departure_time: Some(depart_ts),
-                                dest_id: Some(dest_id),
+                                timetabled_time: Some(timetabled_ts),
+                                estimated_time,
+                                dest_id,
+                                dest_text,
+                                line,
+                                mode: _,
+                                quay,
+                                cancelled: false,
+                                deviation,
                             } => {
                                 debug!("Found a full event: {:?}", &departure);
-                                for dest in &config.destination_points {
-                                    debug!("Checking {:?} for matches", dest);
-                                    if dest.stops.iter().any(|stop| stop == dest_id) {
-                                        let Some(actual_enum) =
-                                            DestinationEnum::from_str_name(&dest.destination_name)
-                                        else {
-                                            error!("Configured destination name didn't match a destination enum: '{}'", &dest.destination_name);
-                                            break;
-                                        };
-                                        let new_departure = Departure {
-                                            departure_time: Some(*depart_ts),
-                                            destination_enum: actual_enum.into(),
-                                        };
-                                        debug!("Considering {:?} for insertion", new_departure);
-                                        match departures.get(&actual_enum.into()) {
-                                            None => {
-                                                // No candidate for this destination yet, let's record ours
-                                                departures
-                                                    .insert(actual_enum.into(), new_departure);
-                                            }
-                                            Some(existing_departure) => {
-                                                // Let's see if ours departs earlier
-                                                let existing_seconds = match existing_departure
-                                                    .departure_time
-                                                {
-                                                    Some(t) => t.seconds,
-                                                    None => {
-                                                        warn!("Existing departure doesn't have a timestamp");
-                                                        break;
-                                                    }
-                                                };
-                                                let new_seconds = match new_departure.departure_time
-                                                {
-                                                    Some(t) => t.seconds,
-                                                    None => {
-                                                        warn!("New departure doesn't have a timestamp");
-                                                        break;
-                                                    }
-                                                };
-                                                if existing_seconds > new_seconds {
-                                                    departures
-                                                        .insert(actual_enum.into(), new_departure);
-                                                }
-                                            }
-                                        }
-                                        break;
-                                    }
+                                let destination = dest_id
+                                    .and_then(|dest_id| get_destination(dest_id, stop))
+                                    .or_else(|| get_destination_by_text(dest_text.as_ref()?, stop));
+                                if let Some(destination) = destination {
+                                    let depart_ts = estimated_time.unwrap_or(*timetabled_ts);
+                                    let new_departure = Departure {
+                                        departure_time: Some(depart_ts),
+                                        timetabled_time: Some(*timetabled_ts),
+                                        delay_minutes: time_util::delay_minutes(
+                                            timetabled_ts.seconds,
+                                            depart_ts.seconds,
+                                        ),
+                                        destination,
+                                        line: line.clone().unwrap_or_default(),
+                                        platform: quay.clone().unwrap_or_default(),
+                                        arrival,
+                                        deviation: *deviation,
+                                        scheduled_only: false,
+                                    };
+                                    debug!("Found {:?}", new_departure);
+                                    departures.push(new_departure);
                                 }
                             }
                             misconstructed => {
@@ -415,7 +916,49 @@ fn extract_departures(
         }
     }
     // Return whatever we collected so far (may be empty, let the caller deal with that)
-    Ok(departures.iter().map(|entry| *entry.1).collect())
+    Ok((merge_departures(departures, per_destination), disruptions))
+}
+
+// The next event, into a cleared `buf`
+pub async fn next_event<'b, R: AsyncBufRead + Unpin>(
+    reader: &mut Reader<R>,
+    buf: &'b mut Vec<u8>,
+) -> Result<Event<'b>, quick_xml::Error> {
+    buf.clear();
+    reader.read_event_into_async(buf).await
+}
+
+// Reads the text of an element that wraps it in an ojp:Text tag, like names and quays do
+pub async fn read_inner_text<R: AsyncBufRead + Unpin>(
+    reader: &mut Reader<R>,
+    buf: &mut Vec<u8>,
+    prefix: &str,
+) -> Option<String> {
+    let _inner = next_event(reader, buf).await;
+    let text = next_event(reader, buf).await;
+    debug_print(&text, prefix);
+    match text {
+        Ok(Event::Text(t)) => t
+            .unescape()
+            .inspect_err(|e| error!("Couldn't unescape text for '{}': {}", prefix, e))
+            .ok()
+            .map(|text| text.into_owned()),
+        other => {
+            error!("Expected text type for '{}', got {:?}", prefix, other);
+            None
+        }
+    }
+}
+
+// Reads an element holding a boolean, false unless it's there and says so
+pub async fn read_flag<R: AsyncBufRead + Unpin>(
+    reader: &mut Reader<R>,
+    buf: &mut Vec<u8>,
+    prefix: &str,
+) -> bool {
+    let text = next_event(reader, buf).await;
+    debug_print(&text, prefix);
+    matches!(text, Ok(Event::Text(t)) if t.as_ref() == b"true")
 }
 
 fn debug_print(text: &Result<Event, quick_xml::Error>, prefix: &str) -> () {
@@ -434,22 +977,11 @@ fn debug_print(text: &Result<Event, quick_xml::Error>, prefix: &str) -> () {
     };
 }
 
-fn get_time(text: &BytesText) -> Result<Timestamp, Box<dyn std::error::Error>> {
-    let time = text.unescape().unwrap();
+pub fn get_time(text: &BytesText) -> Result<Timestamp, Box<dyn std::error::Error>> {
+    let time = text.unescape()?;
     debug!("  Parsing OJP timestamp: {:#?}", time);
-    // Consider exporting this in a helper module (see also gcal updater)
-    let rust_ts = NaiveDateTime::parse_from_str(&time, "%Y-%m-%dT%H:%M:%SZ")
-        .map_err(|e| format!("ICS timestamp parsing error {:?} parsing '{}'", e, time))?;
-    debug!("  Parsed timestamp: {:#?}", rust_ts);
-    let time = Timestamp::date_time(
-        rust_ts.year().try_into().unwrap(),
-        rust_ts.month().try_into().unwrap(),
-        rust_ts.day().try_into().unwrap(),
-        rust_ts.hour().try_into().unwrap(),
-        rust_ts.minute().try_into().unwrap(),
-        rust_ts.second().try_into().unwrap(),
-    )
-    .map_err(|e| format!("Proto timestamp creation error: {:?}", e))?;
+    let time = time_util::parse_iso8601(&time, &Local)?;
+    debug!("  Parsed timestamp: {:#?}", time);
     Ok(time)
 }
 
@@ -459,8 +991,8 @@ mod tests {
     use api_config::transport_config::DestinationPoints;
     use std::{i64, vec};
 
-    #[test]
-    fn extracts_departures() {
+    #[tokio::test]
+    async fn extracts_departures() {
         let body = r#"<?xml version="1.0" encoding="UTF-8"?>
 <siri:OJP xmlns:siri="http://www.siri.org.uk/siri" xmlns:ojp="http://www.vdv.de/ojp" version="1.0">
     <siri:OJPResponse>
@@ -475,6 +1007,12 @@ mod tests {
                                 <ojp:StopPointName>
                                     <ojp:Text xml:lang="de">The stop</ojp:Text>
                                 </ojp:StopPointName>
+                                <ojp:PlannedQuay>
+                                    <ojp:Text xml:lang="de">A</ojp:Text>
+                                </ojp:PlannedQuay>
+                                <ojp:EstimatedQuay>
+                                    <ojp:Text xml:lang="de">B</ojp:Text>
+                                </ojp:EstimatedQuay>
                                 <ojp:ServiceDeparture>
                                     <ojp:TimetabledTime>2024-07-23T11:02:00Z</ojp:TimetabledTime>
                                     <ojp:EstimatedTime>2024-07-23T11:02:30Z</ojp:EstimatedTime>
@@ -506,6 +1044,9 @@ mod tests {
                                 <ojp:StopPointName>
                                     <ojp:Text xml:lang="de">The stop</ojp:Text>
                                 </ojp:StopPointName>
+                                <ojp:PlannedQuay>
+                                    <ojp:Text xml:lang="de">C</ojp:Text>
+                                </ojp:PlannedQuay>
                                 <ojp:ServiceDeparture>
                                     <ojp:TimetabledTime>2024-07-23T11:04:00Z</ojp:TimetabledTime>
                                 </ojp:ServiceDeparture>
@@ -626,22 +1167,25 @@ mod tests {
     </siri:OJPResponse>
 </siri:OJP>
 "#;
-        let config = TransportConfig {
-            url: "".into(),
-            api_key: "".into(),
+        let stop = Stop {
             stop_id: 123,
             destination_points: vec![
                 DestinationPoints {
                     stops: vec![234, 345],
-                    destination_name: DestinationEnum::Renens.as_str_name().into(),
+                    destination_name: "RENENS".into(),
+                    ..Default::default()
                 },
                 DestinationPoints {
                     stops: vec![456],
-                    destination_name: DestinationEnum::Flon.as_str_name().into(),
+                    destination_name: "FLON".into(),
+                    ..Default::default()
                 },
             ],
+            ..Default::default()
         };
-        let mut departures = extract_departures(&body, &config).expect("should succeed");
+        let (mut departures, _) = extract_departures(body.as_bytes(), &stop, 1)
+            .await
+            .expect("should succeed");
         // Let's sort to avoid any nondeterministic flakiness
         departures.sort_by_key(|d| d.departure_time.map_or(i64::MAX, |t| t.seconds));
         assert_eq!(departures.len(), 2);
@@ -661,19 +1205,47 @@ mod tests {
                 .seconds,
             1721732640
         );
+        // Both are less than a minute late
+        assert_eq!(departures[0].timetabled_time.unwrap().seconds, 1721732520);
+        assert_eq!(departures[0].delay_minutes, 0);
+        assert_eq!(departures[1].timetabled_time, departures[1].departure_time);
+
+        // The ones after the next, when asked for, earliest first
+        let (departures, _) = extract_departures(body.as_bytes(), &stop, 2)
+            .await
+            .expect("should succeed");
+        let seconds: Vec<i64> = departures
+            .iter()
+            .map(|d| d.departure_time.unwrap().seconds)
+            .collect();
+        assert_eq!(
+            seconds,
+            vec![1721732550, 1721732640, 1721733270, 1721733330]
+        );
+        assert_eq!(departures[0].line, "4");
+        assert_eq!(departures[1].line, "8");
+        // The estimated quay wins over the planned one
+        assert_eq!(departures[0].platform, "B");
+        assert_eq!(departures[1].platform, "C");
     }
 
-    #[test]
-    fn doesnt_panic_on_empty_response() {
+    #[tokio::test]
+    async fn doesnt_panic_on_empty_response() {
         let body = "";
-        let config = TransportConfig::default();
-        let departures = extract_departures(&body, &config).expect("should succeed");
+        let (departures, _) = extract_departures(body.as_bytes(), &Stop::default(), 1)
+            .await
+            .expect("should succeed");
         assert_eq!(departures.len(), 0);
     }
 
-    #[test]
-    fn enum_string_check() {
-        assert_eq!(DestinationEnum::Flon.as_str_name(), "FLON");
+    #[tokio::test]
+    async fn fails_on_broken_off_response() {
+        let chunks: Vec<std::io::Result<prost::bytes::Bytes>> = vec![
+            Ok("<siri:OJP><siri:OJPResponse>".into()),
+            Err(std::io::ErrorKind::ConnectionReset.into()),
+        ];
+        let body = StreamReader::new(futures_util::stream::iter(chunks));
+        assert!(extract_departures(body, &Stop::default(), 1).await.is_err());
     }
 
     #[test]
@@ -697,11 +1269,14 @@ mod tests {
                             <ojp:Text>ignored</ojp:Text>
                         </ojp:LocationName>
                     </ojp:PlaceRef>
-                    <ojp:DepArrTime>2024-07-26T09:42:09.123Z</ojp:DepArrTime>
+                    <ojp:DepArrTime>2024-07-26T09:47:09.123Z</ojp:DepArrTime>
                 </ojp:Location>
                 <ojp:Params>
-                    <ojp:NumberOfResults>10</ojp:NumberOfResults>
+                    <ojp:NumberOfResults>25</ojp:NumberOfResults>
                     <ojp:StopEventType>departure</ojp:StopEventType>
+                    <ojp:IncludePreviousCalls>false</ojp:IncludePreviousCalls>
+                    <ojp:IncludeOnwardCalls>false</ojp:IncludeOnwardCalls>
+                    <ojp:IncludeOperatingDays>false</ojp:IncludeOperatingDays>
                     <ojp:IncludeRealtimeData>true</ojp:IncludeRealtimeData>
                 </ojp:Params>
             </ojp:OJPStopEventRequest>
@@ -709,12 +1284,492 @@ mod tests {
     </OJPRequest>
 </OJP>
 "#;
-        let config = TransportConfig {
-            url: "".into(),
-            api_key: "".into(),
+        let in_five_minutes = fake_now + chrono::Duration::minutes(5);
+        let stop = Stop {
+            stop_id: 123,
+            ..Default::default()
+        };
+        assert_eq!(
+            create_ojp_request(&stop, 25, &in_five_minutes, &fake_now),
+            expected_xml
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_cancelled_departures() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<siri:OJP xmlns:siri="http://www.siri.org.uk/siri" xmlns:ojp="http://www.vdv.de/ojp" version="1.0">
+    <siri:OJPResponse>
+        <siri:ServiceDelivery>
+            <ojp:OJPStopEventDelivery>
+                <ojp:StopEventResult>
+                    <ojp:StopEvent>
+                        <ojp:ThisCall>
+                            <ojp:CallAtStop>
+                                <siri:StopPointRef>123</siri:StopPointRef>
+                                <ojp:ServiceDeparture>
+                                    <ojp:TimetabledTime>2024-07-23T11:02:00Z</ojp:TimetabledTime>
+                                </ojp:ServiceDeparture>
+                            </ojp:CallAtStop>
+                        </ojp:ThisCall>
+                        <ojp:Service>
+                            <ojp:DestinationStopPointRef>456</ojp:DestinationStopPointRef>
+                            <ojp:Cancelled>true</ojp:Cancelled>
+                        </ojp:Service>
+                    </ojp:StopEvent>
+                </ojp:StopEventResult>
+                <ojp:StopEventResult>
+                    <ojp:StopEvent>
+                        <ojp:ThisCall>
+                            <ojp:CallAtStop>
+                                <siri:StopPointRef>123</siri:StopPointRef>
+                                <ojp:ServiceDeparture>
+                                    <ojp:TimetabledTime>2024-07-23T11:05:00Z</ojp:TimetabledTime>
+                                </ojp:ServiceDeparture>
+                            </ojp:CallAtStop>
+                        </ojp:ThisCall>
+                        <ojp:Service>
+                            <ojp:DestinationStopPointRef>456</ojp:DestinationStopPointRef>
+                            <ojp:Deviation>true</ojp:Deviation>
+                        </ojp:Service>
+                    </ojp:StopEvent>
+                </ojp:StopEventResult>
+            </ojp:OJPStopEventDelivery>
+        </siri:ServiceDelivery>
+    </siri:OJPResponse>
+</siri:OJP>
+"#;
+        let stop = Stop {
             stop_id: 123,
-            destination_points: vec![],
+            destination_points: vec![DestinationPoints {
+                stops: vec![456],
+                destination_name: "FLON".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let (departures, _) = extract_departures(body.as_bytes(), &stop, 1).await.unwrap();
+        assert_eq!(departures.len(), 1);
+        assert_eq!(departures[0].departure_time.unwrap().seconds, 1721732700);
+        assert!(departures[0].deviation);
+    }
+
+    #[tokio::test]
+    async fn skips_departures_with_unreadable_times() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<siri:OJP xmlns:siri="http://www.siri.org.uk/siri" xmlns:ojp="http://www.vdv.de/ojp" version="1.0">
+    <siri:OJPResponse>
+        <siri:ServiceDelivery>
+            <ojp:OJPStopEventDelivery>
+                <ojp:StopEventResult>
+                    <ojp:StopEvent>
+                        <ojp:ThisCall>
+                            <ojp:CallAtStop>
+                                <siri:StopPointRef>123</siri:StopPointRef>
+                                <ojp:ServiceDeparture>
+                                    <ojp:TimetabledTime>2024-07-23T11:02:00Z</ojp:TimetabledTime>
+                                </ojp:ServiceDeparture>
+                            </ojp:CallAtStop>
+                        </ojp:ThisCall>
+                        <ojp:Service>
+                            <ojp:DestinationStopPointRef>456</ojp:DestinationStopPointRef>
+                        </ojp:Service>
+                    </ojp:StopEvent>
+                </ojp:StopEventResult>
+                <ojp:StopEventResult>
+                    <ojp:StopEvent>
+                        <ojp:ThisCall>
+                            <ojp:CallAtStop>
+                                <siri:StopPointRef>123</siri:StopPointRef>
+                                <ojp:ServiceDeparture>
+                                    <ojp:TimetabledTime>2024-07-23T11:05:00Z</ojp:TimetabledTime>
+                                    <ojp:EstimatedTime>soon</ojp:EstimatedTime>
+                                </ojp:ServiceDeparture>
+                            </ojp:CallAtStop>
+                        </ojp:ThisCall>
+                        <ojp:Service>
+                            <ojp:DestinationStopPointRef>456</ojp:DestinationStopPointRef>
+                        </ojp:Service>
+                    </ojp:StopEvent>
+                </ojp:StopEventResult>
+                <ojp:StopEventResult>
+                    <ojp:StopEvent>
+                        <ojp:ThisCall>
+                            <ojp:CallAtStop>
+                                <siri:StopPointRef>123</siri:StopPointRef>
+                                <ojp:ServiceDeparture>
+                                    <ojp:TimetabledTime>2024-07-23T11:08:00Z</ojp:TimetabledTime>
+                                </ojp:ServiceDeparture>
+                            </ojp:CallAtStop>
+                        </ojp:ThisCall>
+                        <ojp:Service>
+                            <ojp:DestinationStopPointRef>456</ojp:DestinationStopPointRef>
+                        </ojp:Service>
+                    </ojp:StopEvent>
+                </ojp:StopEventResult>
+            </ojp:OJPStopEventDelivery>
+        </siri:ServiceDelivery>
+    </siri:OJPResponse>
+</siri:OJP>
+"#;
+        let stop = Stop {
+            stop_id: 123,
+            destination_points: vec![DestinationPoints {
+                stops: vec![456],
+                destination_name: "FLON".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let (departures, _) = extract_departures(body.as_bytes(), &stop, 3).await.unwrap();
+        let seconds: Vec<i64> = departures
+            .iter()
+            .map(|d| d.departure_time.unwrap().seconds)
+            .collect();
+        assert_eq!(seconds, vec![1721732520, 1721732880]);
+    }
+
+    #[tokio::test]
+    async fn reads_arrivals_or_departures() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<siri:OJP xmlns:siri="http://www.siri.org.uk/siri" xmlns:ojp="http://www.vdv.de/ojp" version="1.0">
+    <siri:OJPResponse>
+        <siri:ServiceDelivery>
+            <ojp:OJPStopEventDelivery>
+                <ojp:StopEventResult>
+                    <ojp:StopEvent>
+                        <ojp:ThisCall>
+                            <ojp:CallAtStop>
+                                <siri:StopPointRef>123</siri:StopPointRef>
+                                <ojp:ServiceArrival>
+                                    <ojp:TimetabledTime>2024-07-23T11:02:00Z</ojp:TimetabledTime>
+                                </ojp:ServiceArrival>
+                                <ojp:ServiceDeparture>
+                                    <ojp:TimetabledTime>2024-07-23T11:03:00Z</ojp:TimetabledTime>
+                                </ojp:ServiceDeparture>
+                            </ojp:CallAtStop>
+                        </ojp:ThisCall>
+                        <ojp:Service>
+                            <ojp:DestinationStopPointRef>456</ojp:DestinationStopPointRef>
+                        </ojp:Service>
+                    </ojp:StopEvent>
+                </ojp:StopEventResult>
+            </ojp:OJPStopEventDelivery>
+        </siri:ServiceDelivery>
+    </siri:OJPResponse>
+</siri:OJP>
+"#;
+        let mut stop = Stop {
+            stop_id: 123,
+            destination_points: vec![DestinationPoints {
+                stops: vec![456],
+                destination_name: "FLON".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let (departures, _) = extract_departures(body.as_bytes(), &stop, 1).await.unwrap();
+        assert_eq!(departures[0].departure_time.unwrap().seconds, 1721732580);
+        assert!(!departures[0].arrival);
+
+        stop.set_event_type(EventType::Arrival);
+        let (arrivals, _) = extract_departures(body.as_bytes(), &stop, 1).await.unwrap();
+        assert_eq!(arrivals[0].departure_time.unwrap().seconds, 1721732520);
+        assert!(arrivals[0].arrival);
+        let now = chrono::Utc::now();
+        assert!(create_ojp_request(&stop, 10, &now, &now)
+            .contains("<ojp:StopEventType>arrival</ojp:StopEventType>"));
+    }
+
+    #[tokio::test]
+    async fn reads_times_across_dst_end() {
+        // Clocks go back from 03:00 CEST to 02:00 CET: the second bus leaves at an earlier
+        // wall-clock time, but after the first one
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<siri:OJP xmlns:siri="http://www.siri.org.uk/siri" xmlns:ojp="http://www.vdv.de/ojp" version="1.0">
+    <siri:OJPResponse>
+        <siri:ServiceDelivery>
+            <ojp:OJPStopEventDelivery>
+                <ojp:StopEventResult>
+                    <ojp:StopEvent>
+                        <ojp:ThisCall>
+                            <ojp:CallAtStop>
+                                <siri:StopPointRef>123</siri:StopPointRef>
+                                <ojp:ServiceDeparture>
+                                    <ojp:TimetabledTime>2024-10-27T02:50:00+02:00</ojp:TimetabledTime>
+                                    <ojp:EstimatedTime>2024-10-27T02:55:00.500+02:00</ojp:EstimatedTime>
+                                </ojp:ServiceDeparture>
+                            </ojp:CallAtStop>
+                        </ojp:ThisCall>
+                        <ojp:Service>
+                            <ojp:DestinationStopPointRef>456</ojp:DestinationStopPointRef>
+                        </ojp:Service>
+                    </ojp:StopEvent>
+                </ojp:StopEventResult>
+                <ojp:StopEventResult>
+                    <ojp:StopEvent>
+                        <ojp:ThisCall>
+                            <ojp:CallAtStop>
+                                <siri:StopPointRef>123</siri:StopPointRef>
+                                <ojp:ServiceDeparture>
+                                    <ojp:TimetabledTime>2024-10-27T02:10:00+01:00</ojp:TimetabledTime>
+                                </ojp:ServiceDeparture>
+                            </ojp:CallAtStop>
+                        </ojp:ThisCall>
+                        <ojp:Service>
+                            <ojp:DestinationStopPointRef>456</ojp:DestinationStopPointRef>
+                        </ojp:Service>
+                    </ojp:StopEvent>
+                </ojp:StopEventResult>
+            </ojp:OJPStopEventDelivery>
+        </siri:ServiceDelivery>
+    </siri:OJPResponse>
+</siri:OJP>
+"#;
+        let stop = Stop {
+            stop_id: 123,
+            destination_points: vec![DestinationPoints {
+                stops: vec![456],
+                destination_name: "FLON".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let (departures, _) = extract_departures(body.as_bytes(), &stop, 2).await.unwrap();
+        assert_eq!(departures.len(), 2);
+        assert_eq!(
+            departures[0].departure_time,
+            Some(Timestamp {
+                seconds: 1729990500,
+                nanos: 500_000_000
+            })
+        );
+        assert_eq!(departures[0].timetabled_time.unwrap().seconds, 1729990200);
+        assert_eq!(departures[0].delay_minutes, 5);
+        assert_eq!(departures[1].departure_time.unwrap().seconds, 1729991400);
+        assert_eq!(departures[1].delay_minutes, 0);
+    }
+
+    #[test]
+    fn merges_departures_of_all_stops() {
+        let departure = |destination: &str, seconds| Departure {
+            destination: destination.into(),
+            departure_time: Some(Timestamp { seconds, nanos: 0 }),
+            ..Default::default()
+        };
+        let departures = vec![
+            // From the bus stop
+            departure("FLON", 600),
+            departure("RENENS", 300),
+            // From the metro station
+            departure("FLON", 120),
+            departure("FLON", 420),
+        ];
+        assert_eq!(
+            merge_departures(departures.clone(), 1),
+            vec![departure("FLON", 120), departure("RENENS", 300),]
+        );
+        assert_eq!(
+            merge_departures(departures, 2),
+            vec![
+                departure("FLON", 120),
+                departure("RENENS", 300),
+                departure("FLON", 420),
+            ]
+        );
+    }
+
+    #[test]
+    fn shows_the_next_cached_departures() {
+        let departure = |destination: &str, seconds| Departure {
+            destination: destination.into(),
+            departure_time: Some(Timestamp { seconds, nanos: 0 }),
+            ..Default::default()
+        };
+        let cached = vec![
+            departure("FLON", 120),
+            departure("FLON", 420),
+            departure("RENENS", 300),
+        ];
+        assert_eq!(
+            upcoming_departures(&cached, 100, 1),
+            vec![departure("FLON", 120), departure("RENENS", 300)]
+        );
+        // The first bus to Flon left, the one after it moves up
+        assert_eq!(
+            upcoming_departures(&cached, 121, 1),
+            vec![departure("RENENS", 300), departure("FLON", 420)]
+        );
+        assert_eq!(upcoming_departures(&cached, 421, 1), vec![]);
+    }
+
+    #[test]
+    fn updates_within_bounds() {
+        let second = Duration::from_secs(1);
+        let min = Duration::from_secs(30);
+        let max = Duration::from_secs(1200);
+        assert_eq!(
+            get_update_delay(300, second, min, max).unwrap(),
+            Duration::from_secs(301)
+        );
+        // Departures in quick succession, or ~now
+        assert_eq!(get_update_delay(10, second, min, max).unwrap(), min);
+        assert_eq!(get_update_delay(-30, second, min, max).unwrap(), min);
+        // Nothing for a while, but delays may still come up
+        assert_eq!(get_update_delay(7200, second, min, max).unwrap(), max);
+        assert!(get_update_delay(-600, second, min, max).is_err());
+    }
+
+    #[tokio::test]
+    async fn filters_modes() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<siri:OJP xmlns:siri="http://www.siri.org.uk/siri" xmlns:ojp="http://www.vdv.de/ojp" version="1.0">
+    <siri:OJPResponse>
+        <siri:ServiceDelivery>
+            <ojp:OJPStopEventDelivery>
+                <ojp:StopEventResult>
+                    <ojp:StopEvent>
+                        <ojp:ThisCall>
+                            <ojp:CallAtStop>
+                                <siri:StopPointRef>123</siri:StopPointRef>
+                                <ojp:ServiceDeparture>
+                                    <ojp:TimetabledTime>2024-07-23T11:02:00Z</ojp:TimetabledTime>
+                                </ojp:ServiceDeparture>
+                            </ojp:CallAtStop>
+                        </ojp:ThisCall>
+                        <ojp:Service>
+                            <ojp:Mode>
+                                <ojp:PtMode>rail</ojp:PtMode>
+                            </ojp:Mode>
+                            <ojp:DestinationStopPointRef>456</ojp:DestinationStopPointRef>
+                        </ojp:Service>
+                    </ojp:StopEvent>
+                </ojp:StopEventResult>
+                <ojp:StopEventResult>
+                    <ojp:StopEvent>
+                        <ojp:ThisCall>
+                            <ojp:CallAtStop>
+                                <siri:StopPointRef>123</siri:StopPointRef>
+                                <ojp:ServiceDeparture>
+                                    <ojp:TimetabledTime>2024-07-23T11:05:00Z</ojp:TimetabledTime>
+                                </ojp:ServiceDeparture>
+                            </ojp:CallAtStop>
+                        </ojp:ThisCall>
+                        <ojp:Service>
+                            <ojp:Mode>
+                                <ojp:PtMode>bus</ojp:PtMode>
+                            </ojp:Mode>
+                            <ojp:DestinationStopPointRef>456</ojp:DestinationStopPointRef>
+                        </ojp:Service>
+                    </ojp:StopEvent>
+                </ojp:StopEventResult>
+            </ojp:OJPStopEventDelivery>
+        </siri:ServiceDelivery>
+    </siri:OJPResponse>
+</siri:OJP>
+"#;
+        let mut stop = Stop {
+            stop_id: 123,
+            destination_points: vec![DestinationPoints {
+                stops: vec![456],
+                destination_name: "FLON".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let now = chrono::Utc::now();
+        assert!(!create_ojp_request(&stop, 10, &now, &now).contains("PtModeFilter"));
+        let (departures, _) = extract_departures(body.as_bytes(), &stop, 2).await.unwrap();
+        assert_eq!(departures.len(), 2);
+
+        stop.modes = vec![Mode::Bus.into(), Mode::Tram.into()];
+        assert!(create_ojp_request(&stop, 10, &now, &now).contains(
+            r#"<ojp:PtModeFilter>
+                        <ojp:Exclude>false</ojp:Exclude>
+                        <ojp:PtMode>bus</ojp:PtMode>
+                        <ojp:PtMode>tram</ojp:PtMode>
+                    </ojp:PtModeFilter>
+                    <ojp:NumberOfResults>10</ojp:NumberOfResults>"#
+        ));
+        let (departures, _) = extract_departures(body.as_bytes(), &stop, 2).await.unwrap();
+        assert_eq!(departures.len(), 1);
+        assert_eq!(departures[0].departure_time.unwrap().seconds, 1721732700);
+    }
+
+    #[tokio::test]
+    async fn reads_disruptions() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<siri:OJP xmlns:siri="http://www.siri.org.uk/siri" xmlns:ojp="http://www.vdv.de/ojp" version="1.0">
+    <siri:OJPResponse>
+        <siri:ServiceDelivery>
+            <ojp:OJPStopEventDelivery>
+                <ojp:StopEventResponseContext>
+                    <ojp:Situations>
+                        <ojp:PtSituation>
+                            <siri:SituationNumber>1</siri:SituationNumber>
+                            <siri:PublishingActions>
+                                <siri:PublishingAction>
+                                    <siri:PassengerInformationAction>
+                                        <siri:TextualContent>
+                                            <siri:SummaryContent>
+                                                <siri:SummaryText xml:lang="fr">Ligne 32 déviée</siri:SummaryText>
+                                            </siri:SummaryContent>
+                                        </siri:TextualContent>
+                                    </siri:PassengerInformationAction>
+                                </siri:PublishingAction>
+                                <siri:PublishingAction>
+                                    <siri:PassengerInformationAction>
+                                        <siri:TextualContent>
+                                            <siri:SummaryContent>
+                                                <siri:SummaryText xml:lang="de">Linie 32 umgeleitet</siri:SummaryText>
+                                            </siri:SummaryContent>
+                                        </siri:TextualContent>
+                                    </siri:PassengerInformationAction>
+                                </siri:PublishingAction>
+                            </siri:PublishingActions>
+                        </ojp:PtSituation>
+                        <ojp:PtSituation>
+                            <siri:SituationNumber>2</siri:SituationNumber>
+                            <siri:Summary xml:lang="fr">Grève &amp; perturbations</siri:Summary>
+                        </ojp:PtSituation>
+                        <ojp:PtSituation>
+                            <siri:SituationNumber>3</siri:SituationNumber>
+                            <siri:Summary xml:lang="fr">Ligne 32 déviée</siri:Summary>
+                        </ojp:PtSituation>
+                    </ojp:Situations>
+                </ojp:StopEventResponseContext>
+            </ojp:OJPStopEventDelivery>
+        </siri:ServiceDelivery>
+    </siri:OJPResponse>
+</siri:OJP>
+"#;
+        let (departures, disruptions) = extract_departures(body.as_bytes(), &Stop::default(), 1)
+            .await
+            .unwrap();
+        assert!(departures.is_empty());
+        assert_eq!(disruptions, ["Ligne 32 déviée", "Grève & perturbations"]);
+    }
+
+    #[test]
+    fn asks_for_gzip_unless_disabled() {
+        let updater = |gzip| {
+            let config = api_config::ApiConfig {
+                transport: Some(TransportConfig {
+                    url: "https://ojp.example/ojp".into(),
+                    gzip,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            TransportUpdater::new(UpdateMode::Real, &config, Client::new()).unwrap()
         };
-        assert_eq!(create_ojp_request(&config, &fake_now), expected_xml);
+        // Left to the client, which adds it when sending
+        let request = updater(None).post_ojp(String::new()).build().unwrap();
+        assert_eq!(request.headers().get(ACCEPT_ENCODING), None);
+        let request = updater(Some(false))
+            .post_ojp(String::new())
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[ACCEPT_ENCODING], "identity");
     }
 }
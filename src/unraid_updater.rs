@@ -0,0 +1,249 @@
+//! The state of an Unraid NAS: whether its array is started, which disks aren't fine, and how far
+//! a parity check got, with a warning line for the screen when something's degraded.
+//!
+//! See https://docs.unraid.net/API/ for its GraphQL API, and how to get an API key.
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::NasStatus;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+const ARRAY_QUERY: &str = "query {
+  array {
+    state
+    disks { name status }
+    parities { name status }
+    parityCheckStatus { running progress errors }
+  }
+}";
+// Disk statuses that don't call for a warning: fine, or a slot without a disk
+const FINE_DISK_STATUSES: [&str; 2] = ["DISK_OK", "DISK_NP"];
+
+#[derive(Debug)]
+pub struct UnraidUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    url: String,
+    api_key: String,
+    unraid_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for UnraidUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(41),
+            UpdateMode::Real => Instant::now() + self.unraid_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} Unraid", self.update_mode);
+        let nas;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // A parity check in the first half of every hour, and a disk failing in the last
+                // ten minutes
+                let mut status = NasStatus {
+                    array_state: "STARTED".into(),
+                    ..Default::default()
+                };
+                if now.minute() < 30 {
+                    status.parity_check_progress = Some(now.minute() as f32 * 100.0 / 30.0);
+                }
+                if now.minute() >= 50 {
+                    status.degraded_disks = vec!["disk2".into()];
+                }
+                status.warning = get_warning(&status);
+                nas = Some(status);
+                error_bit.store(
+                    now.second().is_multiple_of(19),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                nas = match self.get_status().await {
+                    Ok(status) => {
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        self.unraid_period.set_success();
+                        Some(status)
+                    }
+                    Err(e) => {
+                        error!("Error getting the Unraid status: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.unraid_period.set_error();
+                        None
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Nas(nas)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl UnraidUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let unraid_config = config.unraid.as_ref().ok_or("No Unraid config")?;
+        if unraid_config.url.is_empty() {
+            return Err("No Unraid URL".into());
+        }
+        let unraid_period_config = Duration::from_secs(
+            unraid_config
+                .update_period
+                .as_ref()
+                .ok_or("no Unraid update period")?
+                .seconds
+                .try_into()?,
+        );
+        let unraid_period = ExponentialBackoff::new(
+            unraid_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(UnraidUpdater {
+            update_mode,
+            client,
+            url: format!("{}/graphql", unraid_config.url.trim_end_matches('/')),
+            api_key: unraid_config.api_key.clone(),
+            unraid_period,
+        })
+    }
+
+    async fn get_status(&self) -> Result<NasStatus, Box<dyn std::error::Error>> {
+        let query = serde_json::json!({ "query": ARRAY_QUERY }).to_string();
+        let body = traced(
+            "fetch",
+            retry_http("Unraid fetch", || async {
+                self.client
+                    .post(&self.url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .header("x-api-key", &self.api_key)
+                    .body(query.clone())
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        traced_sync("parse", || parse_status(&body))
+    }
+}
+
+// Reads the array state out of the GraphQL response
+fn parse_status(body: &str) -> Result<NasStatus, Box<dyn std::error::Error>> {
+    let response: Value = serde_json::from_str(body)?;
+    // GraphQL reports errors in a 200 response
+    if let Some(message) = response
+        .pointer("/errors/0/message")
+        .and_then(Value::as_str)
+    {
+        return Err(format!("Unraid API error: {}", message).into());
+    }
+    let array = response
+        .pointer("/data/array")
+        .ok_or("No array in the response")?;
+    let text = |value: &Value, pointer: &str| {
+        value
+            .pointer(pointer)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let disks = ["/disks", "/parities"]
+        .into_iter()
+        .filter_map(|pointer| array.pointer(pointer)?.as_array())
+        .flatten();
+    let degraded_disks = disks
+        .filter(|disk| !FINE_DISK_STATUSES.contains(&text(disk, "/status").as_str()))
+        .map(|disk| text(disk, "/name"))
+        .collect();
+    let check = array.pointer("/parityCheckStatus");
+    let parity_check_progress = check
+        .filter(|check| check.get("running").and_then(Value::as_bool) == Some(true))
+        .and_then(|check| check.get("progress")?.as_f64())
+        .map(|progress| progress as f32);
+    let mut status = NasStatus {
+        array_state: text(array, "/state"),
+        degraded_disks,
+        parity_check_progress,
+        parity_errors: check
+            .and_then(|check| check.get("errors")?.as_u64())
+            .unwrap_or_default() as u32,
+        ..Default::default()
+    };
+    status.warning = get_warning(&status);
+    Ok(status)
+}
+
+// What's wrong in a line, empty if nothing is
+fn get_warning(status: &NasStatus) -> String {
+    let mut problems = vec![];
+    if status.array_state != "STARTED" {
+        problems.push(format!("array {}", status.array_state.to_lowercase()));
+    }
+    if !status.degraded_disks.is_empty() {
+        problems.push(format!("{} degraded", status.degraded_disks.join(",")));
+    }
+    if status.parity_errors > 0 {
+        problems.push(format!("{} parity errors", status.parity_errors));
+    }
+    match problems.is_empty() {
+        true => String::new(),
+        false => format!("NAS: {}", problems.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_array_status() {
+        let body = r#"{"data": {"array": {
+            "state": "STARTED",
+            "disks": [{"name": "disk1", "status": "DISK_OK"}, {"name": "disk2", "status": "DISK_DSBL"},
+                      {"name": "disk3", "status": "DISK_NP"}],
+            "parities": [{"name": "parity", "status": "DISK_OK"}],
+            "parityCheckStatus": {"running": true, "progress": 42.5, "errors": 0}
+        }}}"#;
+        let status = parse_status(body).unwrap();
+        assert_eq!(status.degraded_disks, ["disk2"]);
+        assert_eq!(status.parity_check_progress, Some(42.5));
+        assert_eq!(status.warning, "NAS: disk2 degraded");
+
+        let body = r#"{"data": {"array": {"state": "STOPPED", "disks": [],
+            "parityCheckStatus": {"running": false, "progress": 100, "errors": 3}}}}"#;
+        let status = parse_status(body).unwrap();
+        assert_eq!(status.parity_check_progress, None);
+        assert_eq!(status.warning, "NAS: array stopped, 3 parity errors");
+
+        let body = r#"{"data": {"array": {"state": "STARTED", "disks": [
+            {"name": "disk1", "status": "DISK_OK"}]}}}"#;
+        assert_eq!(parse_status(body).unwrap().warning, "");
+
+        let body = r#"{"errors": [{"message": "API key validation failed"}], "data": null}"#;
+        let err = parse_status(body).unwrap_err();
+        assert!(err.to_string().contains("API key validation failed"));
+    }
+}
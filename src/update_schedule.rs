@@ -0,0 +1,203 @@
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::screen_service::ScreenContentReply;
+use crate::updater_registry::UpdaterCommand;
+use chrono::{DateTime, Local, TimeZone, Timelike};
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// When an updater is allowed to run, as a cron expression with seconds
+/// ("sec min hour day-of-month month day-of-week [year]").
+/// Fine-grained expressions act as windows (e.g. `* * 6-22 * * *` for "between 06:00 and 23:00"),
+/// coarse ones as fixed times (e.g. `0 0 7 * * Mon-Fri` for "at 7 on weekdays").
+#[derive(Debug)]
+pub struct UpdateSchedule {
+    cron: cron::Schedule,
+}
+
+impl UpdateSchedule {
+    pub fn parse(expression: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let cron = cron::Schedule::from_str(expression)
+            .map_err(|e| format!("Invalid schedule '{}': {}", expression, e))?;
+        Ok(UpdateSchedule { cron })
+    }
+
+    /// The first time the schedule allows at or after `at` (to the second), if there is any left
+    pub fn next_allowed<Tz: TimeZone>(&self, at: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        // `after` is exclusive, so start from just before `at`
+        let just_before = at.with_nanosecond(0)? - chrono::Duration::seconds(1);
+        self.cron.after(&just_before).next()
+    }
+
+    /// Same as `next_allowed`, but on the monotonic clock the updaters use
+    pub fn next_allowed_instant(&self, at: Instant) -> Instant {
+        let (now, local_now) = (Instant::now(), Local::now());
+        let local_at = local_now
+            + chrono::Duration::from_std(at.saturating_duration_since(now)).unwrap_or_default();
+        match self.next_allowed(&local_at) {
+            Some(allowed) => {
+                now + allowed
+                    .signed_duration_since(local_now)
+                    .to_std()
+                    .unwrap_or_default()
+            }
+            None => {
+                warn!("Schedule has no upcoming time left, ignoring it");
+                at
+            }
+        }
+    }
+}
+
+/// Wraps an updater so its updates only ever get scheduled when its `UpdateSchedule` allows
+pub struct OnSchedule {
+    inner: Box<dyn DataUpdater>,
+    schedule: UpdateSchedule,
+}
+
+impl OnSchedule {
+    pub fn new(inner: Box<dyn DataUpdater>, schedule: UpdateSchedule) -> Self {
+        OnSchedule { inner, schedule }
+    }
+}
+
+#[tonic::async_trait]
+impl DataUpdater for OnSchedule {
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        self.inner.update(error_bit).await
+    }
+
+    fn get_next_update_time(&self) -> Instant {
+        let wanted = self.inner.get_next_update_time();
+        let allowed = self.schedule.next_allowed_instant(wanted);
+        if allowed > wanted {
+            debug!(
+                "Update postponed by {:?} to fit the schedule",
+                allowed - wanted
+            );
+        }
+        allowed
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        self.inner.update_mode()
+    }
+
+    fn staged_content(&self) -> Option<ScreenContentReply> {
+        self.inner.staged_content()
+    }
+
+    fn command(&mut self, command: UpdaterCommand) -> Result<(), String> {
+        self.inner.command(command)
+    }
+}
+
+/// Wraps an updater to run it `factor` times as often as it asks for (for soak tests)
+pub struct Accelerated {
+    inner: Box<dyn DataUpdater>,
+    factor: u32,
+}
+
+impl Accelerated {
+    pub fn new(inner: Box<dyn DataUpdater>, factor: u32) -> Self {
+        Accelerated { inner, factor }
+    }
+}
+
+#[tonic::async_trait]
+impl DataUpdater for Accelerated {
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        self.inner.update(error_bit).await
+    }
+
+    fn get_next_update_time(&self) -> Instant {
+        let now = Instant::now();
+        let wanted = self.inner.get_next_update_time();
+        now + wanted.saturating_duration_since(now) / self.factor.max(1)
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        self.inner.update_mode()
+    }
+
+    fn staged_content(&self) -> Option<ScreenContentReply> {
+        self.inner.staged_content()
+    }
+
+    fn command(&mut self, command: UpdaterCommand) -> Result<(), String> {
+        self.inner.command(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().to_utc()
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(UpdateSchedule::parse("every now and then").is_err());
+        assert!(UpdateSchedule::parse("* * 6-22 * * *").is_ok());
+    }
+
+    #[test]
+    fn keeps_times_within_window() {
+        let schedule = UpdateSchedule::parse("* * 6-22 * * *").unwrap();
+        let at = utc("2024-07-23T12:34:56Z");
+        assert_eq!(schedule.next_allowed(&at), Some(at));
+    }
+
+    #[test]
+    fn postpones_to_next_window() {
+        let schedule = UpdateSchedule::parse("* * 6-22 * * *").unwrap();
+        assert_eq!(
+            schedule.next_allowed(&utc("2024-07-23T23:10:00Z")),
+            Some(utc("2024-07-24T06:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn skips_weekends() {
+        let schedule = UpdateSchedule::parse("* * * * * Mon-Fri").unwrap();
+        // Saturday
+        assert_eq!(
+            schedule.next_allowed(&utc("2024-07-27T10:00:00Z")),
+            Some(utc("2024-07-29T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn handles_exhausted_schedules() {
+        let schedule = UpdateSchedule::parse("0 0 0 1 1 * 2020").unwrap();
+        assert_eq!(schedule.next_allowed(&utc("2024-07-23T12:00:00Z")), None);
+        let at = Instant::now();
+        assert_eq!(schedule.next_allowed_instant(at), at);
+    }
+
+    struct Hourly;
+
+    #[tonic::async_trait]
+    impl DataUpdater for Hourly {
+        async fn update(&mut self, _error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+            vec![]
+        }
+
+        fn get_next_update_time(&self) -> Instant {
+            Instant::now() + tokio::time::Duration::from_secs(3600)
+        }
+    }
+
+    #[test]
+    fn accelerates_updates() {
+        let next = Accelerated::new(Box::new(Hourly), 60).get_next_update_time();
+        let in_a_minute = Instant::now() + tokio::time::Duration::from_secs(60);
+        assert!(next <= in_a_minute);
+        assert!(next > in_a_minute - tokio::time::Duration::from_secs(1));
+    }
+}
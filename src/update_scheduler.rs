@@ -0,0 +1,386 @@
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::update_tracing;
+use crate::updater_registry::{UpdaterCommand, UpdaterHandle};
+use std::collections::HashMap;
+use std::time::SystemTime;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::{Id, JoinSet};
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn, Instrument};
+
+/// An updater along with what the scheduler needs to drive it.
+struct ScheduledUpdater {
+    handle: UpdaterHandle,
+    updater: Box<dyn DataUpdater>,
+}
+
+impl ScheduledUpdater {
+    fn name(&self) -> &'static str {
+        self.handle.name()
+    }
+
+    // Applies the command, moving the next run if needed
+    fn apply(&mut self, command: UpdaterCommand, next_run: &mut Instant) {
+        info!("Applying {:?} to {}", command, self.name());
+        match command {
+            UpdaterCommand::Refresh => *next_run = Instant::now(),
+            UpdaterCommand::SetDummyMode(dummy_mode) => {
+                let Some(update_mode) = self.updater.update_mode() else {
+                    warn!(
+                        "Can't switch {} to dummy mode {}: it has none",
+                        self.name(),
+                        dummy_mode
+                    );
+                    return;
+                };
+                *update_mode = UpdateMode::from_dummy(dummy_mode);
+                let dummy_mode = is_dummy(self.updater.as_mut());
+                self.handle.record(|stats| stats.dummy_mode = dummy_mode);
+                // Show what the new mode gets right away
+                *next_run = Instant::now();
+            }
+            command => match self.updater.command(command.clone()) {
+                Ok(()) => {
+                    let staged = self.updater.staged_content();
+                    self.handle.record(|stats| stats.staged = staged);
+                    // Show what the command changed right away, e.g. whose turn it is now
+                    *next_run = Instant::now();
+                }
+                Err(e) => warn!("Couldn't apply {:?} to {}: {}", command, self.name(), e),
+            },
+        }
+    }
+}
+
+fn is_dummy(updater: &mut dyn DataUpdater) -> bool {
+    updater
+        .update_mode()
+        .is_some_and(|mode| *mode == UpdateMode::Dummy)
+}
+
+/// Drives all the data updaters from a single task: each updater runs in a `JoinSet` when its
+/// next update time comes, and gets rescheduled once its update is done. What the updates return
+/// goes to the content aggregator, and commands come in through the updaters' handles.
+pub struct UpdateScheduler {
+    updaters: Vec<ScheduledUpdater>,
+    command_sender: UnboundedSender<(&'static str, UpdaterCommand)>,
+    commands: UnboundedReceiver<(&'static str, UpdaterCommand)>,
+}
+
+impl UpdateScheduler {
+    pub fn new() -> Self {
+        let (command_sender, commands) = mpsc::unbounded_channel();
+        UpdateScheduler {
+            updaters: vec![],
+            command_sender,
+            commands,
+        }
+    }
+
+    /// Registers an updater, which will first run as soon as the scheduler starts, and returns the
+    /// handle to reach it afterwards
+    pub fn add(&mut self, name: &'static str, mut updater: Box<dyn DataUpdater>) -> UpdaterHandle {
+        let handle = UpdaterHandle::new(name, self.command_sender.clone());
+        let (dummy_mode, staged) = (is_dummy(updater.as_mut()), updater.staged_content());
+        handle.record(|stats| {
+            stats.dummy_mode = dummy_mode;
+            stats.staged = staged;
+        });
+        self.updaters.push(ScheduledUpdater {
+            handle: handle.clone(),
+            updater,
+        });
+        handle
+    }
+
+    /// Runs the updaters until none are left (i.e. forever, unless they all panicked).
+    /// Dropping the returned future aborts any update in flight.
+    pub async fn run(self, content_updates: UnboundedSender<ContentUpdate>) {
+        // Only keep the senders handed out so far, so the channel closes with the handles
+        drop(self.command_sender);
+        let mut commands = self.commands;
+        let mut running = JoinSet::new();
+        // Whose update each running task is, to know who's gone when one panics
+        let mut running_handles: HashMap<Id, UpdaterHandle> = HashMap::new();
+        // Updaters waiting for their next run, along with when that is
+        let mut idle: Vec<(Instant, ScheduledUpdater)> = self
+            .updaters
+            .into_iter()
+            .map(|updater| (Instant::now(), updater))
+            .collect();
+        // Commands for updaters that were busy when they came in
+        let mut deferred: Vec<(&'static str, UpdaterCommand)> = vec![];
+
+        loop {
+            // Start everything that is due
+            let now = Instant::now();
+            let (due, waiting): (Vec<_>, Vec<_>) =
+                idle.into_iter().partition(|(next_run, _)| *next_run <= now);
+            idle = waiting;
+            for (_, mut scheduled) in due {
+                debug!("Starting {} update", scheduled.name());
+                let content_updates = content_updates.clone();
+                let span = update_tracing::cycle_span(scheduled.name());
+                let handle = scheduled.handle.clone();
+                let task = running.spawn(
+                    async move {
+                        let start = Instant::now();
+                        let updates = scheduled.updater.update(scheduled.handle.error_bit()).await;
+                        let sent = update_tracing::traced_sync("write", || {
+                            updates
+                                .into_iter()
+                                .try_for_each(|update| content_updates.send(update))
+                        });
+                        if let Err(e) = sent {
+                            error!(
+                                "Dropping {} update, the aggregator is gone: {}",
+                                scheduled.name(),
+                                e
+                            );
+                        }
+                        let duration = start.elapsed();
+                        let failed = scheduled.handle.has_error();
+                        let staged = scheduled.updater.staged_content();
+                        scheduled.handle.record(|stats| {
+                            stats.staged = staged;
+                            stats.updates += 1;
+                            stats.failures += u64::from(failed);
+                            stats.last_update = Some(SystemTime::now());
+                            stats.last_duration = duration;
+                        });
+                        let outcome = match failed {
+                            true => "error",
+                            false => "ok",
+                        };
+                        info!(
+                            ?duration,
+                            outcome,
+                            "{} update cycle done in {:?} ({})",
+                            scheduled.name(),
+                            duration,
+                            outcome
+                        );
+                        scheduled
+                    }
+                    .instrument(span),
+                );
+                running_handles.insert(task.id(), handle);
+            }
+
+            if running.is_empty() && idle.is_empty() {
+                warn!("No updaters left to schedule, stopping the scheduler");
+                return;
+            }
+
+            let next_wakeup = idle.iter().map(|(next_run, _)| *next_run).min();
+            tokio::select! {
+                Some(result) = running.join_next_with_id() => match result {
+                    Ok((id, mut scheduled)) => {
+                        running_handles.remove(&id);
+                        let mut next_run = scheduled.updater.get_next_update_time();
+                        debug!("{} update done, next one at {:?}", scheduled.name(), next_run);
+                        let (own, others) = deferred
+                            .into_iter()
+                            .partition(|(name, _)| *name == scheduled.name());
+                        deferred = others;
+                        for (_, command) in own {
+                            scheduled.apply(command, &mut next_run);
+                        }
+                        idle.push((next_run, scheduled));
+                    }
+                    // We lose the updater along with its task, so the others just carry on without it
+                    Err(e) => match running_handles.remove(&e.id()) {
+                        Some(handle) => {
+                            error!("{} update failed, it won't be rescheduled: {}", handle.name(), e);
+                            handle.record(|stats| stats.dead = true);
+                            deferred.retain(|(name, _)| *name != handle.name());
+                        }
+                        None => error!("An updater task failed and won't be rescheduled: {}", e),
+                    },
+                },
+                Some((name, command)) = commands.recv() => {
+                    match idle.iter_mut().find(|(_, scheduled)| scheduled.name() == name) {
+                        Some((next_run, scheduled)) => scheduled.apply(command, next_run),
+                        // Neither idle nor running: its update panicked
+                        None if !running_handles.values().any(|handle| handle.name() == name) => {
+                            warn!("Dropping {:?} for {}: it's no longer scheduled", command, name)
+                        }
+                        // It's updating right now, so a refresh would be redundant
+                        None if command == UpdaterCommand::Refresh => {
+                            debug!("Ignoring refresh of {}: already updating", name)
+                        }
+                        None => deferred.push((name, command)),
+                    }
+                }
+                _ = tokio::time::sleep_until(next_wakeup.unwrap_or(now)), if next_wakeup.is_some() => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::time::Duration;
+
+    struct CountingUpdater {
+        count: Arc<AtomicUsize>,
+        period: Duration,
+        panics: bool,
+        update_mode: UpdateMode,
+    }
+
+    #[tonic::async_trait]
+    impl DataUpdater for CountingUpdater {
+        async fn update(&mut self, _error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+            self.count.fetch_add(1, Ordering::Relaxed);
+            if self.panics {
+                panic!("failing on purpose");
+            }
+            vec![ContentUpdate::Weather(None)]
+        }
+
+        fn get_next_update_time(&self) -> Instant {
+            Instant::now() + self.period
+        }
+
+        fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+            Some(&mut self.update_mode)
+        }
+    }
+
+    fn counting_updater(period_ms: u64, panics: bool) -> (Box<CountingUpdater>, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let updater = Box::new(CountingUpdater {
+            count: Arc::clone(&count),
+            period: Duration::from_millis(period_ms),
+            panics,
+            update_mode: UpdateMode::Real,
+        });
+        (updater, count)
+    }
+
+    #[tokio::test]
+    async fn runs_updaters_at_their_own_pace() {
+        let (fast, fast_count) = counting_updater(10, false);
+        let (slow, slow_count) = counting_updater(1000, false);
+        let mut scheduler = UpdateScheduler::new();
+        scheduler.add("fast", fast);
+        scheduler.add("slow", slow);
+
+        let (updates, _received) = mpsc::unbounded_channel();
+        let _ = tokio::time::timeout(Duration::from_millis(200), scheduler.run(updates)).await;
+
+        assert!(fast_count.load(Ordering::Relaxed) >= 5);
+        assert_eq!(slow_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_going_when_an_updater_panics() {
+        let (healthy, healthy_count) = counting_updater(10, false);
+        let (failing, failing_count) = counting_updater(10, true);
+        let mut scheduler = UpdateScheduler::new();
+        scheduler.add("healthy", healthy);
+        scheduler.add("failing", failing);
+
+        let (updates, _received) = mpsc::unbounded_channel();
+        let _ = tokio::time::timeout(Duration::from_millis(200), scheduler.run(updates)).await;
+
+        assert!(healthy_count.load(Ordering::Relaxed) >= 5);
+        assert_eq!(failing_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn drops_updaters_that_panicked() {
+        let (failing, _) = counting_updater(10, true);
+        let mut scheduler = UpdateScheduler::new();
+        let handle = scheduler.add("failing", failing);
+        let (slow, _) = counting_updater(1000, false);
+        let _keep_running = scheduler.add("slow", slow);
+
+        let (updates, _received) = mpsc::unbounded_channel();
+        let run = tokio::spawn(scheduler.run(updates));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(handle.stats().dead);
+        assert!(handle.send(UpdaterCommand::SetDummyMode(true)).is_err());
+        run.abort();
+    }
+
+    #[tokio::test]
+    async fn refreshes_on_request() {
+        let (slow, slow_count) = counting_updater(1000, false);
+        let mut scheduler = UpdateScheduler::new();
+        let handle = scheduler.add("slow", slow);
+
+        let (updates, _received) = mpsc::unbounded_channel();
+        let run = tokio::spawn(scheduler.run(updates));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.send(UpdaterCommand::Refresh).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        run.abort();
+
+        assert_eq!(slow_count.load(Ordering::Relaxed), 2);
+        assert_eq!(handle.stats().updates, 2);
+        assert_eq!(handle.stats().failures, 0);
+    }
+
+    #[tokio::test]
+    async fn switches_modes_on_request() {
+        let (slow, slow_count) = counting_updater(1000, false);
+        let mut scheduler = UpdateScheduler::new();
+        let handle = scheduler.add("slow", slow);
+        assert!(!handle.stats().dummy_mode);
+
+        let (updates, _received) = mpsc::unbounded_channel();
+        let run = tokio::spawn(scheduler.run(updates));
+        // Sent while the first update may still be running, which defers it
+        handle.send(UpdaterCommand::SetDummyMode(true)).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        run.abort();
+
+        assert!(handle.stats().dummy_mode);
+        // Updated again right away to show the new mode's data
+        assert_eq!(slow_count.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn skips_unsupported_commands() {
+        let (slow, slow_count) = counting_updater(1000, false);
+        let mut scheduler = UpdateScheduler::new();
+        let handle = scheduler.add("slow", slow);
+
+        let (updates, _received) = mpsc::unbounded_channel();
+        let run = tokio::spawn(scheduler.run(updates));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.send(UpdaterCommand::Acknowledge).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        run.abort();
+
+        // Not updated again, since nothing changed
+        assert_eq!(slow_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn forwards_updates() {
+        let (updater, _) = counting_updater(1000, false);
+        let mut scheduler = UpdateScheduler::new();
+        scheduler.add("weather", updater);
+
+        let (updates, mut received) = mpsc::unbounded_channel();
+        let _ = tokio::time::timeout(Duration::from_millis(50), scheduler.run(updates)).await;
+
+        assert_eq!(received.try_recv(), Ok(ContentUpdate::Weather(None)));
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn stops_without_updaters() {
+        let (updates, _received) = mpsc::unbounded_channel();
+        let result =
+            tokio::time::timeout(Duration::from_secs(1), UpdateScheduler::new().run(updates)).await;
+        assert!(result.is_ok());
+    }
+}
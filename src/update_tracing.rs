@@ -0,0 +1,73 @@
+//! Tracing for the update cycles: each cycle of an updater runs in an `update_cycle` span (see the
+//! scheduler), and its phases ("fetch", "parse", "write") in child spans, each logging how long it
+//! took and how it went. Without a tracing subscriber, all of this ends up in log4rs through the
+//! `log` crate like the rest of our logs.
+
+use std::fmt::Display;
+use std::future::Future;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, info_span, warn, Instrument, Span};
+
+pub fn cycle_span(updater: &str) -> Span {
+    info_span!("update_cycle", updater)
+}
+
+fn log_outcome<T, E: Display>(phase: &str, duration: Duration, result: &Result<T, E>) {
+    match result {
+        Ok(_) => debug!(
+            phase,
+            ?duration,
+            outcome = "ok",
+            "{} done in {:?}",
+            phase,
+            duration
+        ),
+        Err(e) => warn!(
+            phase,
+            ?duration,
+            outcome = "error",
+            "{} failed after {:?}: {}",
+            phase,
+            duration,
+            e
+        ),
+    }
+}
+
+/// Runs an async phase of an update cycle in its own span
+pub async fn traced<T, E: Display>(
+    phase: &'static str,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let span = info_span!("phase", phase);
+    let start = Instant::now();
+    let result = future.instrument(span.clone()).await;
+    span.in_scope(|| log_outcome(phase, start.elapsed(), &result));
+    result
+}
+
+/// Runs a blocking phase of an update cycle in its own span
+pub fn traced_sync<T, E: Display>(
+    phase: &'static str,
+    operation: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let span = info_span!("phase", phase);
+    let _entered = span.enter();
+    let start = Instant::now();
+    let result = operation();
+    log_outcome(phase, start.elapsed(), &result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn passes_results_through() {
+        let ok: Result<u32, String> = traced("fetch", async { Ok(42) }).await;
+        assert_eq!(ok, Ok(42));
+        let error: Result<u32, String> = traced_sync("parse", || Err("garbled".into()));
+        assert_eq!(error, Err("garbled".to_string()));
+    }
+}
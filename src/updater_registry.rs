@@ -0,0 +1,204 @@
+//! How the service reaches the updaters once the scheduler owns them: each registered updater gets
+//! an `UpdaterHandle` holding its error bit, its stats, and a channel to send it commands through.
+//! The status, refresh and mode RPCs all go through the `UpdaterRegistry` of those handles.
+
+use crate::screen_service::ScreenContentReply;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
+
+// How often to check whether the first updates are all done
+const WARM_UP_POLL_PERIOD: Duration = Duration::from_millis(50);
+
+/// What can be asked of a registered updater, applied by the scheduler between its updates
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdaterCommand {
+    Refresh,
+    SetDummyMode(bool),
+    Approve,
+    Acknowledge,
+    // The item done, e.g. a chore's label
+    Complete(String),
+}
+
+/// What the scheduler keeps track of about an updater
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UpdaterStats {
+    pub updates: u64,
+    pub failures: u64,
+    pub last_update: Option<SystemTime>,
+    pub last_duration: Duration,
+    pub dummy_mode: bool,
+    // Set while the updater is under review, see content_review.rs
+    pub staged: Option<ScreenContentReply>,
+    // Its update panicked, so the scheduler dropped it for good
+    pub dead: bool,
+}
+
+// Cheap to clone: all clones share the same error bit, stats and command channel
+#[derive(Clone)]
+pub struct UpdaterHandle {
+    name: &'static str,
+    error_bit: Arc<AtomicBool>,
+    stats: Arc<Mutex<UpdaterStats>>,
+    commands: UnboundedSender<(&'static str, UpdaterCommand)>,
+}
+
+impl UpdaterHandle {
+    pub fn new(
+        name: &'static str,
+        commands: UnboundedSender<(&'static str, UpdaterCommand)>,
+    ) -> Self {
+        UpdaterHandle {
+            name,
+            error_bit: Arc::new(AtomicBool::new(false)),
+            stats: Arc::new(Mutex::new(UpdaterStats::default())),
+            commands,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The bit the updater sets when its last update failed
+    pub fn error_bit(&self) -> &Arc<AtomicBool> {
+        &self.error_bit
+    }
+
+    pub fn has_error(&self) -> bool {
+        self.error_bit.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> UpdaterStats {
+        match self.stats.lock() {
+            Ok(stats) => stats.clone(),
+            Err(e) => {
+                error!("Poisoned lock when reading {} stats: {}", self.name, e);
+                UpdaterStats::default()
+            }
+        }
+    }
+
+    pub fn record(&self, change: impl FnOnce(&mut UpdaterStats)) {
+        match self.stats.lock() {
+            Ok(mut stats) => change(&mut stats),
+            Err(e) => error!("Poisoned lock when recording {} stats: {}", self.name, e),
+        }
+    }
+
+    /// Hands the command over to the scheduler, which fails if the updater or the scheduler is gone
+    pub fn send(&self, command: UpdaterCommand) -> Result<(), String> {
+        if self.stats().dead {
+            return Err(format!(
+                "{} is no longer scheduled, its update panicked",
+                self.name
+            ));
+        }
+        self.commands
+            .send((self.name, command))
+            .map_err(|e| format!("Scheduler is gone: {}", e))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct UpdaterRegistry {
+    handles: Vec<UpdaterHandle>,
+}
+
+impl UpdaterRegistry {
+    pub fn register(&mut self, handle: UpdaterHandle) {
+        self.handles.push(handle);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&UpdaterHandle> {
+        self.handles.iter().find(|handle| handle.name == name)
+    }
+
+    pub fn handles(&self) -> &[UpdaterHandle] {
+        &self.handles
+    }
+
+    pub fn any_error(&self) -> bool {
+        self.handles.iter().any(UpdaterHandle::has_error)
+    }
+
+    /// Waits until every updater went through its first update (failed or not), giving up after
+    /// `timeout`. Returns whether they all did, not counting the ones that died on the way.
+    pub async fn wait_for_first_updates(&self, timeout: Duration) -> bool {
+        let all_updated = async {
+            while self.handles.iter().any(|handle| {
+                let stats = handle.stats();
+                !stats.dead && stats.updates == 0
+            }) {
+                tokio::time::sleep(WARM_UP_POLL_PERIOD).await;
+            }
+        };
+        tokio::time::timeout(timeout, all_updated).await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn finds_updaters_and_their_errors() {
+        let (commands, mut received) = mpsc::unbounded_channel();
+        let mut registry = UpdaterRegistry::default();
+        registry.register(UpdaterHandle::new("kitty", commands.clone()));
+        registry.register(UpdaterHandle::new("gcal", commands));
+        assert!(registry.get("weather").is_none());
+        assert!(!registry.any_error());
+
+        let gcal = registry.get("gcal").unwrap();
+        gcal.error_bit().store(true, Ordering::Relaxed);
+        assert!(registry.any_error());
+        gcal.record(|stats| stats.updates += 1);
+        assert_eq!(gcal.stats().updates, 1);
+
+        gcal.send(UpdaterCommand::SetDummyMode(true)).unwrap();
+        assert_eq!(
+            received.try_recv(),
+            Ok(("gcal", UpdaterCommand::SetDummyMode(true)))
+        );
+        let kitty = registry.get("kitty").unwrap();
+        kitty.record(|stats| stats.dead = true);
+        assert!(kitty.send(UpdaterCommand::Refresh).is_err());
+        assert!(received.try_recv().is_err());
+
+        drop(received);
+        assert!(gcal.send(UpdaterCommand::Refresh).is_err());
+    }
+
+    #[tokio::test]
+    async fn waits_for_first_updates() {
+        let (commands, _received) = mpsc::unbounded_channel();
+        let mut registry = UpdaterRegistry::default();
+        registry.register(UpdaterHandle::new("kitty", commands.clone()));
+        registry.register(UpdaterHandle::new("gcal", commands));
+        assert!(
+            !registry
+                .wait_for_first_updates(Duration::from_millis(100))
+                .await
+        );
+
+        // Dead updaters never get there
+        registry.handles()[0].record(|stats| stats.dead = true);
+        let handles = registry.clone();
+        tokio::spawn(async move {
+            for handle in &handles.handles()[1..] {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                handle.record(|stats| stats.updates += 1);
+            }
+        });
+        assert!(
+            registry
+                .wait_for_first_updates(Duration::from_secs(5))
+                .await
+        );
+    }
+}
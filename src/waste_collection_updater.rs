@@ -0,0 +1,365 @@
+//! Reminders to take the bins out, e.g. "Tomorrow: paper", shown as a text widget only in the
+//! hours before each collection.
+//!
+//! The collections come either from an ICS (e.g. the municipality's calendar, whose events are
+//! named after the kind of waste) or from a JSON API listing them with a date and a kind, such as
+//! OpenERZ's (https://openerz.metaodi.ch/api/calendar.json?zip=8001).
+
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::gcal_updater;
+use crate::retry::retry_http;
+use crate::screen_service::TextWidget;
+use crate::time_util;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, TimeZone, Timelike};
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub const SOURCE: &str = "waste_collection";
+const DEFAULT_REMINDER_HOURS: u32 = 6;
+const DEFAULT_DATE_FIELD: &str = "date";
+const DEFAULT_KIND_FIELD: &str = "waste_type";
+
+#[derive(Debug)]
+enum Calendar {
+    Ics(String),
+    Api {
+        url: String,
+        list_pointer: String,
+        date_field: String,
+        kind_field: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Collection {
+    // POSIX time, the midnight starting the collection day for all-day ones
+    start: i64,
+    kind: String,
+}
+
+#[derive(Debug)]
+pub struct WasteCollectionUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    calendar: Calendar,
+    reminder_seconds: i64,
+    waste_collection_period: ExponentialBackoff,
+    next_fetch: Instant,
+    // From the last calendar we got, kept through fetch failures
+    collections: Vec<Collection>,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for WasteCollectionUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(53),
+            // The reminders come and go in between fetches
+            UpdateMode::Real => {
+                let now = Local::now().timestamp();
+                match next_change(&self.collections, self.reminder_seconds, now) {
+                    Some(change) => {
+                        let until_change = Duration::from_secs((change - now) as u64);
+                        self.next_fetch.min(Instant::now() + until_change)
+                    }
+                    None => self.next_fetch,
+                }
+            }
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} waste collection", self.update_mode);
+        let widgets;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // Paper tomorrow, for the second half of every hour
+                widgets = match now.minute() < 30 {
+                    true => vec![],
+                    false => vec![TextWidget {
+                        label: "Tomorrow".into(),
+                        text: "paper".into(),
+                        source: SOURCE.to_string(),
+                    }],
+                };
+                error_bit.store(
+                    now.second().is_multiple_of(53),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                if self.next_fetch <= Instant::now() {
+                    match self.fetch_collections().await {
+                        Ok(collections) => {
+                            error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                            self.waste_collection_period.set_success();
+                            self.collections = collections;
+                        }
+                        Err(e) => {
+                            error!("Error getting the waste collections: {}", e);
+                            error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                            self.waste_collection_period.set_error();
+                        }
+                    }
+                    self.next_fetch =
+                        Instant::now() + self.waste_collection_period.get_current_duration();
+                }
+                widgets = get_reminders(&self.collections, self.reminder_seconds, &Local::now());
+            }
+        }
+        vec![ContentUpdate::TextWidgets {
+            source: SOURCE,
+            widgets,
+        }]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl WasteCollectionUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let waste_collection_config = config
+            .waste_collection
+            .as_ref()
+            .ok_or("No waste collection config")?;
+        let calendar = match (
+            waste_collection_config.ics_url.is_empty(),
+            waste_collection_config.api_url.is_empty(),
+        ) {
+            (false, true) => Calendar::Ics(waste_collection_config.ics_url.clone()),
+            (true, false) => {
+                let or_default = |field: &str, default: &str| match field.is_empty() {
+                    true => default.to_string(),
+                    false => field.to_string(),
+                };
+                Calendar::Api {
+                    url: waste_collection_config.api_url.clone(),
+                    list_pointer: waste_collection_config.api_list_pointer.clone(),
+                    date_field: or_default(
+                        &waste_collection_config.api_date_field,
+                        DEFAULT_DATE_FIELD,
+                    ),
+                    kind_field: or_default(
+                        &waste_collection_config.api_kind_field,
+                        DEFAULT_KIND_FIELD,
+                    ),
+                }
+            }
+            _ => return Err("Waste collection needs either an ICS URL or an API URL".into()),
+        };
+        let waste_collection_period_config = Duration::from_secs(
+            waste_collection_config
+                .update_period
+                .as_ref()
+                .ok_or("no waste collection update period")?
+                .seconds
+                .try_into()?,
+        );
+        let waste_collection_period = ExponentialBackoff::new(
+            waste_collection_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        let reminder_hours = waste_collection_config
+            .reminder_hours
+            .unwrap_or(DEFAULT_REMINDER_HOURS);
+        Ok(WasteCollectionUpdater {
+            update_mode,
+            client,
+            calendar,
+            reminder_seconds: reminder_hours as i64 * 3600,
+            waste_collection_period,
+            next_fetch: Instant::now(),
+            collections: vec![],
+        })
+    }
+
+    async fn fetch_collections(&self) -> Result<Vec<Collection>, Box<dyn std::error::Error>> {
+        let url = match &self.calendar {
+            Calendar::Ics(url) => url,
+            Calendar::Api { url, .. } => url,
+        };
+        let body = traced(
+            "fetch",
+            retry_http("Waste collection fetch", || async {
+                self.client
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        traced_sync("parse", || match &self.calendar {
+            Calendar::Ics(_) => parse_ics_collections(body),
+            Calendar::Api {
+                list_pointer,
+                date_field,
+                kind_field,
+                ..
+            } => parse_api_collections(&body, list_pointer, date_field, kind_field, &Local),
+        })
+    }
+}
+
+fn parse_ics_collections(ics: String) -> Result<Vec<Collection>, Box<dyn std::error::Error>> {
+    Ok(gcal_updater::parse_events(ics)?
+        .into_iter()
+        .filter_map(|event| {
+            Some(Collection {
+                start: event.event_start?.seconds,
+                kind: event.event_title,
+            })
+        })
+        .collect())
+}
+
+// Dates are either plain days (e.g. "2024-10-23", the collection starting at their midnight) or
+// ISO-8601 timestamps
+fn parse_api_collections<Tz: TimeZone>(
+    body: &str,
+    list_pointer: &str,
+    date_field: &str,
+    kind_field: &str,
+    local_tz: &Tz,
+) -> Result<Vec<Collection>, Box<dyn std::error::Error>> {
+    let response: Value = serde_json::from_str(body)?;
+    let list = response
+        .pointer(list_pointer)
+        .and_then(Value::as_array)
+        .ok_or_else(|| format!("No list of collections at '{}'", list_pointer))?;
+    let mut collections = vec![];
+    for item in list {
+        let text = |field: &str| item.get(field).and_then(Value::as_str);
+        let (Some(date), Some(kind)) = (text(date_field), text(kind_field)) else {
+            continue;
+        };
+        let start = match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(day) => local_tz
+                .from_local_datetime(&day.and_time(NaiveTime::MIN))
+                .earliest()
+                .ok_or_else(|| format!("{} has no midnight", day))?
+                .timestamp(),
+            Err(_) => time_util::parse_iso8601(date, local_tz)?.seconds,
+        };
+        collections.push(Collection {
+            start,
+            kind: kind.to_string(),
+        });
+    }
+    Ok(collections)
+}
+
+// The collections coming within `reminder_seconds` of `now`, one widget per day (e.g.
+// "Tomorrow: paper, glass"). They're gone once the collection starts, the bins must be out by
+// then.
+fn get_reminders<Tz: TimeZone>(
+    collections: &[Collection],
+    reminder_seconds: i64,
+    now: &DateTime<Tz>,
+) -> Vec<TextWidget> {
+    let mut coming: Vec<&Collection> = collections
+        .iter()
+        .filter(|collection| {
+            let until = collection.start - now.timestamp();
+            0 < until && until <= reminder_seconds
+        })
+        .collect();
+    coming.sort_by_key(|collection| collection.start);
+    let mut widgets: Vec<TextWidget> = vec![];
+    for collection in coming {
+        let Some(start) = now.timezone().timestamp_opt(collection.start, 0).single() else {
+            continue;
+        };
+        let label = match (start.date_naive() - now.date_naive()).num_days() {
+            0 => "Today".to_string(),
+            1 => "Tomorrow".to_string(),
+            _ => start.weekday().to_string(),
+        };
+        match widgets.iter_mut().find(|widget| widget.label == label) {
+            Some(widget) if widget.text.split(", ").any(|kind| kind == collection.kind) => (),
+            Some(widget) => widget.text = format!("{}, {}", widget.text, collection.kind),
+            None => widgets.push(TextWidget {
+                label,
+                text: collection.kind.clone(),
+                source: SOURCE.to_string(),
+            }),
+        }
+    }
+    widgets
+}
+
+// When the next reminder shows up or goes away after `now` (POSIX times)
+fn next_change(collections: &[Collection], reminder_seconds: i64, now: i64) -> Option<i64> {
+    collections
+        .iter()
+        .flat_map(|collection| [collection.start - reminder_seconds, collection.start])
+        .filter(|&change| change > now)
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn reminds_the_evening_before() {
+        let body = r#"{"_metadata": {"total_count": 3}, "result": [
+            {"date": "2024-10-23", "waste_type": "paper", "zip": 8001},
+            {"date": "2024-10-23", "waste_type": "cardboard", "zip": 8001},
+            {"date": "2024-10-30", "waste_type": "paper", "zip": 8001}
+        ]}"#;
+        let collections =
+            parse_api_collections(body, "/result", "date", "waste_type", &Utc).unwrap();
+        assert_eq!(collections.len(), 3);
+        // 2024-10-23 00:00 UTC
+        assert_eq!(collections[0].start, 1729641600);
+
+        let reminders = |time: &str| {
+            let now = DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc);
+            get_reminders(&collections, 6 * 3600, &now)
+        };
+        assert!(reminders("2024-10-22T17:59:00Z").is_empty());
+        let widgets = reminders("2024-10-22T18:00:00Z");
+        assert_eq!(widgets.len(), 1);
+        assert_eq!(
+            (widgets[0].label.as_str(), widgets[0].text.as_str()),
+            ("Tomorrow", "paper, cardboard")
+        );
+        assert!(reminders("2024-10-23T00:00:00Z").is_empty());
+        assert_eq!(
+            next_change(&collections, 6 * 3600, 1729641600),
+            Some(1730246400 - 6 * 3600)
+        );
+
+        let ics = "BEGIN:VCALENDAR
+BEGIN:VEVENT
+DTSTART;VALUE=DATE:20241023
+SUMMARY:Glass
+END:VEVENT
+END:VCALENDAR
+"
+        .to_string();
+        let collections = parse_ics_collections(ics).unwrap();
+        assert_eq!(collections[0].kind, "Glass");
+    }
+}
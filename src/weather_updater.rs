@@ -0,0 +1,208 @@
+use crate::config_extractor::api_config;
+use crate::data_updater::{ContentUpdate, DataUpdater, UpdateMode};
+use crate::exponential_backoff::ExponentialBackoff;
+use crate::retry::retry_http;
+use crate::screen_service::Weather;
+use crate::update_tracing::{traced, traced_sync};
+use chrono::Timelike;
+use reqwest::Client;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+const OPEN_METEO_URL: &str = "https://api.open-meteo.com/v1/forecast";
+// How many hourly temperatures to fetch for the trend
+const TREND_HOURS: usize = 12;
+
+#[derive(Debug)]
+pub struct WeatherUpdater {
+    update_mode: UpdateMode,
+    client: Client,
+    latitude: f64,
+    longitude: f64,
+    weather_period: ExponentialBackoff,
+}
+
+#[tonic::async_trait]
+impl DataUpdater for WeatherUpdater {
+    fn get_next_update_time(&self) -> Instant {
+        match self.update_mode {
+            UpdateMode::Dummy => Instant::now() + Duration::from_secs(31),
+            UpdateMode::Real => Instant::now() + self.weather_period.get_current_duration(),
+        }
+    }
+
+    async fn update(&mut self, error_bit: &Arc<AtomicBool>) -> Vec<ContentUpdate> {
+        info!("Updating {:?} weather", self.update_mode);
+        let weather;
+        match self.update_mode {
+            UpdateMode::Dummy => {
+                let now = chrono::offset::Local::now();
+                // A wave shifting with the minutes, so charts have something to move
+                let temperature_trend = (0..TREND_HOURS)
+                    .map(|hour| 15.0 + 5.0 * ((now.minute() as usize + hour) as f32 / 3.0).sin())
+                    .collect();
+                weather = Some(Weather {
+                    cloud_cover: (now.minute() * 100 / 59) as f32,
+                    is_day: (7..20).contains(&now.hour()),
+                    temperature_trend,
+                });
+                error_bit.store(
+                    now.second().is_multiple_of(11),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            UpdateMode::Real => {
+                weather = match self.get_weather().await {
+                    Ok(returned_weather) => {
+                        // Make sure the server knows there are no errors
+                        error_bit.store(false, std::sync::atomic::Ordering::Relaxed);
+                        // And potentially resume normal update cadence
+                        self.weather_period.set_success();
+                        Some(returned_weather)
+                    }
+                    Err(e) => {
+                        error!("Error getting weather: {}", e);
+                        error_bit.store(true, std::sync::atomic::Ordering::Relaxed);
+                        self.weather_period.set_error();
+                        // Better no compensation than one for stale weather
+                        None
+                    }
+                }
+            }
+        }
+        vec![ContentUpdate::Weather(weather)]
+    }
+
+    fn update_mode(&mut self) -> Option<&mut UpdateMode> {
+        Some(&mut self.update_mode)
+    }
+}
+
+impl WeatherUpdater {
+    pub fn new(
+        update_mode: UpdateMode,
+        config: &api_config::ApiConfig,
+        client: Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let weather_config = config.weather.as_ref().ok_or("No weather config")?;
+        let weather_period_config = Duration::from_secs(
+            weather_config
+                .update_period
+                .as_ref()
+                .ok_or("no weather update period")?
+                .seconds
+                .try_into()?,
+        );
+        let weather_period = ExponentialBackoff::new(
+            weather_period_config,
+            Duration::from_secs(60), // 1 min
+            Duration::from_secs(1200), // 20 min
+        );
+        Ok(WeatherUpdater {
+            update_mode,
+            client,
+            latitude: weather_config.latitude,
+            longitude: weather_config.longitude,
+            weather_period,
+        })
+    }
+
+    async fn get_weather(&self) -> Result<Weather, Box<dyn std::error::Error>> {
+        let query = [
+            ("latitude", self.latitude.to_string()),
+            ("longitude", self.longitude.to_string()),
+            ("current", "cloud_cover,is_day".to_string()),
+            ("hourly", "temperature_2m".to_string()),
+            ("forecast_hours", TREND_HOURS.to_string()),
+        ];
+        let body = traced(
+            "fetch",
+            retry_http("Weather fetch", || async {
+                self.client
+                    .get(OPEN_METEO_URL)
+                    .query(&query)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await
+            }),
+        )
+        .await?;
+        traced_sync("parse", || parse_weather(&body))
+    }
+}
+
+// Reads the current conditions out of an Open-Meteo forecast response
+fn parse_weather(body: &str) -> Result<Weather, Box<dyn std::error::Error>> {
+    let response: serde_json::Value = serde_json::from_str(body)?;
+    let current = response.get("current").ok_or("No current weather")?;
+    let cloud_cover = current
+        .get("cloud_cover")
+        .and_then(|c| c.as_f64())
+        .ok_or("No cloud cover in current weather")?;
+    let is_day = current
+        .get("is_day")
+        .and_then(|d| d.as_i64())
+        .ok_or("No day indicator in current weather")?;
+    // Only charted, so better no trend than no weather at all
+    let temperature_trend = response
+        .get("hourly")
+        .and_then(|hourly| hourly.get("temperature_2m"))
+        .and_then(|temperatures| temperatures.as_array())
+        .map(|temperatures| {
+            temperatures
+                .iter()
+                .filter_map(|t| t.as_f64())
+                .map(|t| t as f32)
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(Weather {
+        cloud_cover: cloud_cover as f32,
+        is_day: is_day == 1,
+        temperature_trend,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_weather() {
+        let body = r#"{"latitude":46.54,"longitude":6.58,"generationtime_ms":0.02,
+            "utc_offset_seconds":0,"timezone":"GMT","timezone_abbreviation":"GMT","elevation":412.0,
+            "current_units":{"time":"iso8601","interval":"seconds","cloud_cover":"%","is_day":""},
+            "current":{"time":"2024-07-23T12:00","interval":900,"cloud_cover":75,"is_day":1}}"#;
+        assert_eq!(
+            parse_weather(body).unwrap(),
+            Weather {
+                cloud_cover: 75.0,
+                is_day: true,
+                temperature_trend: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_temperature_trend() {
+        let body = r#"{"current":{"cloud_cover":10,"is_day":0},
+            "hourly_units":{"time":"iso8601","temperature_2m":"°C"},
+            "hourly":{"time":["2024-07-23T12:00","2024-07-23T13:00","2024-07-23T14:00"],
+                "temperature_2m":[21.5,23.0,null]}}"#;
+        assert_eq!(
+            parse_weather(body).unwrap().temperature_trend,
+            vec![21.5, 23.0]
+        );
+    }
+
+    #[test]
+    fn doesnt_panic_on_garbled_input() {
+        assert!(parse_weather("").is_err());
+        assert!(parse_weather("{}").is_err());
+        assert!(parse_weather(r#"{"current":{"cloud_cover":"lots"}}"#).is_err());
+    }
+}
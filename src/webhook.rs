@@ -0,0 +1,255 @@
+//! Lets other systems put a notice on the screen over plain HTTP, for one-off integrations that
+//! don't deserve an updater (e.g. a Home Assistant automation telling the washing machine is done).
+//!
+//! They POST some JSON to /notice on the configured `webhook_port`:
+//!     {"text": "Washing done", "duration_seconds": 900}
+//! or with an "expires_at" RFC 3339 timestamp instead of the duration. With a `webhook_token`
+//! configured, requests must also carry an "Authorization: Bearer <token>" header.
+
+use crate::my_screen_service::MyScreenService;
+use crate::screen_service::Notice;
+use chrono::DateTime;
+use prost_types::Timestamp;
+use serde_json::Value;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+// Headers and body together, notices are short
+const MAX_REQUEST_BYTES: u64 = 8192;
+// For slow or stuck clients not to keep their connection forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// When the request doesn't say
+const DEFAULT_DURATION: Duration = Duration::from_secs(3600);
+// Before accepting again after a failure (e.g. out of file descriptors), not to spin on it
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    // Names in lowercase
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Takes notices from other systems, forever (or until we can't listen on the port)
+pub async fn serve_webhook(
+    service: MyScreenService,
+    port: u32,
+    token: String,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    info!("Taking notices over HTTP on port {}", port);
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Error accepting a webhook request: {}", e);
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let service = service.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_one(socket, &service, &token).await {
+                warn!("Error serving webhook request from {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn serve_one(
+    socket: TcpStream,
+    service: &MyScreenService,
+    token: &str,
+) -> Result<(), String> {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader.take(MAX_REQUEST_BYTES));
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut reader))
+        .await
+        .map_err(|_| "Timed out reading the request".to_string())?;
+    let notice = request.and_then(|request| get_notice(&request, token, SystemTime::now()));
+    let (status, message) = match notice {
+        Ok(notice) => {
+            info!("Pushing notice from webhook: {:?}", notice);
+            match service.push_notice(notice) {
+                Ok(()) => (204, String::new()),
+                Err(e) => (503, e),
+            }
+        }
+        Err((status, message)) => {
+            warn!("Rejecting webhook request ({}): {}", status, message);
+            (status, message)
+        }
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        message.len(),
+        message
+    );
+    writer
+        .write_all(response.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    writer.shutdown().await.map_err(|e| e.to_string())
+}
+
+// Just enough HTTP/1.1 for curl and the likes: a request line, headers and a sized body
+async fn read_request<R: AsyncBufReadExt + Unpin>(
+    reader: &mut R,
+) -> Result<HttpRequest, (u16, String)> {
+    let bad_request = |message: &str| (400, message.to_string());
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .await
+        .map_err(|e| bad_request(&e.to_string()))?;
+    let mut request_line = line.split_whitespace();
+    let mut request = HttpRequest {
+        method: request_line.next().unwrap_or_default().to_string(),
+        path: request_line.next().unwrap_or_default().to_string(),
+        ..Default::default()
+    };
+    loop {
+        line.clear();
+        reader
+            .read_line(&mut line)
+            .await
+            .map_err(|e| bad_request(&e.to_string()))?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| bad_request("Bad header"))?;
+        request
+            .headers
+            .push((name.trim().to_lowercase(), value.trim().to_string()));
+    }
+    let length = match request.header("content-length") {
+        Some(length) => length
+            .parse()
+            .map_err(|_| bad_request("Bad content length"))?,
+        None => 0,
+    };
+    if length as u64 > MAX_REQUEST_BYTES {
+        return Err((413, "Body too long".into()));
+    }
+    request.body = vec![0; length];
+    reader
+        .read_exact(&mut request.body)
+        .await
+        .map_err(|_| (413, "Body too long or cut short".to_string()))?;
+    Ok(request)
+}
+
+fn get_notice(
+    request: &HttpRequest,
+    token: &str,
+    now: SystemTime,
+) -> Result<Notice, (u16, String)> {
+    if request.path != "/notice" {
+        return Err((404, "Only /notice is served".into()));
+    }
+    if request.method != "POST" {
+        return Err((405, "Notices are POSTed".into()));
+    }
+    let bearer = request
+        .header("authorization")
+        .and_then(|authorization| authorization.strip_prefix("Bearer "));
+    if !token.is_empty() && bearer != Some(token) {
+        return Err((401, "Missing or wrong token".into()));
+    }
+    let bad_request = |message: String| (400, message);
+    let body: Value =
+        serde_json::from_slice(&request.body).map_err(|e| bad_request(e.to_string()))?;
+    let text = body
+        .get("text")
+        .and_then(Value::as_str)
+        .filter(|text| !text.trim().is_empty())
+        .ok_or_else(|| bad_request("No text".into()))?;
+    let expires_at = match (body.get("duration_seconds"), body.get("expires_at")) {
+        (Some(duration), _) => {
+            let seconds = duration
+                .as_u64()
+                .ok_or_else(|| bad_request("Bad duration_seconds".into()))?;
+            now.checked_add(Duration::from_secs(seconds))
+                .ok_or_else(|| bad_request("duration_seconds too long".into()))?
+        }
+        (None, Some(expires_at)) => {
+            let expires_at = expires_at
+                .as_str()
+                .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+                .ok_or_else(|| bad_request("Bad expires_at".into()))?;
+            SystemTime::from(expires_at)
+        }
+        (None, None) => now + DEFAULT_DURATION,
+    };
+    Ok(Notice {
+        text: text.trim().to_string(),
+        expires_at: Some(Timestamp::from(expires_at)),
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Service Unavailable",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn takes_posted_notices() {
+        let raw = "POST /notice HTTP/1.1\r\nHost: screen\r\nAuthorization: Bearer s3cret\r\n\
+            Content-Type: application/json\r\nContent-Length: 47\r\n\r\n\
+            {\"text\": \"Washing done\", \"duration_seconds\": 9}";
+        let request = read_request(&mut raw.as_bytes()).await.unwrap();
+        assert_eq!(request.header("content-type"), Some("application/json"));
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let notice = get_notice(&request, "s3cret", now).unwrap();
+        assert_eq!(notice.text, "Washing done");
+        assert_eq!(notice.expires_at.unwrap().seconds, 1009);
+
+        assert_eq!(get_notice(&request, "other", now).unwrap_err().0, 401);
+        let request = HttpRequest {
+            body: br#"{"text": "Door open", "expires_at": "1970-01-01T00:20:00Z"}"#.to_vec(),
+            ..request
+        };
+        let notice = get_notice(&request, "s3cret", now).unwrap();
+        assert_eq!(notice.expires_at.unwrap().seconds, 1200);
+        let request = HttpRequest {
+            body: br#"{"text": "Forever", "duration_seconds": 18446744073709551615}"#.to_vec(),
+            ..request
+        };
+        assert_eq!(get_notice(&request, "s3cret", now).unwrap_err().0, 400);
+        let request = HttpRequest {
+            body: br#"{"duration_seconds": 60}"#.to_vec(),
+            ..request
+        };
+        assert_eq!(get_notice(&request, "s3cret", now).unwrap_err().0, 400);
+    }
+}